@@ -0,0 +1,192 @@
+use core::*;
+use eval::*;
+use game::*;
+
+// Ends games early once the engines' own root scores leave no doubt about the result.
+// Every score is reported from the point of view of the side that just searched.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct AdjudicationRules {
+    // a side resigns once every one of resign_count reports in a row has it this far behind
+    pub resign_score: i16,
+    pub resign_count: usize,
+    // a draw once draw_count reports in a row are this close to even, counting from draw_after_move
+    pub draw_score: i16,
+    pub draw_count: usize,
+    pub draw_after_move: u32
+}
+
+impl AdjudicationRules {
+    pub fn new() -> AdjudicationRules {
+        AdjudicationRules {
+            resign_score: 900,
+            resign_count: 4,
+            draw_score: 10,
+            draw_count: 16,
+            draw_after_move: 40
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Adjudication {
+    // by the losing side
+    Resignation(Color),
+    Draw
+}
+
+impl Adjudication {
+    pub fn result(self) -> GameResult {
+        match self {
+            Adjudication::Resignation(loser) => GameResult::Win(!loser),
+            Adjudication::Draw => GameResult::Draw
+        }
+    }
+
+    // for a PGN Termination tag
+    pub fn termination(self) -> &'static str {
+        match self {
+            Adjudication::Resignation(Color::White) => "adjudication: white resigns",
+            Adjudication::Resignation(Color::Black) => "adjudication: black resigns",
+            Adjudication::Draw => "adjudication: drawn"
+        }
+    }
+}
+
+pub struct Adjudicator {
+    pub rules: AdjudicationRules,
+    // consecutive reports with white, or black, hopelessly behind
+    losing_streak: [usize; 2],
+    drawn_streak: usize
+}
+
+impl Adjudicator {
+    pub fn new(rules: AdjudicationRules) -> Adjudicator {
+        Adjudicator {
+            rules: rules,
+            losing_streak: [0; 2],
+            drawn_streak: 0
+        }
+    }
+
+    // With two engines reporting in turn, a resignation needs both of them to agree
+    // on the score for resign_count / 2 moves each.
+    pub fn record(&mut self, mover: Color, score: Score, fullmove: u32) -> Option<Adjudication> {
+        let white_score = match mover {
+            Color::White => score.unwrap(),
+            Color::Black => score.flipped().unwrap()
+        };
+
+        for color in [Color::White, Color::Black].iter() {
+            let own_score = if *color == Color::White { white_score } else { -white_score };
+            let streak = &mut self.losing_streak[*color as usize];
+
+            *streak = if own_score <= -self.rules.resign_score { *streak + 1 } else { 0 };
+        }
+
+        if fullmove >= self.rules.draw_after_move && white_score.abs() <= self.rules.draw_score {
+            self.drawn_streak += 1;
+        } else {
+            self.drawn_streak = 0;
+        }
+
+        for color in [Color::White, Color::Black].iter() {
+            if self.losing_streak[*color as usize] >= self.rules.resign_count {
+                return Some(Adjudication::Resignation(*color));
+            }
+        }
+
+        if self.drawn_streak >= self.rules.draw_count {
+            return Some(Adjudication::Draw);
+        }
+
+        return None;
+    }
+
+    // a declined draw offer has to be earned again from scratch
+    pub fn decline_draw(&mut self) {
+        self.drawn_streak = 0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use adjudication::*;
+
+    fn rules() -> AdjudicationRules {
+        AdjudicationRules {
+            resign_score: 900,
+            resign_count: 4,
+            draw_score: 10,
+            draw_count: 6,
+            draw_after_move: 30
+        }
+    }
+
+    // alternating reports starting with white, stopping at the first adjudication
+    fn play_out(scores: &[i16], first_move: u32) -> Option<(usize, Adjudication)> {
+        let mut adjudicator = Adjudicator::new(rules());
+
+        for (i, score) in scores.iter().enumerate() {
+            let mover = if i % 2 == 0 { Color::White } else { Color::Black };
+            let fullmove = first_move + i as u32 / 2;
+
+            if let Some(adjudication) = adjudicator.record(mover, Score::new(*score), fullmove) {
+                return Some((i, adjudication));
+            }
+        }
+
+        return None;
+    }
+
+    #[test]
+    fn resignation() {
+        // black agrees with white that it's lost
+        assert_eq!(play_out(&[950, -950, 1000, -1200], 10), Some((3, Adjudication::Resignation(Color::Black))));
+
+        // and when black's engine thinks it's fine, there's no resignation
+        assert_eq!(play_out(&[950, 0, 1000, -1200, 1300], 10), None);
+        assert_eq!(play_out(&[950, 0, 1000, -1200, 1300, -1400], 10),
+                   Some((5, Adjudication::Resignation(Color::Black))));
+
+        assert_eq!(play_out(&[-900, 900, -900, 900], 10), Some((3, Adjudication::Resignation(Color::White))));
+        assert_eq!(play_out(&[-899, 900, -900, 900], 10), None);
+
+        // a mate score counts as far behind
+        let mated = Score::min_at_depth(5).unwrap();
+        assert_eq!(play_out(&[mated, -mated, mated, -mated], 10), Some((3, Adjudication::Resignation(Color::White))));
+    }
+
+    #[test]
+    fn draw() {
+        let even = [0, 5, -10, 10, 3, -2];
+
+        assert_eq!(play_out(&even, 30), Some((5, Adjudication::Draw)));
+
+        // too early in the game
+        assert_eq!(play_out(&even, 20), None);
+
+        // only the moves past the threshold count
+        assert_eq!(play_out(&[0, 0, 0, 0, 0, 0, 0, 0], 28), None);
+        assert_eq!(play_out(&[0, 0, 0, 0, 0, 0, 0, 0, 0, 0], 28), Some((9, Adjudication::Draw)));
+
+        // one unbalanced score starts the count again
+        assert_eq!(play_out(&[0, 0, 0, 0, 0, 50, 0, 0, 0, 0, 0], 30), None);
+
+        let mut adjudicator = Adjudicator::new(rules());
+        for _ in 0 .. 5 {
+            assert_eq!(adjudicator.record(Color::Black, Score::new(0), 50), None);
+        }
+        adjudicator.decline_draw();
+        for _ in 0 .. 5 {
+            assert_eq!(adjudicator.record(Color::Black, Score::new(0), 50), None);
+        }
+        assert_eq!(adjudicator.record(Color::Black, Score::new(0), 50), Some(Adjudication::Draw));
+    }
+
+    #[test]
+    fn results() {
+        assert_eq!(Adjudication::Resignation(Color::Black).result(), GameResult::Win(Color::White));
+        assert_eq!(Adjudication::Draw.result(), GameResult::Draw);
+        assert_eq!(Adjudication::Resignation(Color::White).termination(), "adjudication: white resigns");
+    }
+}
@@ -0,0 +1,187 @@
+use core::*;
+use eval::*;
+use feldspar::*;
+use game::*;
+use moves::*;
+use pgn::*;
+use search::*;
+use uci::*;
+use zobrist::*;
+
+use std::io;
+
+const ANNOTATE_TABLE_SIZE: usize = 10000000;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct AnnotateOptions {
+    // how long to search each position, either or both
+    pub depth: Option<u8>,
+    pub movetime: Option<u32>,
+    // positions before this move number are left as they are
+    pub first_move: u32,
+    // centipawns a move may lose against the engine's choice before it gets a "?", or a "??"
+    pub mistake: i16,
+    pub blunder: i16,
+    // entries in the engine's transposition table
+    pub table_size: usize
+}
+
+impl AnnotateOptions {
+    pub fn new() -> AnnotateOptions {
+        AnnotateOptions {
+            depth: Some(10),
+            movetime: None,
+            first_move: 1,
+            mistake: 100,
+            blunder: 300,
+            table_size: ANNOTATE_TABLE_SIZE
+        }
+    }
+}
+
+// The engine's view of every move played in game from options.first_move on: a comment with
+// its evaluation, and a "?" or "??" when it's that much worse than the move the engine would
+// have played. The played move is searched on its own with the same limits, so the two scores
+// are comparable. progress(move, moves) is called before each position is searched.
+pub fn annotate_game(engine: &mut Feldspar, game: &PgnGame, options: &AnnotateOptions,
+                     progress: &mut FnMut(usize, usize)) -> Vec<MoveNote> {
+    let mut silent = UciIo::new(Box::new(io::empty()), Box::new(io::sink()));
+    let mut notes = Vec::new();
+
+    let mut position = game.start;
+    let mut history = vec![position.hash];
+
+    engine.reset();
+
+    for (i, &played) in game.moves.iter().enumerate() {
+        if position.fullmoves < options.first_move {
+            notes.push(MoveNote::none());
+        } else {
+            progress(i + 1, game.moves.len());
+
+            let mut limits = SearchLimits::new();
+            limits.depth = options.depth;
+            limits.movetime = options.movetime;
+
+            engine.replace_game(position, history.clone());
+            let best = engine.search_position(&limits, &mut silent);
+
+            let played_result = if played == best.best_move {
+                best.clone()
+            } else {
+                limits.searchmoves = vec![played];
+                engine.search_position(&limits, &mut silent)
+            };
+
+            let (played_score, depth) = (played_result.score, played_result.depth);
+            let loss = best.score.unwrap() as i32 - played_score.unwrap() as i32;
+            let suffix = if loss >= options.blunder as i32 {
+                "??"
+            } else if loss >= options.mistake as i32 {
+                "?"
+            } else {
+                ""
+            };
+
+            let comment = format!("[%eval {}/{}]", eval_str(played_score, position.to_move), depth);
+            notes.push(MoveNote { suffix: suffix, comment: Some(comment) });
+        }
+
+        position.make_move(played);
+        history.push(position.hash);
+    }
+
+    return notes;
+}
+
+// Every game in a PGN file annotated as annotate_game does, with the engine as Annotator.
+// progress(game, games, move, moves) is called before each position is searched.
+pub fn annotate_pgn(pgn: &str, options: &AnnotateOptions,
+                    progress: &mut FnMut(usize, usize, usize, usize)) -> Result<String, String> {
+    init_zobrist_hashing();
+
+    let games = read_pgn(pgn)?;
+    let mut engine = Feldspar::with_table_size(options.table_size);
+    let mut annotated = String::new();
+
+    for (i, game) in games.iter().enumerate() {
+        let notes = annotate_game(&mut engine, game, options, &mut |m, moves| progress(i + 1, games.len(), m, moves));
+
+        let mut game = game.clone();
+        game.set_tag("Annotator", "feldspar");
+
+        if i > 0 {
+            annotated.push('\n');
+        }
+        annotated.push_str(&write_pgn(&game, &notes));
+    }
+
+    return Ok(annotated);
+}
+
+// As a [%eval] comment has it, in pawns from white's point of view, or #N for a mate in
+// N moves, #-N when it's black who mates.
+fn eval_str(score: Score, to_move: Color) -> String {
+    let white_score = if to_move == Color::White { score } else { score.flipped() };
+
+    if let Some(moves) = white_score.mate_moves() {
+        return format!("#{}", moves);
+    }
+
+    return format!("{:.2}", white_score.unwrap() as f32 / 100.0);
+}
+
+#[cfg(test)]
+mod test {
+    use annotate::*;
+
+    #[test]
+    fn eval_strings() {
+        assert_eq!(eval_str(Score::new(35), Color::White), "0.35");
+        assert_eq!(eval_str(Score::new(35), Color::Black), "-0.35");
+        assert_eq!(eval_str(Score::new(-250), Color::White), "-2.50");
+
+        // mates in one and two found by white, and by black
+        assert_eq!(eval_str(Score::max(), Color::White), "#1");
+        assert_eq!(eval_str(Score::max_at_depth(2), Color::White), "#2");
+        assert_eq!(eval_str(Score::max_at_depth(2), Color::Black), "#-2");
+        assert_eq!(eval_str(Score::min_at_depth(1), Color::White), "#-1");
+    }
+
+    #[test]
+    fn blunder() {
+        // black's 6...Qxd4?? throws the queen away, and the game breaks off without a result
+        let pgn = r#"
+[Event "Scandinavian"]
+[White "W"]
+[Black "B"]
+
+1. e4 d5 2. exd5 Qxd5 3. Nf3 Bg4 4. Be2 e6 5. d4 Bxf3 6. Bxf3 Qxd4
+"#;
+
+        let mut options = AnnotateOptions::new();
+        options.depth = Some(4);
+        options.first_move = 5;
+        options.table_size = 1 << 16;
+
+        let mut searched = Vec::new();
+        let annotated = annotate_pgn(pgn, &options, &mut |game, games, m, moves| {
+            searched.push((game, games, m, moves));
+        }).unwrap();
+
+        // only from move 5 on, and every one of those
+        assert_eq!(searched.first(), Some(&(1, 1, 9, 12)));
+        assert_eq!(searched.len(), 4);
+
+        assert!(annotated.contains("[Annotator \"feldspar\"]"));
+        assert!(annotated.trim_right().ends_with("*"));
+
+        let blundered: Vec<&str> = annotated.split_whitespace().filter(|t| t.ends_with("??")).collect();
+        assert_eq!(blundered, vec!["Qxd4??"]);
+        assert!(annotated.contains("6... Qxd4?? {[%eval "));
+
+        // earlier moves have no comments, later ones do
+        assert!(annotated.contains("5. d4 {[%eval "));
+        assert!(!annotated.contains("Be2 {"));
+    }
+}
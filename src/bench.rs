@@ -0,0 +1,201 @@
+use core::*;
+use eval::*;
+use feldspar::*;
+use game::*;
+use movegen::*;
+use move_list::*;
+use positions::*;
+use search::*;
+use stats::*;
+use tree::*;
+use uci::*;
+use zobrist::*;
+
+use std::io;
+
+use prettytable::Table;
+
+const BENCH_TABLE_SIZE: usize = 1000000;
+
+pub const BENCH_POSITIONS: [&'static str; 8] = [
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+    "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+    "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+    "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+    "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+    "r1bq1rk1/pp2bppp/2n1pn2/3p4/2PP4/2N1PN2/PP1B1PPP/R2QKB1R w KQ - 3 8",
+    "6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1"
+];
+
+// Searches every bench position to a fixed depth and returns (total nodes, nodes per second).
+// Each position starts from an empty transposition table so the node count is reproducible.
+pub fn bench(depth: usize) -> (u64, f64) {
+    let start_time = Counter::new();
+    let mut total_nodes = 0;
+    let mut total_stats = SearchStats::new();
+
+    let mut limits = SearchLimits::new();
+    limits.depth = Some(depth as u8);
+    let mut silent = UciIo::new(Box::new(io::empty()), Box::new(io::sink()));
+
+    for (i, fen) in BENCH_POSITIONS.iter().enumerate() {
+        let mut engine = Feldspar::with_table_size(BENCH_TABLE_SIZE);
+        engine.replace_game(Game::from_fen_str(fen).unwrap(), Vec::new());
+
+        let result = engine.search_position(&limits, &mut silent);
+        println!("position {}: {} nodes, bestmove {}", i + 1, result.nodes, result.best_move.to_uci_str());
+        total_nodes += result.nodes;
+        total_stats.merge(engine.stats());
+    }
+
+    let elapsed_ns = start_time.elapsed_ns();
+    let nps = if elapsed_ns > 0 {
+        1e9 * total_nodes as f64 / elapsed_ns as f64
+    } else {
+        0.0
+    };
+
+    println!("===========================");
+    println!("Total time (ms) : {}", elapsed_ns / 1_000_000);
+    println!("Nodes searched  : {}", total_nodes);
+    println!("Nodes/second    : {:.0}", nps);
+
+    if let Some(summary) = total_stats.summary() {
+        println!("{}", summary);
+    }
+    total_stats.print_summary();
+
+    return (total_nodes, nps);
+}
+
+// Static eval throughput over random positions, with and without the pawn hash table.
+pub fn bench_eval(num_positions: usize) {
+    const PASSES: usize = 20;
+
+    let games: Vec<Game> = (0 .. num_positions).map(|_| Game::random_game()).collect();
+
+    let mut timer = Counter::new();
+    let mut uncached_sum: i64 = 0;
+
+    for _ in 0 .. PASSES {
+        for game in games.iter() {
+            uncached_sum += Score::recompute_uncached(game, 0).unwrap() as i64;
+        }
+    }
+
+    let uncached_ns = timer.elapsed_ns();
+    timer.restart();
+    let mut cached_sum: i64 = 0;
+
+    for _ in 0 .. PASSES {
+        for game in games.iter() {
+            cached_sum += Score::recompute(game, 0).unwrap() as i64;
+        }
+    }
+
+    let cached_ns = timer.elapsed_ns();
+
+    assert_eq!(uncached_sum, cached_sum);
+
+    let evals = (PASSES * num_positions) as f64;
+    let evals_per_second = |ns: u64| if ns > 0 { 1e9 * evals / ns as f64 } else { 0.0 };
+
+    println!("Evals/second (no pawn hash) : {:.0}", evals_per_second(uncached_ns));
+    println!("Evals/second (pawn hash)    : {:.0}", evals_per_second(cached_ns));
+}
+
+pub struct Timing {
+    pub iterations: u64,
+    // sum of everything the timed function returned
+    pub total: u64,
+    pub elapsed_ns: u64
+}
+
+impl Timing {
+    pub fn per_second(&self, count: u64) -> f64 {
+        if self.elapsed_ns > 0 { 1e9 * count as f64 / self.elapsed_ns as f64 } else { 0.0 }
+    }
+}
+
+// Calls f repeatedly for at least duration_ms. The clock is only read every few calls
+// so that reading it doesn't dominate very fast functions.
+pub fn time_repeatedly<F: FnMut() -> usize>(duration_ms: u64, mut f: F) -> Timing {
+    const BATCH: u64 = 64;
+
+    let duration_ns = duration_ms * 1_000_000;
+    let timer = Counter::new();
+    let mut timing = Timing { iterations: 0, total: 0, elapsed_ns: 0 };
+
+    loop {
+        for _ in 0 .. BATCH {
+            timing.total += f() as u64;
+        }
+
+        timing.iterations += BATCH;
+        timing.elapsed_ns = timer.elapsed_ns();
+
+        if timing.elapsed_ns >= duration_ns {
+            return timing;
+        }
+    }
+}
+
+// Move generation alone, per category of position, so that a regression in one kind of
+// position (evasions, say) isn't averaged away by the others.
+pub fn bench_movegen(ms_per_position: u64) {
+    let mut buffer = MoveList::new();
+
+    let mut table = Table::new();
+    table.add_row(row!["CATEGORY", "GENERATOR", "POSITIONS/SECOND", "MOVES/SECOND"]);
+
+    for category in MOVEGEN_CATEGORIES.iter() {
+        for &(generator, captures_only) in [("all", false), ("captures", true)].iter() {
+            let mut combined = Timing { iterations: 0, total: 0, elapsed_ns: 0 };
+
+            for fen in category.fens.iter() {
+                let game = Game::from_fen_str(fen).unwrap();
+
+                let timing = time_repeatedly(ms_per_position, || {
+                    generate_moves(&game, &mut buffer, captures_only);
+                    buffer.len()
+                });
+
+                combined.iterations += timing.iterations;
+                combined.total += timing.total;
+                combined.elapsed_ns += timing.elapsed_ns;
+            }
+
+            table.add_row(row![category.name, generator,
+                               format!("{:.0}", combined.per_second(combined.iterations)),
+                               format!("{:.0}", combined.per_second(combined.total))]);
+        }
+    }
+
+    table.print_tty(false);
+}
+
+#[cfg(test)]
+mod test {
+    use bench::*;
+
+    #[test]
+    fn deterministic() {
+        init_zobrist_hashing();
+        let (first_nodes, _) = bench(3);
+        let (second_nodes, _) = bench(3);
+        assert!(first_nodes > 0);
+        assert_eq!(first_nodes, second_nodes);
+    }
+
+    #[test]
+    fn time_repeatedly_counts() {
+        let mut calls = 0;
+        let timing = time_repeatedly(1, || { calls += 1; 3 });
+
+        assert!(timing.iterations > 0);
+        assert_eq!(timing.iterations, calls);
+        assert_eq!(timing.total, 3 * calls);
+        assert!(timing.elapsed_ns >= 1_000_000);
+    }
+}
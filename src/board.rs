@@ -1,11 +1,34 @@
 use core::*;
 use bitboard::*;
+use pins::*;
 use tables::*;
+use zobrist::*;
 
-#[derive(Debug, PartialEq, Clone, Copy)]
+use std::cmp::min;
+
+// game phase with every piece on the board, down to 0 with only kings and pawns
+pub const MAX_GAME_PHASE: u8 = 24;
+
+// how much each piece counts towards the game phase
+pub fn phase_weight(ptype: PieceType) -> u32 {
+    use PieceType::*;
+
+    match ptype {
+        Knight | Bishop => 1,
+        Rook => 2,
+        Queen => 4,
+        Pawn | King => 0
+    }
+}
+
+#[derive(PartialEq, Clone, Copy)]
 pub struct Board {
     pieces: [Bitboard;12],
-    occupied: [Bitboard;2]
+    occupied: [Bitboard;2],
+    // both colors together, kept up to date so occupied() doesn't have to combine them
+    all_occupied: Bitboard,
+    // indexed by color, meaningless for a color without a king
+    king_squares: [Square; 2]
 }
 
 impl Board {
@@ -17,10 +40,39 @@ impl Board {
                       Bitboard::new(0), Bitboard::new(0),
                       Bitboard::new(0), Bitboard::new(0),
                       Bitboard::new(0), Bitboard::new(0)],
-            occupied: [ Bitboard::new(0), Bitboard::new(0) ]
+            occupied: [ Bitboard::new(0), Bitboard::new(0) ],
+            all_occupied: Bitboard::new(0),
+            king_squares: [Square::new(0), Square::new(0)]
         }
     }
 
+    // Eight ranks of ".pnbrqkPNBRQK", eighth rank first. Whitespace is ignored, so the
+    // ranks can be laid out on separate lines the way the board looks:
+    //
+    //     Board::from_diagram("....k...
+    //                          ........
+    //                          ...")
+    pub fn from_diagram(diagram: &str) -> Option<Board> {
+        let mut board = Board::empty_position();
+        let squares: Vec<char> = diagram.chars().filter(|c| !c.is_whitespace()).collect();
+
+        if squares.len() != 64 {
+            return None;
+        }
+
+        // the diagram is read from a8, which is index 63
+        for (i, &c) in squares.iter().enumerate() {
+            if c == '.' {
+                continue;
+            }
+
+            let piece = Piece::from_char(c)?;
+            board.put(piece.color, piece.ptype, Square::new(63 - i as u32));
+        }
+
+        return Some(board);
+    }
+
     // queens or >2 bishops (from promotions)
     // pub fn bishops_queen(&self, color: Color) -> QuadBitboard {
     //     let mut b = [0,0];
@@ -58,22 +110,64 @@ impl Board {
         return unsafe { self.pieces.get_unchecked_mut(idx) };
     }
 
-    pub fn set_piece_bit(&mut self, color: Color, ptype: PieceType, square: Square) {
+    // Places a piece, replacing whatever was on the square, with the occupancy and king
+    // squares kept in step. Game::put does the same for the hashes and material key.
+    pub fn put(&mut self, color: Color, ptype: PieceType, square: Square) {
+        self.take(square);
+
         let bit = square.bitrep();
         *self.get_pieces_mut(color, ptype) |= bit;
         self.occupied[color as usize] |= bit;
+        self.all_occupied |= bit;
+
+        if ptype == PieceType::King {
+            self.king_squares[color as usize] = square;
+        }
+    }
+
+    pub fn take(&mut self, square: Square) -> Option<Piece> {
+        let piece = self.piece_at(square)?;
+        let bit = square.bitrep();
+
+        *self.get_pieces_mut(piece.color, piece.ptype) &= !bit;
+        self.occupied[piece.color as usize] &= !bit;
+        self.all_occupied &= !bit;
+
+        // as empty_position has it, so a board emptied piece by piece is an empty board
+        if piece.ptype == PieceType::King {
+            self.king_squares[piece.color as usize] = Square::new(0);
+        }
+
+        return Some(piece);
+    }
+
+    // the king's bitboard is moved along with everything else's, this keeps the square in step
+    pub fn set_king_square(&mut self, color: Color, square: Square) {
+        self.king_squares[color as usize] = square;
     }
 
     pub fn occupied_by(&self, color: Color) -> Bitboard {
         return self.occupied[color as usize];
     }
 
-    pub fn occupied_by_mut(&mut self, color: Color) -> &mut Bitboard {
-        return &mut self.occupied[color as usize];
+    // Moving or capturing a piece toggles its squares for its own color. Both colors can
+    // briefly claim the same square during make_move, so all_occupied is kept as the xor of
+    // the two, which is their union again once the move is complete.
+    pub fn toggle_occupied(&mut self, color: Color, bits: Bitboard) {
+        self.occupied[color as usize] ^= bits;
+        self.all_occupied ^= bits;
+    }
+
+    // only for squares the other color doesn't occupy
+    pub fn clear_occupied(&mut self, color: Color, bits: Bitboard) {
+        debug_assert!((bits & self.occupied[!color as usize]).empty());
+        self.occupied[color as usize] &= !bits;
+        self.all_occupied &= !bits;
     }
 
     pub fn occupied(&self) -> Bitboard {
-        return self.occupied[0] | self.occupied[1];
+        debug_assert!(self.all_occupied == self.occupied[0] | self.occupied[1]);
+        return self.all_occupied;
     }
 
     pub fn unoccupied(&self) -> Bitboard {
@@ -109,10 +203,9 @@ impl Board {
         return None;
     }
 
-    //OPTIMIZE: keep king squares in Game struct?
     pub fn get_king_square(&self, color: Color) -> Square {
-        let k = self.get_pieces(color, PieceType::King);
-        k.bitscan_forward()
+        debug_assert!(self.get_pieces(color, PieceType::King) == self.king_squares[color as usize].bitrep());
+        return self.king_squares[color as usize];
     }
 
     pub fn attackers(&self, square: Square, color: Color) -> Bitboard {
@@ -138,6 +231,69 @@ impl Board {
         return attackers;
     }
 
+    // The same as attackers, for a square that color didn't attack before some of its pieces
+    // left the vacated squares and arrived on the arrived squares. Only the arrived pieces and
+    // the sliders on lines through any of those squares can be attacking it now.
+    pub fn attackers_after(&self, square: Square, color: Color, vacated: Bitboard, arrived: Bitboard) -> Bitboard {
+        use PieceType::*;
+
+        let mut attackers: Bitboard = Bitboard::new(0);
+        let idx = square.idx();
+
+        unsafe {
+            attackers |= *PAWN_ATTACKS.get_unchecked(!color as usize).get_unchecked(idx) & self.get_pieces(color, Pawn);
+            attackers |= *KNIGHT_TABLE.get_unchecked(idx) & self.get_pieces(color, Knight);
+            attackers |= *KING_TABLE.get_unchecked(idx) & self.get_pieces(color, King);
+        }
+
+        attackers &= arrived;
+
+        let changed = vacated | arrived;
+        let occupied = self.occupied();
+
+        if (get_bishop_rays(square, Bitboard::new(0)) & changed).nonempty() {
+            let bishops_queens = self.get_pieces(color, Queen) | self.get_pieces(color, Bishop);
+            attackers |= get_bishop_rays(square, occupied) & bishops_queens;
+        }
+
+        if (get_rook_rays(square, Bitboard::new(0)) & changed).nonempty() {
+            let rooks_queens = self.get_pieces(color, Queen) | self.get_pieces(color, Rook);
+            attackers |= get_rook_rays(square, occupied) & rooks_queens;
+        }
+
+        debug_assert!(attackers == self.attackers(square, color));
+        return attackers;
+    }
+
+    // The cheapest piece of color `by` attacking square, considering only pieces in occupied.
+    // Removing pieces from occupied as they capture uncovers the x-ray attackers behind them, as in SEE.
+    pub fn least_valuable_attacker(&self, square: Square, by: Color, occupied: Bitboard) -> Option<(PieceType, Square)> {
+        use PieceType::*;
+
+        let idx = square.idx();
+
+        for ptype in PieceType::all() {
+            let attacks = unsafe {
+                match *ptype {
+                    Pawn   => *PAWN_ATTACKS.get_unchecked(!by as usize).get_unchecked(idx),
+                    Knight => *KNIGHT_TABLE.get_unchecked(idx),
+                    Bishop => get_bishop_rays(square, occupied),
+                    Rook   => get_rook_rays(square, occupied),
+                    Queen  => get_bishop_rays(square, occupied) | get_rook_rays(square, occupied),
+                    King   => *KING_TABLE.get_unchecked(idx)
+                }
+            };
+
+            let attackers = attacks & self.get_pieces(by, *ptype) & occupied;
+
+            if attackers.nonempty() {
+                return Some((*ptype, attackers.bitscan_forward()));
+            }
+        }
+
+        return None;
+    }
+
     pub fn attackers_flood(&self, square: Square, color: Color) -> Bitboard {
         use PieceType::*;
 
@@ -173,6 +329,38 @@ impl Board {
         return attackers;
     }
 
+    // the squares attacked by the piece on sq, none if it's empty
+    pub fn attacks_from(&self, sq: Square) -> Bitboard {
+        use PieceType::*;
+
+        let piece = match self.piece_at(sq) {
+            Some(piece) => piece,
+            None => return Bitboard::new(0)
+        };
+
+        let occupied = self.occupied();
+
+        match piece.ptype {
+            Pawn   => PAWN_ATTACKS[piece.color as usize][sq.idx()],
+            Knight => KNIGHT_TABLE[sq.idx()],
+            Bishop => get_bishop_rays(sq, occupied),
+            Rook   => get_rook_rays(sq, occupied),
+            Queen  => get_bishop_rays(sq, occupied) | get_rook_rays(sq, occupied),
+            King   => KING_TABLE[sq.idx()]
+        }
+    }
+
+    // The attacks of the piece on sq that it could also move along, so only those on the line
+    // it's pinned on when it's absolutely pinned. Squares with pieces of its own color are left in.
+    pub fn legal_mobility(&self, sq: Square, pin_info: &PinInfo) -> Bitboard {
+        self.attacks_from(sq) & pin_info.legal_ray(sq)
+    }
+
+    // every square attacked by color, for king safety and mobility
+    pub fn attacks_by(&self, color: Color) -> Bitboard {
+        self.attacked(color, false)
+    }
+
     //OPTIMIZE: do flood fill instead of generating attacks for individual pieces
     // since it doesn't matter which piece is attacking where.
     pub fn attacked(&self, attacking_color: Color, remove_king: bool) -> Bitboard {
@@ -266,6 +454,48 @@ impl Board {
         return attacked;
     }
 
+    // Recomputed from piece counts each time, which is only a few popcounts. Promotions can
+    // put more pieces on the board than the start position has, but not past MAX_GAME_PHASE.
+    pub fn game_phase(&self) -> u8 {
+        use PieceType::*;
+
+        let mut phase = 0;
+
+        for color in [Color::White, Color::Black].iter() {
+            for ptype in [Knight, Bishop, Rook, Queen].iter() {
+                phase += phase_weight(*ptype) * self.get_pieces(*color, *ptype).population();
+            }
+        }
+
+        return min(phase, MAX_GAME_PHASE as u32) as u8;
+    }
+
+    // Computes the full zobrist key from scratch. This is the reference that the
+    // incrementally updated Game::hash is checked against.
+    pub fn zobrist_key(&self, to_move: Color, castling_rights: CastlingRights, ep_square: Option<Square>) -> u64 {
+        let mut hash = Hash::empty();
+
+        for color in [Color::White, Color::Black].iter() {
+            for ptype in PieceType::all() {
+                for square in self.get_pieces(*color, *ptype) {
+                    hash.change_piece(*color, *ptype, square);
+                }
+            }
+        }
+
+        hash.update_castling_rights(castling_rights);
+
+        if to_move == Color::Black {
+            hash.update_black_to_move();
+        }
+
+        if let Some(square) = ep_square {
+            hash.modify_ep_square(square);
+        }
+
+        return hash.unwrap();
+    }
+
     pub fn flip_color(&mut self) {
         use Color::*;
 
@@ -279,7 +509,294 @@ impl Board {
         let white_occupied = self.occupied_by(White);
         let black_occupied = self.occupied_by(Black);
 
-        *self.occupied_by_mut(White) = black_occupied.flip_color();
-        *self.occupied_by_mut(Black) = white_occupied.flip_color();
+        self.occupied[White as usize] = black_occupied.flip_color();
+        self.occupied[Black as usize] = white_occupied.flip_color();
+        self.all_occupied = self.all_occupied.flip_color();
+
+        let white_king = self.king_squares[White as usize];
+        let black_king = self.king_squares[Black as usize];
+
+        self.king_squares[White as usize] = black_king.bitrep().flip_color().bitscan_forward();
+        self.king_squares[Black as usize] = white_king.bitrep().flip_color().bitscan_forward();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use board::*;
+    use game::*;
+    use movegen::*;
+
+    #[test]
+    fn zobrist_key() {
+        init_zobrist_hashing();
+
+        let key_of = |game: &Game| game.board.zobrist_key(game.to_move, game.castling_rights, game.ep_square);
+
+        let start = Game::starting_position();
+        assert_eq!(key_of(&start), start.hash.unwrap());
+
+        let kiwipete = Game::from_fen_str("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+        assert_eq!(key_of(&kiwipete), kiwipete.hash.unwrap());
+
+        // the incremental updates in make_move must agree with a full recomputation
+        for _ in 0 .. 1000 {
+            let game = Game::random_game();
+
+            for m in next_moves_standalone(&game).iter() {
+                let mut game_copy = game;
+                game_copy.make_move(*m);
+                assert_eq!(key_of(&game_copy), game_copy.hash.unwrap(), "{} {}", game.to_fen(), m.to_uci_str());
+            }
+        }
+    }
+
+    #[test]
+    fn attacks_by() {
+        use Color::*;
+
+        init_zobrist_hashing();
+
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "7k/8/8/3n4/4P3/5Q2/3R2B1/3R3K w - - 0 1"
+        ];
+
+        let mut games: Vec<Game> = fens.iter().map(|fen| Game::from_fen_str(fen).unwrap()).collect();
+        for _ in 0 .. 100 {
+            games.push(Game::random_game());
+        }
+
+        for game in games.iter() {
+            for color in [White, Black].iter() {
+                let mut expected = Bitboard::none_set();
+
+                for idx in 0 .. 64 {
+                    let sq = Square::new(idx);
+                    if game.board.attackers(sq, *color).nonempty() {
+                        expected |= sq.bitrep();
+                    }
+                }
+
+                assert_eq!(game.board.attacks_by(*color), expected, "{}", game.to_fen());
+                assert_eq!(game.board.attacked_flood(*color, false), expected, "{}", game.to_fen());
+            }
+        }
+    }
+
+    #[test]
+    fn least_valuable_attacker() {
+        use PieceType::*;
+        use Color::*;
+
+        // d5 is attacked by a pawn with a queen and bishop lined up behind it,
+        // and by two rooks stacked on the d-file
+        let game = Game::from_fen_str("7k/8/8/3n4/4P3/5Q2/3R2B1/3R3K w - - 0 1").unwrap();
+        let target = Square::from_algebraic("d5").unwrap();
+        let mut occupied = game.board.occupied();
+
+        let expected = [(Pawn, "e4"), (Rook, "d2"), (Rook, "d1"), (Queen, "f3"), (Bishop, "g2")];
+
+        for &(ptype, square_str) in expected.iter() {
+            let square = Square::from_algebraic(square_str).unwrap();
+            assert_eq!(game.board.least_valuable_attacker(target, White, occupied), Some((ptype, square)));
+            occupied = occupied & !square.bitrep();
+        }
+
+        assert_eq!(game.board.least_valuable_attacker(target, White, occupied), None);
+        assert_eq!(game.board.least_valuable_attacker(target, Black, game.board.occupied()), None);
+    }
+
+    #[test]
+    fn cached_occupancy() {
+        use rand::{Rng, SeedableRng};
+        use rand::prng::XorShiftRng;
+        use Color::*;
+        use PieceType::*;
+
+        let mut rng = XorShiftRng::from_seed([3; 16]);
+
+        let check = |board: &Board| {
+            assert_eq!(board.occupied(), board.occupied_by(White) | board.occupied_by(Black));
+            assert_eq!(board.unoccupied(), !board.occupied());
+            assert_eq!(board.get_king_square(White).bitrep(), board.get_pieces(White, King));
+            assert_eq!(board.get_king_square(Black).bitrep(), board.get_pieces(Black, King));
+        };
+
+        // chess960 starts too, for castles where the king lands on its own rook's square
+        let starts = ["rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                      "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+                      "1r4kr/8/8/8/8/8/8/1R4KR w HBhb - 0 1"];
+
+        for fen in starts.iter() {
+            for _ in 0 .. 50 {
+                let mut game = Game::from_fen_str(fen).unwrap();
+                check(&game.board);
+
+                while game.outcome().is_none() {
+                    let moves = next_moves_standalone(&game);
+                    game.make_move(moves.at(rng.gen_range(0, moves.len())));
+                    check(&game.board);
+
+                    let mut flipped = game;
+                    flipped.flip_color();
+                    check(&flipped.board);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn king_squares() {
+        use Color::*;
+
+        let mut game = Game::from_fen_str("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+        assert_eq!((game.board.get_king_square(White).to_algebraic(), game.board.get_king_square(Black).to_algebraic()),
+                   ("e1".to_string(), "e8".to_string()));
+
+        for &(move_str, color, expected) in [("e1g1", White, "g1"), ("e8c8", Black, "c8"),
+                                             ("g1g2", White, "g2"), ("c8b7", Black, "b7")].iter() {
+            let m = move_from_algebraic(&game, move_str.to_string()).unwrap();
+            game.make_move(m);
+            assert_eq!(game.board.get_king_square(color).to_algebraic(), expected);
+            assert_eq!(game.board.get_king_square(color).bitrep(), game.board.get_pieces(color, PieceType::King));
+        }
+
+        game.flip_color();
+        assert_eq!(game.board.get_king_square(White).to_algebraic(), "g2");
+        assert_eq!(game.board.get_king_square(Black).to_algebraic(), "b7");
+
+        // Chess960 castles where the king stays put or lands on the rook's square
+        let chess960 = Game::from_fen_str("1r4kr/8/8/8/8/8/8/1R4KR w HBhb - 0 1").unwrap();
+        for &(move_str, expected) in [("g1h1", "g1"), ("g1b1", "c1")].iter() {
+            let mut game = chess960;
+            game.make_move(move_from_algebraic(&game, move_str.to_string()).unwrap());
+            assert_eq!(game.board.get_king_square(White).to_algebraic(), expected);
+            assert_eq!(game.board.get_king_square(White).bitrep(), game.board.get_pieces(White, PieceType::King));
+        }
+    }
+
+    #[test]
+    fn from_diagram() {
+        let kiwipete = Game::from_fen_str("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+
+        let board = Board::from_diagram("r...k..r
+                                         p.ppqpb.
+                                         bn..pnp.
+                                         ...PN...
+                                         .p..P...
+                                         ..N..Q.p
+                                         PPPBBPPP
+                                         R...K..R").unwrap();
+
+        assert_eq!(board, kiwipete.board);
+
+        assert_eq!(Board::from_diagram(&".".repeat(64)), Some(Board::empty_position()));
+        assert_eq!(Board::from_diagram(&".".repeat(63)), None);
+        assert_eq!(Board::from_diagram(&format!("x{}", ".".repeat(63))), None);
+    }
+
+    #[test]
+    fn put_and_take() {
+        use Color::*;
+        use PieceType::*;
+
+        let start = Game::starting_position().board;
+        let back_rank = [Rook, Knight, Bishop, Queen, King, Bishop, Knight, Rook];
+
+        let mut board = Board::empty_position();
+        for (i, file) in "abcdefgh".chars().enumerate() {
+            let square = |rank: u32| Square::from_algebraic(&format!("{}{}", file, rank)).unwrap();
+            board.put(White, back_rank[i], square(1));
+            board.put(White, Pawn, square(2));
+            board.put(Black, Pawn, square(7));
+            board.put(Black, back_rank[i], square(8));
+        }
+
+        // the caches are compared along with the bitboards
+        assert_eq!(board, start);
+
+        // a piece put on an occupied square replaces the one there
+        let e7 = Square::from_algebraic("e7").unwrap();
+        board.put(White, Knight, e7);
+        assert_eq!(board.piece_at(e7), Some(Piece::new(White, Knight)));
+        assert!((board.occupied_by(Black) & e7.bitrep()).empty());
+        assert_eq!(board.occupied(), start.occupied());
+
+        assert_eq!(board.take(e7), Some(Piece::new(White, Knight)));
+        assert_eq!(board.take(e7), None);
+        board.put(Black, Pawn, e7);
+        assert_eq!(board, start);
+
+        for idx in 0 .. 64 {
+            assert_eq!(board.take(Square::new(idx)), start.piece_at(Square::new(idx)));
+        }
+        assert_eq!(board, Board::empty_position());
+    }
+
+    #[test]
+    fn game_phase() {
+        let start = Game::starting_position();
+        assert_eq!(start.board.game_phase(), MAX_GAME_PHASE);
+
+        let bare_kings = Game::from_fen_str("8/8/4k3/8/8/3K4/8/8 w - - 0 1").unwrap();
+        assert_eq!(bare_kings.board.game_phase(), 0);
+
+        // pawns don't count
+        let pawns = Game::from_fen_str("8/pp3kpp/8/8/8/8/PP3KPP/8 w - - 0 1").unwrap();
+        assert_eq!(pawns.board.game_phase(), 0);
+
+        // queens, a rook and a knight each
+        let middlegame = Game::from_fen_str("r3k3/ppp2ppp/2nq4/8/8/2NQ4/PPP2PPP/R3K3 w - - 0 1").unwrap();
+        assert_eq!(middlegame.board.game_phase(), 14);
+
+        // four queens are no more than the start position
+        let promoted = Game::from_fen_str("4k3/8/8/8/8/8/QQQQ4/rnbqKbnr w - - 0 1").unwrap();
+        assert_eq!(promoted.board.game_phase(), MAX_GAME_PHASE);
+    }
+
+    #[test]
+    fn legal_mobility() {
+        use PieceType::*;
+
+        init_zobrist_hashing();
+
+        let square = |alg: &str| Square::from_algebraic(alg).unwrap();
+
+        // each side's bishop on the a-file pins a piece to the other king
+        let game = Game::from_fen_str("4k3/3b4/8/b7/B7/8/3N4/4K3 w - - 0 1").unwrap();
+        let pins = PinInfo::compute(&game.board);
+
+        let expected = square("c6").bitrep() | square("b5").bitrep() | square("a4").bitrep();
+        assert_eq!(game.board.legal_mobility(square("d7"), &pins), expected);
+        assert_eq!(game.board.legal_mobility(square("d2"), &pins), Bitboard::new(0));
+        assert_eq!(game.board.legal_mobility(square("a4"), &pins), game.board.attacks_from(square("a4")));
+        assert_eq!(game.board.attacks_from(square("e4")), Bitboard::new(0));
+
+        // out of check, a piece's legal mobility onto squares without its own pieces is where it can move
+        for _ in 0 .. 1000 {
+            let game = Game::random_game();
+            if game.in_check() || game.outcome.is_some() {
+                continue;
+            }
+
+            let pins = PinInfo::compute(&game.board);
+            let moves = next_moves_standalone(&game);
+            let own = game.board.occupied_by(game.to_move);
+
+            for ptype in [Knight, Bishop, Rook, Queen].iter() {
+                for sq in game.board.get_pieces(game.to_move, *ptype) {
+                    let mut destinations = Bitboard::new(0);
+                    for m in moves.iter().filter(|m| m.from() == sq) {
+                        destinations |= m.to().bitrep();
+                    }
+
+                    assert_eq!(game.board.legal_mobility(sq, &pins) & !own, destinations,
+                               "{} {}", game.to_fen(), sq.to_algebraic());
+                }
+            }
+        }
     }
 }
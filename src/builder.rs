@@ -0,0 +1,298 @@
+#![allow(dead_code)]
+
+use core::*;
+use game::*;
+use tables::*;
+
+// Sets up a game piece by piece, for tests that would otherwise need a FEN string:
+//
+//     GameBuilder::new().piece(White, King, "e1").piece(Black, King, "e8")
+//         .piece(White, Pawn, "a7").to_move(Black).build()
+//
+// The position gets the same checks a FEN would need to pass to be a legal position,
+// and the first thing wrong with it is what build() reports.
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum BuildError {
+    BadSquare(String),
+    SquareTaken(Square),
+    KingCount(Color, u32),
+    PawnOnBackRank(Square),
+    BadCastling(String),
+    // no pawn can have just pushed past it
+    BadEpSquare(Square),
+    // the side that just moved has left its king in check
+    WaitingKingInCheck
+}
+
+// a square given either way, as "e4" or as a Square
+pub trait ToSquare {
+    fn to_square(self) -> Result<Square, String>;
+}
+
+impl ToSquare for Square {
+    fn to_square(self) -> Result<Square, String> { Ok(self) }
+}
+
+impl<'a> ToSquare for &'a str {
+    fn to_square(self) -> Result<Square, String> {
+        Square::from_algebraic(self).ok_or(self.to_string())
+    }
+}
+
+pub struct GameBuilder {
+    game: Game,
+    castling: String,
+    ep_square: Option<Square>,
+    error: Option<BuildError>
+}
+
+impl GameBuilder {
+    pub fn new() -> GameBuilder {
+        GameBuilder {
+            game: Game::empty_position(),
+            castling: "-".to_string(),
+            ep_square: None,
+            error: None
+        }
+    }
+
+    fn fail(&mut self, error: BuildError) {
+        if self.error.is_none() {
+            self.error = Some(error);
+        }
+    }
+
+    fn square<S: ToSquare>(&mut self, sq: S) -> Option<Square> {
+        match sq.to_square() {
+            Ok(square) => Some(square),
+            Err(name) => {
+                self.fail(BuildError::BadSquare(name));
+                None
+            }
+        }
+    }
+
+    pub fn piece<S: ToSquare>(mut self, color: Color, ptype: PieceType, sq: S) -> GameBuilder {
+        if let Some(square) = self.square(sq) {
+            if self.game.board.piece_at(square).is_some() {
+                self.fail(BuildError::SquareTaken(square));
+            } else {
+                self.game.board.put(color, ptype, square);
+            }
+        }
+
+        self
+    }
+
+    pub fn to_move(mut self, color: Color) -> GameBuilder {
+        self.game.to_move = color;
+        self
+    }
+
+    // as in a FEN, "KQkq", "-" or X-FEN rook files
+    pub fn castling(mut self, rights: &str) -> GameBuilder {
+        self.castling = rights.to_string();
+        self
+    }
+
+    pub fn ep<S: ToSquare>(mut self, sq: S) -> GameBuilder {
+        self.ep_square = self.square(sq);
+        self
+    }
+
+    pub fn halfmove_clock(mut self, halfmoves: u16) -> GameBuilder {
+        self.game.halfmove_clock = halfmoves;
+        self
+    }
+
+    pub fn fullmoves(mut self, fullmoves: u32) -> GameBuilder {
+        self.game.fullmoves = fullmoves;
+        self
+    }
+
+    pub fn build(self) -> Result<Game, BuildError> {
+        use PieceType::*;
+        use Color::*;
+
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+
+        let mut game = self.game;
+
+        for &color in [White, Black].iter() {
+            let kings = game.board.get_pieces(color, King).population();
+            if kings != 1 {
+                return Err(BuildError::KingCount(color, kings));
+            }
+        }
+
+        let pawns = game.board.get_pieces(White, Pawn) | game.board.get_pieces(Black, Pawn);
+        let misplaced = pawns & (RANK1 | RANK8);
+        if misplaced.nonempty() {
+            return Err(BuildError::PawnOnBackRank(misplaced.bitscan_forward()));
+        }
+
+        let waiting_king = game.board.get_king_square(!game.to_move);
+        if game.board.attackers(waiting_king, game.to_move).nonempty() {
+            return Err(BuildError::WaitingKingInCheck);
+        }
+
+        if game.add_castling_rights(&self.castling).is_none() || !castling_pieces_in_place(&game) {
+            return Err(BuildError::BadCastling(self.castling.clone()));
+        }
+
+        if let Some(sq) = self.ep_square {
+            if !ep_square_possible(&game, sq) {
+                return Err(BuildError::BadEpSquare(sq));
+            }
+            game.ep_square = Some(sq);
+        }
+
+        // this also drops an en passant square that no pawn can legally capture on, like a FEN
+        game.update_derived_fields();
+
+        return Ok(game);
+    }
+}
+
+// a king on its back rank and a rook on the square for every castling right
+fn castling_pieces_in_place(game: &Game) -> bool {
+    for i in 0 .. 4 {
+        let right = CastlingRights::from_bits(1 << i).unwrap();
+        if !game.castling_rights.contains(right) {
+            continue;
+        }
+
+        let color = right.color();
+        let back_rank = if color == Color::White { RANK1 } else { RANK8 };
+        let rooks = game.board.get_pieces(color, PieceType::Rook);
+
+        let rook_in_place = match game.castling_rooks[right.index()] {
+            Some(sq) => (rooks & sq.bitrep()).nonempty(),
+            None => false
+        };
+
+        if !rook_in_place || (game.board.get_king_square(color).bitrep() & back_rank).empty() {
+            return false;
+        }
+    }
+
+    return true;
+}
+
+// right behind a pawn of the side that just moved, which had both squares free to pass
+fn ep_square_possible(game: &Game, sq: Square) -> bool {
+    let ep_rank = if game.to_move == Color::White { 6 } else { 3 };
+    if sq.rank() != ep_rank {
+        return false;
+    }
+
+    let (pushed_to, pushed_from) = match game.to_move {
+        Color::White => (sq.unwrap() - 8, sq.unwrap() + 8),
+        Color::Black => (sq.unwrap() + 8, sq.unwrap() - 8)
+    };
+
+    let pawn = game.board.get_pieces(!game.to_move, PieceType::Pawn) & Square::new(pushed_to).bitrep();
+    let passed = (sq.bitrep() | Square::new(pushed_from).bitrep()) & game.board.occupied();
+
+    return pawn.nonempty() && passed.empty();
+}
+
+#[cfg(test)]
+mod test {
+    use builder::*;
+    use zobrist::*;
+    use PieceType::*;
+    use Color::*;
+
+    fn kings() -> GameBuilder {
+        GameBuilder::new().piece(White, King, "e1").piece(Black, King, "e8")
+    }
+
+    #[test]
+    fn same_as_fen() {
+        init_zobrist_hashing();
+
+        let expect_fen = |builder: GameBuilder, fen: &str| {
+            let built = builder.build().unwrap();
+            let parsed = Game::from_fen_str(fen).unwrap();
+
+            assert!(built.diff(&parsed).is_empty(), "{}\n{}", fen, built.diff(&parsed));
+            assert_eq!(built.to_fen(), fen);
+        };
+
+        expect_fen(kings().piece(White, Pawn, "a7").to_move(Black),
+                   "4k3/P7/8/8/8/8/8/4K3 b - - 0 1");
+
+        expect_fen(kings().piece(White, Rook, "a1").piece(White, Rook, "h1").piece(Black, Rook, Square::new(63))
+                   .castling("Kq").halfmove_clock(3).fullmoves(20),
+                   "r3k3/8/8/8/8/8/8/R3K2R w Kq - 3 20");
+
+        // the king's attackers are worked out too
+        let checked = kings().piece(Black, Queen, "e4").build().unwrap();
+        assert!(checked.in_check());
+
+        // an en passant square is kept only when it can be taken
+        expect_fen(kings().piece(White, Pawn, "e5").piece(Black, Pawn, "d5").ep("d6"),
+                   "4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1");
+        expect_fen(kings().piece(White, Pawn, "a5").piece(Black, Pawn, "d5").ep("d6"),
+                   "4k3/8/8/P2p4/8/8/8/4K3 w - - 0 1");
+    }
+
+    #[test]
+    fn kiwipete() {
+        init_zobrist_hashing();
+
+        let mut builder = GameBuilder::new()
+            .piece(Black, Rook, "a8").piece(Black, King, "e8").piece(Black, Rook, "h8")
+            .piece(Black, Queen, "e7").piece(Black, Bishop, "g7")
+            .piece(Black, Bishop, "a6").piece(Black, Knight, "b6").piece(Black, Knight, "f6")
+            .piece(White, Pawn, "d5").piece(White, Knight, "e5")
+            .piece(White, Knight, "c3").piece(White, Queen, "f3")
+            .piece(White, Bishop, "d2").piece(White, Bishop, "e2")
+            .piece(White, Rook, "a1").piece(White, King, "e1").piece(White, Rook, "h1");
+
+        for sq in ["a7", "c7", "d7", "f7", "e6", "g6", "b4", "h3"].iter() {
+            builder = builder.piece(Black, Pawn, *sq);
+        }
+
+        for sq in ["e4", "a2", "b2", "c2", "f2", "g2", "h2"].iter() {
+            builder = builder.piece(White, Pawn, *sq);
+        }
+
+        let game = builder.castling("KQkq").build().unwrap();
+        assert_eq!(game.to_fen(), "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+    }
+
+    #[test]
+    fn invalid_positions() {
+        assert_eq!(kings().piece(White, Pawn, "e9").build().err(), Some(BuildError::BadSquare("e9".to_string())));
+        assert_eq!(kings().piece(White, Queen, "e1").build().err(),
+                   Some(BuildError::SquareTaken(Square::from_algebraic("e1").unwrap())));
+
+        assert_eq!(GameBuilder::new().piece(White, King, "e1").build().err(), Some(BuildError::KingCount(Black, 0)));
+        assert_eq!(kings().piece(White, King, "a1").build().err(), Some(BuildError::KingCount(White, 2)));
+
+        assert_eq!(kings().piece(Black, Pawn, "h1").build().err(),
+                   Some(BuildError::PawnOnBackRank(Square::from_algebraic("h1").unwrap())));
+
+        // white to move with black in check
+        assert_eq!(kings().piece(White, Rook, "e4").build().err(), Some(BuildError::WaitingKingInCheck));
+
+        // no rook to castle with, or the king has left its back rank
+        assert_eq!(kings().castling("K").build().err(), Some(BuildError::BadCastling("K".to_string())));
+        let displaced = GameBuilder::new().piece(White, King, "e2").piece(Black, King, "e8").piece(White, Rook, "h1");
+        assert_eq!(displaced.castling("K").build().err(), Some(BuildError::BadCastling("K".to_string())));
+        assert_eq!(kings().castling("KX").build().err(), Some(BuildError::BadCastling("KX".to_string())));
+
+        // no pawn in front, the wrong rank for the side to move, or a square the pawn couldn't pass
+        let d6 = Square::from_algebraic("d6").unwrap();
+        assert_eq!(kings().ep("d6").build().err(), Some(BuildError::BadEpSquare(d6)));
+        assert_eq!(kings().piece(Black, Pawn, "d5").to_move(Black).ep("d6").build().err(),
+                   Some(BuildError::BadEpSquare(d6)));
+        assert_eq!(kings().piece(Black, Pawn, "d5").piece(Black, Knight, "d7").ep("d6").build().err(),
+                   Some(BuildError::BadEpSquare(d6)));
+    }
+}
@@ -0,0 +1,208 @@
+use annotate::*;
+use game::*;
+use perft::*;
+
+use clap::{App, ArgMatches, Error, ErrorKind};
+
+const DEFAULT_BENCH_DEPTH: usize = 5;
+const DEFAULT_TUNE_ITERATIONS: usize = 100;
+
+// What the command line asks for, with every value parsed and every FEN checked, so that
+// main only has to run it.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Command {
+    Uci,
+    Perft { game: Game, depth: usize, divide: bool },
+    Play { game: Game, depth: Option<u8> },
+    Bench { depth: usize },
+    BenchEval { positions: usize },
+    BenchMovegen { ms_per_position: u64 },
+    Ponder { game: Game },
+    Tune { training_path: String, output_path: String, iterations: usize, quiesce: bool, threads: Option<usize> },
+    Annotate { input: String, output: Option<String>, options: AnnotateOptions }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct CommandLine {
+    pub eval_file: Option<String>,
+    pub command: Command
+}
+
+// The arguments as std::env::args has them, program name first. Help, the version and
+// mistakes all come back as errors, which know how to print themselves and exit.
+pub fn parse_command_line<I: IntoIterator<Item = String>>(args: I) -> Result<CommandLine, Error> {
+    let yaml = load_yaml!("../cli.yml");
+    let matches = App::from_yaml(yaml).get_matches_from_safe(args)?;
+
+    let command = match matches.subcommand() {
+        ("uci", _) => Command::Uci,
+
+        ("perft", Some(perft)) => perft_command(perft, value_t!(perft, "depth", usize)?, perft.is_present("divide"))?,
+
+        ("play", Some(play)) => {
+            let depth = if play.is_present("depth") { Some(value_t!(play, "depth", u8)?) } else { None };
+            Command::Play { game: start_position(play)?, depth: depth }
+        },
+
+        ("bench", Some(bench)) => {
+            let depth = if bench.is_present("depth") { value_t!(bench, "depth", usize)? } else { DEFAULT_BENCH_DEPTH };
+            Command::Bench { depth: depth }
+        },
+
+        ("annotate", Some(annotate)) => {
+            let mut options = AnnotateOptions::new();
+
+            if annotate.is_present("movetime") {
+                options.depth = None;
+                options.movetime = Some(value_t!(annotate, "movetime", u32)?);
+            }
+
+            if annotate.is_present("depth") {
+                options.depth = Some(value_t!(annotate, "depth", u8)?);
+            }
+
+            options.first_move = value_t!(annotate, "from-move", u32).unwrap_or(options.first_move);
+            options.mistake = value_t!(annotate, "mistake", i16).unwrap_or(options.mistake);
+            options.blunder = value_t!(annotate, "blunder", i16).unwrap_or(options.blunder);
+
+            Command::Annotate {
+                input: annotate.value_of("input").unwrap().to_string(),
+                output: annotate.value_of("output").map(|path| path.to_string()),
+                options: options
+            }
+        },
+
+        _ => flag_command(&matches)?
+    };
+
+    Ok(CommandLine {
+        eval_file: matches.value_of("eval-file").map(|path| path.to_string()),
+        command: command
+    })
+}
+
+// the modes still chosen with a flag, and UCI when there's none, as a GUI starts the engine
+fn flag_command(matches: &ArgMatches) -> Result<Command, Error> {
+    if matches.is_present("perft") {
+        return perft_command(matches, value_t!(matches, "perft", usize)?, false);
+    }
+
+    if let Some(fen) = matches.value_of("ponder") {
+        return Ok(Command::Ponder { game: parse_fen(fen)? });
+    }
+
+    if matches.is_present("bench-eval") {
+        return Ok(Command::BenchEval { positions: value_t!(matches, "bench-eval", usize)? });
+    }
+
+    if matches.is_present("bench-movegen") {
+        return Ok(Command::BenchMovegen { ms_per_position: value_t!(matches, "bench-movegen", u64)? });
+    }
+
+    if let Some(training_path) = matches.value_of("tune") {
+        let threads = if matches.is_present("threads") { Some(value_t!(matches, "threads", usize)?) } else { None };
+
+        return Ok(Command::Tune {
+            training_path: training_path.to_string(),
+            output_path: matches.value_of("tune-output").unwrap_or("tuned_params.txt").to_string(),
+            iterations: value_t!(matches, "tune-iterations", usize).unwrap_or(DEFAULT_TUNE_ITERATIONS),
+            quiesce: matches.is_present("tune-quiesce"),
+            threads: threads
+        });
+    }
+
+    return Ok(Command::Uci);
+}
+
+fn perft_command(matches: &ArgMatches, depth: usize, divide: bool) -> Result<Command, Error> {
+    if depth == 0 || depth >= MAX_PERFT_DEPTH {
+        return Err(invalid(&format!("perft depth must be between 1 and {}", MAX_PERFT_DEPTH - 1)));
+    }
+
+    Ok(Command::Perft { game: start_position(matches)?, depth: depth, divide: divide })
+}
+
+// --fen, or the starting position without one
+fn start_position(matches: &ArgMatches) -> Result<Game, Error> {
+    match matches.value_of("fen") {
+        Some(fen) => parse_fen(fen),
+        None => Ok(Game::starting_position())
+    }
+}
+
+// a FEN, or startpos as UCI has it
+fn parse_fen(fen: &str) -> Result<Game, Error> {
+    if fen == "startpos" {
+        return Ok(Game::starting_position());
+    }
+
+    Game::from_fen_str(fen).ok_or_else(|| invalid(&format!("invalid FEN \"{}\"", fen)))
+}
+
+fn invalid(message: &str) -> Error {
+    Error::with_description(message, ErrorKind::ValueValidation)
+}
+
+#[cfg(test)]
+mod test {
+    use command::*;
+    use zobrist::*;
+
+    fn parse(args: &str) -> Result<Command, Error> {
+        let args = ["feldspar"].iter().chain(args.split_whitespace().collect::<Vec<&str>>().iter())
+            .map(|arg| arg.to_string()).collect::<Vec<String>>();
+        parse_command_line(args).map(|line| line.command)
+    }
+
+    #[test]
+    fn commands() {
+        init_zobrist_hashing();
+
+        // what a GUI runs
+        assert_eq!(parse("").unwrap(), Command::Uci);
+        assert_eq!(parse("uci").unwrap(), Command::Uci);
+        assert_eq!(parse("--uci").unwrap(), Command::Uci);
+
+        assert_eq!(parse("perft 5").unwrap(), Command::Perft { game: Game::starting_position(), depth: 5, divide: false });
+        assert_eq!(parse("bench").unwrap(), Command::Bench { depth: DEFAULT_BENCH_DEPTH });
+        assert_eq!(parse("bench 8").unwrap(), Command::Bench { depth: 8 });
+        assert_eq!(parse("play --depth 6").unwrap(), Command::Play { game: Game::starting_position(), depth: Some(6) });
+        assert_eq!(parse("--bench-eval 1000").unwrap(), Command::BenchEval { positions: 1000 });
+
+        // a FEN has spaces in it, as one argument it would have been quoted in the shell
+        let fen = "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1";
+        let args = vec!["feldspar", "perft", "3", "--divide", "--fen", fen].iter().map(|a| a.to_string()).collect::<Vec<String>>();
+        assert_eq!(parse_command_line(args).unwrap().command,
+                   Command::Perft { game: Game::from_fen_str(fen).unwrap(), depth: 3, divide: true });
+
+        // the flags, as the subcommands were before
+        assert_eq!(parse("--perft 3 --fen startpos").unwrap(), Command::Perft { game: Game::starting_position(), depth: 3, divide: false });
+        let args = vec!["feldspar", "--perft", "2", "--fen", fen].iter().map(|a| a.to_string()).collect::<Vec<String>>();
+        assert_eq!(parse_command_line(args).unwrap().command,
+                   Command::Perft { game: Game::from_fen_str(fen).unwrap(), depth: 2, divide: false });
+        assert_eq!(parse("play --fen startpos").unwrap(), Command::Play { game: Game::starting_position(), depth: None });
+
+        let args = vec!["feldspar", "--eval-file", "params.txt", "uci"].iter().map(|a| a.to_string()).collect::<Vec<String>>();
+        assert_eq!(parse_command_line(args).unwrap().eval_file, Some("params.txt".to_string()));
+    }
+
+    #[test]
+    fn mistakes() {
+        init_zobrist_hashing();
+
+        let kind = |args: &str| parse(args).unwrap_err().kind;
+
+        assert_eq!(kind("castle"), ErrorKind::UnknownArgument);
+        assert_eq!(kind("perft"), ErrorKind::MissingRequiredArgument);
+        assert_eq!(kind("perft five"), ErrorKind::ValueValidation);
+        assert_eq!(kind("perft 0"), ErrorKind::ValueValidation);
+        assert_eq!(kind("--perft 0"), ErrorKind::ValueValidation);
+        assert_eq!(kind("--perft 3 --uci"), ErrorKind::ArgumentConflict);
+        assert_eq!(kind("--fen startpos"), ErrorKind::MissingRequiredArgument);
+        assert_eq!(kind("perft 3 --fen nonsense"), ErrorKind::ValueValidation);
+        assert_eq!(kind("play --depth -1"), ErrorKind::UnknownArgument);
+        assert_eq!(kind("--help"), ErrorKind::HelpDisplayed);
+
+        assert!(parse("perft 3 --fen nonsense").unwrap_err().message.contains("invalid FEN \"nonsense\""));
+    }
+}
@@ -2,6 +2,7 @@ use std::ops::Not;
 use std::slice::Iter;
 use std::str::Chars;
 use chrono::prelude::*;
+use std::time::{Duration, Instant};
 
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Direction { N, S, E, W, NE, NW, SE, SW }
@@ -13,36 +14,62 @@ pub struct Square(u32);
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub struct SearchTimer {
     start_time: i64,
-    duration_ms: i64
-        //TODO: include max possible search time to clamp when scale_remaining is called
+    duration_ms: i64,
+    // scale_remaining never pushes the duration past this
+    maximum_ms: i64
 }
 
 impl SearchTimer {
     pub fn new(search_length: u32) -> SearchTimer {
+        SearchTimer::with_maximum(search_length, search_length)
+    }
+
+    pub fn with_maximum(search_length: u32, maximum: u32) -> SearchTimer {
         SearchTimer {
             start_time: Utc::now().timestamp_millis(),
-            duration_ms: search_length as i64
+            duration_ms: search_length as i64,
+            maximum_ms: maximum.max(search_length) as i64
         }
     }
 
     pub fn scale_remaining(&mut self, factor: f32) {
-        self.duration_ms = (factor * self.duration_ms as f32) as i64;
+        let scaled = (factor * self.duration_ms as f32) as i64;
+        self.duration_ms = if scaled > self.maximum_ms { self.maximum_ms } else { scaled };
     }
 
     pub fn finished(&self) -> bool {
         Utc::now().timestamp_millis() - self.start_time > self.duration_ms
     }
+
+    // what the search is allowed in all, after any scaling
+    pub fn duration_ms(&self) -> i64 {
+        self.duration_ms
+    }
 }
 
-pub struct Counter(i64);
+// monotonic stopwatch, unaffected by wall-clock adjustments
+pub struct Counter(Instant);
 
 impl Counter {
     pub fn new() -> Counter {
-        Counter(Utc::now().timestamp_millis())
+        Counter(Instant::now())
+    }
+
+    pub fn restart(&mut self) {
+        self.0 = Instant::now();
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.0.elapsed()
     }
 
-    pub fn elapsed_ms(self) -> f64 {
-        (Utc::now().timestamp_millis() - self.0) as f64
+    pub fn elapsed_ns(&self) -> u64 {
+        let elapsed = self.elapsed();
+        elapsed.as_secs() * 1_000_000_000 + elapsed.subsec_nanos() as u64
+    }
+
+    pub fn elapsed_ms(&self) -> f64 {
+        self.elapsed_ns() as f64 / 1e6
     }
 }
 
@@ -169,6 +196,32 @@ impl PieceType {
             _ => panic!("Invalid bits passed to PieceType::from_bits!")
         }
     }
+
+    pub fn to_char_upper(self) -> char {
+        use self::PieceType::*;
+        match self {
+            Pawn   => 'P',
+            Knight => 'N',
+            Bishop => 'B',
+            Rook   => 'R',
+            Queen  => 'Q',
+            King   => 'K'
+        }
+    }
+
+    // either case
+    pub fn from_char(c: char) -> Option<PieceType> {
+        use self::PieceType::*;
+        match c.to_ascii_uppercase() {
+            'P' => Some(Pawn),
+            'N' => Some(Knight),
+            'B' => Some(Bishop),
+            'R' => Some(Rook),
+            'Q' => Some(Queen),
+            'K' => Some(King),
+            _ => None
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -197,6 +250,47 @@ impl Piece {
             color: c
         }
     }
+
+    // FEN style, upper case for white
+    pub fn to_char(&self) -> char {
+        match self.color {
+            Color::White => self.ptype.to_char_upper(),
+            Color::Black => self.ptype.to_char_upper().to_ascii_lowercase()
+        }
+    }
+
+    pub fn from_char(c: char) -> Option<Piece> {
+        let color = if c.is_ascii_uppercase() { Color::White } else { Color::Black };
+        PieceType::from_char(c).map(|ptype| Piece::new(color, ptype))
+    }
+
+    pub fn to_unicode(&self) -> char {
+        use self::PieceType::*;
+        match (self.color, self.ptype) {
+            (Color::White, Pawn)   => '♙',
+            (Color::White, Knight) => '♘',
+            (Color::White, Bishop) => '♗',
+            (Color::White, Rook)   => '♖',
+            (Color::White, Queen)  => '♕',
+            (Color::White, King)   => '♔',
+            (Color::Black, Pawn)   => '♟',
+            (Color::Black, Knight) => '♞',
+            (Color::Black, Bishop) => '♝',
+            (Color::Black, Rook)   => '♜',
+            (Color::Black, Queen)  => '♛',
+            (Color::Black, King)   => '♚'
+        }
+    }
+
+    pub fn from_unicode(c: char) -> Option<Piece> {
+        Piece::all().find(|piece| piece.to_unicode() == c)
+    }
+
+    pub fn all() -> impl Iterator<Item = Piece> {
+        PieceType::all().flat_map(|&ptype| {
+            [Color::White, Color::Black].iter().map(move |&color| Piece::new(color, ptype))
+        })
+    }
 }
 
 bitflags! {
@@ -212,4 +306,78 @@ impl CastlingRights {
     pub fn flip_color(self) -> CastlingRights {
         CastlingRights::from_bits(self.bits().reverse_bits() >> 4).unwrap()
     }
+
+    // the following only make sense for a single right
+
+    pub fn index(self) -> usize {
+        debug_assert!(self.bits().count_ones() == 1);
+        self.bits().trailing_zeros() as usize
+    }
+
+    pub fn color(self) -> Color {
+        if self.intersects(CastlingRights::WHITE_KINGSIDE | CastlingRights::WHITE_QUEENSIDE) {
+            Color::White
+        } else {
+            Color::Black
+        }
+    }
+
+    pub fn is_kingside(self) -> bool {
+        self.intersects(CastlingRights::WHITE_KINGSIDE | CastlingRights::BLACK_KINGSIDE)
+    }
+
+    pub fn for_side(color: Color, kingside: bool) -> CastlingRights {
+        match (color, kingside) {
+            (Color::White, true)  => CastlingRights::WHITE_KINGSIDE,
+            (Color::White, false) => CastlingRights::WHITE_QUEENSIDE,
+            (Color::Black, true)  => CastlingRights::BLACK_KINGSIDE,
+            (Color::Black, false) => CastlingRights::BLACK_QUEENSIDE
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::*;
+
+    #[test]
+    fn counter_monotonic() {
+        let mut counter = Counter::new();
+
+        for _ in 0 .. 3 {
+            let mut last_ns = counter.elapsed_ns();
+            for _ in 0 .. 10000 {
+                let ns = counter.elapsed_ns();
+                assert!(ns >= last_ns);
+                last_ns = ns;
+            }
+
+            counter.restart();
+            assert!(counter.elapsed_ns() <= last_ns);
+        }
+    }
+
+    #[test]
+    fn piece_chars() {
+        assert_eq!(Piece::all().count(), 12);
+
+        for piece in Piece::all() {
+            assert_eq!(Piece::from_char(piece.to_char()), Some(piece));
+            assert_eq!(Piece::from_unicode(piece.to_unicode()), Some(piece));
+            assert_eq!(PieceType::from_char(piece.ptype.to_char_upper()), Some(piece.ptype));
+            assert_eq!(PieceType::from_char(piece.to_char()), Some(piece.ptype));
+        }
+
+        let fen_chars: String = Piece::all().map(|piece| piece.to_char()).collect();
+        assert_eq!(fen_chars, "PpNnBbRrQqKk");
+
+        assert_eq!(Piece::from_char('n'), Some(Piece::new(Color::Black, PieceType::Knight)));
+        assert_eq!(Piece::from_unicode('♔'), Some(Piece::new(Color::White, PieceType::King)));
+
+        for c in "1/- xX.".chars() {
+            assert_eq!(Piece::from_char(c), None);
+            assert_eq!(PieceType::from_char(c), None);
+            assert_eq!(Piece::from_unicode(c), None);
+        }
+    }
 }
@@ -1,9 +1,23 @@
+use bitboard::*;
 use board::*;
 use core::*;
 use moves::*;
 use game::*;
 use tables::*;
 use movegen::*;
+use material::*;
+use eval_params::*;
+use pins::*;
+
+use prettytable::Table;
+
+use std::cell::RefCell;
+use std::cmp::min;
+
+const MAX_MATE_DEPTH: i16 = 256;
+
+// must be a power of two
+const PAWN_HASH_ENTRIES: usize = 16384;
 
 #[derive(Debug, PartialEq, PartialOrd, Clone, Copy)]
 pub struct Score(i16);
@@ -17,12 +31,13 @@ impl Score {
         Score(s)
     }
 
+    // not through Score::new, whose bounds check calls these
     pub fn max() -> Score {
-        Score::new(i16::max_value()/2)
+        Score(i16::max_value()/2)
     }
 
     pub fn min() -> Score {
-        Score::new(-1 * i16::max_value()/2)
+        Score(-1 * i16::max_value()/2)
     }
 
     pub fn max_at_depth(depth: usize) -> Score {
@@ -49,33 +64,45 @@ impl Score {
         self.0
     }
 
+    // scores this close to the bounds come from a forced mate somewhere in the tree
+    pub fn is_mate(self) -> bool {
+        self.0.abs() >= Score::max().0 - MAX_MATE_DEPTH
+    }
+
+    // moves until the mate, as UCI's "score mate" has it, negative when the side to move is mated
+    pub fn mate_moves(self) -> Option<i16> {
+        if !self.is_mate() {
+            return None;
+        }
+
+        // a mate on the next ply scores as much as a score can
+        let plies = Score::max().0 - self.0.abs() + 1;
+        let moves = (plies + 1) / 2;
+        return Some(if self.0 > 0 { moves } else { -moves });
+    }
+
 }
 
 impl Phase {
     pub fn unwrap(&self) -> u16 { self.0 }
 
-    fn recompute(board: &Board) -> Phase {
-        let knight_phase = 1;
-        let bishop_phase = 1;
-        let rook_phase = 2;
-        let queen_phase = 4;
-        let total_phase = knight_phase*4 + bishop_phase*4 + rook_phase*4 + queen_phase*2;
-
-        let mut phase = total_phase;
-
+    // 0 with every piece on the board, up to 256 with only kings and pawns, the other way
+    // round from Board::game_phase
+    pub fn recompute(material: MaterialKey) -> Phase {
         use PieceType::*;
         use Color::*;
 
-        phase -= knight_phase * board.get_pieces(White, Knight).population() as u16;
-        phase -= knight_phase * board.get_pieces(Black, Knight).population() as u16;
-        phase -= bishop_phase * board.get_pieces(White, Bishop).population() as u16;
-        phase -= bishop_phase * board.get_pieces(Black, Bishop).population() as u16;
-        phase -= rook_phase * board.get_pieces(White, Rook).population() as u16;
-        phase -= rook_phase * board.get_pieces(Black, Rook).population() as u16;
-        phase -= queen_phase * board.get_pieces(White, Queen).population() as u16;
-        phase -= queen_phase * board.get_pieces(Black, Queen).population() as u16;
+        let total_phase = MAX_GAME_PHASE as u32;
+        let mut pieces_phase = 0;
 
-        Phase((phase * 256 + (total_phase / 2)) / total_phase)
+        for color in [White, Black].iter() {
+            for ptype in [Knight, Bishop, Rook, Queen].iter() {
+                pieces_phase += phase_weight(*ptype) * material.count(*color, *ptype);
+            }
+        }
+
+        let phase = total_phase - min(pieces_phase, total_phase);
+        Phase(((phase * 256 + (total_phase / 2)) / total_phase) as u16)
     }
 }
 
@@ -89,7 +116,24 @@ impl Score {
     }
 
     pub fn recompute(game: &Game, search_depth: usize) -> Score {
-        use PieceType::*;
+        let pawns = PAWN_HASH_TABLE.with(|table| table.borrow_mut().probe(game));
+        Score::evaluate(game, search_depth, &pawns, &probe_material(game), &eval_params())
+    }
+
+    // bypasses the pawn and material tables, only useful for benchmarking them
+    pub fn recompute_uncached(game: &Game, search_depth: usize) -> Score {
+        let params = eval_params();
+        let material = MaterialInfo::compute(game.material_key);
+        Score::evaluate(game, search_depth, &PawnEntry::compute(&game.board, &params), &material, &params)
+    }
+
+    // with parameters other than the engine's own, for tuning
+    pub fn recompute_with_params(game: &Game, search_depth: usize, params: &EvalParams) -> Score {
+        let material = MaterialInfo::compute(game.material_key);
+        Score::evaluate(game, search_depth, &PawnEntry::compute(&game.board, params), &material, params)
+    }
+
+    fn evaluate(game: &Game, search_depth: usize, pawns: &PawnEntry, material: &MaterialInfo, params: &EvalParams) -> Score {
         use Color::*;
 
         match game.outcome {
@@ -99,46 +143,484 @@ impl Score {
             None => {}
         }
 
-        let material_score = |ptype: PieceType| {
-            let diff = game.board.get_pieces(White, ptype).population() as i16
-                     - game.board.get_pieces(Black, ptype).population() as i16;
+        if let Some((endgame, strong)) = material.specialized_eval {
+            if let Some(score) = endgame.evaluate(game, strong, params) {
+                return fade_toward_draw(game, score.unwrap() as f32);
+            }
+        }
+
+        let terms = eval_terms(game, pawns, params);
+        let (midgame_score, endgame_score) = term_totals(&terms);
+
+        let favoured = if endgame_score >= 0 { White } else { Black };
+        let scale = material.scale_factor(&game.board, favoured);
+
+        return fade_toward_draw(game, blend(midgame_score, endgame_score, scale, material.phase));
+    }
+}
+
+// One term of the evaluation as (middle game, end game) values. Each side's own part of it
+// counts positive for that side, and the total is what the term adds to white's score.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct TraceTerm {
+    pub name: &'static str,
+    pub white: (i16, i16),
+    pub black: (i16, i16),
+    pub total: (i16, i16)
+}
+
+pub const EVAL_TERMS: usize = 6;
 
-            let value: i16 = material_value(ptype);
-            return value * diff;
+fn eval_terms(game: &Game, pawns: &PawnEntry, params: &EvalParams) -> [TraceTerm; EVAL_TERMS] {
+    use Color::*;
+
+    let pins = if params.uses_pins() { Some(PinInfo::compute(&game.board)) } else { None };
+
+    let side_terms = |color: Color| -> [(i16, i16); EVAL_TERMS] {
+        let mut material = (0, 0);
+        let mut piece_square = (0, 0);
+
+        for ptype in PieceType::all() {
+            // both sides always have their king, so it's left out rather than counted twice over
+            if *ptype != PieceType::King {
+                let count = game.board.get_pieces(color, *ptype).population() as i16;
+                let (mid_val, end_val) = params.material(*ptype);
+                material.0 += mid_val * count;
+                material.1 += end_val * count;
+            }
+
+            for sq in game.board.get_pieces(color, *ptype) {
+                let (x, y) = piece_square_value(color, *ptype, sq);
+                piece_square.0 += x;
+                piece_square.1 += y;
+            }
+        }
+
+        // piece_square_value is from white's point of view
+        if color == Black {
+            piece_square = (-piece_square.0, -piece_square.1);
+        }
+
+        let outposts = knight_outposts(&game.board, color).population() as i16;
+        let bad_bishop_pawns = bad_bishop_pawns(&game.board, color) as i16;
+        let minor = (outposts * params.knight_outpost_bonus.0 - bad_bishop_pawns * params.bad_bishop_penalty.0,
+                     outposts * params.knight_outpost_bonus.1 - bad_bishop_pawns * params.bad_bishop_penalty.1);
+
+        let (mobility, threats) = match pins {
+            Some(ref pins) => (mobility(&game.board, color, pins, params), threats(&game.board, color, pins, params)),
+            None => ((0, 0), (0, 0))
         };
 
-        let piece_square_score = |ptype: PieceType| -> (i16, i16) {
-            let mut diff = (0, 0);
+        return [material, piece_square, pawns.score(color), minor, mobility, threats];
+    };
+
+    let (white, black) = (side_terms(White), side_terms(Black));
+    let names = ["material", "piece square", "pawns", "minor pieces", "mobility", "threats"];
+
+    let mut terms = [TraceTerm { name: "", white: (0, 0), black: (0, 0), total: (0, 0) }; EVAL_TERMS];
+    for i in 0 .. EVAL_TERMS {
+        terms[i] = TraceTerm {
+            name: names[i],
+            white: white[i],
+            black: black[i],
+            total: (white[i].0 - black[i].0, white[i].1 - black[i].1)
+        };
+    }
+
+    // the tables are scaled as a whole, not side by side, so they round the same way either way
+    let pst = &mut terms[1];
+    pst.total = ((pst.total.0 as i32 * params.pst_scale.0 as i32 / 100) as i16,
+                 (pst.total.1 as i32 * params.pst_scale.1 as i32 / 100) as i16);
+
+    return terms;
+}
+
+fn term_totals(terms: &[TraceTerm]) -> (i16, i16) {
+    let mut totals = (0, 0);
+
+    for term in terms.iter() {
+        totals.0 += term.total.0;
+        totals.1 += term.total.1;
+    }
+
+    return totals;
+}
+
+// the end game score is scaled down for endings that are harder to win than the material says
+fn blend(midgame_score: i16, endgame_score: i16, scale: u8, phase: Phase) -> f32 {
+    let phase = phase.unwrap() as f32;
+    let endgame_score = endgame_score as f32 * scale as f32 / NORMAL_SCALE as f32;
+
+    return ((midgame_score as f32 * (256.0 - phase)) + (endgame_score * phase)) / 256.0;
+}
+
+// Everything that went into the evaluation of a position, to find a term that misbehaves.
+// Scores are from white's point of view, like Score::recompute.
+pub struct EvalTrace {
+    pub terms: [TraceTerm; EVAL_TERMS],
+    pub phase: Phase,
+    // out of NORMAL_SCALE, applied to the end game total
+    pub scale: u8,
+    // an ending with its own evaluation, which takes the place of the terms
+    pub specialized: Option<(Endgame, Score)>,
+    pub outcome: Option<GameResult>,
+    pub score: Score
+}
+
+pub fn eval_trace(game: &Game) -> EvalTrace {
+    let params = eval_params();
+    let material = MaterialInfo::compute(game.material_key);
+    let pawns = PawnEntry::compute(&game.board, &params);
 
-            for color in [White, Black].iter() {
-                for sq in game.board.get_pieces(*color, ptype) {
-                    let (x,y) = piece_square_value(*color, ptype, sq);
-                    diff.0 += x;
-                    diff.1 += y;
+    let terms = eval_terms(game, &pawns, &params);
+    let (_, endgame_score) = term_totals(&terms);
+    let favoured = if endgame_score >= 0 { Color::White } else { Color::Black };
+
+    let specialized = match material.specialized_eval {
+        Some((endgame, strong)) => endgame.evaluate(game, strong, &params).map(|score| (endgame, score)),
+        None => None
+    };
+
+    EvalTrace {
+        terms: terms,
+        phase: material.phase,
+        scale: material.scale_factor(&game.board, favoured),
+        specialized: specialized,
+        outcome: game.outcome,
+        score: Score::evaluate(game, 0, &pawns, &material, &params)
+    }
+}
+
+impl EvalTrace {
+    #[allow(dead_code)]
+    pub fn print(&self) {
+        let mut table = Table::new();
+        table.add_row(row!["TERM", "WHITE MG", "WHITE EG", "BLACK MG", "BLACK EG", "TOTAL MG", "TOTAL EG"]);
+
+        for term in self.terms.iter() {
+            table.add_row(row![term.name, term.white.0, term.white.1, term.black.0, term.black.1,
+                               term.total.0, term.total.1]);
+        }
+
+        let (midgame_score, endgame_score) = term_totals(&self.terms);
+        table.add_row(row!["sum", "", "", "", "", midgame_score, endgame_score]);
+
+        table.print_tty(false);
+
+        for line in self.notes() {
+            println!("{}", line);
+        }
+    }
+
+    // as print has it, a line for each term instead of a table
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines: Vec<String> = self.terms.iter()
+            .map(|term| format!("{} mg {} eg {} (white {} {}, black {} {})", term.name, term.total.0, term.total.1,
+                                term.white.0, term.white.1, term.black.0, term.black.1))
+            .collect();
+
+        let (midgame_score, endgame_score) = term_totals(&self.terms);
+        lines.push(format!("sum mg {} eg {}", midgame_score, endgame_score));
+
+        lines.extend(self.notes());
+        return lines;
+    }
+
+    // what goes after the terms
+    fn notes(&self) -> Vec<String> {
+        let mut notes = vec![format!("phase {} / 256, end game scale {} / {}", self.phase.unwrap(), self.scale, NORMAL_SCALE)];
+
+        if let Some((endgame, score)) = self.specialized {
+            notes.push(format!("specialized {:?} evaluation {}, which replaces the terms", endgame, score.unwrap()));
+        }
+
+        if let Some(outcome) = self.outcome {
+            notes.push(format!("game over: {:?}", outcome));
+        }
+
+        notes.push(format!("final {}", self.score.unwrap()));
+        return notes;
+    }
+}
+
+// fade toward a draw as the fifty move rule approaches, so shuffling isn't free
+fn fade_toward_draw(game: &Game, eval: f32) -> Score {
+    let quiet_halfmoves = min(game.halfmove_clock, FIFTY_MOVE_HALFMOVES) as f32;
+    let fifty_move_scale = (FIFTY_MOVE_HALFMOVES as f32 - quiet_halfmoves) / FIFTY_MOVE_HALFMOVES as f32;
+
+    return Score::new((eval * fifty_move_scale) as i16);
+}
+
+// Win, draw and loss chances in permille for the side the score is for. The win chance
+// is a logistic curve in the score, the loss chance the same curve for the negated score.
+// Where the curve is centred, and how steep it is, go from the middle game to the end
+// game values with the phase.
+pub fn win_draw_loss(score: Score, phase: Phase, params: &EvalParams) -> (u16, u16, u16) {
+    if score.is_mate() {
+        return if score.unwrap() > 0 { (1000, 0, 0) } else { (0, 0, 1000) };
+    }
+
+    let endgame = phase.unwrap() as f64 / 256.0;
+    let interpolate = |(mg, eg): (i16, i16)| mg as f64 * (1.0 - endgame) + eg as f64 * endgame;
+
+    // a negative center would make winning and losing add up to more than certain
+    let center = interpolate(params.wdl_center).max(0.0);
+    let spread = interpolate(params.wdl_spread).max(1.0);
+
+    let win_chance = |x: f64| (1000.0 / (1.0 + ((center - x) / spread).exp())).round() as u16;
+
+    let win = win_chance(score.unwrap() as f64);
+    let loss = win_chance(-score.unwrap() as f64);
+
+    return (win, 1000u16.saturating_sub(win + loss), loss);
+}
+
+thread_local! {
+    static PAWN_HASH_TABLE: RefCell<PawnHashTable> = RefCell::new(PawnHashTable::new(PAWN_HASH_ENTRIES));
+}
+
+// Pawn structure terms only depend on the pawns, which rarely change during a search.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct PawnEntry {
+    // the exact pawn configuration this entry was computed for
+    pawns: [Bitboard; 2],
+    // (middle-game, end-game), from each color's own point of view
+    scores: [(i16, i16); 2],
+    pub passed: [Bitboard; 2]
+}
+
+impl PawnEntry {
+    fn empty() -> PawnEntry {
+        PawnEntry {
+            pawns: [Bitboard::none_set(); 2],
+            scores: [(0, 0); 2],
+            passed: [Bitboard::none_set(); 2]
+        }
+    }
+
+    pub fn score(&self, color: Color) -> (i16, i16) {
+        self.scores[color as usize]
+    }
+
+    pub fn compute(board: &Board, params: &EvalParams) -> PawnEntry {
+        use PieceType::*;
+        use Color::*;
+
+        let mut entry = PawnEntry::empty();
+
+        for color in [White, Black].iter() {
+            let color = *color;
+            let friendly = board.get_pieces(color, Pawn);
+            let enemy = board.get_pieces(!color, Pawn);
+            let mut score = (0, 0);
+
+            for sq in friendly {
+                let file = file_mask(sq);
+                let adjacent = adjacent_files_mask(sq);
+                let front = forward_mask(color, sq);
+
+                // only the rearmost pawns on a file count as doubled
+                if (friendly & file & front).nonempty() {
+                    score.0 -= params.doubled_pawn_penalty.0;
+                    score.1 -= params.doubled_pawn_penalty.1;
+                } else if (enemy & (file | adjacent) & front).empty() {
+                    entry.passed[color as usize] |= sq.bitrep();
+
+                    let relative_rank = match color {
+                        White => sq.rank(),
+                        Black => 9 - sq.rank()
+                    };
+
+                    let (mg, eg) = params.passed_pawn_bonus[relative_rank as usize - 1];
+                    score.0 += mg;
+                    score.1 += eg;
+                }
+
+                if (friendly & adjacent).empty() {
+                    score.0 -= params.isolated_pawn_penalty.0;
+                    score.1 -= params.isolated_pawn_penalty.1;
                 }
             }
 
-            return diff;
-        };
+            entry.pawns[color as usize] = friendly;
+            entry.scores[color as usize] = score;
+        }
 
-        let mut mat_score: i16 = 0;
-        let mut psq_score: (i16,i16) = (0,0);
+        return entry;
+    }
+}
 
-        for ptype in PieceType::all() {
-            mat_score += material_score(*ptype);
-            let (x,y) = piece_square_score(*ptype);
-            psq_score.0 += x;
-            psq_score.1 += y;
+// direct-mapped on Game::pawn_hash
+pub struct PawnHashTable {
+    entries: Vec<PawnEntry>,
+    // the entries were computed with this version of the eval parameters
+    params_version: usize
+}
+
+impl PawnHashTable {
+    pub fn new(num_entries: usize) -> PawnHashTable {
+        debug_assert!(num_entries.is_power_of_two());
+
+        // an empty entry is also the correct entry for a position without pawns
+        PawnHashTable {
+            entries: vec![PawnEntry::empty(); num_entries],
+            params_version: eval_params_version()
         }
+    }
 
-        let phase = Phase::recompute(&game.board).unwrap() as f32;
-        let midgame_score = psq_score.0 as f32 + mat_score as f32;
-        let endgame_score = psq_score.1 as f32 + mat_score as f32;
+    pub fn probe(&mut self, game: &Game) -> PawnEntry {
+        use PieceType::*;
+        use Color::*;
 
-        let eval = ((midgame_score * (256.0 - phase)) + (endgame_score * phase)) / 256.0;
+        if self.params_version != eval_params_version() {
+            for entry in self.entries.iter_mut() {
+                *entry = PawnEntry::empty();
+            }
+            self.params_version = eval_params_version();
+        }
+
+        let params = eval_params();
+        let idx = game.pawn_hash.unwrap() as usize & (self.entries.len() - 1);
+        let pawns = [game.board.get_pieces(White, Pawn), game.board.get_pieces(Black, Pawn)];
+
+        let entry = self.entries[idx];
+
+        if entry.pawns == pawns {
+            debug_assert!(entry == PawnEntry::compute(&game.board, &params));
+            return entry;
+        }
+
+        let new_entry = PawnEntry::compute(&game.board, &params);
+        self.entries[idx] = new_entry;
+        return new_entry;
+    }
+}
+
+const FILE_H: u64 = 0x0101010101010101;
+
+fn file_mask(sq: Square) -> Bitboard {
+    Bitboard::new(FILE_H << (sq.idx() % 8))
+}
+
+fn adjacent_files_mask(sq: Square) -> Bitboard {
+    let column = sq.idx() % 8;
+    let mut mask = 0;
+
+    if column > 0 {
+        mask |= FILE_H << (column - 1);
+    }
+
+    if column < 7 {
+        mask |= FILE_H << (column + 1);
+    }
 
-        return Score::new(eval as i16);
+    return Bitboard::new(mask);
+}
+
+// every rank strictly in front of sq, from color's point of view
+fn forward_mask(color: Color, sq: Square) -> Bitboard {
+    let rank_idx = sq.idx() / 8;
+
+    match color {
+        Color::White if rank_idx == 7 => Bitboard::new(0),
+        Color::White => Bitboard::new(u64::max_value() << (8 * (rank_idx + 1))),
+        Color::Black => Bitboard::new((1 << (8 * rank_idx)) - 1)
+    }
+}
+
+// c through f
+const CENTER_FILES: u64 = (FILE_H << 2) | (FILE_H << 3) | (FILE_H << 4) | (FILE_H << 5);
+
+// knights on central squares supported by a pawn, where no enemy pawn can ever challenge them
+fn knight_outposts(board: &Board, color: Color) -> Bitboard {
+    use PieceType::*;
+
+    let friendly_pawns = board.get_pieces(color, Pawn);
+    let enemy_pawns = board.get_pieces(!color, Pawn);
+
+    let outpost_ranks = match color {
+        Color::White => RANK4 | RANK5 | RANK6,
+        Color::Black => RANK3 | RANK4 | RANK5
+    };
+
+    let mut outposts = Bitboard::none_set();
+
+    for sq in board.get_pieces(color, Knight) & outpost_ranks & Bitboard::new(CENTER_FILES) {
+        // a pawn of the other color standing on sq would attack exactly the supporting squares
+        let supported = (PAWN_ATTACKS[!color as usize][sq.idx()] & friendly_pawns).nonempty();
+        let challenged = (enemy_pawns & adjacent_files_mask(sq) & forward_mask(color, sq)).nonempty();
+
+        if supported && !challenged {
+            outposts |= sq.bitrep();
+        }
     }
+
+    return outposts;
+}
+
+// The squares each knight, bishop, rook and queen can move to, other than onto its own pieces.
+// A pinned piece only counts the ones along its pin.
+fn mobility(board: &Board, color: Color, pins: &PinInfo, params: &EvalParams) -> (i16, i16) {
+    use PieceType::*;
+
+    let own_pieces = board.occupied_by(color);
+    let mut mobility = (0, 0);
+
+    for ptype in [Knight, Bishop, Rook, Queen].iter() {
+        let (mid_val, end_val) = params.mobility(*ptype);
+
+        for sq in board.get_pieces(color, *ptype) {
+            let squares = (board.legal_mobility(sq, pins) & !own_pieces).population() as i16;
+            mobility.0 += squares * mid_val;
+            mobility.1 += squares * end_val;
+        }
+    }
+
+    return mobility;
+}
+
+// The enemy knights, bishops, rooks and queens attacked by anything but the king, each counted
+// once however many pieces attack it. Like mobility, a pinned piece only threatens along its pin.
+fn threats(board: &Board, color: Color, pins: &PinInfo, params: &EvalParams) -> (i16, i16) {
+    use PieceType::*;
+
+    let mut attacked = Bitboard::new(0);
+    for ptype in [Pawn, Knight, Bishop, Rook, Queen].iter() {
+        for sq in board.get_pieces(color, *ptype) {
+            attacked |= board.legal_mobility(sq, pins);
+        }
+    }
+
+    let mut threats = (0, 0);
+
+    for ptype in [Knight, Bishop, Rook, Queen].iter() {
+        let (mid_val, end_val) = params.threat(*ptype);
+        let count = (attacked & board.get_pieces(!color, *ptype)).population() as i16;
+        threats.0 += count * mid_val;
+        threats.1 += count * end_val;
+    }
+
+    return threats;
+}
+
+// friendly pawns sharing a square color with one of the bishops, counted once per bishop
+fn bad_bishop_pawns(board: &Board, color: Color) -> u32 {
+    use PieceType::*;
+
+    let pawns = board.get_pieces(color, Pawn);
+    let mut count = 0;
+
+    for sq in board.get_pieces(color, Bishop) {
+        let bishop_squares = if (sq.bitrep() & LIGHT_SQUARES).nonempty() {
+            LIGHT_SQUARES
+        } else {
+            DARK_SQUARES
+        };
+
+        count += (pawns & bishop_squares).population();
+    }
+
+    return count;
 }
 
 fn piece_square_value(color: Color, ptype: PieceType, sq: Square) -> (i16,i16) {
@@ -164,17 +646,6 @@ fn piece_square_value(color: Color, ptype: PieceType, sq: Square) -> (i16,i16) {
     return (sf * mid_val, sf * end_val);
 }
 
-fn material_value(ptype: PieceType) -> i16 {
-    match ptype {
-        PieceType::Pawn   => 100,
-        PieceType::Knight => 320,
-        PieceType::Bishop => 330,
-        PieceType::Rook   => 500,
-        PieceType::Queen  => 900,
-        PieceType::King   => 20000
-    }
-}
-
 // (middle-game, end-game)
 const PAWN_TABLE: [(i16,i16); 64] =
 [
@@ -252,6 +723,82 @@ const KING_TABLE: [(i16,i16); 64] =
 mod test {
     use eval::*;
 
+    #[test]
+    fn win_draw_loss_model() {
+        let params = EvalParams::default();
+        let middlegame = Phase::recompute(Game::starting_position().material_key);
+        let endgame = Phase::recompute(MaterialKey::bare_kings());
+        let wdl = |cp: i16, phase: Phase| win_draw_loss(Score::new(cp), phase, &params);
+
+        assert_eq!(wdl(0, middlegame), (59, 882, 59));
+        assert_eq!(wdl(100, middlegame), (159, 821, 20));
+        assert_eq!(wdl(250, middlegame), (500, 496, 4));
+        assert_eq!(wdl(600, middlegame), (980, 20, 0));
+        assert_eq!(wdl(200, endgame), (500, 497, 3));
+
+        assert_eq!(win_draw_loss(Score::max_at_depth(3), middlegame, &params), (1000, 0, 0));
+        assert_eq!(win_draw_loss(Score::min_at_depth(4), endgame, &params), (0, 0, 1000));
+
+        for phase in [middlegame, endgame].iter() {
+            let mut previous = wdl(-3000, *phase);
+
+            for cp in -3000 .. 3001 {
+                let (win, draw, loss) = wdl(cp, *phase);
+                assert_eq!(win + draw + loss, 1000);
+
+                // negating the score swaps the win and loss chances
+                assert_eq!(wdl(-cp, *phase), (loss, draw, win));
+
+                // a better score never makes a win less likely, or a loss more
+                assert!(win >= previous.0 && loss <= previous.2, "{}", cp);
+                previous = (win, draw, loss);
+            }
+        }
+    }
+
+    #[test]
+    fn trace_adds_up() {
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r1bqkb1r/pp3ppp/2n1pn2/3pN3/3P4/2P1B3/PP3PPP/RN1QKB1R b KQkq - 5 6",
+            // opposite colored bishops scale the end game down, and the clock fades it
+            "8/5k2/4b3/8/3B4/2K5/1P6/8 w - - 12 40",
+            // a specialized ending
+            "8/8/8/4k3/8/8/4P3/4K3 w - - 0 1"
+        ];
+
+        for fen in fens.iter() {
+            let game = Game::from_fen_str(fen).unwrap();
+            let trace = eval_trace(&game);
+
+            assert_eq!(trace.score, Score::recompute(&game, 0), "{}", fen);
+
+            let mut totals = (0, 0);
+            for term in trace.terms.iter() {
+                totals.0 += term.total.0;
+                totals.1 += term.total.1;
+
+                if term.name != "piece square" {
+                    assert_eq!(term.total, (term.white.0 - term.black.0, term.white.1 - term.black.1), "{}", fen);
+                }
+            }
+
+            let eval = match trace.specialized {
+                Some((_, score)) => score.unwrap() as f32,
+                None => blend(totals.0, totals.1, trace.scale, trace.phase)
+            };
+
+            assert_eq!(fade_toward_draw(&game, eval), trace.score, "{}", fen);
+        }
+
+        let opposite_bishops = eval_trace(&Game::from_fen_str(fens[3]).unwrap());
+        assert!(opposite_bishops.scale < NORMAL_SCALE);
+
+        let kpk = eval_trace(&Game::from_fen_str(fens[4]).unwrap());
+        assert_eq!(kpk.specialized.map(|(endgame, _)| endgame), Some(Endgame::KPvK));
+    }
+
     #[test]
     fn flip() {
         for _ in 0 .. 100000 {
@@ -266,4 +813,190 @@ mod test {
             }
         }
     }
+
+    #[test]
+    fn kpk() {
+        let win = Game::from_fen_str("4k3/8/4K3/4P3/8/8/8/8 b - - 0 1").unwrap();
+        let queen_value = DEFAULT_EVAL_PARAMS.material(PieceType::Queen).1;
+        assert!(Score::recompute(&win, 0).unwrap() > queen_value);
+
+        let draw = Game::from_fen_str("8/4k3/8/4K3/4P3/8/8/8 w - - 0 1").unwrap();
+        assert_eq!(Score::recompute(&draw, 0), Score::new(0));
+
+        let black_win = Game::from_fen_str("8/8/8/8/4p3/4k3/8/4K3 w - - 0 1").unwrap();
+        assert!(Score::recompute(&black_win, 0).unwrap() < -queen_value);
+    }
+
+    #[test]
+    fn fifty_move_scaling() {
+        let rook_up = |halfmoves: u16| -> i16 {
+            let mut game = Game::from_fen_str("4k3/8/8/8/8/8/8/R3K3 w - - 0 60").unwrap();
+            game.halfmove_clock = halfmoves;
+            // keep the fifty move draw from being decided before eval sees the position
+            game.strict_fide = true;
+            game.outcome = None;
+            Score::recompute(&game, 0).unwrap()
+        };
+
+        assert!(rook_up(0) > 0);
+        assert!(rook_up(80) > 0 && rook_up(80) < rook_up(0));
+        assert_eq!(rook_up(100), 0);
+        assert_eq!(rook_up(120), 0);
+
+        let drawn = Game::from_fen_str("4k3/8/8/8/8/8/8/R3K3 w - - 100 60").unwrap();
+        assert_eq!(Score::recompute(&drawn, 0), Score::new(0));
+    }
+
+    #[test]
+    fn loaded_params() {
+        use std::env;
+        use std::fs;
+        use std::process;
+
+        let path = env::temp_dir().join(format!("feldspar_eval_params_{}.txt", process::id()));
+        fs::write(&path, "pawn_value = 200\n").unwrap();
+        let params = EvalParams::load(path.to_str().unwrap()).unwrap();
+        let _ = fs::remove_file(&path);
+
+        // two pawns up, so the score moves by twice the change in the pawn's value
+        let game = Game::from_fen_str("4k3/8/8/8/8/8/3PP3/4K3 w - - 0 1").unwrap();
+        let default_score = Score::recompute_with_params(&game, 0, &DEFAULT_EVAL_PARAMS);
+        let score = Score::recompute_with_params(&game, 0, &params);
+
+        assert_eq!(Score::recompute(&game, 0), default_score);
+        assert!(default_score.unwrap() > 0);
+        assert_eq!(score.unwrap() - default_score.unwrap(), 200);
+    }
+
+    #[test]
+    fn pawn_structure() {
+        use Color::*;
+
+        // white: doubled c-pawns, the front one and the d-pawn passed. black: isolated passed a-pawn
+        let game = Game::from_fen_str("4k3/8/p7/3P4/2P5/2P5/8/4K3 w - - 0 1").unwrap();
+        let params = DEFAULT_EVAL_PARAMS;
+        let entry = PawnEntry::compute(&game.board, &params);
+
+        let d5 = Square::from_algebraic("d5").unwrap();
+        let c4 = Square::from_algebraic("c4").unwrap();
+        let a6 = Square::from_algebraic("a6").unwrap();
+
+        assert_eq!(entry.passed[White as usize], d5.bitrep() | c4.bitrep());
+        assert_eq!(entry.passed[Black as usize], a6.bitrep());
+
+        let expected_white_mg = params.passed_pawn_bonus[4].0 + params.passed_pawn_bonus[3].0
+                              - params.doubled_pawn_penalty.0;
+        assert_eq!(entry.score(White).0, expected_white_mg);
+        assert_eq!(entry.score(Black).0, params.passed_pawn_bonus[2].0 - params.isolated_pawn_penalty.0);
+    }
+
+    #[test]
+    fn knight_outpost() {
+        use Color::*;
+
+        let d5 = Square::from_algebraic("d5").unwrap();
+
+        let outpost = Game::from_fen_str("4k3/8/8/3N4/4P3/8/8/4K3 w - - 0 1").unwrap();
+        assert_eq!(knight_outposts(&outpost.board, White), d5.bitrep());
+
+        // c7-c6 would drive the knight away
+        let challenged = Game::from_fen_str("4k3/2p5/8/3N4/4P3/8/8/4K3 w - - 0 1").unwrap();
+        assert!(knight_outposts(&challenged.board, White).empty());
+
+        let unsupported = Game::from_fen_str("4k3/8/8/3N4/8/4P3/8/4K3 w - - 0 1").unwrap();
+        assert!(knight_outposts(&unsupported.board, White).empty());
+
+        assert!(Score::recompute(&outpost, 0) > Score::recompute(&challenged, 0));
+
+        let mut flipped = outpost;
+        flipped.flip_color();
+        assert_eq!(knight_outposts(&flipped.board, Black).population(), 1);
+    }
+
+    #[test]
+    fn bad_bishop() {
+        // a dark squared bishop behind a dark squared chain, versus the light squared one
+        let bad = Game::from_fen_str("4k3/8/4p3/3pP3/2pP4/2P5/8/2B1K3 w - - 0 1").unwrap();
+        let good = Game::from_fen_str("4k3/8/4p3/3pP3/2pP4/2P5/8/4KB2 w - - 0 1").unwrap();
+
+        assert_eq!(bad_bishop_pawns(&bad.board, Color::White), 3);
+        assert_eq!(bad_bishop_pawns(&good.board, Color::White), 0);
+        assert!(Score::recompute(&bad, 0) < Score::recompute(&good, 0));
+    }
+
+    #[test]
+    fn pinned_mobility() {
+        // the bishop on d7 can only move along the a4-e8 diagonal it's pinned on
+        let game = Game::from_fen_str("4k3/3b4/8/8/B7/8/8/4K3 w - - 0 1").unwrap();
+        let d7 = Square::from_algebraic("d7").unwrap();
+
+        // the default weights are off, so give every square a weight of its own
+        let mut params = EvalParams::default();
+        params.mobility_bonus = [(2, 3); 4];
+        let (mid_val, end_val) = params.mobility(PieceType::Bishop);
+
+        let pawns = PawnEntry::compute(&game.board, &params);
+        let terms = eval_terms(&game, &pawns, &params);
+        let mobility = terms.iter().find(|term| term.name == "mobility").unwrap();
+
+        // c6, b5 and a4, where attacks alone would also count c8 and e6 through h3
+        let naive_squares = (game.board.attacks_from(d7) & !game.board.occupied_by(Color::Black)).population() as i16;
+        assert_eq!(naive_squares, 8);
+        assert_eq!(mobility.black, (3 * mid_val, 3 * end_val));
+
+        // the pinning bishop has b5, c6, d7 and b3, c2, d1
+        assert_eq!(mobility.white, (6 * mid_val, 6 * end_val));
+        assert_eq!(mobility.total, (3 * mid_val, 3 * end_val));
+    }
+
+    #[test]
+    fn pinned_threats() {
+        // the pinned bishop on d7 can take the bishop pinning it, but not the knight on e6
+        let game = Game::from_fen_str("4k3/3b4/4N3/8/B7/8/8/4K3 w - - 0 1").unwrap();
+
+        let mut params = EvalParams::default();
+        params.threat_bonus = [(1, 2), (3, 4), (5, 6), (7, 8)];
+        assert_eq!(params.threat(PieceType::Bishop), (3, 4));
+
+        let pawns = PawnEntry::compute(&game.board, &params);
+        let terms = eval_terms(&game, &pawns, &params);
+        let threats = terms.iter().find(|term| term.name == "threats").unwrap();
+
+        let e6 = Square::from_algebraic("e6").unwrap();
+        assert!((game.board.attacks_from(Square::from_algebraic("d7").unwrap()) & e6.bitrep()).nonempty());
+        assert_eq!(threats.black, (3, 4));
+
+        // the a4 bishop is the only white piece attacking anything
+        assert_eq!(threats.white, (3, 4));
+        assert_eq!(threats.total, (0, 0));
+
+        // with nothing weighted, neither term is worked out at all
+        let terms = eval_terms(&game, &pawns, &EvalParams::default());
+        assert!(terms.iter().filter(|term| term.name == "mobility" || term.name == "threats")
+                     .all(|term| term.white == (0, 0) && term.black == (0, 0)));
+    }
+
+    #[test]
+    fn pawn_hash() {
+        use zobrist::*;
+        use movegen::*;
+
+        init_zobrist_hashing();
+
+        let mut table = PawnHashTable::new(1024);
+
+        for _ in 0 .. 1000 {
+            let mut game = Game::random_game();
+
+            for m in next_moves_standalone(&game).iter() {
+                let mut game_copy = game;
+                game_copy.make_move(*m);
+                assert_eq!(game_copy.pawn_hash, Hash::pawns(&game_copy.board));
+                assert_eq!(table.probe(&game_copy), PawnEntry::compute(&game_copy.board, &eval_params()));
+            }
+
+            game.flip_color();
+            assert_eq!(game.pawn_hash, Hash::pawns(&game.board));
+        }
+    }
 }
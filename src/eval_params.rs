@@ -0,0 +1,223 @@
+use core::*;
+
+use std::fs;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Every evaluation weight, as (middle-game, end-game), so that they can be tuned outside
+// the engine and loaded back in from a file of "name = mg, eg" lines.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct EvalParams {
+    // indexed by PieceType as usize - 1
+    pub material: [(i16, i16); 6],
+    // percent applied to the piece-square tables
+    pub pst_scale: (i16, i16),
+    pub doubled_pawn_penalty: (i16, i16),
+    pub isolated_pawn_penalty: (i16, i16),
+    // indexed by relative rank - 1
+    pub passed_pawn_bonus: [(i16, i16); 8],
+    pub knight_outpost_bonus: (i16, i16),
+    // per friendly pawn on the bishop's square color
+    pub bad_bishop_penalty: (i16, i16),
+    // per square a knight, bishop, rook or queen can move to, indexed by PieceType as usize - 2,
+    // off until it's been tuned
+    pub mobility_bonus: [(i16, i16); 4],
+    // per enemy knight, bishop, rook or queen attacked, indexed the same way and also off for now
+    pub threat_bonus: [(i16, i16); 4],
+    // Not part of the evaluation, these turn it into win/draw/loss chances: the score that
+    // wins half the time, and how quickly the chances change around it
+    pub wdl_center: (i16, i16),
+    pub wdl_spread: (i16, i16)
+}
+
+pub const DEFAULT_EVAL_PARAMS: EvalParams = EvalParams {
+    material: [(100, 100), (320, 320), (330, 330), (500, 500), (900, 900), (20000, 20000)],
+    pst_scale: (100, 100),
+    doubled_pawn_penalty: (10, 20),
+    isolated_pawn_penalty: (10, 15),
+    passed_pawn_bonus: [(0, 0), (5, 10), (5, 15), (10, 25), (20, 45), (35, 70), (60, 110), (0, 0)],
+    knight_outpost_bonus: (20, 10),
+    bad_bishop_penalty: (4, 6),
+    mobility_bonus: [(0, 0); 4],
+    threat_bonus: [(0, 0); 4],
+    wdl_center: (250, 200),
+    wdl_spread: (90, 70)
+};
+
+const PIECE_NAMES: [&'static str; 6] = ["pawn", "knight", "bishop", "rook", "queen", "king"];
+
+impl Default for EvalParams {
+    fn default() -> EvalParams {
+        DEFAULT_EVAL_PARAMS
+    }
+}
+
+impl EvalParams {
+    pub fn material(&self, ptype: PieceType) -> (i16, i16) {
+        self.material[ptype as usize - 1]
+    }
+
+    pub fn mobility(&self, ptype: PieceType) -> (i16, i16) {
+        self.mobility_bonus[ptype as usize - 2]
+    }
+
+    pub fn threat(&self, ptype: PieceType) -> (i16, i16) {
+        self.threat_bonus[ptype as usize - 2]
+    }
+
+    // the terms that need pins worked out, so that it's skipped while they count for nothing
+    pub fn uses_pins(&self) -> bool {
+        self.mobility_bonus.iter().chain(self.threat_bonus.iter()).any(|&bonus| bonus != (0, 0))
+    }
+
+    // the name of every parameter in the file format, with the value it sets
+    pub fn named_values(&mut self) -> Vec<(String, &mut (i16, i16))> {
+        let mut values = Vec::new();
+
+        for (name, value) in PIECE_NAMES.iter().zip(self.material.iter_mut()) {
+            values.push((format!("{}_value", name), value));
+        }
+
+        values.push(("pst_scale".to_string(), &mut self.pst_scale));
+        values.push(("doubled_pawn_penalty".to_string(), &mut self.doubled_pawn_penalty));
+        values.push(("isolated_pawn_penalty".to_string(), &mut self.isolated_pawn_penalty));
+
+        for (i, value) in self.passed_pawn_bonus.iter_mut().enumerate() {
+            values.push((format!("passed_pawn_bonus_{}", i + 1), value));
+        }
+
+        values.push(("knight_outpost_bonus".to_string(), &mut self.knight_outpost_bonus));
+        values.push(("bad_bishop_penalty".to_string(), &mut self.bad_bishop_penalty));
+
+        for (name, value) in PIECE_NAMES[1 .. 5].iter().zip(self.mobility_bonus.iter_mut()) {
+            values.push((format!("{}_mobility", name), value));
+        }
+
+        for (name, value) in PIECE_NAMES[1 .. 5].iter().zip(self.threat_bonus.iter_mut()) {
+            values.push((format!("{}_threat", name), value));
+        }
+
+        values.push(("wdl_center".to_string(), &mut self.wdl_center));
+        values.push(("wdl_spread".to_string(), &mut self.wdl_spread));
+
+        return values;
+    }
+
+    // Parameters missing from the text keep their default value. Blank lines and lines
+    // starting with '#' are skipped, anything else that doesn't parse makes the whole text invalid.
+    pub fn from_str(text: &str) -> Option<EvalParams> {
+        let mut params = EvalParams::default();
+
+        for line in text.lines().map(|l| l.trim()) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut sides = line.splitn(2, '=');
+            let name = sides.next()?.trim();
+            let numbers: Vec<&str> = sides.next()?.split(',').map(|n| n.trim()).collect();
+
+            let value = match numbers.len() {
+                // a single number sets both phases
+                1 => { let x = numbers[0].parse().ok()?; (x, x) },
+                2 => (numbers[0].parse().ok()?, numbers[1].parse().ok()?),
+                _ => return None
+            };
+
+            let mut named_values = params.named_values();
+            let target = named_values.iter_mut().find(|named| named.0 == name)?;
+            *target.1 = value;
+        }
+
+        return Some(params);
+    }
+
+    pub fn to_string(&self) -> String {
+        let mut params = *self;
+        let mut text = String::new();
+
+        for (name, value) in params.named_values() {
+            text.push_str(&format!("{} = {}, {}\n", name, value.0, value.1));
+        }
+
+        return text;
+    }
+
+    pub fn load(path: &str) -> Option<EvalParams> {
+        match fs::read_to_string(path) {
+            Ok(text) => EvalParams::from_str(&text),
+            Err(_) => None
+        }
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+}
+
+static mut EVAL_PARAMS: EvalParams = DEFAULT_EVAL_PARAMS;
+
+// bumped whenever the parameters change, so caches of evaluation terms know to start over
+static EVAL_PARAMS_VERSION: AtomicUsize = AtomicUsize::new(0);
+
+pub fn eval_params() -> EvalParams {
+    unsafe { EVAL_PARAMS }
+}
+
+pub fn eval_params_version() -> usize {
+    EVAL_PARAMS_VERSION.load(Ordering::SeqCst)
+}
+
+// only safe while nothing is being evaluated, so between searches
+pub fn set_eval_params(params: EvalParams) {
+    unsafe {
+        EVAL_PARAMS = params;
+    }
+
+    EVAL_PARAMS_VERSION.fetch_add(1, Ordering::SeqCst);
+}
+
+#[cfg(test)]
+mod test {
+    use eval_params::*;
+
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn text_format() {
+        let defaults = EvalParams::default();
+        assert_eq!(EvalParams::from_str(&defaults.to_string()), Some(defaults));
+        assert_eq!(EvalParams::from_str(""), Some(defaults));
+
+        let text = "# comments and blank lines are skipped\n\
+                    \n\
+                    pawn_value = 90, 120\n\
+                    knight_outpost_bonus=25\n\
+                    passed_pawn_bonus_7 = 70, 130\n";
+        let params = EvalParams::from_str(text).unwrap();
+
+        assert_eq!(params.material(PieceType::Pawn), (90, 120));
+        assert_eq!(params.knight_outpost_bonus, (25, 25));
+        assert_eq!(params.passed_pawn_bonus[6], (70, 130));
+        assert_eq!(params.material(PieceType::Knight), defaults.material(PieceType::Knight));
+
+        assert_eq!(EvalParams::from_str("pawn_value = 90, 120, 150"), None);
+        assert_eq!(EvalParams::from_str("pawn_value = ninety"), None);
+        assert_eq!(EvalParams::from_str("pawn_value"), None);
+        assert_eq!(EvalParams::from_str("mobility = 5, 5"), None);
+        assert_eq!(EvalParams::from_str("rook_mobility = 3, 5").unwrap().mobility(PieceType::Rook), (3, 5));
+        assert_eq!(EvalParams::from_str("queen_threat = 20").unwrap().threat(PieceType::Queen), (20, 20));
+
+        assert!(!defaults.uses_pins());
+        assert!(EvalParams::from_str("knight_threat = 1, 0").unwrap().uses_pins());
+
+        let path = env::temp_dir().join(format!("feldspar_params_{}.txt", process::id()));
+        let path = path.to_str().unwrap();
+        params.save(path).unwrap();
+        assert_eq!(EvalParams::load(path), Some(params));
+        let _ = fs::remove_file(path);
+
+        assert_eq!(EvalParams::load("/nonexistent/feldspar/params.txt"), None);
+    }
+}
@@ -1,49 +1,171 @@
 use eval::*;
+use eval_params::*;
 use core::*;
 use game::*;
 use movegen::*;
 use moves::*;
 use search::*;
+use skill::*;
+use stats::*;
 use tree::*;
 use uci::*;
 use zobrist::*;
 
-use std::time::Instant;
-use std::cmp::max;
+use std::time::{Duration, Instant};
+use std::cmp::{max, min};
+use std::io;
+use std::mem::size_of;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
 
 use std::str::SplitWhitespace;
 
+const MAX_HASH_MB: usize = 65536;
+const MAX_THREADS: usize = 64;
+const MAX_CONTEMPT: i16 = 100;
+const CURRMOVE_DELAY_MS: u32 = 1000;
+
+// a clock-managed search may stretch to this multiple of its nominal time when the best move is unstable
+const MAX_TIME_EXTENSION: u32 = 3;
+const UNSTABLE_TIME_FACTOR: f32 = 1.5;
+const UNSTABLE_SCORE_DROP: i16 = 50;
+// the best move is played early when every alternative is this much worse
+const EASY_MOVE_MARGIN: i16 = 200;
+const EASY_MOVE_DEPTH: u8 = 4;
+const EASY_MOVE_TIME_FACTOR: f32 = 1.0 / 3.0;
+// for one-off searches through search(), which also stop at this depth when given no limits at all
+const STANDALONE_TABLE_SIZE: usize = 1000000;
+const STANDALONE_DEPTH: u8 = 8;
+
 pub struct Feldspar {
-    context: SearchContext
+    context: SearchContext,
+    pub skill: Skill,
+    // used when "go" doesn't say otherwise, None means no limit
+    pub default_depth: Option<u8>,
+    pub default_movetime: Option<u32>,
+    table_entries: usize,
+    // threads searching each position, all but one of them helpers sharing the table (Lazy SMP)
+    pub threads: usize,
+    // currmove lines are only sent once a search has run this long
+    pub currmove_delay_ms: u32,
+    pub contempt: i16,
+    // UCI_ShowWDL, win/draw/loss chances in every info line with a score
+    pub show_wdl: bool,
+    // UCI_Chess960, castles written as the king capturing its own rook
+    pub chess960: bool,
+    // StrictFIDE, every game searched goes on to the seventy five move rule (Game::strict_fide)
+    pub strict_fide: bool,
+    // the time for this move comes from the clock, so it can be adjusted while searching
+    time_managed: bool
 }
 
 impl Feldspar {
     pub fn new() -> Feldspar {
-        let mut tmp_tree = SearchTree::new(Game::starting_position());
-        let mut tmp_qtree = SearchTree::new(Game::starting_position());
-        tmp_qtree.in_quiescence = true;
-        let mut tmp_table = TranspositionTable::new(100000000);
-
-        let mut new_context = SearchContext {
-            tree: tmp_tree,
-            qtree: tmp_qtree,
-            table: tmp_table,
-            timer: SearchTimer::new(3000),
-            ran_out_of_time: false
-        };
+        Feldspar::with_table_size(100000000)
+    }
 
+    pub fn with_table_size(table_entries: usize) -> Feldspar {
         Feldspar {
-            context: new_context
+            context: SearchContext::new(Game::starting_position(), table_entries),
+            skill: Skill::new(MAX_SKILL_LEVEL),
+            default_depth: None,
+            default_movetime: None,
+            table_entries: table_entries,
+            threads: 1,
+            currmove_delay_ms: CURRMOVE_DELAY_MS,
+            contempt: 0,
+            show_wdl: false,
+            chess960: false,
+            strict_fide: false,
+            time_managed: false
         }
     }
-}
 
-impl UCIEngine for Feldspar {
-    fn name(&self) -> &'static str { "feldspar" }
-    fn author(&self) -> &'static str { "Zac Meadows" }
+    // a GUI playing Chess960 may still send a position with the standard rook squares, which
+    // the game can't tell apart from standard chess
+    fn chess960_notation(&self, game: &Game) -> bool {
+        self.chess960 || game.chess960
+    }
+
+    pub fn stats(&self) -> &SearchStats {
+        &self.context.stats
+    }
+
+    // by this thread, over everything searched since the context was built
+    fn searched_nodes(&self) -> u64 {
+        self.context.tree.nodes + self.context.qtree.nodes
+    }
+
+    // best_move and the line the search found after it, if it's the move the line was found for.
+    // Table hits cut the line short, so the table carries it on from there.
+    fn principal_variation(&self, root: Game, best_move: Move, max_length: usize) -> Vec<Move> {
+        let mut pv: Vec<Move> = match self.context.tree.principal_variation() {
+            searched if searched.first() == Some(&best_move) => searched.iter().take(max_length).cloned().collect(),
+            _ => vec![best_move]
+        };
+
+        let mut end_of_pv = root;
+        for m in pv.iter() {
+            end_of_pv.make_move(*m);
+        }
+
+        for entry in self.context.table.get_pv(end_of_pv, max_length - pv.len()).iter() {
+            pv.push(entry.best_move());
+        }
+
+        return pv;
+    }
+
+    fn win_draw_loss(&self, root: &Game, score: Score) -> Option<(u16, u16, u16)> {
+        if self.show_wdl {
+            Some(win_draw_loss(score, Phase::recompute(root.material_key), &eval_params()))
+        } else {
+            None
+        }
+    }
+
+    pub fn hash_mb(&self) -> usize {
+        self.table_entries * size_of::<TableEntry>() / (1024 * 1024)
+    }
+
+    pub fn set_hash_mb(&mut self, mb: usize) {
+        self.table_entries = max(1, mb * 1024 * 1024 / size_of::<TableEntry>());
+        self.context.table = Arc::new(TranspositionTable::new(self.table_entries));
+    }
+
+    // the configured defaults fill in whatever the GUI left unspecified
+    fn with_defaults(&self, limits: &SearchLimits) -> SearchLimits {
+        let mut limits = limits.clone();
+
+        if limits.infinite {
+            limits.depth = None;
+            return limits;
+        }
+
+        let untimed = limits.movetime.is_none() && limits.wtime == 0 && limits.btime == 0;
+
+        // when there's no mate, look about as far as it would have been
+        if limits.depth.is_none() && untimed {
+            if let Some(n) = limits.mate {
+                limits.depth = Some(min(n.saturating_mul(2), MAX_SEARCH_DEPTH));
+            }
+        }
+
+        if limits.depth.is_none() {
+            limits.depth = self.default_depth;
+        }
+
+        if untimed {
+            limits.movetime = self.default_movetime;
+        }
+
+        return limits;
+    }
 
-    //TODO: print promotion type!
-    fn find_best_move(&mut self, wtime: u32, btime: u32, winc: u32, binc: u32) -> () {
+    fn start_timer(&mut self, limits: &SearchLimits) {
+        let (wtime, btime, winc, binc) = (limits.wtime, limits.btime, limits.winc, limits.binc);
 
         let mut my_time = 0;
         let mut opp_time = 0;
@@ -62,64 +184,1053 @@ impl UCIEngine for Feldspar {
             opp_inc = winc;
         }
 
-        if my_time > opp_time {
-            self.context.timer = SearchTimer::new( max(my_time - opp_time, my_time/50) );
+        self.time_managed = false;
+
+        let nominal_time = if limits.infinite {
+            self.context.timer = SearchTimer::new(u32::max_value());
+            return;
+        } else if let Some(t) = limits.movetime {
+            self.context.timer = SearchTimer::new(t);
+            return;
+        } else if limits.depth.is_some() && wtime == 0 && btime == 0 {
+            self.context.timer = SearchTimer::new(u32::max_value());
+            return;
+        } else if my_time > opp_time {
+            max(my_time - opp_time, my_time/50)
         } else {
             if my_time > 10000 {
-                self.context.timer = SearchTimer::new( max(my_time/40, 1500) );
+                max(my_time/40, 1500)
             } else {
-                self.context.timer = SearchTimer::new( max(my_time/40, 500) );
+                max(my_time/40, 500)
+            }
+        };
+
+        // never risk more than a quarter of the clock on one move
+        let maximum_time = min(nominal_time.saturating_mul(MAX_TIME_EXTENSION), my_time / 4);
+
+        self.context.timer = SearchTimer::with_maximum(nominal_time, maximum_time);
+        self.time_managed = true;
+    }
+
+    // Searches every other root move with a null window just below best_score - EASY_MOVE_MARGIN.
+    // If they all fail low, nothing is close to the best move and there's no point thinking longer.
+    fn is_easy_move(&mut self, best_move: Move, best_score: Score, root_moves: &[Move], depth: u8) -> bool {
+        if best_score.is_mate() || depth < 2 {
+            return false;
+        }
+
+        let threshold = Score::new(best_score.unwrap() - EASY_MOVE_MARGIN);
+
+        for m in root_moves.iter().filter(|m| **m != best_move) {
+            self.context.tree.make_move(*m);
+            let (score, _) = negamax(&mut self.context, depth - 1,
+                                     threshold.flipped(), Score::new(threshold.unwrap() - 1).flipped());
+            self.context.tree.unmake_move();
+
+            if self.context.ran_out_of_time || score.flipped() >= threshold {
+                return false;
             }
         }
 
+        return true;
+    }
+
+    // Picks the move to play, respecting the skill level.
+    pub fn choose_move(&mut self, limits: &SearchLimits, io: &mut UciIo) -> Move {
+        self.search_position(limits, io).best_move
+    }
+
+    // The same, with everything else the search found out about the chosen move.
+    pub fn search_position(&mut self, limits: &SearchLimits, io: &mut UciIo) -> SearchResult {
+        let limits = &self.with_defaults(limits);
+        self.start_timer(limits);
+        self.context.contempt = self.contempt;
+
+        if self.skill.is_full_strength() {
+            return self.search_best_move(limits, io);
+        }
+
+        let root = *self.context.tree.focus();
+        if !can_move(&root) {
+            return SearchResult::new(Move::null(), Score::recompute_symmetric(&root, 0));
+        }
+
+        let search_timer = Counter::new();
+        let nodes_before = self.searched_nodes();
+
+        let depth = match limits.depth {
+            Some(d) => max(1, d.min(self.skill.search_depth())),
+            None => self.skill.search_depth()
+        };
+
+        self.restrict_root_moves(&limits.searchmoves);
+        let scored_moves = score_root_moves(&mut self.context, depth);
+        self.context.ran_out_of_time = false;
+        self.context.root_moves.clear();
+
+        let m = self.skill.pick_move(&scored_moves);
+        let score = scored_moves.iter().find(|&&(sm, _)| sm == m).unwrap().1;
+
+        // a weakened engine doesn't play the line it would have, so there's no pv beyond its move
+        let mut result = SearchResult::new(m, score);
+        result.depth = depth;
+        result.seldepth = depth;
+        result.nodes = self.searched_nodes() - nodes_before;
+        result.time_ms = search_timer.elapsed_ns() / 1_000_000;
+
+        return result;
+    }
+
+    // Iterative deepening with whatever timer is currently set in the context.
+    // Always returns a legal move when one exists, even if depth 1 never completes.
+    fn search_best_move(&mut self, limits: &SearchLimits, io: &mut UciIo) -> SearchResult {
         self.context.ran_out_of_time = false;
 
-        let mut depth_reached = 0;
-        let mut best_move = Move::null();
-        let mut best_score = Score::min();
-
-        for i in 1 .. 999 {
-            negamax( &mut self.context, i, Score::min(), Score::max() );
-            if !self.context.ran_out_of_time {
-                depth_reached = i;
-                let pv = self.context.table.get_pv(*self.context.tree.focus(), depth_reached as usize);
-                if pv.len() > 0 {
-                    best_move = pv[0].best_move();
-                    best_score = pv[0].score();
-
-                    let mut pv_str = String::new();
-
-                    for entry in pv.iter() {
-                        if pv_str.len() > 0 {
-                            pv_str.push_str(" ");
-                        }
-                        pv_str.push_str(&entry.best_move().to_uci_str());
+        let root = *self.context.tree.focus();
+        let legal_moves = next_moves_standalone(&root);
+
+        if legal_moves.len() == 0 {
+            return SearchResult::new(Move::null(), Score::recompute_symmetric(&root, 0));
+        }
+
+        self.restrict_root_moves(&limits.searchmoves);
+
+        let root_moves: Vec<Move> = if self.context.root_moves.is_empty() {
+            legal_moves.iter().cloned().collect()
+        } else {
+            self.context.root_moves.clone()
+        };
+
+        let is_root_move = |m: Move| root_moves.contains(&m);
+
+        // emergency move in case we are interrupted before any depth completes
+        let emergency_move = match self.context.table.probe(root.hash) {
+            Some(tentry) if is_root_move(tentry.best_move()) => tentry.best_move(),
+            _ => if root_moves.len() > 0 { root_moves[0] } else { Move::null() }
+        };
+
+        // nothing to think about
+        if self.time_managed && root_moves.len() == 1 {
+            return SearchResult::new(root_moves[0], Score::recompute_symmetric(&root, 0));
+        }
+
+        let mut result = SearchResult::new(emergency_move, Score::min());
+        let max_depth = limits.depth.unwrap_or(MAX_SEARCH_DEPTH);
+
+        self.context.stats.clear();
+        self.context.seldepth = 0;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let _stop_helpers = StopOnDrop(stop.clone());
+        let helpers: Vec<_> = (1 .. self.threads).map(|helper| {
+            let mut context = SearchContext::with_table(root, self.context.table.clone());
+            context.tree.reset_root(root, self.context.tree.root_history.clone());
+            context.root_moves = self.context.root_moves.clone();
+            context.options = self.context.options;
+            context.contempt = self.context.contempt;
+            context.stop = stop.clone();
+
+            thread::spawn(move || helper_search(context, helper, max_depth))
+        }).collect();
+
+        let search_timer = Counter::new();
+        let nodes_before = self.searched_nodes();
+        let currmove_delay_ns = self.currmove_delay_ms as u64 * 1_000_000;
+        let chess960 = self.chess960_notation(&root);
+
+        for depth in 1 .. max_depth + 1 {
+            self.context.stats.begin_iteration();
+
+            let (score, m) = {
+                let mut report_currmove = |_: u8, root_move: Move, move_number: usize| {
+                    if search_timer.elapsed_ns() >= currmove_delay_ns {
+                        io.send(&format!("info depth {} currmove {} currmovenumber {}",
+                                         depth, move_to_uci_str(&root, root_move, chess960), move_number));
                     }
+                };
 
-                    println!("info depth {} score cp {} pv {}", depth_reached, best_score.unwrap(), pv_str);
-                    eprintln!("best_move from negamax: {}{}", best_move.from().to_algebraic(), best_move.to().to_algebraic());
-                }
-            } else {
+                negamax_reporting(&mut self.context, depth, Score::min(), Score::max(), &mut report_currmove)
+            };
+
+            // results from an interrupted iteration can't be trusted
+            if self.context.ran_out_of_time {
                 break;
             }
+
+            result.depth = depth;
+            result.seldepth = min(self.context.seldepth, u8::max_value() as usize) as u8;
+            result.nodes = self.searched_nodes() - nodes_before;
+            result.time_ms = search_timer.elapsed_ns() / 1_000_000;
+
+            if is_root_move(m) {
+                let previous_best = (result.best_move, result.score);
+                result.best_move = m;
+                result.score = score;
+                result.pv = self.principal_variation(root, m, depth as usize);
+
+                result.wdl = self.win_draw_loss(&root, score);
+
+                io.send(&result.info_line(&root, chess960, None, Some(self.context.table.hashfull())));
+
+                if self.time_managed && depth > 1 {
+                    let best_move_changed = m != previous_best.0;
+                    let score_dropped = score.unwrap() < previous_best.1.unwrap() - UNSTABLE_SCORE_DROP;
+
+                    if best_move_changed || score_dropped {
+                        self.context.timer.scale_remaining(UNSTABLE_TIME_FACTOR);
+                    } else if depth == EASY_MOVE_DEPTH && self.is_easy_move(m, score, &root_moves, depth - 1) {
+                        self.context.timer.scale_remaining(EASY_MOVE_TIME_FACTOR);
+                    }
+                }
+            }
         }
 
-        // match self.context.tree.focus().to_move {
-        //     Color::White => eprintln!("score: {:?}", (best_score.unwrap() as f32)/100.0),
-        //     Color::Black => eprintln!("score: {:?}", (best_score.flipped().unwrap() as f32)/100.0)
-        // }
+        stop.store(true, Ordering::Relaxed);
+        let mut adopted_helper = false;
+        for helper in helpers {
+            // a helper that got further than this thread has the better answer
+            if let Ok(Some((depth, seldepth, m, score))) = helper.join() {
+                if depth > result.depth && is_root_move(m) {
+                    result.depth = depth;
+                    result.seldepth = seldepth;
+                    result.best_move = m;
+                    result.score = score;
+                    result.pv = self.principal_variation(root, m, depth as usize);
+                    result.wdl = self.win_draw_loss(&root, score);
+                    adopted_helper = true;
+                }
+            }
+        }
 
-        println!( "bestmove {}{}"
-                , best_move.from().to_algebraic()
-                , best_move.to().to_algebraic()
-                );
+        // the nodes are this thread's alone, the helpers don't count theirs
+        result.nodes = self.searched_nodes() - nodes_before;
+        result.time_ms = search_timer.elapsed_ns() / 1_000_000;
 
+        // the GUI's last info line is still this thread's, and that's no longer the move being played
+        if adopted_helper {
+            io.send(&result.info_line(&root, chess960, None, Some(self.context.table.hashfull())));
+        }
+
+        if let Some(summary) = self.context.stats.summary() {
+            io.info_string(&summary);
+        }
+        if let Some(summary) = self.context.stats.extension_summary() {
+            io.info_string(&summary);
+        }
+
+        self.context.ran_out_of_time = false;
+        self.context.root_moves.clear();
+
+        return result;
+    }
+
+    // the legal ones of "go searchmoves", none meaning every move may be searched
+    fn restrict_root_moves(&mut self, searchmoves: &[Move]) {
+        let legal_moves = next_moves_standalone(self.context.tree.focus());
+        self.context.root_moves = searchmoves.iter()
+            .filter(|m| legal_moves.iter().any(|lm| lm == *m))
+            .cloned()
+            .collect();
+    }
+
+    // "go mate n": a mate in n moves or fewer, reported along with its line. The mate search
+    // keeps to the same clock and searchmoves as any other.
+    fn search_mate(&mut self, limits: &SearchLimits, moves: u8) -> Option<SearchResult> {
+        let limits = &self.with_defaults(limits);
+        self.start_timer(limits);
         self.context.ran_out_of_time = false;
 
+        self.restrict_root_moves(&limits.searchmoves);
+
+        let line = find_mate(&mut self.context, moves);
+        self.context.root_moves.clear();
+        let line = line?;
+
+        let mut result = SearchResult::new(line[0], Score::max_at_depth(line.len()));
+        result.depth = line.len() as u8;
+        result.seldepth = line.len() as u8;
+        result.pv = line;
+
+        return Some(result);
+    }
+
+    // what to play for "go", a mate if one was asked for and there is one
+    fn search_for_move(&mut self, limits: &SearchLimits, io: &mut UciIo) -> SearchResult {
+        let mate = match limits.mate {
+            Some(n) => self.search_mate(limits, n),
+            None => None
+        };
+
+        // the time went on looking for the mate, so answer with whatever a quick look finds
+        if limits.mate.is_some() && self.context.ran_out_of_time {
+            let mut quick = limits.clone();
+            quick.depth = Some(1);
+            quick.mate = None;
+            return self.search_position(&quick, io);
+        }
+
+        match mate {
+            Some(result) => {
+                let root = *self.context.tree.focus();
+                let pv_str = uci_move_strs(&root, &result.pv, self.chess960_notation(&root));
+                io.send(&format!("info depth {} score mate {} pv {}",
+                                 result.depth, result.score.mate_moves().unwrap(), pv_str.join(" ")));
+                result
+            },
+            None => self.search_position(limits, io)
+        }
+    }
+}
+
+// Stops the helper threads however search_best_move is left, by a panic too, which would
+// otherwise leave them searching forever.
+struct StopOnDrop(Arc<AtomicBool>);
+
+impl Drop for StopOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+// A helper thread of a Lazy SMP search, iterative deepening on its own until it's stopped.
+// Mostly it's there to fill the shared table with what the main thread will need, so odd
+// helpers start a ply deeper to be ahead of it. Returns the deepest iteration it finished.
+// (depth, seldepth, best move, score) of the deepest iteration the helper finished
+fn helper_search(mut context: SearchContext, helper: usize, max_depth: u8) -> Option<(u8, u8, Move, Score)> {
+    let mut result = None;
+
+    for depth in 1 + (helper % 2) as u8 .. max_depth + 1 {
+        let (score, m) = negamax(&mut context, depth, Score::min(), Score::max());
+
+        if context.ran_out_of_time {
+            break;
+        }
+
+        if !m.is_null() {
+            let seldepth = min(context.seldepth, u8::max_value() as usize) as u8;
+            result = Some((depth, seldepth, m, score));
+        }
+    }
+
+    return result;
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchResult {
+    // null when there are no legal moves
+    pub best_move: Move,
+    // from the side to move's point of view
+    pub score: Score,
+    // starting with best_move
+    pub pv: Vec<Move>,
+    // the deepest iteration that finished, and the furthest any line of it went
+    pub depth: u8,
+    pub seldepth: u8,
+    pub nodes: u64,
+    pub time_ms: u64,
+    // there are no tablebases yet, so always 0
+    pub tb_hits: u64,
+    // per-mille win/draw/loss chances, when UCI_ShowWDL asks for them
+    pub wdl: Option<(u16, u16, u16)>
+}
+
+impl SearchResult {
+    // the move and its score, before anything has been searched
+    pub fn new(best_move: Move, score: Score) -> SearchResult {
+        SearchResult {
+            best_move: best_move,
+            score: score,
+            pv: if best_move.is_null() { Vec::new() } else { vec![best_move] },
+            depth: 0,
+            seldepth: 0,
+            nodes: 0,
+            time_ms: 0,
+            tb_hits: 0,
+            wdl: None
+        }
+    }
+
+    // The UCI info line reporting this result from root, numbered when several lines are searched
+    // with multipv. hashfull comes from the table rather than the search, so it's passed in.
+    pub fn info_line(&self, root: &Game, chess960: bool, multipv: Option<usize>, hashfull: Option<u32>) -> String {
+        let mut line = format!("info depth {} seldepth {}", self.depth, self.seldepth);
+
+        if let Some(n) = multipv {
+            line.push_str(&format!(" multipv {}", n));
+        }
+
+        match self.score.mate_moves() {
+            Some(moves) => line.push_str(&format!(" score mate {}", moves)),
+            None => line.push_str(&format!(" score cp {}", self.score.unwrap()))
+        }
+
+        if let Some((win, draw, loss)) = self.wdl {
+            line.push_str(&format!(" wdl {} {} {}", win, draw, loss));
+        }
+
+        let nps = self.nodes * 1000 / max(self.time_ms, 1);
+        line.push_str(&format!(" nodes {} nps {} tbhits {} time {}", self.nodes, nps, self.tb_hits, self.time_ms));
+
+        if let Some(hashfull) = hashfull {
+            line.push_str(&format!(" hashfull {}", hashfull));
+        }
+
+        if !self.pv.is_empty() {
+            let pv_str = uci_move_strs(root, &self.pv, chess960);
+            line.push_str(&format!(" pv {}", pv_str.join(" ")));
+        }
+
+        return line;
+    }
+}
+
+// Searches game as the engine would, for embedding without a UCI loop. The engine, its
+// transposition table and the search tree are built here and thrown away afterwards.
+pub fn search(game: Game, limits: SearchLimits) -> SearchResult {
+    let mut engine = Feldspar::with_table_size(STANDALONE_TABLE_SIZE);
+    let mut silent = UciIo::new(Box::new(io::empty()), Box::new(io::sink()));
+
+    engine.replace_game(game, Vec::new());
+
+    let unlimited = limits.depth.is_none() && limits.mate.is_none() && limits.movetime.is_none()
+                 && limits.wtime == 0 && limits.btime == 0;
+
+    if unlimited {
+        engine.default_depth = Some(STANDALONE_DEPTH);
+    }
+
+    return engine.search_for_move(&limits, &mut silent);
+}
+
+impl UCIEngine for Feldspar {
+    fn name(&self) -> &'static str { "feldspar" }
+    fn author(&self) -> &'static str { "Zac Meadows" }
+
+    fn find_best_move(&mut self, limits: SearchLimits, io: &mut UciIo) -> () {
+        let root = *self.context.tree.focus();
+        let history = self.context.tree.root_history.clone();
+        let stop = io.stop_signal();
+        self.context.stop = stop.clone();
+
+        // played if the search dies, searchmoves are already checked to be legal
+        let emergency_move = match limits.searchmoves.first() {
+            Some(&m) => m,
+            None => next_moves_standalone(&root).iter().next().cloned().unwrap_or(Move::null())
+        };
+
+        // A bug in the search mustn't leave the GUI waiting for a bestmove forever. Unwinding
+        // can stop the tree partway down a line, with the table, move ordering and statistics
+        // half updated, so none of the context is trusted afterwards: it's rebuilt for the same
+        // position. io is only ever left with a line half written.
+        let searched = panic::catch_unwind(AssertUnwindSafe(|| self.search_for_move(&limits, io)));
+
+        let best_move = match searched {
+            Ok(result) => result.best_move,
+            Err(payload) => {
+                let message = match payload.downcast_ref::<&str>() {
+                    Some(message) => message.to_string(),
+                    None => payload.downcast_ref::<String>().cloned().unwrap_or("unknown panic".to_string())
+                };
+
+                let report = format!("search panicked ({}) in position {}", message, root.to_fen());
+                eprintln!("{}", report);
+                io.info_string(&report);
+
+                self.context = SearchContext::new(root, self.table_entries);
+                self.context.tree.reset_root(root, history);
+
+                emergency_move
+            }
+        };
+
+        // an infinite search only answers once it's told to stop, however soon it runs out of depth
+        while limits.infinite && !stop.load(Ordering::SeqCst) {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        if best_move.is_null() {
+            io.send("bestmove (none)");
+        } else {
+            io.send(&format!("bestmove {}", move_to_uci_str(&root, best_move, self.chess960_notation(&root))));
+        }
+
         //TODO: ponder while opponent thinks
     }
 
-    fn replace_game(&mut self, new_game: Game, history: Vec<Hash>) {
+    fn reset(&mut self) {
+        // options persist, everything learned while searching the previous game does not
+        self.context = SearchContext::new(Game::starting_position(), self.table_entries);
+    }
+
+    fn print_options(&self, io: &mut UciIo) {
+        io.send(&format!("option name Skill Level type spin default {} min 0 max {}", MAX_SKILL_LEVEL, MAX_SKILL_LEVEL));
+        io.send(&format!("option name Hash type spin default {} min 1 max {}", self.hash_mb(), MAX_HASH_MB));
+        io.send(&format!("option name Threads type spin default 1 min 1 max {}", MAX_THREADS));
+        io.send(&format!("option name Depth type spin default 0 min 0 max {}", MAX_SEARCH_DEPTH));
+        io.send(&format!("option name MoveTime type spin default 0 min 0 max {}", u32::max_value()));
+        io.send(&format!("option name Contempt type spin default 0 min {} max {}", -MAX_CONTEMPT, MAX_CONTEMPT));
+        io.send("option name EvalFile type string default <empty>");
+        io.send("option name UCI_ShowWDL type check default false");
+        io.send("option name UCI_Chess960 type check default false");
+        io.send("option name StrictFIDE type check default false");
+    }
+
+    // for Depth and MoveTime, 0 removes the default
+    fn set_option(&mut self, name: &str, value: &str) {
+        match name {
+            "Skill Level" => match value.parse::<u8>() {
+                Ok(level) => self.skill.set_level(level),
+                Err(_) => eprintln!("invalid Skill Level: {}", value)
+            },
+            "Hash" => match value.parse::<usize>() {
+                Ok(mb) if mb > 0 => self.set_hash_mb(min(mb, MAX_HASH_MB)),
+                _ => eprintln!("invalid Hash: {}", value)
+            },
+            "Threads" => match value.parse::<usize>() {
+                Ok(n) if n > 0 => self.threads = min(n, MAX_THREADS),
+                _ => eprintln!("invalid Threads: {}", value)
+            },
+            "Depth" => match value.parse::<u8>() {
+                Ok(0) => self.default_depth = None,
+                Ok(d) => self.default_depth = Some(min(d, MAX_SEARCH_DEPTH)),
+                Err(_) => eprintln!("invalid Depth: {}", value)
+            },
+            "MoveTime" => match value.parse::<u32>() {
+                Ok(0) => self.default_movetime = None,
+                Ok(t) => self.default_movetime = Some(t),
+                Err(_) => eprintln!("invalid MoveTime: {}", value)
+            },
+            "Contempt" => match value.parse::<i16>() {
+                Ok(c) => self.contempt = max(-MAX_CONTEMPT, min(c, MAX_CONTEMPT)),
+                Err(_) => eprintln!("invalid Contempt: {}", value)
+            },
+            "UCI_ShowWDL" => match value {
+                "true" => self.show_wdl = true,
+                "false" => self.show_wdl = false,
+                _ => eprintln!("invalid UCI_ShowWDL: {}", value)
+            },
+            "UCI_Chess960" => match value {
+                "true" => self.chess960 = true,
+                "false" => self.chess960 = false,
+                _ => eprintln!("invalid UCI_Chess960: {}", value)
+            },
+            "StrictFIDE" => match value {
+                "true" | "false" => {
+                    self.strict_fide = value == "true";
+
+                    // for the position already set up too
+                    let root = self.current_game();
+                    let history = self.context.tree.root_history.clone();
+                    self.replace_game(root, history);
+                },
+                _ => eprintln!("invalid StrictFIDE: {}", value)
+            },
+            "EvalFile" if value.is_empty() || value == "<empty>" => set_eval_params(EvalParams::default()),
+            "EvalFile" => match EvalParams::load(value) {
+                Some(params) => set_eval_params(params),
+                None => eprintln!("invalid EvalFile: {}", value)
+            },
+            _ => eprintln!("unknown option: {}", name)
+        }
+    }
+
+    // a game already set to the seventy five move rule keeps it, the option can only add it
+    fn replace_game(&mut self, mut new_game: Game, history: Vec<Hash>) {
+        if self.strict_fide && !new_game.strict_fide {
+            new_game.strict_fide = true;
+            new_game.update_derived_fields();
+        }
+
         self.context.tree.reset_root(new_game, history);
     }
+
+    fn current_game(&self) -> Game {
+        *self.context.tree.focus()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use feldspar::*;
+    use std::io;
+
+    #[test]
+    fn standalone_search() {
+        init_zobrist_hashing();
+
+        let start = Game::starting_position();
+        let mut limits = SearchLimits::new();
+        limits.depth = Some(4);
+
+        let result = search(start, limits.clone());
+        assert!(next_moves_standalone(&start).iter().any(|m| *m == result.best_move));
+        assert!(!result.score.is_mate() && result.score.unwrap().abs() < 200);
+        assert_eq!(result.pv[0], result.best_move);
+
+        // the pv is a playable line
+        let mut game = start;
+        for m in result.pv.iter() {
+            assert!(game.is_legal(*m));
+            game.make_move(*m);
+        }
+
+        let mut engine = Feldspar::with_table_size(STANDALONE_TABLE_SIZE);
+        engine.replace_game(start, Vec::new());
+        let engine_result = engine.search_position(&limits, &mut silent_io());
+        assert_eq!((engine_result.best_move, engine_result.score), (result.best_move, result.score));
+        assert_eq!(result.depth, 4);
+        assert!(result.seldepth >= 4 && result.nodes > 0);
+
+        let mate_in_two = Game::from_fen_str("7k/8/8/8/8/8/R7/1R4K1 w - - 0 1").unwrap();
+        let mut limits = SearchLimits::new();
+        limits.mate = Some(2);
+        let result = search(mate_in_two, limits);
+        assert!(result.score.is_mate() && result.score > Score::new(0));
+        assert_eq!(result.pv.len(), 3);
+
+        // a mate search that runs out of time still answers with a move
+        let mut limits = SearchLimits::new();
+        limits.mate = Some(10);
+        limits.movetime = Some(100);
+        let result = search(start, limits);
+        assert!(start.is_legal(result.best_move) && !result.score.is_mate());
+
+        // no limits at all still finishes
+        assert!(!search(start, SearchLimits::new()).best_move.is_null());
+
+        let checkmate = Game::from_fen_str("rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3").unwrap();
+        let result = search(checkmate, SearchLimits::new());
+        assert!(result.best_move.is_null() && result.pv.is_empty());
+    }
+
+    #[test]
+    fn info_lines() {
+        init_zobrist_hashing();
+
+        let root = Game::starting_position();
+        let mut game = root;
+        let mut pv = Vec::new();
+        for m in ["e2e4", "e7e5", "g1f3"].iter() {
+            let m = move_from_algebraic(&game, m.to_string()).unwrap();
+            game.make_move(m);
+            pv.push(m);
+        }
+
+        let mut result = SearchResult::new(pv[0], Score::new(34));
+        result.pv = pv;
+        result.depth = 7;
+        result.seldepth = 12;
+        result.nodes = 150000;
+        result.time_ms = 200;
+
+        assert_eq!(result.info_line(&root, false, None, Some(12)),
+                   "info depth 7 seldepth 12 score cp 34 nodes 150000 nps 750000 tbhits 0 time 200 hashfull 12 pv e2e4 e7e5 g1f3");
+
+        result.wdl = Some((120, 850, 30));
+        assert_eq!(result.info_line(&root, false, Some(2), None),
+                   "info depth 7 seldepth 12 multipv 2 score cp 34 wdl 120 850 30 nodes 150000 nps 750000 tbhits 0 time 200 pv e2e4 e7e5 g1f3");
+
+        // mates for and against the side to move, and a search too quick to have taken any time
+        result.wdl = None;
+        result.time_ms = 0;
+        result.score = Score::max_at_depth(2);
+        assert_eq!(result.info_line(&root, false, None, None),
+                   "info depth 7 seldepth 12 score mate 2 nodes 150000 nps 150000000 tbhits 0 time 0 pv e2e4 e7e5 g1f3");
+
+        result.score = Score::min_at_depth(1);
+        result.pv.clear();
+        assert_eq!(result.info_line(&root, false, Some(1), None),
+                   "info depth 7 seldepth 12 multipv 1 score mate -1 nodes 150000 nps 150000000 tbhits 0 time 0");
+    }
+
+    fn silent_io() -> UciIo {
+        UciIo::new(Box::new(io::empty()), Box::new(io::sink()))
+    }
+
+    #[test]
+    fn always_legal_bestmove() {
+        let mut engine = Feldspar::with_table_size(100000);
+
+        let mut searched = 0;
+        while searched < 300 {
+            let game = Game::random_game();
+            if game.outcome.is_some() {
+                continue;
+            }
+
+            engine.replace_game(game, Vec::new());
+            engine.context.timer = SearchTimer::new(1);
+            let best_move = engine.search_best_move(&SearchLimits::new(), &mut silent_io()).best_move;
+
+            let legal_moves = next_moves_standalone(&game);
+            assert!(legal_moves.iter().any(|m| *m == best_move),
+                    "illegal bestmove {} in {}", best_move.to_uci_str(), game.to_fen());
+
+            searched += 1;
+        }
+    }
+
+    #[test]
+    fn searchmoves() {
+        init_zobrist_hashing();
+
+        let game = Game::starting_position();
+        let a2a3 = move_from_algebraic(&game, "a2a3".to_string()).unwrap();
+
+        let mut limits = SearchLimits::new();
+        limits.depth = Some(6);
+
+        let mut engine = Feldspar::with_table_size(100000);
+        engine.replace_game(game, Vec::new());
+        engine.context.timer = SearchTimer::new(u32::max_value());
+        let unrestricted = engine.search_best_move(&limits, &mut silent_io());
+        let (unrestricted_move, unrestricted_score) = (unrestricted.best_move, unrestricted.score);
+        assert!(unrestricted_move != a2a3);
+
+        limits.searchmoves.push(a2a3);
+
+        let mut engine = Feldspar::with_table_size(100000);
+        engine.replace_game(game, Vec::new());
+        engine.context.timer = SearchTimer::new(u32::max_value());
+        let restricted = engine.search_best_move(&limits, &mut silent_io());
+        let (restricted_move, restricted_score) = (restricted.best_move, restricted.score);
+        assert_eq!(restricted_move, a2a3);
+        assert!(restricted_score <= unrestricted_score);
+    }
+
+    #[test]
+    fn skill_level() {
+        init_zobrist_hashing();
+
+        let game = Game::starting_position();
+        let mut limits = SearchLimits::new();
+        limits.depth = Some(4);
+
+        let mut reference = Feldspar::with_table_size(100000);
+        reference.replace_game(game, Vec::new());
+        let (reference_move, reference_score) = {
+            reference.start_timer(&limits);
+            let result = reference.search_best_move(&limits, &mut silent_io());
+            (result.best_move, result.score)
+        };
+
+        let mut full_strength = Feldspar::with_table_size(100000);
+        full_strength.skill = Skill::seeded(20, 1);
+        full_strength.replace_game(game, Vec::new());
+        let full_strength_result = full_strength.search_position(&limits, &mut silent_io());
+        assert_eq!((full_strength_result.best_move, full_strength_result.score), (reference_move, reference_score));
+
+        let mut weakened = Feldspar::with_table_size(100000);
+        weakened.skill = Skill::seeded(5, 1);
+        weakened.replace_game(game, Vec::new());
+
+        let legal_moves = next_moves_standalone(&game);
+        let mut chosen_moves: Vec<Move> = Vec::new();
+
+        for _ in 0 .. 50 {
+            let m = weakened.choose_move(&limits, &mut silent_io());
+            assert!(legal_moves.iter().any(|lm| *lm == m));
+            if !chosen_moves.contains(&m) {
+                chosen_moves.push(m);
+            }
+        }
+
+        assert!(chosen_moves.len() > 1);
+    }
+
+    #[test]
+    fn lazy_smp() {
+        init_zobrist_hashing();
+
+        // the knight fork wins the rook, and the back rank mate
+        let positions = [
+            ("r3k3/8/8/1N6/8/8/PP6/4K3 w - - 0 1", "b5c7", Score::new(250)),
+            ("6k1/5ppp/8/8/8/8/8/R3R1K1 w - - 0 1", "e1e8", Score::max())
+        ];
+
+        let mut limits = SearchLimits::new();
+        limits.movetime = Some(100);
+
+        // over and over, so that threads racing for the table get every chance to go wrong
+        for _ in 0 .. 5 {
+            for &(fen, expected, winning) in positions.iter() {
+                let game = Game::from_fen_str(fen).unwrap();
+
+                let mut single = Feldspar::with_table_size(100000);
+                single.replace_game(game, Vec::new());
+                let single_result = single.search_position(&limits, &mut silent_io());
+                let (single_move, single_score) = (single_result.best_move, single_result.score);
+
+                let mut smp = Feldspar::with_table_size(100000);
+                smp.set_option("Threads", "2");
+                smp.replace_game(game, Vec::new());
+                let output = SharedBuffer::new();
+                let smp_result = smp.search_position(&limits, &mut UciIo::new(Box::new(io::empty()), Box::new(output.clone())));
+                let (smp_move, smp_score) = (smp_result.best_move, smp_result.score);
+
+                // whichever thread's answer is kept, the last info line sent is the one it describes
+                let last_info = output.contents().lines().filter(|l| l.contains(" pv ")).last().unwrap().to_string();
+                assert!(last_info.starts_with(&format!("info depth {} ", smp_result.depth)), "{}", last_info);
+                assert!(last_info.contains(&format!(" pv {}", expected)), "{}", last_info);
+
+                assert_eq!(single_move.to_uci_str(), expected);
+                assert_eq!(smp_move.to_uci_str(), expected);
+                assert!(single_score >= winning && smp_score >= winning, "{} {:?} {:?}", fen, single_score, smp_score);
+            }
+        }
+    }
+
+    #[test]
+    fn search_panic() {
+        init_zobrist_hashing();
+
+        let input = "position startpos moves e2e4\ngo depth 6\nisready\ngo depth 3\nquit\n";
+        let output = SharedBuffer::new();
+        let mut io = UciIo::new(Box::new(io::Cursor::new(input.to_string().into_bytes())), Box::new(output.clone()));
+
+        let mut engine = Feldspar::with_table_size(1000);
+        engine.set_option("Threads", "2");
+        engine.context.panic_at_node = Some(500);
+        engine.run_with(&mut io);
+
+        // a legal move in spite of the panic, and the engine carries on with a fresh search
+        let output = output.contents();
+        let bestmoves: Vec<&str> = output.lines().filter(|l| l.starts_with("bestmove ")).collect();
+        assert_eq!(bestmoves.len(), 2, "{}", output);
+        assert!(output.contains("readyok"));
+
+        let mut game = Game::starting_position();
+        game.apply_moves(&["e2e4"]).unwrap();
+        for line in bestmoves {
+            assert!(move_from_algebraic(&game, line[9 ..].to_string()).is_some(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn configured_defaults() {
+        init_zobrist_hashing();
+
+        let game = Game::starting_position();
+
+        let mut configured = Feldspar::with_table_size(100000);
+        configured.set_option("Depth", "3");
+        assert_eq!(configured.default_depth, Some(3));
+
+        configured.replace_game(game, Vec::new());
+        let configured_move = configured.choose_move(&SearchLimits::new(), &mut silent_io());
+
+        let mut limits = SearchLimits::new();
+        limits.depth = Some(3);
+
+        let mut reference = Feldspar::with_table_size(100000);
+        reference.replace_game(game, Vec::new());
+        let reference_move = reference.choose_move(&limits, &mut silent_io());
+
+        assert_eq!(configured_move, reference_move);
+        assert_eq!(configured.context.tree.nodes, reference.context.tree.nodes);
+
+        // an explicit depth from "go" still wins
+        limits.depth = Some(1);
+        configured.reset();
+        configured.choose_move(&limits, &mut silent_io());
+        assert!(configured.context.tree.nodes < reference.context.tree.nodes);
+
+        configured.set_option("Hash", "1");
+        configured.set_option("Threads", "4");
+        configured.reset();
+        assert_eq!(configured.default_depth, Some(3));
+        assert_eq!(configured.threads, 4);
+        assert_eq!(configured.hash_mb(), 1);
+        assert_eq!(configured.context.tree.nodes, 0);
+    }
+
+    #[test]
+    fn show_wdl() {
+        init_zobrist_hashing();
+
+        // white is a rook up
+        let game = Game::from_fen_str("4k3/pppppppp/8/8/8/8/PPPPPPPP/R3K3 w - - 0 1").unwrap();
+        let mut limits = SearchLimits::new();
+        limits.depth = Some(3);
+
+        let score_lines = |show_wdl: &str| -> Vec<String> {
+            let output = SharedBuffer::new();
+            let mut io = UciIo::new(Box::new(io::empty()), Box::new(output.clone()));
+
+            let mut engine = Feldspar::with_table_size(100000);
+            engine.set_option("UCI_ShowWDL", show_wdl);
+            engine.replace_game(game, Vec::new());
+            engine.choose_move(&limits, &mut io);
+
+            output.contents().lines().filter(|l| l.contains(" score ")).map(|l| l.to_string()).collect()
+        };
+
+        assert!(score_lines("false").iter().all(|l| !l.contains(" wdl ")));
+
+        let lines = score_lines("true");
+        assert_eq!(lines.len(), 3);
+
+        for line in lines.iter() {
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            let cp_index = tokens.iter().position(|t| *t == "cp").unwrap();
+            assert_eq!(tokens[cp_index + 2], "wdl", "{}", line);
+
+            let cp: i16 = tokens[cp_index + 1].parse().unwrap();
+            let wdl: Vec<u16> = tokens[cp_index + 3 .. cp_index + 6].iter().map(|t| t.parse::<u16>().unwrap()).collect();
+            let expected = win_draw_loss(Score::new(cp), Phase::recompute(game.material_key), &eval_params());
+            assert_eq!((wdl[0], wdl[1], wdl[2]), expected);
+            assert!(wdl[0] > 900, "{}", line);
+        }
+    }
+
+    #[test]
+    fn currmove() {
+        init_zobrist_hashing();
+
+        let kiwipete = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        let game = Game::from_fen_str(kiwipete).unwrap();
+        let legal_moves = next_moves_standalone(&game);
+
+        let mut limits = SearchLimits::new();
+        limits.depth = Some(3);
+
+        let search_output = |delay_ms: u32| -> String {
+            let output = SharedBuffer::new();
+            let mut io = UciIo::new(Box::new(io::empty()), Box::new(output.clone()));
+
+            let mut engine = Feldspar::with_table_size(100000);
+            engine.currmove_delay_ms = delay_ms;
+            engine.replace_game(game, Vec::new());
+            engine.choose_move(&limits, &mut io);
+
+            output.contents()
+        };
+
+        // pretend the search is slow so currmove isn't throttled away
+        let output = search_output(0);
+        let mut currmove_lines = 0;
+
+        for line in output.lines().filter(|l| l.contains("currmove")) {
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            assert_eq!(tokens.len(), 7, "{}", line);
+            assert_eq!((tokens[0], tokens[1], tokens[3], tokens[5]), ("info", "depth", "currmove", "currmovenumber"));
+
+            let depth: u8 = tokens[2].parse().unwrap();
+            assert!(depth >= 1 && depth <= 3);
+            assert!(legal_moves.iter().any(|m| m.to_uci_str() == tokens[4]), "{}", line);
+
+            let move_number: usize = tokens[6].parse().unwrap();
+            assert!(move_number >= 1 && move_number <= legal_moves.len());
+
+            currmove_lines += 1;
+        }
+
+        assert!(currmove_lines >= legal_moves.len());
+
+        // a quick search stays quiet
+        assert!(!search_output(CURRMOVE_DELAY_MS * 60).contains("currmove"));
+    }
+
+    #[test]
+    fn no_legal_moves() {
+        let mut engine = Feldspar::with_table_size(100000);
+        let mut limits = SearchLimits::new();
+        limits.depth = Some(3);
+
+        engine.update_position(&mut "fen rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3".split_whitespace(), &mut silent_io());
+        assert_eq!(terminal_position_response(&engine.current_game()), Some(vec![
+            "info depth 0 score mate 0".to_string(),
+            "info string no legal moves, position is checkmate".to_string(),
+            "bestmove (none)".to_string()
+        ]));
+        assert!(engine.choose_move(&limits, &mut silent_io()).is_null());
+
+        engine.update_position(&mut "fen 7k/5Q2/6K1/8/8/8/8/8 b - - 0 1".split_whitespace(), &mut silent_io());
+        assert_eq!(terminal_position_response(&engine.current_game()), Some(vec![
+            "info depth 0 score cp 0".to_string(),
+            "info string no legal moves, position is stalemate".to_string(),
+            "bestmove (none)".to_string()
+        ]));
+        assert!(engine.choose_move(&limits, &mut silent_io()).is_null());
+
+        // already drawn by the fifty-move rule, but there are moves to play
+        let fifty_moves = "7k/8/8/8/8/8/R7/K7 w - - 100 80";
+        engine.update_position(&mut format!("fen {}", fifty_moves).split_whitespace(), &mut silent_io());
+        assert_eq!(terminal_position_response(&engine.current_game()), None);
+
+        let (best_move, score) = {
+            engine.start_timer(&limits);
+            let result = engine.search_best_move(&limits, &mut silent_io());
+            (result.best_move, result.score)
+        };
+
+        let game = Game::from_fen_str(fifty_moves).unwrap();
+        assert!(next_moves_standalone(&game).iter().any(|m| *m == best_move));
+        assert_eq!(score, Score::new(0));
+    }
+
+    #[test]
+    fn strict_fide_option() {
+        init_zobrist_hashing();
+
+        // a rook up with 110 halfmoves gone, past the fifty move rule but not the seventy five
+        let game = Game::from_fen_str("4k3/8/8/8/8/8/8/R3K3 w - - 110 80").unwrap();
+        assert_eq!(game.outcome, Some(GameResult::Draw));
+
+        let mut engine = Feldspar::with_table_size(1000);
+        engine.replace_game(game, vec![game.hash]);
+        assert_eq!(engine.current_game().outcome, Some(GameResult::Draw));
+
+        // set after the position too
+        engine.set_option("StrictFIDE", "true");
+        assert!(engine.current_game().strict_fide);
+        assert_eq!(engine.current_game().outcome, None);
+
+        engine.replace_game(game, vec![game.hash]);
+        assert_eq!(engine.current_game().outcome, None);
+
+        // off again, a game that asked for the rule itself keeps it
+        engine.set_option("StrictFIDE", "false");
+        let mut strict = game;
+        strict.strict_fide = true;
+        strict.update_derived_fields();
+        engine.replace_game(strict, vec![strict.hash]);
+        assert_eq!(engine.current_game().outcome, None);
+    }
+
+    #[test]
+    fn easy_moves() {
+        init_zobrist_hashing();
+
+        // what the search allowed itself in the end, with the result, rather than the time it
+        // took, which depends on the machine
+        let clocked_search = |fen: &str, depth: Option<u8>| -> (i64, SearchResult) {
+            let mut limits = SearchLimits::new();
+            limits.wtime = 600000;
+            limits.btime = 600000;
+            limits.depth = depth;
+
+            let mut engine = Feldspar::with_table_size(100000);
+            engine.replace_game(Game::from_fen_str(fen).unwrap(), Vec::new());
+            let result = engine.search_position(&limits, &mut silent_io());
+            (engine.context.timer.duration_ms(), result)
+        };
+
+        // the king has to take the queen, and there's nothing to search
+        let (_, result) = clocked_search("7k/8/8/8/8/8/6q1/7K w - - 0 1", None);
+        assert_eq!(result.best_move.to_uci_str(), "h1g2");
+        assert_eq!((result.depth, result.nodes), (0, 0));
+
+        // winning the queen is far better than anything else, so the 15 seconds a ten minute clock
+        // gives nominally are cut once the easy move is seen. The depth limit ends the search
+        // well before either budget runs out.
+        let (budget_ms, result) = clocked_search("4k3/8/8/8/3q4/8/8/3RK3 w - - 0 1", Some(6));
+        assert_eq!(result.best_move.to_uci_str(), "d1d4");
+        assert_eq!(result.depth, 6);
+        assert!(budget_ms <= 15000 / 2, "{}", budget_ms);
+
+        // and without the easy move the budget would have stayed
+        let (budget_ms, _) = clocked_search("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", Some(2));
+        assert_eq!(budget_ms, 15000);
+    }
 }
@@ -4,32 +4,88 @@ use core::*;
 use moves::*;
 use tables::*;
 use eval::*;
+use material::*;
+use move_list::*;
 use movegen::*;
+use pins::*;
 use zobrist::*;
 
-use std::str::SplitWhitespace;
+use std::cmp::{max, min};
+use std::fmt;
+use std::iter::Peekable;
 use rand::{thread_rng, Rng};
 
+pub const FIFTY_MOVE_HALFMOVES: u16 = 100;
+// under strict FIDE rules the fifty move draw must be claimed, only at seventy five moves is it automatic
+pub const SEVENTY_FIVE_MOVE_HALFMOVES: u16 = 150;
+
+
 #[derive(Debug,PartialEq,Clone, Copy)]
 pub enum GameResult {
     Win(Color),
     Draw
 }
 
-#[derive(PartialEq, Clone, Copy)]
+#[derive(Clone, Copy)]
 pub struct Game {
     pub board: Board,
     pub to_move: Color,
     pub ep_square: Option<Square>,
     pub castling_rights: CastlingRights,
-    pub halfmove_clock: u8,
-    pub fullmoves: u16,
+    // rook start square for each right, indexed by CastlingRights::index
+    pub castling_rooks: [Option<Square>; 4],
+    pub chess960: bool,
+    // nobody claims the fifty move draw, the game goes on until the seventy five move rule
+    pub strict_fide: bool,
+    pub halfmove_clock: u16,
+    pub fullmoves: u32,
     pub king_attackers: Bitboard,
     pub outcome: Option<GameResult>,
     pub hash: Hash,
+    // only the pawns, for the pawn structure cache in eval.rs
+    pub pawn_hash: Hash,
+    // piece counts, for the material table in material.rs
+    pub material_key: MaterialKey,
     // pub score: Score
 }
 
+// Where Game::apply_moves stopped: the move at index, counting from zero, as it was given,
+// and the position it couldn't be played in.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ApplyError {
+    pub index: usize,
+    pub input: String,
+    pub fen_before: String,
+    pub legal_moves: Vec<String>
+}
+
+impl fmt::Display for ApplyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "illegal move {} (move {} of the list) in {}", self.input, self.index + 1, self.fen_before)?;
+
+        if self.legal_moves.is_empty() {
+            write!(f, ", which has no legal moves")
+        } else {
+            write!(f, ", legal moves are {}", self.legal_moves.join(" "))
+        }
+    }
+}
+
+// Two games are equal when they are at the same position: the same pieces, side to
+// move, castling rights and en passant square. The clocks, rule flags and everything
+// derived from the position (hashes, checkers, outcome) are left out.
+impl PartialEq for Game {
+    fn eq(&self, other: &Game) -> bool {
+        self.board == other.board
+            && self.to_move == other.to_move
+            && self.castling_rights == other.castling_rights
+            && self.castling_rooks == other.castling_rooks
+            && self.ep_square == other.ep_square
+    }
+}
+
+impl Eq for Game {}
+
 impl Game {
     #[allow(dead_code)]
     pub fn starting_position() -> Game {
@@ -37,23 +93,400 @@ impl Game {
     }
 
     pub fn empty_position() -> Game {
-        Game {
+        let mut game = Game {
             board: Board::empty_position(),
             to_move: Color::White,
             ep_square: None,
             castling_rights: CastlingRights::empty(),
+            castling_rooks: [Some(Square::new(CASTLES[0].rook_from)), Some(Square::new(CASTLES[1].rook_from)),
+                             Some(Square::new(CASTLES[2].rook_from)), Some(Square::new(CASTLES[3].rook_from))],
+            chess960: false,
+            strict_fide: false,
             halfmove_clock: 0,
             fullmoves: 1,
             king_attackers: Bitboard::none_set(),
             outcome: None,
-            hash: Hash::empty()
-        }
+            hash: Hash::empty(),
+            pawn_hash: Hash::empty(),
+            material_key: MaterialKey::bare_kings()
+        };
+
+        game.hash = Hash::new(&game);
+        return game;
     }
 
     pub fn in_check(&self) -> bool {
         self.king_attackers.population() > 0
     }
 
+    // the pieces giving check to the side to move
+    pub fn checkers(&self) -> Bitboard {
+        self.king_attackers
+    }
+
+    // color's pieces that can't leave the line between their king and an enemy slider
+    pub fn pinned(&self, color: Color) -> Bitboard {
+        let mut pin_finder = PinFinder::new();
+        pin_finder.update(color, &self.board);
+        return pin_finder.pinned();
+    }
+
+    // Single source of truth for terminal positions. Repetitions can't be seen
+    // from a lone Game, see outcome_with_history for those.
+    pub fn outcome(&self) -> Option<GameResult> {
+        match self.outcome {
+            Some(result) => Some(result),
+            None => self.compute_outcome()
+        }
+    }
+
+    // history holds the hashes of all earlier positions in the game
+    pub fn outcome_with_history(&self, history: &[Hash]) -> Option<GameResult> {
+        match self.outcome() {
+            Some(GameResult::Win(color)) => Some(GameResult::Win(color)),
+            _ if self.repetition_count(history) >= 3 => Some(GameResult::Draw),
+            result => result
+        }
+    }
+
+    // How many times this position has been reached, this time included, for claiming a draw
+    // by threefold repetition. history is the same as for outcome_with_history, and only the
+    // part of it since the last capture or pawn move can hold the same position again.
+    pub fn repetition_count(&self, history: &[Hash]) -> u8 {
+        let reversible = min(self.halfmove_clock as usize, history.len());
+        let count = 1 + count_occurrences(&history[history.len() - reversible ..], self.hash);
+        return min(count, u8::max_value() as usize) as u8;
+    }
+
+    fn compute_outcome(&self) -> Option<GameResult> {
+        if !can_move(self) {
+            if self.in_check() {
+                return Some(GameResult::Win(!self.to_move));
+            } else {
+                return Some(GameResult::Draw);
+            }
+        }
+
+        let draw_halfmoves = if self.strict_fide { SEVENTY_FIVE_MOVE_HALFMOVES } else { FIFTY_MOVE_HALFMOVES };
+
+        if self.halfmove_clock >= draw_halfmoves || self.insufficient_material() {
+            return Some(GameResult::Draw);
+        }
+
+        return None;
+    }
+
+    pub fn insufficient_material(&self) -> bool {
+        use PieceType::*;
+        use Color::*;
+
+        let pieces = |ptype: PieceType| {
+            self.board.get_pieces(White, ptype) | self.board.get_pieces(Black, ptype)
+        };
+
+        if (pieces(Pawn) | pieces(Rook) | pieces(Queen)).nonempty() {
+            return false;
+        }
+
+        let knights = pieces(Knight);
+        let bishops = pieces(Bishop);
+
+        if knights.population() + bishops.population() <= 1 {
+            return true;
+        }
+
+        // any number of bishops, all on the same square color
+        return knights.empty() && ((bishops & LIGHT_SQUARES).empty() || (bishops & DARK_SQUARES).empty());
+    }
+
+    pub fn legal_moves(&self) -> MoveList {
+        next_moves_standalone(self)
+    }
+
+    // the legal moves of the piece on sq, none if it isn't one of the side to move's
+    pub fn legal_moves_from(&self, sq: Square) -> MoveList {
+        let mut moves = MoveList::new();
+        generate_moves_from(self, &mut moves, false, sq.bitrep());
+        return moves;
+    }
+
+    // The legal moves from one square to another, with all their flags, for a GUI that has a
+    // click on each. Only a promotion has more than one, for the four pieces it can become.
+    // Castling goes by where the king ends up, as it does in UCI for standard chess.
+    pub fn legal_move_between(&self, from: Square, to: Square) -> Option<Vec<Move>> {
+        let moves: Vec<Move> = self.legal_moves_from(from).iter().filter(|m| m.to() == to).cloned().collect();
+
+        if moves.is_empty() {
+            return None;
+        }
+
+        return Some(moves);
+    }
+
+    // Checks an arbitrary move encoding (e.g. from the transposition table)
+    // against the position without generating moves.
+    pub fn is_legal(&self, m: Move) -> bool {
+        use PieceType::*;
+        use Color::*;
+
+        let bits = m.unwrap();
+
+        // unused high bits, unknown piece types and the two unused flags
+        if m.is_null() || bits >> 22 != 0 {
+            return false;
+        }
+
+        let moved_bits = (bits >> 16) & 0x7;
+        let captured_bits = (bits >> 19) & 0x7;
+
+        let flag = match MoveFlag::from_bits((bits >> 12) & 0xf) {
+            Some(flag) => flag,
+            None => return false
+        };
+
+        if moved_bits == 0 || moved_bits > 6 || captured_bits > 6 {
+            return false;
+        }
+
+        let moving_color = self.to_move;
+        let opponent_color = !moving_color;
+        let from_sq = m.from();
+        let to_sq = m.to();
+        let moved_ptype = m.moved_piece();
+        let occupied = self.board.occupied();
+
+        match self.board.piece_at(from_sq) {
+            Some(p) if p.color == moving_color && p.ptype == moved_ptype => {},
+            _ => return false
+        }
+
+        // in Chess960 the king may stay put or land on its own rook, so castles are checked separately
+        match flag {
+            MoveFlag::KingCastle | MoveFlag::QueenCastle => {
+                let right = CastlingRights::for_side(moving_color, flag == MoveFlag::KingCastle);
+
+                return moved_ptype == King && captured_bits == 0
+                    && self.can_castle(right, self.board.attacked(opponent_color, true))
+                    && self.castle_squares(right).map(|(king_from, king_to, _, _)| (king_from, king_to)) == Some((from_sq, to_sq));
+            },
+            _ => {}
+        }
+
+        // what the encoding claims to capture must be what is actually there
+        let target = self.board.piece_at(to_sq);
+
+        if flag == MoveFlag::EpCapture {
+            if target.is_some() || m.captured_piece() != Some(Pawn) {
+                return false;
+            }
+        } else if m.is_capture() {
+            match target {
+                Some(p) if p.color == opponent_color && p.ptype != King
+                        && Some(p.ptype) == m.captured_piece() => {},
+                _ => return false
+            }
+        } else if target.is_some() || captured_bits != 0 {
+            return false;
+        }
+
+        let last_rank = match moving_color { White => 8, Black => 1 };
+
+        if m.is_promotion() && (moved_ptype != Pawn || to_sq.rank() != last_rank) {
+            return false;
+        }
+
+        if flag == MoveFlag::DoublePawnPush && moved_ptype != Pawn {
+            return false;
+        }
+
+        let to_bit = to_sq.bitrep();
+        let mut captured_sq = to_sq;
+
+        match moved_ptype {
+            Pawn => {
+                if to_sq.rank() == last_rank && !m.is_promotion() {
+                    return false;
+                }
+
+                let (push_sq, double_push_sq, start_rank) = match moving_color {
+                    White => (from_sq.unwrap() + 8, from_sq.unwrap() + 16, 2),
+                    Black => (from_sq.unwrap().wrapping_sub(8), from_sq.unwrap().wrapping_sub(16), 7)
+                };
+
+                if m.is_capture() {
+                    if (PAWN_ATTACKS[moving_color as usize][from_sq.idx()] & to_bit).empty() {
+                        return false;
+                    }
+
+                    if flag == MoveFlag::EpCapture {
+                        if self.ep_square != Some(to_sq) {
+                            return false;
+                        }
+
+                        captured_sq = match moving_color {
+                            White => Square::new(to_sq.unwrap() - 8),
+                            Black => Square::new(to_sq.unwrap() + 8)
+                        };
+
+                        if self.board.piece_at(captured_sq) != Some(Piece::new(opponent_color, Pawn)) {
+                            return false;
+                        }
+                    }
+                } else if flag == MoveFlag::DoublePawnPush {
+                    if from_sq.rank() != start_rank || to_sq.unwrap() != double_push_sq
+                        || (Square::new(push_sq).bitrep() & occupied).nonempty() {
+                        return false;
+                    }
+                } else if to_sq.unwrap() != push_sq {
+                    return false;
+                }
+            },
+
+            King   => if (KING_TABLE[from_sq.idx()] & to_bit).empty() { return false; },
+            Knight => if (KNIGHT_TABLE[from_sq.idx()] & to_bit).empty() { return false; },
+            Bishop => if (get_bishop_rays(from_sq, occupied) & to_bit).empty() { return false; },
+            Rook   => if (get_rook_rays(from_sq, occupied) & to_bit).empty() { return false; },
+            Queen  => if (get_queen_rays(from_sq, occupied) & to_bit).empty() { return false; }
+        }
+
+        // only pawns make the other kinds of move
+        match (moved_ptype, flag) {
+            (Pawn, _) | (_, MoveFlag::Quiet) | (_, MoveFlag::Capture) => {},
+            _ => return false
+        }
+
+        // finally, the king can't be left in check
+        let mut board_copy = self.board.clone();
+
+        if m.is_capture() {
+            let captured_ptype = m.captured_piece().unwrap();
+            *board_copy.get_pieces_mut(opponent_color, captured_ptype) &= !captured_sq.bitrep();
+            board_copy.clear_occupied(opponent_color, captured_sq.bitrep());
+        }
+
+        let from_to_bit = from_sq.bitrep() | to_bit;
+        *board_copy.get_pieces_mut(moving_color, moved_ptype) ^= from_to_bit;
+        board_copy.toggle_occupied(moving_color, from_to_bit);
+
+        if moved_ptype == King {
+            board_copy.set_king_square(moving_color, to_sq);
+        }
+
+        let king_square = board_copy.get_king_square(moving_color);
+        return board_copy.attackers(king_square, opponent_color).empty();
+    }
+
+    // An en passant square only counts when a pawn can legally capture there, pins included.
+    // Otherwise identical positions would get different FENs and hashes, and repetitions
+    // would go unnoticed.
+    fn legal_ep_capture_exists(&self, ep_square: Square) -> bool {
+        use PieceType::*;
+
+        let mut with_ep = *self;
+        with_ep.ep_square = Some(ep_square);
+
+        let capturers = PAWN_ATTACKS[!self.to_move as usize][ep_square.idx()] & self.board.get_pieces(self.to_move, Pawn);
+
+        for from in capturers {
+            if with_ep.is_legal(Move::new_ep(from, ep_square)) {
+                return true;
+            }
+        }
+
+        return false;
+    }
+
+    // (king from, king to, rook from, rook to) for the given right
+    pub fn castle_squares(&self, right: CastlingRights) -> Option<(Square, Square, Square, Square)> {
+        let rook_from = match self.castling_rooks[right.index()] {
+            Some(sq) => sq,
+            None => return None
+        };
+
+        let castle = &CASTLES[right.index()];
+        let king_from = self.board.get_king_square(right.color());
+
+        return Some((king_from, Square::new(castle.king_to), rook_from, Square::new(castle.rook_to)));
+    }
+
+    // king_danger_squares must be computed with the castling king removed from the board
+    pub fn can_castle(&self, right: CastlingRights, king_danger_squares: Bitboard) -> bool {
+        use PieceType::*;
+
+        if !self.castling_rights.intersects(right) {
+            return false;
+        }
+
+        let (king_from, king_to, rook_from, rook_to) = match self.castle_squares(right) {
+            Some(squares) => squares,
+            None => return false
+        };
+
+        let color = right.color();
+
+        if self.board.piece_at(rook_from) != Some(Piece::new(color, Rook)) {
+            return false;
+        }
+
+        let castlers = king_from.bitrep() | rook_from.bitrep();
+        let others = self.board.occupied() & !castlers;
+        let king_path = rank_span(king_from, king_to);
+
+        if ((king_path | rank_span(rook_from, rook_to)) & others).nonempty() {
+            return false;
+        }
+
+        if (king_path & king_danger_squares).nonempty() {
+            return false;
+        }
+
+        // the castling rook may have been shielding the king's destination along the back rank
+        let enemy_rooks_queens = self.board.get_pieces(!color, Rook) | self.board.get_pieces(!color, Queen);
+        return (get_rook_rays(king_to, others) & enemy_rooks_queens).empty();
+    }
+
+    fn remove_castling_rights(&mut self, rights: CastlingRights) {
+        if self.castling_rights.intersects(rights) {
+            self.hash.update_castling_rights(self.castling_rights);
+            self.castling_rights.remove(rights);
+            self.hash.update_castling_rights(self.castling_rights);
+        }
+    }
+
+    // the rook furthest from the king on the given side of the back rank
+    fn outermost_rook(&self, right: CastlingRights) -> Option<Square> {
+        let color = right.color();
+        let back_rank = if color == Color::White { 0 } else { 56 };
+        let king_square = self.board.get_king_square(color).unwrap();
+        let rooks = self.board.get_pieces(color, PieceType::Rook);
+
+        for i in 0 .. 8 {
+            let idx = if right.is_kingside() { back_rank + i } else { back_rank + 7 - i };
+            if idx == king_square {
+                break;
+            }
+
+            if (rooks & Square::new(idx).bitrep()).nonempty() {
+                return Some(Square::new(idx));
+            }
+        }
+
+        return None;
+    }
+
+    // rights that are lost once the piece on sq moves or is captured
+    fn castling_rights_at(&self, sq: Square) -> CastlingRights {
+        let mut rights = CastlingRights::empty();
+
+        for i in 0 .. 4 {
+            if self.castling_rooks[i] == Some(sq) {
+                rights |= CastlingRights::from_bits(1 << i).unwrap();
+            }
+        }
+
+        return rights;
+    }
+
     pub fn to_fen(&self) -> String {
         use PieceType::*;
         use Color::*;
@@ -78,22 +511,7 @@ impl Game {
             }
 
             match maybe_piece {
-                Some(piece) => {
-                    match (piece.color, piece.ptype) {
-                        (Black , Pawn  ) => board_str.push('p'),
-                        (Black , Knight) => board_str.push('n'),
-                        (Black , Bishop) => board_str.push('b'),
-                        (Black , Rook  ) => board_str.push('r'),
-                        (Black , Queen ) => board_str.push('q'),
-                        (Black , King  ) => board_str.push('k'),
-                        (White , Pawn  ) => board_str.push('P'),
-                        (White , Knight) => board_str.push('N'),
-                        (White , Bishop) => board_str.push('B'),
-                        (White , Rook  ) => board_str.push('R'),
-                        (White , Queen ) => board_str.push('Q'),
-                        (White , King  ) => board_str.push('K'),
-                    }
-                }
+                Some(piece) => board_str.push(piece.to_char()),
                 None => empty_tally += 1
             }
         }
@@ -112,17 +530,23 @@ impl Game {
         if self.castling_rights == CastlingRights::empty() {
             castling_str = "-".to_string();
         } else {
-            if self.castling_rights.intersects(CastlingRights::WHITE_KINGSIDE) {
-                castling_str.push('K');
-            }
-            if self.castling_rights.intersects(CastlingRights::WHITE_QUEENSIDE) {
-                castling_str.push('Q');
-            }
-            if self.castling_rights.intersects(CastlingRights::BLACK_KINGSIDE) {
-                castling_str.push('k');
-            }
-            if self.castling_rights.intersects(CastlingRights::BLACK_QUEENSIDE) {
-                castling_str.push('q');
+            // X-FEN: KQkq when the castling rook is the outermost one, otherwise its file
+            let symbols = [(CastlingRights::WHITE_KINGSIDE, 'K'), (CastlingRights::WHITE_QUEENSIDE, 'Q'),
+                           (CastlingRights::BLACK_KINGSIDE, 'k'), (CastlingRights::BLACK_QUEENSIDE, 'q')];
+
+            for &(right, symbol) in symbols.iter() {
+                if !self.castling_rights.intersects(right) {
+                    continue;
+                }
+
+                let rook_square = self.castling_rooks[right.index()];
+                let outermost = self.outermost_rook(right);
+                if rook_square.is_none() || outermost.is_none() || rook_square == outermost {
+                    castling_str.push(symbol);
+                } else {
+                    let file = (b'a' + rook_square.unwrap().file() as u8 - 1) as char;
+                    castling_str.push(if right.color() == White { file.to_ascii_uppercase() } else { file });
+                }
             }
         }
 
@@ -141,11 +565,13 @@ impl Game {
     }
 
     pub fn from_fen_str<'a>(fen: &'a str) -> Option<Game> {
-        let mut fen_split = fen.split_whitespace();
-        Game::from_fen(&mut fen_split)
+        Game::from_fen(&mut fen.split_whitespace().peekable())
     }
 
-    pub fn from_fen<'a>(args: &mut SplitWhitespace<'a>) -> Option<Game> {
+    // The fields after the side to move may be left out, as some tools do with the clocks.
+    // Left out they default to no castling rights, no en passant square and "0 1", and the
+    // next token may then be the "moves" of a UCI position command, which is left in place.
+    pub fn from_fen<'a, I: Iterator<Item = &'a str>>(args: &mut Peekable<I>) -> Option<Game> {
         let mut game = Game::empty_position();
 
         use PieceType::*;
@@ -162,77 +588,272 @@ impl Game {
                 }
             };
 
-            let mut add_piece = |piece_color: Color, piece_type: PieceType, sq: &mut Square| {
-                game.board.set_piece_bit(piece_color, piece_type, *sq);
-                decrement_square(sq, 1);
-            };
-
-            for ch in args.next().expect("Missing FEN string").chars() {
+            for ch in args.next()?.chars() {
                 match ch {
-                    'p' => add_piece(Black , Pawn   , &mut current_square) ,
-                    'n' => add_piece(Black , Knight , &mut current_square) ,
-                    'b' => add_piece(Black , Bishop , &mut current_square) ,
-                    'r' => add_piece(Black , Rook   , &mut current_square) ,
-                    'q' => add_piece(Black , Queen  , &mut current_square) ,
-                    'k' => add_piece(Black , King   , &mut current_square) ,
-                    'P' => add_piece(White , Pawn   , &mut current_square) ,
-                    'N' => add_piece(White , Knight , &mut current_square) ,
-                    'B' => add_piece(White , Bishop , &mut current_square) ,
-                    'R' => add_piece(White , Rook   , &mut current_square) ,
-                    'Q' => add_piece(White , Queen  , &mut current_square) ,
-                    'K' => add_piece(White , King   , &mut current_square) ,
-                    '1' => decrement_square(&mut current_square, 1),
-                    '2' => decrement_square(&mut current_square, 2),
-                    '3' => decrement_square(&mut current_square, 3),
-                    '4' => decrement_square(&mut current_square, 4),
-                    '5' => decrement_square(&mut current_square, 5),
-                    '6' => decrement_square(&mut current_square, 6),
-                    '7' => decrement_square(&mut current_square, 7),
-                    '8' => decrement_square(&mut current_square, 8),
+                    '1' ..= '8' => decrement_square(&mut current_square, ch.to_digit(10).unwrap()),
                     '/' => {},
-                    _ => return None
+                    _ => match Piece::from_char(ch) {
+                        Some(piece) => {
+                            game.board.put(piece.color, piece.ptype, current_square);
+                            decrement_square(&mut current_square, 1);
+                        },
+                        None => return None
+                    }
                 }
             }
         }
 
-        match args.next().expect("Missing color-to-move in FEN string") {
+        // the rest of the engine takes there to be exactly one king of each color
+        for &color in [White, Black].iter() {
+            if game.board.get_pieces(color, King).population() != 1 {
+                return None;
+            }
+        }
+
+        match args.next()? {
             "w" => game.to_move = White,
             "b" => game.to_move = Black,
             _ => return None
         }
 
-        for ch in args.next().expect("Missing castling rights in FEN string").chars() {
+        let mut optional_field = || -> Option<&'a str> {
+            match args.peek() {
+                None | Some(&"moves") => None,
+                Some(_) => args.next()
+            }
+        };
+
+        if let Some(castling) = optional_field() {
+            game.add_castling_rights(castling)?;
+        }
+
+        if let Some(ep) = optional_field() {
+            game.ep_square = Square::from_algebraic(ep);
+        }
+
+        if let Some(halfmoves) = optional_field() {
+            game.halfmove_clock = halfmoves.parse::<u16>().ok()?;
+        }
+
+        // FEN numbers moves from 1, but some tools write 0 for a game that hasn't started
+        if let Some(fullmoves) = optional_field() {
+            game.fullmoves = max(1, fullmoves.parse::<u32>().ok()?);
+        }
+
+        game.update_derived_fields();
+
+        return Some(game);
+    }
+
+    // castling rights as in the castling field of a FEN or X-FEN string, with the kings
+    // and rooks already on the board
+    pub fn add_castling_rights(&mut self, castling: &str) -> Option<()> {
+        use Color::*;
+
+        for ch in castling.chars() {
             match ch {
-                'K' => game.castling_rights |= CastlingRights::WHITE_KINGSIDE,
-                'Q' => game.castling_rights |= CastlingRights::WHITE_QUEENSIDE,
-                'k' => game.castling_rights |= CastlingRights::BLACK_KINGSIDE,
-                'q' => game.castling_rights |= CastlingRights::BLACK_QUEENSIDE,
+                'K' | 'Q' | 'k' | 'q' => {
+                    let color = if ch.is_uppercase() { White } else { Black };
+                    let right = CastlingRights::for_side(color, ch.to_ascii_lowercase() == 'k');
+                    self.castling_rights |= right;
+
+                    // resolved to the outermost rook, which is only a Chess960 position
+                    // when the king or that rook is off its standard square
+                    if let Some(rook_square) = self.outermost_rook(right) {
+                        let standard = &CASTLES[right.index()];
+                        if rook_square.unwrap() != standard.rook_from
+                            || self.board.get_king_square(color).unwrap() != standard.king_from {
+                            self.chess960 = true;
+                        }
+                        self.castling_rooks[right.index()] = Some(rook_square);
+                    }
+                }
                 '-' => {},
+
+                // X-FEN: the file of the castling rook, for Chess960
+                _ if ch.to_ascii_lowercase() >= 'a' && ch.to_ascii_lowercase() <= 'h' => {
+                    let color = if ch.is_uppercase() { White } else { Black };
+                    let rank = if color == White { '1' } else { '8' };
+                    let rook_square = Square::from_algebraic(&format!("{}{}", ch.to_ascii_lowercase(), rank)).unwrap();
+                    let king_square = self.board.get_king_square(color);
+
+                    let right = CastlingRights::for_side(color, rook_square.file() > king_square.file());
+                    self.castling_rights |= right;
+                    self.castling_rooks[right.index()] = Some(rook_square);
+                    self.chess960 = true;
+                }
+
                 _ => return None
             }
         }
 
-        match Square::from_algebraic(args.next().expect("Missing en-passante square in FEN string")) {
-            None => game.ep_square = None,
-            Some(sq) => game.ep_square = Some(sq)
+        return Some(());
+    }
+
+    // everything that follows from the pieces, side to move and rights, for a game that
+    // was set up rather than played into
+    pub fn update_derived_fields(&mut self) {
+        let king_square     = self.board.get_king_square(self.to_move);
+        self.king_attackers = self.board.attackers(king_square, !self.to_move);
+
+        if let Some(sq) = self.ep_square {
+            if !self.legal_ep_capture_exists(sq) {
+                self.ep_square = None;
+            }
         }
 
-        match args.next().expect("Missing fifty move count in FEN string").parse::<u8>() {
-            Err(_) => return None,
-            Ok(x) => game.halfmove_clock = x
+        self.recompute_incremental_fields();
+        self.outcome = self.compute_outcome();
+    }
+
+    // the fields make_move updates a piece at a time rather than working out again
+    fn recompute_incremental_fields(&mut self) {
+        let king_square     = self.board.get_king_square(self.to_move);
+        self.king_attackers = self.board.attackers(king_square, !self.to_move);
+
+        self.hash = Hash::new(self);
+        self.pawn_hash = Hash::pawns(&self.board);
+        self.material_key = MaterialKey::new(&self.board);
+    }
+
+    // Whether the checks, hashes and material key still agree with the position, as diff
+    // reports it when they don't. update_derived_fields repairs them.
+    pub fn verify_derived_fields(&self) -> Result<(), String> {
+        let mut recomputed = *self;
+        recomputed.recompute_incremental_fields();
+
+        let diff = self.diff(&recomputed);
+        if diff.is_empty() {
+            return Ok(());
+        } else {
+            return Err(diff);
         }
+    }
 
-        match args.next().expect("Missing move count in FEN string").parse::<u16>() {
-            Err(_) => return None,
-            Ok(x) => game.fullmoves = x
+    // Board::put and Board::take, with the hashes and material key kept in step. The checks
+    // and outcome depend on the whole position, update_derived_fields once it's set up.
+    pub fn put(&mut self, color: Color, ptype: PieceType, square: Square) {
+        self.take(square);
+        self.board.put(color, ptype, square);
+        self.change_piece_keys(color, ptype, square);
+
+        if ptype != PieceType::King {
+            self.material_key.add(color, ptype);
         }
+    }
 
-        let king_square     = game.board.get_king_square(game.to_move);
-        game.king_attackers = game.board.attackers(king_square, !game.to_move);
+    pub fn take(&mut self, square: Square) -> Option<Piece> {
+        let piece = self.board.take(square)?;
+        self.change_piece_keys(piece.color, piece.ptype, square);
 
-        game.hash = Hash::new(&game);
+        if piece.ptype != PieceType::King {
+            self.material_key.remove(piece.color, piece.ptype);
+        }
 
-        return Some(game);
+        return Some(piece);
+    }
+
+    fn change_piece_keys(&mut self, color: Color, ptype: PieceType, square: Square) {
+        self.hash.change_piece(color, ptype, square);
+        if ptype == PieceType::Pawn {
+            self.pawn_hash.change_piece(color, ptype, square);
+        }
+    }
+
+    // How other differs from self, a line for each difference and empty if there are none.
+    // Derived fields are compared too, since a stale hash is just what make/unmake bugs leave.
+    pub fn diff(&self, other: &Game) -> String {
+        let mut lines = Vec::new();
+
+        for piece in Piece::all() {
+            let before = self.board.get_pieces(piece.color, piece.ptype);
+            let after = other.board.get_pieces(piece.color, piece.ptype);
+            let (removed, added) = (before & !after, after & !before);
+            let name = format!("{:?} {:?}", piece.color, piece.ptype).to_lowercase();
+
+            if removed.population() == 1 && added.population() == 1 {
+                lines.push(format!("{} moved {} -> {}", name,
+                                   removed.bitscan_forward().to_algebraic(), added.bitscan_forward().to_algebraic()));
+                continue;
+            }
+
+            for sq in removed {
+                lines.push(format!("{} removed from {}", name, sq.to_algebraic()));
+            }
+
+            for sq in added {
+                lines.push(format!("{} added on {}", name, sq.to_algebraic()));
+            }
+        }
+
+        let square_str = |sq: Option<Square>| sq.map(|s| s.to_algebraic()).unwrap_or("-".to_string());
+        let squares_str = |bb: Bitboard| bb.into_iter().map(|s| s.to_algebraic()).collect::<Vec<String>>().join(" ");
+        let rooks_str = |game: &Game| game.castling_rooks.iter().map(|&sq| square_str(sq)).collect::<Vec<String>>().join(" ");
+
+        let fields = [
+            ("to move", format!("{:?}", self.to_move), format!("{:?}", other.to_move)),
+            ("castling rights", format!("{:?}", self.castling_rights), format!("{:?}", other.castling_rights)),
+            ("castling rooks", rooks_str(self), rooks_str(other)),
+            ("en passant square", square_str(self.ep_square), square_str(other.ep_square)),
+            ("halfmove clock", self.halfmove_clock.to_string(), other.halfmove_clock.to_string()),
+            ("fullmoves", self.fullmoves.to_string(), other.fullmoves.to_string()),
+            ("king attackers", squares_str(self.king_attackers), squares_str(other.king_attackers)),
+            ("outcome", format!("{:?}", self.outcome), format!("{:?}", other.outcome)),
+            ("hash", format!("{:?}", self.hash), format!("{:?}", other.hash)),
+            ("pawn hash", format!("{:?}", self.pawn_hash), format!("{:?}", other.pawn_hash)),
+            ("material key", format!("{:?}", self.material_key), format!("{:?}", other.material_key))
+        ];
+
+        for &(ref name, ref before, ref after) in fields.iter() {
+            if before != after {
+                lines.push(format!("{}: {} -> {}", name, before, after));
+            }
+        }
+
+        return lines.join("\n");
+    }
+
+    // UCI moves such as "e2e4" or "e7e8q", each checked against the moves legal where it's
+    // played. Every move before the first one that isn't is applied.
+    pub fn apply_moves<I>(&mut self, moves: I) -> Result<(), ApplyError>
+        where I: IntoIterator, I::Item: AsRef<str>
+    {
+        for (index, move_str) in moves.into_iter().enumerate() {
+            self.apply_move_at(index, move_str.as_ref())?;
+        }
+
+        return Ok(());
+    }
+
+    // The positions apply_moves goes through, starting with this one, so there's one more
+    // of them than there are moves.
+    pub fn trace_moves<I>(&self, moves: I) -> Result<Vec<Game>, ApplyError>
+        where I: IntoIterator, I::Item: AsRef<str>
+    {
+        let mut game = *self;
+        let mut positions = vec![game];
+
+        for (index, move_str) in moves.into_iter().enumerate() {
+            game.apply_move_at(index, move_str.as_ref())?;
+            positions.push(game);
+        }
+
+        return Ok(positions);
+    }
+
+    fn apply_move_at(&mut self, index: usize, move_str: &str) -> Result<(), ApplyError> {
+        match move_from_algebraic(self, move_str.to_string()) {
+            Some(m) => {
+                self.make_move(m);
+                return Ok(());
+            },
+            None => return Err(ApplyError {
+                index: index,
+                input: move_str.to_string(),
+                fen_before: self.to_fen(),
+                legal_moves: next_moves_standalone(self).iter().map(|m| m.to_uci_str()).collect()
+            })
+        }
     }
 
     pub fn make_null_move(&mut self) {
@@ -241,9 +862,9 @@ impl Game {
         let moving_color   = self.to_move;
         let opponent_color = !moving_color;
 
-        self.halfmove_clock += 1;
+        self.halfmove_clock = self.halfmove_clock.saturating_add(1);
         if self.to_move == Color::Black {
-            self.fullmoves += 1;
+            self.fullmoves = self.fullmoves.saturating_add(1);
         }
 
         self.to_move = !self.to_move;
@@ -252,19 +873,10 @@ impl Game {
         let opp_king_square = self.board.get_king_square(opponent_color);
         self.king_attackers = self.board.attackers(opp_king_square, !self.to_move);
 
-        let can_move = can_move(self);
         self.ep_square = None;
+        self.outcome = self.compute_outcome();
 
-        // no moves available, game is over
-        if !can_move {
-            match self.king_attackers.population() {
-                0 => self.outcome = Some(GameResult::Draw),
-                _ => match self.to_move {
-                         Color::White => self.outcome = Some(GameResult::Win(Color::Black)),
-                         Color::Black => self.outcome = Some(GameResult::Win(Color::White))
-                     }
-            }
-        }
+        debug_assert_eq!(self.verify_derived_fields(), Ok(()));
     }
 
     pub fn make_move(&mut self, m: Move) {
@@ -275,7 +887,8 @@ impl Game {
         let from_bit       = from_sq.bitrep();
         let to_sq          = m.to();
         let to_bit         = to_sq.bitrep();
-        let from_to_bit    = from_bit | to_bit;
+        // zero when a Chess960 king castles without moving
+        let from_to_bit    = from_bit ^ to_bit;
         let is_capture     = m.is_capture();
         let is_promotion   = m.is_promotion();
         let flag           = m.flag();
@@ -285,63 +898,56 @@ impl Game {
         let moved_piece    = Piece::new(moving_color, moved_ptype);
         let captured_ptype = m.captured_piece();
 
+        // where pieces came from and went to, the only squares that can reveal a new check
+        let mut vacated    = from_bit;
+        let mut arrived    = to_bit;
+
         self.hash.change_piece(moving_color, moved_ptype, from_sq);
         self.hash.change_piece(moving_color, moved_ptype, to_sq);
 
+        if moved_ptype == Pawn {
+            self.pawn_hash.change_piece(moving_color, Pawn, from_sq);
+            if !is_promotion {
+                self.pawn_hash.change_piece(moving_color, Pawn, to_sq);
+            }
+        }
+
+        if captured_ptype == Some(Pawn) {
+            let captured_sq = match (flag == MoveFlag::EpCapture, moving_color) {
+                (true, White) => Square::new(to_sq.unwrap() - 8),
+                (true, Black) => Square::new(to_sq.unwrap() + 8),
+                (false, _)    => to_sq
+            };
+            self.pawn_hash.change_piece(opponent_color, Pawn, captured_sq);
+        }
+
+        if let Some(ptype) = captured_ptype {
+            self.material_key.remove(opponent_color, ptype);
+        }
+
+        if let Some(ptype) = m.promotion_piece() {
+            self.material_key.remove(moving_color, Pawn);
+            self.material_key.add(moving_color, ptype);
+        }
+
         *self.board.get_pieces_mut(self.to_move, moved_ptype) ^= from_to_bit;
-        *self.board.occupied_by_mut(self.to_move) ^= from_to_bit;
+        self.board.toggle_occupied(self.to_move, from_to_bit);
 
         if is_capture {
-            match to_sq.idx() {
-                0 => {
-                    self.hash.update_castling_rights(self.castling_rights);
-                    self.castling_rights.remove(CastlingRights::WHITE_KINGSIDE);
-                    self.hash.update_castling_rights(self.castling_rights);
-                }
-                7 => {
-                    self.hash.update_castling_rights(self.castling_rights);
-                    self.castling_rights.remove(CastlingRights::WHITE_QUEENSIDE);
-                    self.hash.update_castling_rights(self.castling_rights);
-                }
-                56 => {
-                    self.hash.update_castling_rights(self.castling_rights);
-                    self.castling_rights.remove(CastlingRights::BLACK_KINGSIDE);
-                    self.hash.update_castling_rights(self.castling_rights);
-                }
-                63 => {
-                    self.hash.update_castling_rights(self.castling_rights);
-                    self.castling_rights.remove(CastlingRights::BLACK_QUEENSIDE);
-                    self.hash.update_castling_rights(self.castling_rights);
-                }
-                _ => {}
-            }
-
+            let lost_rights = self.castling_rights_at(to_sq);
+            self.remove_castling_rights(lost_rights);
 
             if moved_ptype != Pawn {
                 *self.board.get_pieces_mut(opponent_color, captured_ptype.unwrap()) ^= to_bit;
-                *self.board.occupied_by_mut(opponent_color) ^= to_bit;
+                self.board.toggle_occupied(opponent_color, to_bit);
                 self.hash.change_piece(opponent_color, captured_ptype.unwrap(), to_sq);
             }
         }
 
         match moved_ptype {
             Pawn => {
-
-                if flag == DOUBLE_PAWN_PUSH_FLAG {
-                    if (self.ep_square.is_some()) {
-                        self.hash.modify_ep_square(self.ep_square.unwrap());
-                    }
-
-                    self.ep_square = match moving_color {
-                        White => Some(Square::new(to_sq.unwrap() - 8)),
-                        Black => Some(Square::new(to_sq.unwrap() + 8))
-                    };
-
-                    self.hash.modify_ep_square(self.ep_square.unwrap());
-                }
-
-                if is_capture {
-                    if flag == EP_CAPTURE_FLAG {
+                match flag {
+                    MoveFlag::EpCapture => {
                         debug_assert!(self.ep_square.is_some());
 
                         let captured_bit = match moving_color {
@@ -352,168 +958,102 @@ impl Game {
                         let captured_sq = captured_bit.bitscan_forward();
 
                         *self.board.get_pieces_mut(opponent_color, Pawn) ^= captured_bit;
-                        *self.board.occupied_by_mut(opponent_color) ^= captured_bit;
+                        self.board.toggle_occupied(opponent_color, captured_bit);
+                        vacated |= captured_bit;
                         self.hash.change_piece(opponent_color, captured_ptype.unwrap(), captured_sq);
-                    } else {
+                    },
+
+                    MoveFlag::Capture | MoveFlag::KnightPromoCapture | MoveFlag::BishopPromoCapture
+                        | MoveFlag::RookPromoCapture | MoveFlag::QueenPromoCapture => {
                         *self.board.get_pieces_mut(opponent_color, captured_ptype.unwrap()) ^= to_bit;
-                        *self.board.occupied_by_mut(opponent_color) ^= to_bit;
+                        self.board.toggle_occupied(opponent_color, to_bit);
                         self.hash.change_piece(opponent_color, captured_ptype.unwrap(), to_sq);
-                    }
+                    },
+
+                    MoveFlag::Quiet | MoveFlag::DoublePawnPush | MoveFlag::KnightPromo | MoveFlag::BishopPromo
+                        | MoveFlag::RookPromo | MoveFlag::QueenPromo => {},
+
+                    MoveFlag::KingCastle | MoveFlag::QueenCastle => unreachable!()
                 }
 
-                if is_promotion {
+                if let Some(promoted) = m.promotion_piece() {
                     *self.board.get_pieces_mut(moving_color, Pawn) &= !to_bit;
                     self.hash.change_piece(moving_color, Pawn, to_sq);
 
-                    if flag == KNIGHT_PROMO_FLAG || flag == KNIGHT_PROMO_CAPTURE_FLAG {
-                        *self.board.get_pieces_mut(moving_color, Knight) |= to_bit;
-                        self.hash.change_piece(moving_color, Knight, to_sq);
-
-                    } else if flag == BISHOP_PROMO_FLAG || flag == BISHOP_PROMO_CAPTURE_FLAG {
-                        *self.board.get_pieces_mut(moving_color, Bishop) |= to_bit;
-                        self.hash.change_piece(moving_color, Bishop, to_sq);
-
-                    } else if flag == ROOK_PROMO_FLAG || flag == ROOK_PROMO_CAPTURE_FLAG {
-                        *self.board.get_pieces_mut(moving_color, Rook) |= to_bit;
-                        self.hash.change_piece(moving_color, Rook, to_sq);
-
-                    } else if flag == QUEEN_PROMO_FLAG || flag == QUEEN_PROMO_CAPTURE_FLAG {
-                        *self.board.get_pieces_mut(moving_color, Queen) |= to_bit;
-                        self.hash.change_piece(moving_color, Queen, to_sq);
-                    }
+                    *self.board.get_pieces_mut(moving_color, promoted) |= to_bit;
+                    self.hash.change_piece(moving_color, promoted, to_sq);
                 }
-
             },
 
             Rook => {
-                match moving_color {
-                    White =>
-                        if from_sq.idx() == 0 {
-                            self.hash.update_castling_rights(self.castling_rights);
-                            self.castling_rights.remove(CastlingRights::WHITE_KINGSIDE);
-                            self.hash.update_castling_rights(self.castling_rights);
-                        } else if from_sq.idx() == 7 {
-                            self.hash.update_castling_rights(self.castling_rights);
-                            self.castling_rights.remove(CastlingRights::WHITE_QUEENSIDE);
-                            self.hash.update_castling_rights(self.castling_rights);
-                        },
-
-                    Black =>
-                        if from_sq.idx() == 63 {
-                            self.hash.update_castling_rights(self.castling_rights);
-                            self.castling_rights.remove(CastlingRights::BLACK_QUEENSIDE);
-                            self.hash.update_castling_rights(self.castling_rights);
-                        } else if from_sq.idx() == 56 {
-                            self.hash.update_castling_rights(self.castling_rights);
-                            self.castling_rights.remove(CastlingRights::BLACK_KINGSIDE);
-                            self.hash.update_castling_rights(self.castling_rights);
-                        }
-                }
+                let lost_rights = self.castling_rights_at(from_sq);
+                self.remove_castling_rights(lost_rights);
             },
 
             King => {
-                match moving_color {
-                    White => {
-                        if flag == KING_CASTLE_FLAG {
-                            let rook_old_sq = Square::new(0);
-                            let rook_new_sq = Square::new(2);
-                            let rook_bit = rook_old_sq.bitrep() | rook_new_sq.bitrep();
-
-                            *self.board.get_pieces_mut(self.to_move, Rook) ^= rook_bit;
-                            *self.board.occupied_by_mut(self.to_move) ^= rook_bit;
-
-                            self.hash.change_piece(moving_color, Rook, rook_old_sq);
-                            self.hash.change_piece(moving_color, Rook, rook_new_sq);
-
-                        } else if flag == QUEEN_CASTLE_FLAG {
-                            let rook_old_sq = Square::new(7);
-                            let rook_new_sq = Square::new(4);
-                            let rook_bit = rook_old_sq.bitrep() | rook_new_sq.bitrep();
-
-                            *self.board.get_pieces_mut(self.to_move, Rook) ^= rook_bit;
-                            *self.board.occupied_by_mut(self.to_move) ^= rook_bit;
-
-                            self.hash.change_piece(moving_color, Rook, rook_old_sq);
-                            self.hash.change_piece(moving_color, Rook, rook_new_sq);
-                        }
-
-                        self.hash.update_castling_rights(self.castling_rights);
-                        self.castling_rights.remove(CastlingRights::WHITE_KINGSIDE | CastlingRights::WHITE_QUEENSIDE);
-                        self.hash.update_castling_rights(self.castling_rights);
-                    }
-
-                    Black => {
-                        if flag == KING_CASTLE_FLAG {
-                            let rook_old_sq = Square::new(56);
-                            let rook_new_sq = Square::new(58);
-                            let rook_bit = rook_old_sq.bitrep() | rook_new_sq.bitrep();
-
-                            *self.board.get_pieces_mut(self.to_move, Rook) ^= rook_bit;
-                            *self.board.occupied_by_mut(self.to_move) ^= rook_bit;
-
-                            self.hash.change_piece(moving_color, Rook, rook_old_sq);
-                            self.hash.change_piece(moving_color, Rook, rook_new_sq);
-
-                        } else if flag == QUEEN_CASTLE_FLAG {
-                            let rook_old_sq = Square::new(63);
-                            let rook_new_sq = Square::new(60);
-                            let rook_bit = rook_old_sq.bitrep() | rook_new_sq.bitrep();
-
-                            *self.board.get_pieces_mut(self.to_move, Rook) ^= rook_bit;
-                            *self.board.occupied_by_mut(self.to_move) ^= rook_bit;
-
-                            self.hash.change_piece(moving_color, Rook, rook_old_sq);
-                            self.hash.change_piece(moving_color, Rook, rook_new_sq);
-                        }
-
-                        self.hash.update_castling_rights(self.castling_rights);
-                        self.castling_rights.remove(CastlingRights::BLACK_KINGSIDE | CastlingRights::BLACK_QUEENSIDE);
-                        self.hash.update_castling_rights(self.castling_rights);
-                    }
+                self.board.set_king_square(moving_color, to_sq);
+
+                match flag {
+                    MoveFlag::KingCastle | MoveFlag::QueenCastle => {
+                        let right = CastlingRights::for_side(moving_color, flag == MoveFlag::KingCastle);
+                        let (_, _, rook_from, rook_to) = self.castle_squares(right).unwrap();
+                        let rook_bit = rook_from.bitrep() ^ rook_to.bitrep();
+
+                        *self.board.get_pieces_mut(moving_color, Rook) ^= rook_bit;
+                        self.board.toggle_occupied(moving_color, rook_bit);
+                        vacated |= rook_from.bitrep();
+                        arrived |= rook_to.bitrep();
+
+                        self.hash.change_piece(moving_color, Rook, rook_from);
+                        self.hash.change_piece(moving_color, Rook, rook_to);
+                    },
+                    _ => {}
                 }
 
+                self.remove_castling_rights(CastlingRights::for_side(moving_color, true)
+                                            | CastlingRights::for_side(moving_color, false));
             },
 
             _ => {}
         }
 
-        if flag != DOUBLE_PAWN_PUSH_FLAG {
-            if (self.ep_square.is_some()) {
-                self.hash.modify_ep_square(self.ep_square.unwrap());
-                self.ep_square = None;
-            }
+        if (self.ep_square.is_some()) {
+            self.hash.modify_ep_square(self.ep_square.unwrap());
+            self.ep_square = None;
         }
 
         if is_capture || moved_ptype == Pawn {
             self.halfmove_clock = 0;
         } else {
-            self.halfmove_clock += 1;
+            self.halfmove_clock = self.halfmove_clock.saturating_add(1);
         }
 
         if self.to_move == Black {
-            self.fullmoves += 1;
+            self.fullmoves = self.fullmoves.saturating_add(1);
         }
 
         self.to_move = !self.to_move;
         self.hash.update_black_to_move();
 
         let opp_king_square = self.board.get_king_square(opponent_color);
-        self.king_attackers = self.board.attackers(opp_king_square, !self.to_move);
+        self.king_attackers = self.board.attackers_after(opp_king_square, moving_color, vacated, arrived);
 
-        let can_move = can_move(self);
+        if flag == MoveFlag::DoublePawnPush {
+            let ep_square = match moving_color {
+                White => Square::new(to_sq.unwrap() - 8),
+                Black => Square::new(to_sq.unwrap() + 8)
+            };
 
-        // no moves available, game is over
-        if !can_move {
-            match self.king_attackers.population() {
-                0 => self.outcome = Some(GameResult::Draw),
-                _ => match self.to_move {
-                         Color::White => self.outcome = Some(GameResult::Win(Color::Black)),
-                         Color::Black => self.outcome = Some(GameResult::Win(Color::White))
-                     }
+            if self.legal_ep_capture_exists(ep_square) {
+                self.ep_square = Some(ep_square);
+                self.hash.modify_ep_square(ep_square);
             }
         }
 
-        //NOTE: only the three-fold repetition rule isn't account for here.
+        //NOTE: only the three-fold repetition rule isn't accounted for here.
+        self.outcome = self.compute_outcome();
+
+        debug_assert_eq!(self.verify_derived_fields(), Ok(()));
     }
 
     pub fn random_game() -> Game {
@@ -545,6 +1085,11 @@ impl Game {
         self.board.flip_color();
         self.castling_rights = self.castling_rights.flip_color();
 
+        let old_rooks = self.castling_rooks;
+        for i in 0 .. 4 {
+            self.castling_rooks[i] = old_rooks[3 - i].map(|sq| Square::new(63 - sq.unwrap()));
+        }
+
         self.outcome = match self.outcome {
             Some(GameResult::Win(color)) => Some(GameResult::Win(!color)),
             Some(GameResult::Draw) => Some(GameResult::Draw),
@@ -563,20 +1108,29 @@ impl Game {
         self.king_attackers = self.king_attackers.flip_color();
 
         self.hash = Hash::new(self);
+        self.pawn_hash = Hash::pawns(&self.board);
+        self.material_key = MaterialKey::new(&self.board);
     }
 }
 
 #[cfg(test)]
 mod test {
     use game::*;
+    use builder::*;
+
+    // random, but legal and still going after forty moves
+    const LONG_GAME: &'static str = "b2b4 a7a5 g2g4 a5b4 b1c3 b8c6 c3b5 f7f5 b5d4 g8h6 c2c3 h8g8 c3c4 f5f4 d1a4 f4f3 \
+                                      e2e4 c6a7 f1h3 a8b8 d4b5 e7e5 a1b1 e8f7 b1a1 d8h4 a4b3 h4h5 e1d1 f7f6 b5a3 g7g6 \
+                                      d2d3 a7c6 g1e2 h5h3 e2g1 h3h2 c1f4 f6e7 f4h2 h6f7 b3b1 f8h6 b1c1 f7d6 a3b1 h6g5 \
+                                      b1a3 g8e8 d1c2 h7h6 c1b1 g5f6 c2d2 f6g7 d2e1 g7f6 b1d1 d6f7 h2e5 f6g5 h1h4 f7h8 \
+                                      d1e2 g5c1 e5d6 e7f7 a3b5 c6e5 g1f3 c7c5 e2e3 f7f6 d3d4 f6g7 f3g1 e8g8 d6c5 h8f7";
 
     #[test]
     fn fen() {
         //TODO: generate random games
         let fen_strings: Vec<&'static str> = vec![
             "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
-            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1",
-            "rnbqkbnr/pp1ppppp/8/2p5/4P3/8/PPPP1PPP/RNBQKBNR w KQkq c6 0 2",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
             "rnbqkbnr/pp1ppppp/8/2p5/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2",
             "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
             "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
@@ -592,6 +1146,107 @@ mod test {
             let g = Game::from_fen_str(fen).unwrap();
             assert!(&g.to_fen() == fen);
         }
+
+        // trailing fields left out take their defaults
+        let short_fens = [
+            ("rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6", "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 1"),
+            ("4k3/8/8/8/8/8/8/4K2R w K - 7", "4k3/8/8/8/8/8/8/4K2R w K - 7 1"),
+            ("4k3/8/8/8/8/8/8/4K2R b K", "4k3/8/8/8/8/8/8/4K2R b K - 0 1"),
+            ("4k3/8/8/8/8/8/8/4K2R w", "4k3/8/8/8/8/8/8/4K2R w - - 0 1")
+        ];
+
+        for &(fen, expected) in short_fens.iter() {
+            assert_eq!(Game::from_fen_str(fen).unwrap().to_fen(), expected);
+        }
+
+        // missing kings, or a side with two, aren't positions the engine can play
+        let bad_fens = ["", "4k3/8/8/8/8/8/8/4K2R", "4k3/8/8/8/8/8/8/4K2R w K - x",
+                        "8/8/8/8/8/8/8/4K2R w - - 0 1", "4k3/8/8/8/8/8/8/7R w - - 0 1",
+                        "4k3/8/8/8/8/8/8/K3K3 w - - 0 1", "k3k3/8/8/8/8/8/8/4K3 b - - 0 1"];
+
+        for fen in bad_fens.iter() {
+            assert!(Game::from_fen_str(fen).is_none(), "{}", fen);
+        }
+
+        // the fullmove number only advances after black moves, the halfmove clock resets on
+        // pawn moves and captures
+        let sequences = [
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+             "e2e4 e7e5 g1f3 b8c6 f1b5 a7a6",
+             "r1bqkbnr/1ppp1ppp/p1n5/1B2p3/4P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 0 4"),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+             "g1f3 g8f6 f3g1 f6g8",
+             "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 4 3"),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+             "e2e4 d7d5 e4e5 f7f5",
+             "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3"),
+            ("4k3/8/8/8/8/8/8/4K3 b - - 10 40",
+             "e8d7 e1d2 d7c6",
+             "8/8/2k5/8/8/8/3K4/8 w - - 13 42"),
+            ("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1",
+             "e1g1 e8c8",
+             "2kr3r/8/8/8/8/8/8/R4RK1 w - - 2 2"),
+            ("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1",
+             "e7e5",
+             "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2"),
+            ("4k3/8/8/8/8/8/8/4K3 b - - 0 0",
+             "e8d7",
+             "8/3k4/8/8/8/8/8/4K3 w - - 1 2")
+        ];
+
+        for &(start_fen, moves, expected_fen) in sequences.iter() {
+            let mut g = Game::from_fen_str(start_fen).unwrap();
+            let move_strs: Vec<&str> = moves.split_whitespace().collect();
+            g.apply_moves(&move_strs).unwrap();
+
+            assert_eq!(g.to_fen(), expected_fen);
+            assert_eq!(Game::from_fen_str(expected_fen).unwrap().to_fen(), expected_fen);
+        }
+    }
+
+    #[test]
+    fn chess960_fen() {
+        let fen_strings = [
+            "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w KQkq - 2 9",
+            "nrbbqkrn/pppppppp/8/8/8/8/PPPPPPPP/NRBBQKRN w KQkq - 0 1",
+            "rkrnnqbb/pppppppp/8/8/8/8/PPPPPPPP/RKRNNQBB w KQkq - 0 1",
+            "qrkbbnnr/pppppppp/8/8/8/8/PPPPPPPP/QRKBBNNR w KQkq - 0 1",
+            "bbrknnqr/pppppppp/8/8/8/8/PPPPPPPP/BBRKNNQR w Kk - 0 1",
+            // an inner rook can only be named by its file
+            "1k2r2r/pppppppp/8/8/8/8/PPPPPPPP/1K2R2R w Ee - 0 1",
+            "rk2r2r/pppppppp/8/8/8/8/PPPPPPPP/RK2R2R w EQeq - 0 1"
+        ];
+
+        for fen in fen_strings.iter() {
+            let g = Game::from_fen_str(fen).unwrap();
+            assert!(g.chess960, "{}", fen);
+            assert_eq!(&g.to_fen(), fen);
+        }
+
+        // Shredder-FEN names every rook by file and resolves to the same game
+        let equivalent = [
+            ("bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9", 0),
+            ("nrbbqkrn/pppppppp/8/8/8/8/PPPPPPPP/NRBBQKRN w GBgb - 0 1", 1),
+            ("rkrnnqbb/pppppppp/8/8/8/8/PPPPPPPP/RKRNNQBB w CAca - 0 1", 2),
+            ("rk2r2r/pppppppp/8/8/8/8/PPPPPPPP/RK2R2R w EAea - 0 1", 6)
+        ];
+
+        for &(shredder, i) in equivalent.iter() {
+            let g = Game::from_fen_str(shredder).unwrap();
+            assert!(g == Game::from_fen_str(fen_strings[i]).unwrap(), "{}", shredder);
+            assert_eq!(g.to_fen(), fen_strings[i]);
+        }
+
+        let rooks = Game::from_fen_str(fen_strings[1]).unwrap().castling_rooks;
+        assert_eq!(rooks[CastlingRights::WHITE_KINGSIDE.index()], Square::from_algebraic("g1"));
+        assert_eq!(rooks[CastlingRights::WHITE_QUEENSIDE.index()], Square::from_algebraic("b1"));
+        assert_eq!(rooks[CastlingRights::BLACK_KINGSIDE.index()], Square::from_algebraic("g8"));
+        assert_eq!(rooks[CastlingRights::BLACK_QUEENSIDE.index()], Square::from_algebraic("b8"));
+
+        // the standard setup keeps KQkq and is not treated as Chess960
+        let standard = Game::from_fen_str("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w HAha - 0 1").unwrap();
+        assert_eq!(standard.to_fen(), "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+        assert!(!Game::starting_position().chess960);
     }
 
     #[test]
@@ -602,8 +1257,492 @@ mod test {
             flipped_game.flip_color();
             flipped_game.flip_color();
             assert!(flipped_game == original_game);
+            let diff = original_game.diff(&flipped_game);
+            assert!(diff.is_empty(), "{}", diff);
         }
     }
-}
 
+    #[test]
+    fn apply_moves() {
+        init_zobrist_hashing();
+
+        let mut game = Game::starting_position();
+        game.apply_moves(&["e2e4", "e7e5", "f1c4", "b8c6", "d1h5", "g8f6", "h5f7"]).unwrap();
+        assert_eq!(game.to_fen(), "r1bqkb1r/pppp1Qpp/2n2n2/4p3/2B1P3/8/PPPP1PPP/RNB1K1NR b KQkq - 0 4");
+        assert_eq!(game.outcome, Some(GameResult::Win(Color::White)));
+
+        // the moves before an illegal one are still played
+        let mut game = Game::starting_position();
+        let error = game.apply_moves(&["e2e4", "e7e5", "e4e5", "d7d5"]).unwrap_err();
+        assert_eq!((error.index, error.input.as_str()), (2, "e4e5"));
+        assert_eq!(error.fen_before, "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2");
+        assert_eq!(game.to_fen(), error.fen_before);
+        assert_eq!(error.legal_moves.len(), 29);
+        assert!(error.legal_moves.contains(&"e1e2".to_string()));
+        assert!(error.to_string().starts_with("illegal move e4e5 (move 3 of the list) in rnbqkbnr/"));
+
+        assert_eq!(game.apply_moves(vec!["g1f3".to_string(), "nonsense".to_string()]).unwrap_err().index, 1);
+        assert_eq!(game.apply_moves(&[] as &[&str]), Ok(()));
+
+        // forty moves of a game, long enough for a mistake to be anywhere
+        let moves: Vec<&str> = LONG_GAME.split_whitespace().collect();
+        assert_eq!(moves.len(), 80);
+
+        let positions = Game::starting_position().trace_moves(&moves).unwrap();
+        assert_eq!(positions.len(), 81);
+        for (i, position) in positions.iter().enumerate() {
+            let mut replayed = Game::starting_position();
+            replayed.apply_moves(&moves[.. i]).unwrap();
+            assert_eq!(replayed.diff(position), "", "{}", i);
+        }
+
+        // move 23 moves a piece that isn't there
+        let mut corrupted = moves.clone();
+        corrupted[22] = "a1a1";
+        let error = Game::starting_position().trace_moves(&corrupted).unwrap_err();
+        assert_eq!((error.index, error.input.as_str()), (22, "a1a1"));
+        assert_eq!(error.fen_before, positions[22].to_fen());
+    }
+
+    #[test]
+    fn diff() {
+        init_zobrist_hashing();
+
+        let start = Game::starting_position();
+        assert_eq!(start.diff(&start), "");
+
+        let mut game = start;
+        game.apply_moves(&["e2e4"]).unwrap();
+        let lines: Vec<String> = start.diff(&game).lines().map(|l| l.to_string()).collect();
+        assert_eq!(lines[0], "white pawn moved e2 -> e4");
+        assert!(lines.contains(&"to move: White -> Black".to_string()));
+        assert!(lines.iter().any(|l| l.starts_with("hash: ")));
+        assert!(lines.iter().any(|l| l.starts_with("pawn hash: ")));
+
+        game.apply_moves(&["d7d5", "e4d5"]).unwrap();
+        let lines: Vec<String> = start.diff(&game).lines().map(|l| l.to_string()).collect();
+        assert!(lines.contains(&"white pawn moved e2 -> d5".to_string()));
+        assert!(lines.contains(&"black pawn removed from d7".to_string()));
+        assert!(lines.contains(&"fullmoves: 1 -> 2".to_string()));
+
+        // what a stale incremental update looks like
+        let mut stale = game;
+        stale.hash = start.hash;
+        assert_eq!(stale.diff(&game), format!("hash: {:?} -> {:?}", start.hash, game.hash));
+
+        let mut no_castling = start;
+        no_castling.castling_rights = CastlingRights::empty();
+        no_castling.update_derived_fields();
+        assert!(start.diff(&no_castling).starts_with("castling rights: "));
+    }
+
+    #[test]
+    fn put_and_take() {
+        init_zobrist_hashing();
+
+        let mut game = Game::empty_position();
+        let start = Game::from_fen_str("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w - - 0 1").unwrap();
+
+        for idx in 0 .. 64 {
+            if let Some(piece) = start.board.piece_at(Square::new(idx)) {
+                game.put(piece.color, piece.ptype, Square::new(idx));
+            }
+        }
 
+        // the hashes and material key match a full recomputation without one being done
+        assert_eq!(game.diff(&start), "");
+
+        let square = |s: &str| Square::from_algebraic(s).unwrap();
+        assert_eq!(game.take(square("e2")), Some(Piece::new(Color::White, PieceType::Pawn)));
+        game.put(Color::White, PieceType::Pawn, square("e4"));
+        game.put(Color::Black, PieceType::Queen, square("a7"));
+        assert_eq!(game.take(square("e5")), None);
+
+        let edited = Game::from_fen_str("rnbqkbnr/qppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR w - - 0 1").unwrap();
+        assert_eq!(game.diff(&edited), "");
+    }
+
+    #[test]
+    fn castles() {
+        init_zobrist_hashing();
+
+        let castles = [
+            ("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", "e1g1", "r3k2r/8/8/8/8/8/8/R4RK1 b kq - 1 1"),
+            ("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", "e1c1", "r3k2r/8/8/8/8/8/8/2KR3R b kq - 1 1"),
+            ("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1", "e8c8", "2kr3r/8/8/8/8/8/8/R3K2R w KQ - 1 2"),
+            ("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1", "e8g8", "r4rk1/8/8/8/8/8/8/R3K2R w KQ - 1 2")
+        ];
+
+        for (i, &(fen, castle, expected)) in castles.iter().enumerate() {
+            let mut game = Game::from_fen_str(fen).unwrap();
+
+            // the table is in CastlingRights::index order, and agrees with the standard position
+            let right = CastlingRights::from_bits(1 << i).unwrap();
+            let entry = &CASTLES[i];
+            let squares = game.castle_squares(right).unwrap();
+            assert_eq!((squares.0.unwrap(), squares.1.unwrap(), squares.2.unwrap(), squares.3.unwrap()),
+                       (entry.king_from, entry.king_to, entry.rook_from, entry.rook_to));
+
+            game.apply_moves(&[castle]).unwrap();
+
+            // the board, rights, clocks and every incrementally kept key
+            assert_eq!(game.to_fen(), expected);
+            assert_eq!(game.diff(&Game::from_fen_str(expected).unwrap()), "", "{}", castle);
+        }
+    }
+
+    #[test]
+    fn derived_fields() {
+        init_zobrist_hashing();
+
+        // castling is where make_move moves two pieces at once, here the rook gives check
+        let mut castled = Game::from_fen_str("5k2/8/8/8/8/8/8/4K2R w K - 0 1").unwrap();
+        castled.apply_moves(&["e1g1"]).unwrap();
+        assert!(castled.in_check());
+        assert_eq!(castled.verify_derived_fields(), Ok(()));
+
+        // every move from kiwipete, castles, promotions and en passant among them
+        let kiwipete = Game::from_fen_str("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+        for m in next_moves_standalone(&kiwipete).iter() {
+            let mut game = kiwipete;
+            game.make_move(*m);
+            assert_eq!(game.verify_derived_fields(), Ok(()), "{}", m.to_uci_str());
+        }
+
+        // a stale key is reported, and repaired
+        let mut stale = castled;
+        stale.hash.change_piece(Color::White, PieceType::Rook, Square::from_algebraic("h1").unwrap());
+        stale.king_attackers = Bitboard::none_set();
+
+        let report = stale.verify_derived_fields().unwrap_err();
+        assert!(report.contains("hash:"), "{}", report);
+        assert!(report.contains("king attackers:  -> f1"), "{}", report);
+
+        stale.update_derived_fields();
+        assert_eq!(stale.verify_derived_fields(), Ok(()));
+    }
+
+    #[test]
+    fn position_identity() {
+        init_zobrist_hashing();
+
+        let play = |moves: &[&str]| -> Game {
+            let mut game = Game::starting_position();
+            game.apply_moves(moves).unwrap();
+            game
+        };
+
+        // the same position by different move orders, with different clocks
+        let direct = play(&["e2e4", "e7e5", "g1f3", "b8c6"]);
+        let transposed = play(&["g1f3", "b8c6", "f3g1", "c6b8", "e2e4", "e7e5", "g1f3", "b8c6"]);
+        assert!(direct.fullmoves != transposed.fullmoves);
+        assert_eq!(direct, transposed);
+
+        // no black pawn can take on e3, so it isn't an en passant square at all
+        let pseudo_ep = Game::from_fen_str("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1").unwrap();
+        let without_ep = Game::from_fen_str("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1").unwrap();
+        assert_eq!(pseudo_ep, without_ep);
+
+        let with_ep = Game::from_fen_str("rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3").unwrap();
+        let missed_ep = Game::from_fen_str("rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq - 0 3").unwrap();
+        assert!(with_ep != missed_ep);
+
+        let no_castling = Game::from_fen_str("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b - - 0 1").unwrap();
+        assert!(without_ep != no_castling);
+    }
+
+    #[test]
+    fn legal_ep_square() {
+        init_zobrist_hashing();
+
+        let after = |fen: &str, uci_move: &str| -> Game {
+            let mut game = Game::from_fen_str(fen).unwrap();
+            let m = *next_moves_standalone(&game).iter().find(|m| m.to_uci_str() == uci_move).unwrap();
+            game.make_move(m);
+            game
+        };
+
+        // (position, double push, FEN afterwards)
+        let cases = [
+            // nothing next to the pawn
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", "e2e4",
+             "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1"),
+            ("rnbqkbnr/ppp1pppp/8/8/3p4/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", "c2c4",
+             "rnbqkbnr/ppp1pppp/8/8/2Pp4/8/PP1PPPPP/RNBQKBNR b KQkq c3 0 1"),
+            // taking would leave both pawns' squares empty, exposing the king along the rank
+            ("8/8/8/8/k3p2Q/8/3P4/4K3 w - - 0 1", "d2d4",
+             "8/8/8/8/k2Pp2Q/8/8/4K3 b - - 0 1"),
+            // pinned on a diagonal, it can only take along the pin
+            ("k7/8/8/8/4p3/8/3P4/4K2B w - - 0 1", "d2d4",
+             "k7/8/8/8/3Pp3/8/8/4K2B b - - 0 1"),
+            ("k7/8/8/8/4p3/8/5P2/4K2B w - - 0 1", "f2f4",
+             "k7/8/8/8/4pP2/8/8/4K2B b - f3 0 1"),
+            // the double push uncovers a check, and taking en passant doesn't block it
+            ("8/8/6k1/8/3p4/8/2P5/1B2K3 w - - 0 1", "c2c4",
+             "8/8/6k1/8/2Pp4/8/8/1B2K3 b - - 0 1"),
+            // while here taking the checking pawn is the answer
+            ("8/8/8/2k5/4p3/8/3P4/4K3 w - - 0 1", "d2d4",
+             "8/8/8/2k5/3Pp3/8/8/4K3 b - d3 0 1")
+        ];
+
+        for &(fen, uci_move, expected) in cases.iter() {
+            let game = after(fen, uci_move);
+            assert_eq!(game.to_fen(), expected);
+
+            let from_fen = Game::from_fen_str(expected).unwrap();
+            assert_eq!(game.hash, from_fen.hash, "{}", expected);
+            assert_eq!(game.ep_square, from_fen.ep_square);
+        }
+
+        // an en passant square in the FEN that nobody can use is dropped
+        let pseudo_ep = Game::from_fen_str("8/8/8/8/k2Pp2Q/8/8/4K3 b - d3 0 1").unwrap();
+        assert_eq!(pseudo_ep.to_fen(), "8/8/8/8/k2Pp2Q/8/8/4K3 b - - 0 1");
+        assert_eq!(pseudo_ep.hash, Game::from_fen_str("8/8/8/8/k2Pp2Q/8/8/4K3 b - - 0 1").unwrap().hash);
+
+        // 1. e4 and the knights going back and forth: the first position after e4 counts
+        // as the first of the three
+        let mut game = Game::starting_position();
+        let mut history = Vec::new();
+        let moves = ["e2e4", "g8f6", "g1f3", "f6g8", "f3g1", "g8f6", "g1f3", "f6g8", "f3g1"];
+
+        for (i, uci_move) in moves.iter().enumerate() {
+            assert_eq!(game.outcome_with_history(&history), None, "{}", i);
+            let m = *next_moves_standalone(&game).iter().find(|m| m.to_uci_str() == *uci_move).unwrap();
+            history.push(game.hash);
+            game.make_move(m);
+        }
+
+        assert_eq!(game.outcome_with_history(&history), Some(GameResult::Draw));
+    }
+
+    #[test]
+    fn outcome() {
+        use Color::*;
+
+        let checkmate = Game::from_fen_str("rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3").unwrap();
+        assert_eq!(checkmate.outcome(), Some(GameResult::Win(Black)));
+
+        let stalemate = Game::from_fen_str("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1").unwrap();
+        assert_eq!(stalemate.outcome(), Some(GameResult::Draw));
+
+        let fifty_moves = Game::from_fen_str("7k/8/8/8/8/8/R7/K7 w - - 100 80").unwrap();
+        assert_eq!(fifty_moves.outcome(), Some(GameResult::Draw));
+
+        let mate_on_fiftieth = Game::from_fen_str("R6k/8/7K/8/8/8/8/8 b - - 100 80").unwrap();
+        assert_eq!(mate_on_fiftieth.outcome(), Some(GameResult::Win(White)));
+
+        use PieceType::*;
+        let bishop = || GameBuilder::new().piece(White, King, "d3").piece(White, Bishop, "e3").piece(Black, King, "e6");
+
+        let lone_bishop = bishop().build().unwrap();
+        assert_eq!(lone_bishop.outcome(), Some(GameResult::Draw));
+
+        let same_color_bishops = bishop().piece(Black, Bishop, "d6").build().unwrap();
+        assert_eq!(same_color_bishops.outcome(), Some(GameResult::Draw));
+
+        let opposite_color_bishops = bishop().piece(Black, Bishop, "c6").build().unwrap();
+        assert_eq!(opposite_color_bishops.outcome(), None);
+
+        assert_eq!(Game::starting_position().outcome(), None);
+    }
+
+    #[test]
+    fn long_shuffle() {
+        use rand::SeedableRng;
+        use rand::prng::XorShiftRng;
+
+        // the pawns keep every rook and king move quiet, and the position can never be stalemate
+        let shuffle = |strict_fide: bool| -> Option<usize> {
+            let mut game = Game::from_fen_str("r3k2r/pppppppp/8/8/8/8/PPPPPPPP/R3K2R w - - 0 1").unwrap();
+            game.strict_fide = strict_fide;
+
+            let mut rng = XorShiftRng::from_seed([7; 16]);
+            let mut first_draw = None;
+
+            for halfmove in 1 .. 301 {
+                let quiet_moves: Vec<Move> = next_moves_standalone(&game).iter()
+                    .filter(|m| !m.is_capture() && game.board.piece_at(m.from()).unwrap().ptype != PieceType::Pawn)
+                    .cloned()
+                    .collect();
+
+                game.make_move(quiet_moves[rng.gen_range(0, quiet_moves.len())]);
+                assert_eq!(game.halfmove_clock as usize, halfmove);
+
+                if first_draw.is_none() && game.outcome() == Some(GameResult::Draw) {
+                    first_draw = Some(halfmove);
+                }
+            }
+
+            assert_eq!(game.fullmoves, 151);
+            assert_eq!(game.outcome(), Some(GameResult::Draw));
+            return first_draw;
+        };
+
+        assert_eq!(shuffle(false), Some(FIFTY_MOVE_HALFMOVES as usize));
+        assert_eq!(shuffle(true), Some(SEVENTY_FIVE_MOVE_HALFMOVES as usize));
+    }
+
+    #[test]
+    fn threefold_repetition() {
+        init_zobrist_hashing();
+
+        let mut game = Game::starting_position();
+        let mut history = Vec::new();
+
+        for move_str in ["g1f3", "g8f6", "f3g1", "f6g8", "g1f3", "g8f6", "f3g1", "f6g8"].iter() {
+            assert_eq!(game.outcome_with_history(&history), None);
+
+            let m = move_from_algebraic(&game, move_str.to_string()).unwrap();
+            history.push(game.hash);
+            game.make_move(m);
+        }
+
+        assert_eq!(game.outcome(), None);
+        assert_eq!(game.outcome_with_history(&history), Some(GameResult::Draw));
+    }
+
+    #[test]
+    fn repetition_count() {
+        init_zobrist_hashing();
+
+        let mut game = Game::starting_position();
+        let mut history = Vec::new();
+        let mut counts = Vec::new();
+
+        for move_str in ["g1f3", "g8f6", "f3g1", "f6g8", "g1f3", "g8f6", "f3g1", "f6g8", "e2e4"].iter() {
+            let m = move_from_algebraic(&game, move_str.to_string()).unwrap();
+            history.push(game.hash);
+            game.make_move(m);
+            counts.push(game.repetition_count(&history));
+        }
+
+        // the starting position comes back after every fourth move, and a pawn move starts afresh
+        assert_eq!(counts, vec![1, 1, 1, 2, 2, 2, 2, 3, 1]);
+        assert_eq!(Game::starting_position().repetition_count(&[]), 1);
+    }
+
+    #[test]
+    fn is_legal() {
+        let mut rng = thread_rng();
+
+        for _ in 0 .. 5000 {
+            let game = Game::random_game();
+            let legal_moves: Vec<Move> = next_moves_standalone(&game).iter().cloned().collect();
+
+            for m in legal_moves.iter() {
+                assert!(game.is_legal(*m), "{} {}", game.to_fen(), m.to_uci_str());
+            }
+
+            // fully random encodings, and generated moves with a single bit flipped
+            for i in 0 .. 200 {
+                let candidate = if i % 2 == 0 || legal_moves.is_empty() {
+                    Move::wrap(rng.gen::<u32>() & 0x3fffff)
+                } else {
+                    let m = legal_moves[rng.gen_range(0, legal_moves.len())];
+                    Move::wrap(m.unwrap() ^ (1 << rng.gen_range(0, 22)))
+                };
+
+                if !legal_moves.contains(&candidate) {
+                    assert!(!game.is_legal(candidate), "{} {:x}", game.to_fen(), candidate.unwrap());
+                }
+            }
+        }
+    }
+
+    fn compare_king_attackers(game: Game, depth: usize) {
+        let king_square = game.board.get_king_square(game.to_move);
+        assert_eq!(game.king_attackers, game.board.attackers(king_square, !game.to_move), "{}", game.to_fen());
+
+        if depth == 0 {
+            return;
+        }
+
+        for m in next_moves_standalone(&game).iter() {
+            let mut child = game;
+            child.make_move(*m);
+            compare_king_attackers(child, depth - 1);
+        }
+    }
+
+    #[test]
+    fn king_attackers() {
+        let fen_strings = [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            // discovered checks, one of them by capturing en passant
+            "7k/b7/8/8/3Pp3/8/8/6K1 b - d3 0 1",
+            "4k3/8/8/8/8/8/4B3/4R1K1 w - - 0 1",
+            // the castling rook checks
+            "5k2/8/8/8/8/8/8/4K2R w K - 0 1",
+            "5k2/8/8/8/8/8/8/R3K3 w Q - 0 1",
+            "nrbkqbrn/pppppppp/8/8/8/8/PPPPPPPP/NRBKQBRN w KQkq - 0 1"
+        ];
+
+        for fen in fen_strings.iter() {
+            compare_king_attackers(Game::from_fen_str(fen).unwrap(), 3);
+        }
+    }
+
+    #[test]
+    fn legal_moves_by_square() {
+        let sq = |alg: &str| Square::from_algebraic(alg).unwrap();
+        let uci_strs = |moves: Vec<Move>| -> Vec<String> { moves.iter().map(|m| m.to_uci_str()).collect() };
+
+        // a push and a capture that both promote, four moves for each pair of squares
+        let game = Game::from_fen_str("r3k3/1P6/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        assert_eq!(uci_strs(game.legal_move_between(sq("b7"), sq("b8")).unwrap()), vec!["b7b8n", "b7b8b", "b7b8r", "b7b8q"]);
+        let captures = game.legal_move_between(sq("b7"), sq("a8")).unwrap();
+        assert_eq!(captures.len(), 4);
+        assert!(captures.iter().all(|m| m.is_promotion() && m.captured_piece() == Some(PieceType::Rook)));
+        assert_eq!(game.legal_moves_from(sq("b7")).len(), 8);
+
+        // castling on both sides, from the king's square to where it ends up
+        let game = Game::from_fen_str("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+        assert!(game.legal_move_between(sq("e1"), sq("g1")).unwrap()[0].is_castle());
+        assert!(game.legal_move_between(sq("e1"), sq("c1")).unwrap()[0].is_castle());
+        assert_eq!(game.legal_moves_from(sq("e1")).iter().filter(|m| m.is_castle()).count(), 2);
+
+        // en passant, and nothing for an empty square, the opponent's pieces or an illegal target
+        let game = Game::from_fen_str("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 2").unwrap();
+        let ep = game.legal_move_between(sq("e5"), sq("d6")).unwrap();
+        assert_eq!(ep.len(), 1);
+        assert!(ep[0].is_en_passant());
+        assert_eq!(game.legal_moves_from(sq("a1")).len(), 0);
+        assert_eq!(game.legal_moves_from(sq("d5")).len(), 0);
+        assert_eq!(game.legal_move_between(sq("e5"), sq("e7")), None);
+
+        // every piece's moves together are exactly the position's
+        for _ in 0 .. 1000 {
+            let game = Game::random_game();
+            let all_moves: Vec<Move> = game.legal_moves().iter().cloned().collect();
+
+            let mut by_square = Vec::new();
+            for idx in 0 .. 64 {
+                let from = Square::new(idx);
+                let moves: Vec<Move> = game.legal_moves_from(from).iter().cloned().collect();
+                assert!(moves.iter().all(|m| m.from() == from), "{}", game.to_fen());
+                by_square.extend(moves);
+            }
+
+            assert_eq!(by_square.len(), all_moves.len(), "{}", game.to_fen());
+            assert!(all_moves.iter().all(|m| by_square.contains(m)), "{}", game.to_fen());
+        }
+    }
+
+    #[test]
+    fn checkers_and_pins() {
+        let squares = |algs: &[&str]| -> Bitboard {
+            algs.iter().fold(Bitboard::new(0), |bits, alg| bits | Square::from_algebraic(alg).unwrap().bitrep())
+        };
+
+        // the bishop on e2 is pinned along the file, the knight on d2 along the diagonal
+        let game = Game::from_fen_str("4k3/4r3/8/b7/8/8/3NB3/4K3 w - - 0 1").unwrap();
+        assert_eq!(game.pinned(Color::White), squares(&["d2", "e2"]));
+        assert_eq!(game.pinned(Color::Black), squares(&[]));
+        assert_eq!(game.checkers(), squares(&[]));
+
+        // a rook and a knight check at once, and a piece behind another isn't pinned
+        let game = Game::from_fen_str("k3r3/8/8/8/1b6/2Pn4/3N4/4K3 w - - 0 1").unwrap();
+        assert_eq!(game.checkers(), squares(&["e8", "d3"]));
+        assert_eq!(game.pinned(Color::White), squares(&[]));
+    }
+}
@@ -0,0 +1,261 @@
+use core::*;
+
+use std::sync::{Once, ONCE_INIT};
+
+// King+Pawn vs King bitbase, generated by retrograde analysis.
+// Positions are normalized so that the strong side is white and the pawn
+// is on files a-d. Inside this module squares are indexed a1 = 0 .. h8 = 63.
+
+const KPK_SIZE: usize = 2 * 64 * 64 * 24;
+
+static mut KPK_BITBASE: [u64; KPK_SIZE / 64] = [0; KPK_SIZE / 64];
+static KPK_INIT: Once = ONCE_INIT;
+
+const INVALID: u8 = 0b000;
+const UNKNOWN: u8 = 0b001;
+const DRAW: u8    = 0b010;
+const WIN: u8     = 0b100;
+
+const WHITE_TO_MOVE: usize = 0;
+const BLACK_TO_MOVE: usize = 1;
+
+fn kpk_index(stm: usize, wk: usize, bk: usize, psq: usize) -> usize {
+    let pawn_idx = (psq / 8 - 1) * 4 + psq % 8;
+    ((pawn_idx * 64 + wk) * 64 + bk) * 2 + stm
+}
+
+fn distance(a: usize, b: usize) -> usize {
+    let file_dist = (a % 8) as i32 - (b % 8) as i32;
+    let rank_dist = (a / 8) as i32 - (b / 8) as i32;
+    file_dist.abs().max(rank_dist.abs()) as usize
+}
+
+fn king_moves(sq: usize) -> Vec<usize> {
+    let mut moves = Vec::new();
+
+    for df in -1i32 .. 2 {
+        for dr in -1i32 .. 2 {
+            if df == 0 && dr == 0 {
+                continue;
+            }
+
+            let f = (sq % 8) as i32 + df;
+            let r = (sq / 8) as i32 + dr;
+
+            if f >= 0 && f < 8 && r >= 0 && r < 8 {
+                moves.push((r * 8 + f) as usize);
+            }
+        }
+    }
+
+    return moves;
+}
+
+fn pawn_attacks(psq: usize, sq: usize) -> bool {
+    sq / 8 == psq / 8 + 1 && (sq % 8 == psq % 8 + 1 || sq % 8 + 1 == psq % 8)
+}
+
+fn initial_classification(stm: usize, wk: usize, bk: usize, psq: usize) -> u8 {
+    if distance(wk, bk) <= 1 || wk == psq || bk == psq {
+        return INVALID;
+    }
+
+    // black king can't be in check with white to move
+    if stm == WHITE_TO_MOVE && pawn_attacks(psq, bk) {
+        return INVALID;
+    }
+
+    // pawn can promote immediately and the new queen won't be lost
+    if stm == WHITE_TO_MOVE && psq / 8 == 6 {
+        let promo_sq = psq + 8;
+        if wk != promo_sq && bk != promo_sq
+            && (distance(bk, promo_sq) > 1 || distance(wk, promo_sq) == 1) {
+            return WIN;
+        }
+    }
+
+    if stm == BLACK_TO_MOVE {
+        let attacked_by_white = |sq: usize| distance(wk, sq) <= 1 || pawn_attacks(psq, sq);
+
+        // stalemate
+        if king_moves(bk).iter().all(|sq| attacked_by_white(*sq)) {
+            return DRAW;
+        }
+
+        // black king captures an undefended pawn
+        if distance(bk, psq) == 1 && distance(wk, psq) > 1 {
+            return DRAW;
+        }
+    }
+
+    return UNKNOWN;
+}
+
+fn classify(db: &Vec<u8>, stm: usize, wk: usize, bk: usize, psq: usize) -> u8 {
+    let mut r = INVALID;
+
+    if stm == WHITE_TO_MOVE {
+        for to in king_moves(wk) {
+            r |= db[kpk_index(BLACK_TO_MOVE, to, bk, psq)];
+        }
+
+        let push_sq = psq + 8;
+        if psq / 8 < 6 && push_sq != wk && push_sq != bk {
+            r |= db[kpk_index(BLACK_TO_MOVE, wk, bk, push_sq)];
+
+            let double_push_sq = push_sq + 8;
+            if psq / 8 == 1 && double_push_sq != wk && double_push_sq != bk {
+                r |= db[kpk_index(BLACK_TO_MOVE, wk, bk, double_push_sq)];
+            }
+        }
+
+        if r & WIN != 0 {
+            return WIN;
+        } else if r & UNKNOWN != 0 {
+            return UNKNOWN;
+        } else {
+            return DRAW;
+        }
+    } else {
+        for to in king_moves(bk) {
+            r |= db[kpk_index(WHITE_TO_MOVE, wk, to, psq)];
+        }
+
+        if r & DRAW != 0 {
+            return DRAW;
+        } else if r & UNKNOWN != 0 {
+            return UNKNOWN;
+        } else {
+            return WIN;
+        }
+    }
+}
+
+fn generate_kpk_bitbase() {
+    let mut db = vec![INVALID; KPK_SIZE];
+    let mut squares = Vec::with_capacity(KPK_SIZE);
+
+    for psq in 8 .. 56 {
+        if psq % 8 > 3 {
+            continue;
+        }
+
+        for wk in 0 .. 64 {
+            for bk in 0 .. 64 {
+                for stm in 0 .. 2 {
+                    db[kpk_index(stm, wk, bk, psq)] = initial_classification(stm, wk, bk, psq);
+                    squares.push((stm, wk, bk, psq));
+                }
+            }
+        }
+    }
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+
+        for &(stm, wk, bk, psq) in squares.iter() {
+            let idx = kpk_index(stm, wk, bk, psq);
+            if db[idx] == UNKNOWN {
+                let result = classify(&db, stm, wk, bk, psq);
+                if result != UNKNOWN {
+                    db[idx] = result;
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    unsafe {
+        for (idx, result) in db.iter().enumerate() {
+            if *result == WIN {
+                KPK_BITBASE[idx / 64] |= 1 << (idx % 64);
+            }
+        }
+    }
+}
+
+pub fn init_kpk_bitbase() {
+    KPK_INIT.call_once(generate_kpk_bitbase);
+}
+
+// strong: the side with the pawn, wk/wp: the strong side's king and pawn, bk: the lone king
+pub fn kpk_is_win(strong: Color, wk: Square, wp: Square, bk: Square, stm: Color) -> bool {
+    init_kpk_bitbase();
+
+    let normalize = |sq: Square| -> (u32, u32) {
+        let rank = match strong {
+            Color::White => sq.rank() - 1,
+            Color::Black => 8 - sq.rank()
+        };
+        (sq.file() - 1, rank)
+    };
+
+    let (wk_file, wk_rank) = normalize(wk);
+    let (wp_file, wp_rank) = normalize(wp);
+    let (bk_file, bk_rank) = normalize(bk);
+
+    debug_assert!(wp_rank >= 1 && wp_rank <= 6);
+
+    let mirror = wp_file > 3;
+    let to_idx = |file: u32, rank: u32| -> usize {
+        let f = if mirror { 7 - file } else { file };
+        (rank * 8 + f) as usize
+    };
+
+    let stm_idx = if stm == strong { WHITE_TO_MOVE } else { BLACK_TO_MOVE };
+    let idx = kpk_index(stm_idx,
+                        to_idx(wk_file, wk_rank),
+                        to_idx(bk_file, bk_rank),
+                        to_idx(wp_file, wp_rank));
+
+    unsafe { (KPK_BITBASE[idx / 64] >> (idx % 64)) & 1 == 1 }
+}
+
+#[cfg(test)]
+mod test {
+    use kpk::*;
+
+    fn sq(alg: &str) -> Square {
+        Square::from_algebraic(alg).unwrap()
+    }
+
+    #[test]
+    fn king_in_front_on_sixth_rank() {
+        use Color::*;
+        assert!(kpk_is_win(White, sq("e6"), sq("e5"), sq("e8"), White));
+        assert!(kpk_is_win(White, sq("e6"), sq("e5"), sq("e8"), Black));
+        assert!(kpk_is_win(Black, sq("d3"), sq("d4"), sq("d1"), White));
+        assert!(kpk_is_win(Black, sq("d3"), sq("d4"), sq("d1"), Black));
+    }
+
+    #[test]
+    fn opposition() {
+        use Color::*;
+        // the side to move loses the opposition
+        assert!(!kpk_is_win(White, sq("e5"), sq("e4"), sq("e7"), White));
+        assert!(kpk_is_win(White, sq("e5"), sq("e4"), sq("e7"), Black));
+        assert!(!kpk_is_win(Black, sq("e4"), sq("e5"), sq("e2"), Black));
+        assert!(kpk_is_win(Black, sq("e4"), sq("e5"), sq("e2"), White));
+    }
+
+    #[test]
+    fn rule_of_the_square() {
+        use Color::*;
+        assert!(kpk_is_win(White, sq("h1"), sq("a4"), sq("f5"), White));
+        assert!(!kpk_is_win(White, sq("h1"), sq("a4"), sq("f5"), Black));
+        assert!(kpk_is_win(Black, sq("a8"), sq("h5"), sq("c4"), Black));
+        assert!(!kpk_is_win(Black, sq("a8"), sq("h5"), sq("c4"), White));
+    }
+
+    #[test]
+    fn rook_pawn() {
+        use Color::*;
+        // defending king in the queening corner
+        assert!(!kpk_is_win(White, sq("c6"), sq("a5"), sq("a8"), White));
+        assert!(!kpk_is_win(White, sq("c6"), sq("a5"), sq("a8"), Black));
+        assert!(!kpk_is_win(White, sq("f6"), sq("h5"), sq("h8"), White));
+        // the lone king wins the pawn
+        assert!(!kpk_is_win(White, sq("h1"), sq("a2"), sq("b3"), Black));
+    }
+}
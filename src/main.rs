@@ -4,6 +4,7 @@
 #![feature(extern_prelude)]
 #![feature(stdsimd)]
 #![feature(iterator_step_by)]
+#![feature(integer_atomics)]
 #![feature(plugin, custom_attribute)]
 
 #[macro_use] extern crate bitflags;
@@ -13,104 +14,110 @@ extern crate rand;
 extern crate chrono;
 
 #[macro_use] extern crate clap;
-use clap::App;
 
-use std::fs::File;
-use std::thread;
+use std::fs;
+use std::env;
 use std::process;
 
 mod search; use search::*;
+mod skill; use skill::*;
+mod stats; use stats::*;
+mod adjudication; use adjudication::*;
+mod annotate; use annotate::*;
+mod bench; use bench::*;
+mod builder; use builder::*;
+mod command; use command::*;
 mod bitboard; use bitboard::*;
 mod board; use board::*;
 mod core; use core::*;
 mod eval; use eval::*;
+mod eval_params; use eval_params::*;
 mod feldspar; use feldspar::*;
 mod game; use game::*;
+mod kpk; use kpk::*;
+mod material; use material::*;
 mod movegen; use movegen::*;
 mod moves; use moves::*;
 mod move_list; use move_list::*;
+mod ordering; use ordering::*;
 mod perft; use perft::*;
 mod pins; use pins::*;
+mod positions; use positions::*;
 mod play; use play::*;
+mod pgn; use pgn::*;
 mod print; use print::*;
 mod tables; use tables::*;
 mod uci; use uci::*;
 mod zobrist; use zobrist::*;
 mod tree; use tree::*;
+mod tune; use tune::*;
 
 const FELDSPAR_VERSION: &'static str = env!("CARGO_PKG_VERSION");
 
 fn main() {
-    let yaml = load_yaml!("../cli.yml");
-    let matches = App::from_yaml(yaml).get_matches();
+    let command_line = parse_command_line(env::args()).unwrap_or_else(|e| e.exit());
 
     init_zobrist_hashing();
-    use Color::*;
-    use PieceType::*;
+    init_kpk_bitbase();
 
-    if matches.is_present("ponder") {
-        let ponder_FEN = matches.value_of("ponder").unwrap();
-        match Game::from_fen_str(ponder_FEN) {
+    if let Some(path) = command_line.eval_file {
+        match EvalParams::load(&path) {
+            Some(params) => set_eval_params(params),
             None => {
-                eprintln!("Invalid FEN string passed: {}", ponder_FEN);
+                eprintln!("Invalid eval parameter file: {}", path);
                 process::exit(1);
             }
-
-            Some(game) => {
-                game.board.print();
-                println!("{}", game.to_fen());
-                let mut tmp_tree = SearchTree::new(game);
-                let mut tmp_qtree = SearchTree::new(game);
-                tmp_qtree.in_quiescence = true;
-                let mut tmp_table = TranspositionTable::new(20000000);
-
-                let mut context = SearchContext {
-                    tree: tmp_tree,
-                    qtree: tmp_qtree,
-                    table: tmp_table,
-                    timer: SearchTimer::new(u32::max_value()),
-                    ran_out_of_time: false
-                };
-
-                for i in 1 .. {
-                    let (s,m) = negamax(&mut context, i, Score::min(), Score::max());
-                    m.print();
-                }
-            }
         }
-    } else if matches.is_present("perft") {
-    } else if matches.is_present("uci") {
-        Feldspar::new().run();
     }
 
+    match command_line.command {
+        Command::Uci => Feldspar::new().run(),
+        Command::Perft { game, depth, divide: false } => { perft(game, depth); },
+        Command::Perft { game, depth, divide: true } => { perft_divide(game, depth); },
+        Command::Play { game, depth } => play_against_ai(game, depth),
+        Command::Bench { depth } => { bench(depth); },
+        Command::BenchEval { positions } => bench_eval(positions),
+        Command::BenchMovegen { ms_per_position } => bench_movegen(ms_per_position),
+        Command::Ponder { game } => ponder(game),
+        Command::Tune { training_path, output_path, iterations, quiesce, threads } =>
+            tune(&training_path, &output_path, iterations, quiesce, threads.unwrap_or(num_cpus::get())),
+        Command::Annotate { input, output, options } => annotate_file(&input, output, &options)
+    }
+}
 
-    // g.board.print();
-    // let (best_score, best_move) = alpha_beta(&mut tree, 5);
-    // best_move.print();
-    // g.board.print();
-    // g.board.attacked_flood(Black, false).print();
-    // g.board.attacked(Black, false).print();
-
-    // println!("{:?}", recompute_score(&g.board));
-
-    // let g = Game::starting_position();
-    // perft(g, 5);
-
-
-
-    // for x in threads {
-    //     x.join();
-    // }
-
-    //play_against_ai();
+// searches ever deeper until it's stopped, printing each depth's best move
+fn ponder(game: Game) {
+    game.board.print();
+    println!("{}", game.to_fen());
+    let mut context = SearchContext::new(game, 20000000);
 
+    for depth in 1 .. {
+        let (_, m) = negamax(&mut context, depth, Score::min(), Score::max());
+        m.print();
+    }
+}
 
-    // for m in next_moves_standalone(&g).iter() {
-    //     let mut game_copy = g.clone();
-    //     game_copy.make_move(*m);
-    //     m.print();
-    //     game_copy.board.print();
-    // }
+fn annotate_file(input: &str, output: Option<String>, options: &AnnotateOptions) {
+    let pgn = fs::read_to_string(input).unwrap_or_else(|e| {
+        eprintln!("Can't read {}: {}", input, e);
+        process::exit(1);
+    });
 
+    let mut report_progress = |game: usize, games: usize, m: usize, moves: usize| {
+        eprintln!("annotating game {} of {}, move {} of {}", game, games, m, moves);
+    };
 
+    match annotate_pgn(&pgn, options, &mut report_progress) {
+        Ok(annotated) => match output {
+            Some(path) => fs::write(&path, annotated).unwrap_or_else(|e| {
+                eprintln!("Can't write {}: {}", path, e);
+                process::exit(1);
+            }),
+            None => print!("{}", annotated)
+        },
+        Err(message) => {
+            eprintln!("Invalid PGN in {}: {}", input, message);
+            process::exit(1);
+        }
+    }
 }
@@ -0,0 +1,410 @@
+use board::*;
+use core::*;
+use eval::*;
+use eval_params::*;
+use game::*;
+use kpk::*;
+use tables::*;
+
+use std::cell::RefCell;
+use std::cmp::{max, min};
+
+// must be a power of two
+const MATERIAL_ENTRIES: usize = 8192;
+
+// scale factors are out of this, and only ever shrink the end-game score
+pub const NORMAL_SCALE: u8 = 64;
+const OPPOSITE_BISHOPS_SCALE: u8 = 32;
+
+thread_local! {
+    static MATERIAL_TABLE: RefCell<MaterialTable> = RefCell::new(MaterialTable::new(MATERIAL_ENTRIES));
+}
+
+pub fn probe_material(game: &Game) -> MaterialInfo {
+    MATERIAL_TABLE.with(|table| table.borrow_mut().probe(game.material_key))
+}
+
+// Four bits per piece count, for everything but the kings. Nine queens still fit.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct MaterialKey(u64);
+
+impl MaterialKey {
+    pub fn new(board: &Board) -> MaterialKey {
+        use PieceType::*;
+        use Color::*;
+
+        let mut key = MaterialKey(0);
+
+        for color in [White, Black].iter() {
+            for ptype in [Pawn, Knight, Bishop, Rook, Queen].iter() {
+                for _ in board.get_pieces(*color, *ptype) {
+                    key.add(*color, *ptype);
+                }
+            }
+        }
+
+        return key;
+    }
+
+    fn shift(color: Color, ptype: PieceType) -> u64 {
+        debug_assert!(ptype != PieceType::King);
+        4 * (color as u64 * 5 + ptype as u64 - 1)
+    }
+
+    pub fn add(&mut self, color: Color, ptype: PieceType) {
+        debug_assert!(self.count(color, ptype) < 15);
+        self.0 += 1 << MaterialKey::shift(color, ptype);
+    }
+
+    pub fn remove(&mut self, color: Color, ptype: PieceType) {
+        debug_assert!(self.count(color, ptype) > 0);
+        self.0 -= 1 << MaterialKey::shift(color, ptype);
+    }
+
+    pub fn count(self, color: Color, ptype: PieceType) -> u32 {
+        ((self.0 >> MaterialKey::shift(color, ptype)) & 0xf) as u32
+    }
+
+    // only the kings left
+    pub fn bare_kings() -> MaterialKey {
+        MaterialKey(0)
+    }
+
+    // knights and bishops count three, rooks five and queens nine
+    fn non_pawn_material(self, color: Color) -> i32 {
+        use PieceType::*;
+
+        return 3 * self.count(color, Knight) as i32
+             + 3 * self.count(color, Bishop) as i32
+             + 5 * self.count(color, Rook) as i32
+             + 9 * self.count(color, Queen) as i32;
+    }
+
+    fn pieces(self, color: Color) -> u32 {
+        use PieceType::*;
+        self.count(color, Knight) + self.count(color, Bishop) + self.count(color, Rook) + self.count(color, Queen)
+    }
+}
+
+// Endings with their own evaluation. The color passed along is the side trying to win.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Endgame {
+    KPvK,
+    // a lone king against enough to mate it, without pawns
+    KXvK,
+    // neither side can force mate
+    Insufficient,
+    // only scales the usual eval, when the bishops turn out to be on opposite colors
+    OppositeBishops
+}
+
+impl Endgame {
+    // from white's point of view, None when the usual eval still applies
+    pub fn evaluate(self, game: &Game, strong: Color, params: &EvalParams) -> Option<Score> {
+        let score = match self {
+            Endgame::KPvK => kpk_score(game, strong, params),
+            Endgame::KXvK => mop_up_score(&game.board, strong, params),
+            Endgame::Insufficient => Score::new(0),
+            Endgame::OppositeBishops => return None
+        };
+
+        match strong {
+            Color::White => Some(score),
+            Color::Black => Some(score.flipped())
+        }
+    }
+}
+
+// Everything about the eval that only depends on the piece counts.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct MaterialInfo {
+    key: MaterialKey,
+    pub phase: Phase,
+    // for the end-game score of the side it favours, indexed by that side
+    pub scale_factor: [u8; 2],
+    pub specialized_eval: Option<(Endgame, Color)>
+}
+
+impl MaterialInfo {
+    pub fn compute(key: MaterialKey) -> MaterialInfo {
+        use PieceType::*;
+        use Color::*;
+
+        let mut info = MaterialInfo {
+            key: key,
+            phase: Phase::recompute(key),
+            scale_factor: [NORMAL_SCALE; 2],
+            specialized_eval: None
+        };
+
+        let no_pawns = key.count(White, Pawn) + key.count(Black, Pawn) == 0;
+
+        for strong in [White, Black].iter() {
+            let strong = *strong;
+            let weak = !strong;
+            let npm = key.non_pawn_material(strong);
+            let weak_npm = key.non_pawn_material(weak);
+
+            // without pawns, being a minor piece up is rarely enough
+            if key.count(strong, Pawn) == 0 && npm - weak_npm <= 3 {
+                info.scale_factor[strong as usize] = if npm < 5 { 0 } else if weak_npm <= 3 { 4 } else { 14 };
+            }
+
+            if key.count(weak, Pawn) + key.pieces(weak) != 0 {
+                continue;
+            }
+
+            let pawns = key.count(strong, Pawn);
+            let knights = key.count(strong, Knight);
+            let bishops = key.count(strong, Bishop);
+            let heavies = key.count(strong, Rook) + key.count(strong, Queen);
+
+            if pawns == 1 && key.pieces(strong) == 0 {
+                info.specialized_eval = Some((Endgame::KPvK, strong));
+            } else if pawns == 0 && (heavies > 0 || bishops >= 2 || (bishops > 0 && knights > 0)) {
+                info.specialized_eval = Some((Endgame::KXvK, strong));
+            } else if pawns == 0 && knights == 2 && key.pieces(strong) == 2 {
+                info.specialized_eval = Some((Endgame::Insufficient, strong));
+            }
+        }
+
+        if info.specialized_eval.is_some() {
+            return info;
+        }
+
+        if no_pawns && key.non_pawn_material(White) <= 3 && key.non_pawn_material(Black) <= 3 {
+            info.specialized_eval = Some((Endgame::Insufficient, White));
+        } else if key.pieces(White) == 1 && key.pieces(Black) == 1
+               && key.count(White, Bishop) == 1 && key.count(Black, Bishop) == 1 {
+            info.specialized_eval = Some((Endgame::OppositeBishops, White));
+        }
+
+        return info;
+    }
+
+    pub fn scale_factor(&self, board: &Board, favoured: Color) -> u8 {
+        use PieceType::*;
+        use Color::*;
+
+        let scale = self.scale_factor[favoured as usize];
+
+        match self.specialized_eval {
+            Some((Endgame::OppositeBishops, _)) => {
+                let bishops = board.get_pieces(White, Bishop) | board.get_pieces(Black, Bishop);
+                if (bishops & LIGHT_SQUARES).population() == 1 {
+                    min(scale, OPPOSITE_BISHOPS_SCALE)
+                } else {
+                    scale
+                }
+            },
+            _ => scale
+        }
+    }
+}
+
+// direct-mapped on a hash of the material key
+pub struct MaterialTable {
+    entries: Vec<MaterialInfo>
+}
+
+impl MaterialTable {
+    pub fn new(num_entries: usize) -> MaterialTable {
+        debug_assert!(num_entries.is_power_of_two());
+
+        // like the pawn hash table, an empty entry is also a correct one
+        MaterialTable {
+            entries: vec![MaterialInfo::compute(MaterialKey::bare_kings()); num_entries]
+        }
+    }
+
+    pub fn probe(&mut self, key: MaterialKey) -> MaterialInfo {
+        let idx = (key.0.wrapping_mul(0x9e3779b97f4a7c15) >> 32) as usize & (self.entries.len() - 1);
+        let entry = self.entries[idx];
+
+        if entry.key == key {
+            debug_assert!(entry == MaterialInfo::compute(key));
+            return entry;
+        }
+
+        let new_entry = MaterialInfo::compute(key);
+        self.entries[idx] = new_entry;
+        return new_entry;
+    }
+}
+
+fn king_distance(a: Square, b: Square) -> i16 {
+    let file_dist = (a.file() as i16 - b.file() as i16).abs();
+    let rank_dist = (a.rank() as i16 - b.rank() as i16).abs();
+    max(file_dist, rank_dist)
+}
+
+// from the strong side's point of view
+fn kpk_score(game: &Game, strong: Color, params: &EvalParams) -> Score {
+    use PieceType::*;
+
+    let board = &game.board;
+    let wk = board.get_king_square(strong);
+    let wp = board.get_pieces(strong, Pawn).bitscan_forward();
+    let bk = board.get_king_square(!strong);
+
+    if !kpk_is_win(strong, wk, wp, bk, game.to_move) {
+        return Score::new(0);
+    }
+
+    // known win, but still prefer pushing the pawn
+    let relative_rank = match strong {
+        Color::White => wp.rank(),
+        Color::Black => 9 - wp.rank()
+    };
+
+    return Score::new(params.material(Queen).1 + 10 * relative_rank as i16);
+}
+
+// Drive the lone king to the edge and bring the other king closer, from the
+// strong side's point of view. With only a bishop and a knight to mate with,
+// it has to be a corner the bishop covers.
+fn mop_up_score(board: &Board, strong: Color, params: &EvalParams) -> Score {
+    use PieceType::*;
+
+    let strong_king = board.get_king_square(strong);
+    let weak_king = board.get_king_square(!strong);
+
+    let mut score = 0;
+
+    for ptype in [Knight, Bishop, Rook, Queen].iter() {
+        score += board.get_pieces(strong, *ptype).population() as i16 * params.material(*ptype).1;
+    }
+
+    let bishops = board.get_pieces(strong, Bishop);
+    let knights = board.get_pieces(strong, Knight);
+    let only_minors = board.get_pieces(strong, Rook).empty() && board.get_pieces(strong, Queen).empty();
+
+    if only_minors && bishops.population() == 1 && knights.population() == 1 {
+        let bishop_squares = if (bishops & LIGHT_SQUARES).nonempty() { LIGHT_SQUARES } else { DARK_SQUARES };
+        let corner_distance = [0, 7, 56, 63].iter()
+            .map(|idx| Square::new(*idx))
+            .filter(|sq| (sq.bitrep() & bishop_squares).nonempty())
+            .map(|sq| king_distance(weak_king, sq))
+            .min()
+            .unwrap();
+
+        score += 20 * (7 - corner_distance);
+    } else {
+        let center_distance = |coord: u32| max(4 - coord as i16, coord as i16 - 5);
+        score += 20 * (center_distance(weak_king.file()) + center_distance(weak_king.rank()));
+    }
+
+    score += 10 * (8 - king_distance(strong_king, weak_king));
+
+    return Score::new(score);
+}
+
+#[cfg(test)]
+mod test {
+    use material::*;
+    use moves::*;
+    use zobrist::*;
+
+    #[test]
+    fn material_key() {
+        use movegen::*;
+
+        init_zobrist_hashing();
+
+        for _ in 0 .. 1000 {
+            let mut game = Game::random_game();
+
+            for m in next_moves_standalone(&game).iter() {
+                let mut game_copy = game;
+                game_copy.make_move(*m);
+                assert_eq!(game_copy.material_key, MaterialKey::new(&game_copy.board));
+            }
+
+            game.flip_color();
+            assert_eq!(game.material_key, MaterialKey::new(&game.board));
+        }
+
+        let start = Game::starting_position();
+        assert_eq!(start.material_key.count(Color::White, PieceType::Pawn), 8);
+        assert_eq!(start.material_key.count(Color::Black, PieceType::Knight), 2);
+        assert_eq!(start.material_key.count(Color::Black, PieceType::Queen), 1);
+    }
+
+    // plays the moves, checking which evaluator each position ends up with
+    fn route(fen: &str, moves: &[(&str, Option<(Endgame, Color)>)]) -> Game {
+        use movegen::*;
+
+        let mut table = MaterialTable::new(16);
+        let mut game = Game::from_fen_str(fen).unwrap();
+
+        for (uci_move, expected) in moves.iter() {
+            let m = *next_moves_standalone(&game).iter().find(|m| m.to_uci_str() == *uci_move).unwrap();
+            game.make_move(m);
+            assert_eq!(table.probe(game.material_key).specialized_eval, *expected, "after {}", uci_move);
+            assert_eq!(table.probe(game.material_key), MaterialInfo::compute(MaterialKey::new(&game.board)));
+        }
+
+        return game;
+    }
+
+    #[test]
+    fn specialized_evals() {
+        use Color::*;
+        use Endgame::*;
+
+        init_zobrist_hashing();
+
+        // trading the rooks leaves king and pawn against king
+        route("4k3/8/8/8/7r/3P4/6K1/7R b - - 0 1", &[
+            ("h4h1", None),
+            ("g2h1", Some((KPvK, White)))
+        ]);
+
+        // the last pawn falls, black mops up
+        route("8/8/8/8/8/k7/1P5q/3K4 b - - 0 1", &[
+            ("h2b2", Some((KXvK, Black)))
+        ]);
+
+        // a new queen takes the last black piece
+        route("8/1P6/8/8/5b2/8/2k5/K7 w - - 0 1", &[
+            ("b7b8q", None),
+            ("c2c3", None),
+            ("b8f4", Some((KXvK, White)))
+        ]);
+
+        // but a knight against a bishop can't be won
+        route("8/1P6/8/8/5b2/8/2k5/K7 w - - 0 1", &[
+            ("b7b8n", Some((Insufficient, White)))
+        ]);
+
+        // a bishop each and pawns only change the scale factor
+        let game = route("4k2r/3p4/2b5/8/8/3P4/3B4/4K2R w - - 0 1", &[
+            ("h1h8", None),
+            ("e8e7", None),
+            ("h8f8", None),
+            ("e7f8", Some((OppositeBishops, White)))
+        ]);
+
+        let info = MaterialInfo::compute(game.material_key);
+        assert_eq!(info.scale_factor(&game.board, White), OPPOSITE_BISHOPS_SCALE);
+        assert_eq!(Endgame::OppositeBishops.evaluate(&game, White, &DEFAULT_EVAL_PARAMS), None);
+
+        let same_colors = Game::from_fen_str("5k2/3p4/8/2b5/8/3P4/3B4/4K3 w - - 0 1").unwrap();
+        assert_eq!(info.scale_factor(&same_colors.board, White), NORMAL_SCALE);
+    }
+
+    #[test]
+    fn scale_factors() {
+        use Color::*;
+
+        // a rook against a minor piece is usually a draw, a lone minor piece never wins
+        let rook_v_bishop = Game::from_fen_str("4k3/8/8/2b5/8/8/8/R3K3 w - - 0 1").unwrap();
+        assert_eq!(MaterialInfo::compute(rook_v_bishop.material_key).scale_factor, [4, 0]);
+
+        let bishop_v_pawn = Game::from_fen_str("4k3/4p3/8/8/8/8/8/2B1K3 w - - 0 1").unwrap();
+        assert_eq!(MaterialInfo::compute(bishop_v_pawn.material_key).scale_factor[White as usize], 0);
+
+        let start = Game::starting_position();
+        assert_eq!(MaterialInfo::compute(start.material_key).scale_factor, [NORMAL_SCALE; 2]);
+    }
+}
@@ -1,6 +1,5 @@
 use core::*;
 use moves::*;
-use std::cmp::Ordering;
 
 #[derive(Clone, Copy)]
 pub struct MoveList {
@@ -31,40 +30,9 @@ impl MoveList {
     #[allow(dead_code)]
     pub fn at(&self, idx: usize) -> Move { return self.moves[idx]; }
 
-    pub fn sort(&mut self, best_move_candidate: Option<Move>) {
-        self.moves[..self.count].sort_by(|m1, m2| {
-            if (best_move_candidate.is_some()) {
-                if (*m1 == best_move_candidate.unwrap()) {
-                    return Ordering::Less;
-                }
-                if (*m2 == best_move_candidate.unwrap()) {
-                    return Ordering::Greater;
-                }
-            }
-            if m1.is_capture() && !m2.is_capture() {
-                return Ordering::Less;
-            } else if !m1.is_capture() && m2.is_capture() {
-                return Ordering::Greater;
-            } else if m1.is_capture() && m2.is_capture() {
-                let p1 = m1.captured_piece().unwrap();
-                let m1 = m1.moved_piece();
-                let p2 = m2.captured_piece().unwrap();
-                let m2 = m2.moved_piece();
-
-                let d1 = p1 as i32 - m1 as i32;
-                let d2 = p2 as i32 - m2 as i32;
-
-                if d1 > d2 {
-                    return Ordering::Less;
-                } else if d2 > d1 {
-                    return Ordering::Greater;
-                } else {
-                    return Ordering::Equal;
-                }
-            } else {
-                return Ordering::Equal;
-            }
-        });
+    // stable, see order_moves in ordering.rs for how moves are actually ranked
+    pub fn sort_by_key<K: Ord, F: FnMut(&Move) -> K>(&mut self, f: F) {
+        self.moves[..self.count].sort_by_key(f);
     }
 
 }
@@ -6,29 +6,20 @@ use game::*;
 use move_list::*;
 use bitboard::*;
 
-use std::rc::Rc;
-use std::cell::RefCell;
 use rand::Rng;
 
-pub type MoveBuffer = Rc<RefCell<MoveList>>;
-
-pub fn alloc_move_buffer() -> MoveBuffer {
-    Rc::new(RefCell::new(MoveList::new()))
-}
-
 pub fn next_moves_standalone(game: &Game) -> MoveList {
-    let buf = alloc_move_buffer();
-    generate_moves(game, buf.clone(), false);
-    return buf.borrow().clone();
+    let mut moves = MoveList::new();
+    generate_moves(game, &mut moves, false);
+    return moves;
 }
 
 pub fn next_moves_standalone_chunked(game: &Game, chunks: usize) -> Vec<Vec<Move>> {
-    let buf = alloc_move_buffer();
-    generate_moves(&game, buf.clone(), false);
+    let moves = next_moves_standalone(game);
 
     let mut move_chunks = Vec::new();
 
-    for (i, m) in buf.borrow().iter().enumerate() {
+    for (i, m) in moves.iter().enumerate() {
         if move_chunks.len() <= i {
             move_chunks.push(Vec::new());
         }
@@ -40,11 +31,15 @@ pub fn next_moves_standalone_chunked(game: &Game, chunks: usize) -> Vec<Vec<Move
 }
 
 // returns true if any moves are found
-pub fn generate_moves(game: &Game, buffer: MoveBuffer, captures_only: bool) {
+pub fn generate_moves(game: &Game, moves: &mut MoveList, captures_only: bool) {
+    generate_moves_from(game, moves, captures_only, Bitboard::all_set());
+}
+
+// only the moves of pieces on from_squares, the rest of the position isn't generated at all
+pub fn generate_moves_from(game: &Game, moves: &mut MoveList, captures_only: bool, from_squares: Bitboard) {
     use Color::*;
     use PieceType::*;
 
-    let mut moves = buffer.borrow_mut();
     moves.clear();
 
     // OPTIMIZE: check if any of these can be moved below
@@ -59,6 +54,7 @@ pub fn generate_moves(game: &Game, buffer: MoveBuffer, captures_only: bool) {
     let check_multiplicity  = king_attackers.population();
     let in_check            = check_multiplicity > 0;
     let king_danger_squares = game.board.attacked(opponent_color, true);
+    let king_selected       = (king_square.bitrep() & from_squares).nonempty();
 
     //TODO: replace with Board::piece_at
     let opponent_pawns = game.board.get_pieces(opponent_color, Pawn);
@@ -94,17 +90,21 @@ pub fn generate_moves(game: &Game, buffer: MoveBuffer, captures_only: bool) {
         // If the king is in double+ check, the only legal moves are
         // king moves, so we compute them and return early.
 
+        if !king_selected {
+            return;
+        }
+
         // let king_moves = unsafe { *KING_TABLE.get_unchecked(king_square.idx()) };
         let king_moves = KING_TABLE[king_square.idx()];
 
         if !captures_only {
             for to in king_moves & empty_squares & !king_danger_squares {
-                moves.add(Move::new_quiet(king_square, to, QUIET_FLAG, King));
+                moves.add(Move::new_quiet(king_square, to, MoveFlag::Quiet, King));
             }
         }
 
         for to in king_moves & opponent_pieces & !king_danger_squares {
-            moves.add(Move::new_capture(king_square, to, CAPTURE_FLAG,
+            moves.add(Move::new_capture(king_square, to, MoveFlag::Capture,
                                             King, opp_ptype_at(to)));
         }
 
@@ -135,18 +135,18 @@ pub fn generate_moves(game: &Game, buffer: MoveBuffer, captures_only: bool) {
     /***********/
     /* KNIGHTS */
     /***********/
-    for from in game.board.get_pieces(friendly_color, Knight) & !pinned
+    for from in game.board.get_pieces(friendly_color, Knight) & !pinned & from_squares
     {
         let knight_moves = KNIGHT_TABLE[from.idx()];
 
         if !captures_only {
             for to in knight_moves & empty_squares & quiet_mask {
-                moves.add( Move::new_quiet(from, to, QUIET_FLAG, Knight) );
+                moves.add( Move::new_quiet(from, to, MoveFlag::Quiet, Knight) );
             }
         }
 
         for to in knight_moves & opponent_pieces & capture_mask {
-            moves.add( Move::new_capture(from, to, CAPTURE_FLAG, Knight, opp_ptype_at(to)) );
+            moves.add( Move::new_capture(from, to, MoveFlag::Capture, Knight, opp_ptype_at(to)) );
         }
     }
 
@@ -169,10 +169,10 @@ pub fn generate_moves(game: &Game, buffer: MoveBuffer, captures_only: bool) {
     //         let from = b1.bitscan_forward();
     //         let b1att = att.extract(0);
     //         for to in b1att & empty_squares & quiet_mask {
-    //             moves.add( Move::new_quiet(from, to, QUIET_FLAG, Bishop) );
+    //             moves.add( Move::new_quiet(from, to, MoveFlag::Quiet, Bishop) );
     //         }
     //         for to in b1att & opponent_pieces & capture_mask {
-    //             moves.add( Move::new_capture(from, to, CAPTURE_FLAG, Bishop, opp_ptype_at(to)) );
+    //             moves.add( Move::new_capture(from, to, MoveFlag::Capture, Bishop, opp_ptype_at(to)) );
     //         }
     //     }
 
@@ -181,15 +181,15 @@ pub fn generate_moves(game: &Game, buffer: MoveBuffer, captures_only: bool) {
     //         let from = b2.bitscan_forward();
     //         let b2att = att.extract(1);
     //         for to in b2att & empty_squares & quiet_mask {
-    //             moves.add( Move::new_quiet(from, to, QUIET_FLAG, Bishop) );
+    //             moves.add( Move::new_quiet(from, to, MoveFlag::Quiet, Bishop) );
     //         }
     //         for to in b2att & opponent_pieces & capture_mask {
-    //             moves.add( Move::new_capture(from, to, CAPTURE_FLAG, Bishop, opp_ptype_at(to)) );
+    //             moves.add( Move::new_capture(from, to, MoveFlag::Capture, Bishop, opp_ptype_at(to)) );
     //         }
     //     }
     // }
 
-    let friendly_bishops = game.board.get_pieces(friendly_color, Bishop);
+    let friendly_bishops = game.board.get_pieces(friendly_color, Bishop) & from_squares;
 
     // UNPINNED
     for from in friendly_bishops & !pinned
@@ -198,12 +198,12 @@ pub fn generate_moves(game: &Game, buffer: MoveBuffer, captures_only: bool) {
 
         if !captures_only {
             for to in bishop_moves & empty_squares & quiet_mask {
-                moves.add( Move::new_quiet(from, to, QUIET_FLAG, Bishop) );
+                moves.add( Move::new_quiet(from, to, MoveFlag::Quiet, Bishop) );
             }
         }
 
         for to in bishop_moves & opponent_pieces & capture_mask {
-            moves.add( Move::new_capture(from, to, CAPTURE_FLAG, Bishop, opp_ptype_at(to)) );
+            moves.add( Move::new_capture(from, to, MoveFlag::Capture, Bishop, opp_ptype_at(to)) );
         }
     }
 
@@ -215,12 +215,12 @@ pub fn generate_moves(game: &Game, buffer: MoveBuffer, captures_only: bool) {
 
         if !captures_only {
             for to in bishop_moves & empty_squares & quiet_mask {
-                moves.add( Move::new_quiet(from, to, QUIET_FLAG, Bishop) );
+                moves.add( Move::new_quiet(from, to, MoveFlag::Quiet, Bishop) );
             }
         }
 
         for to in bishop_moves & opponent_pieces & capture_mask {
-            moves.add( Move::new_capture(from, to, CAPTURE_FLAG, Bishop, opp_ptype_at(to)) );
+            moves.add( Move::new_capture(from, to, MoveFlag::Capture, Bishop, opp_ptype_at(to)) );
         }
     }
 
@@ -228,7 +228,7 @@ pub fn generate_moves(game: &Game, buffer: MoveBuffer, captures_only: bool) {
     /* ROOKS */
     /*********/
 
-    let friendly_rooks = game.board.get_pieces(friendly_color, Rook);
+    let friendly_rooks = game.board.get_pieces(friendly_color, Rook) & from_squares;
 
     // unpinned
     for from in friendly_rooks & !pinned
@@ -238,13 +238,13 @@ pub fn generate_moves(game: &Game, buffer: MoveBuffer, captures_only: bool) {
         /* quiets */
         if !captures_only {
             for to in rook_moves & empty_squares & quiet_mask {
-                moves.add( Move::new_quiet(from, to, QUIET_FLAG, Rook) );
+                moves.add( Move::new_quiet(from, to, MoveFlag::Quiet, Rook) );
             }
         }
 
         /* captures */
         for to in rook_moves & opponent_pieces & capture_mask {
-            moves.add( Move::new_capture(from, to, CAPTURE_FLAG, Rook, opp_ptype_at(to)) );
+            moves.add( Move::new_capture(from, to, MoveFlag::Capture, Rook, opp_ptype_at(to)) );
         }
     }
 
@@ -257,13 +257,13 @@ pub fn generate_moves(game: &Game, buffer: MoveBuffer, captures_only: bool) {
         /* quiets */
         if !captures_only {
             for to in rook_moves & empty_squares & quiet_mask {
-                moves.add( Move::new_quiet(from, to, QUIET_FLAG, Rook) );
+                moves.add( Move::new_quiet(from, to, MoveFlag::Quiet, Rook) );
             }
         }
 
         /* captures */
         for to in rook_moves & opponent_pieces & capture_mask {
-            moves.add( Move::new_capture(from, to, CAPTURE_FLAG, Rook, opp_ptype_at(to)) );
+            moves.add( Move::new_capture(from, to, MoveFlag::Capture, Rook, opp_ptype_at(to)) );
         }
     }
 
@@ -271,7 +271,7 @@ pub fn generate_moves(game: &Game, buffer: MoveBuffer, captures_only: bool) {
     /* QUEEN */
     /*********/
 
-    let friendly_queens = game.board.get_pieces(friendly_color, Queen);
+    let friendly_queens = game.board.get_pieces(friendly_color, Queen) & from_squares;
 
     for from in friendly_queens & !pinned
     {
@@ -280,13 +280,13 @@ pub fn generate_moves(game: &Game, buffer: MoveBuffer, captures_only: bool) {
         /* quiets */
         if !captures_only {
             for to in queen_moves & empty_squares & quiet_mask {
-                moves.add( Move::new_quiet(from, to, QUIET_FLAG, Queen) );
+                moves.add( Move::new_quiet(from, to, MoveFlag::Quiet, Queen) );
             }
         }
 
         /* captures */
         for to in queen_moves & opponent_pieces & capture_mask {
-            moves.add( Move::new_capture(from, to, CAPTURE_FLAG, Queen, opp_ptype_at(to)) );
+            moves.add( Move::new_capture(from, to, MoveFlag::Capture, Queen, opp_ptype_at(to)) );
         }
     }
 
@@ -300,13 +300,13 @@ pub fn generate_moves(game: &Game, buffer: MoveBuffer, captures_only: bool) {
         /* quiets */
         if !captures_only {
             for to in queen_moves & empty_squares & quiet_mask {
-                moves.add( Move::new_quiet(from, to, QUIET_FLAG, Queen) );
+                moves.add( Move::new_quiet(from, to, MoveFlag::Quiet, Queen) );
             }
         }
 
         /* captures */
         for to in queen_moves & opponent_pieces & capture_mask {
-            moves.add( Move::new_capture(from, to, CAPTURE_FLAG, Queen, opp_ptype_at(to)) );
+            moves.add( Move::new_capture(from, to, MoveFlag::Capture, Queen, opp_ptype_at(to)) );
         }
     }
 
@@ -318,17 +318,17 @@ pub fn generate_moves(game: &Game, buffer: MoveBuffer, captures_only: bool) {
         /* quiets */
         if !captures_only {
             for to in queen_moves & empty_squares & quiet_mask {
-                moves.add( Move::new_quiet(from, to, QUIET_FLAG, Queen) );
+                moves.add( Move::new_quiet(from, to, MoveFlag::Quiet, Queen) );
             }
         }
 
         /* captures */
         for to in queen_moves & opponent_pieces & capture_mask {
-            moves.add( Move::new_capture(from, to, CAPTURE_FLAG, Queen, opp_ptype_at(to)) );
+            moves.add( Move::new_capture(from, to, MoveFlag::Capture, Queen, opp_ptype_at(to)) );
         }
     }
 
-    let friendly_pawns = game.board.get_pieces(friendly_color, Pawn);
+    let friendly_pawns = game.board.get_pieces(friendly_color, Pawn) & from_squares;
     let delta_pawn_single_push: i32 = if game.to_move == White { -8 } else { 8 };
     let delta_pawn_double_push: i32 = if game.to_move == White { -16 } else { 16 };
     let double_pawn_push_rank = if game.to_move == White { RANK4 } else { RANK5 };
@@ -360,12 +360,12 @@ pub fn generate_moves(game: &Game, buffer: MoveBuffer, captures_only: bool) {
                 }
 
             if to.rank() == promotion_rank {
-                moves.add(Move::new_quiet(from, to, KNIGHT_PROMO_FLAG, Pawn));
-                moves.add(Move::new_quiet(from, to, BISHOP_PROMO_FLAG, Pawn));
-                moves.add(Move::new_quiet(from, to, ROOK_PROMO_FLAG, Pawn));
-                moves.add(Move::new_quiet(from, to, QUEEN_PROMO_FLAG, Pawn));
+                moves.add(Move::new_promotion(from, to, Knight, None));
+                moves.add(Move::new_promotion(from, to, Bishop, None));
+                moves.add(Move::new_promotion(from, to, Rook, None));
+                moves.add(Move::new_promotion(from, to, Queen, None));
             } else {
-                moves.add(Move::new_quiet(from, to, QUIET_FLAG, Pawn));
+                moves.add(Move::new_quiet(from, to, MoveFlag::Quiet, Pawn));
             }
         }
 
@@ -385,7 +385,7 @@ pub fn generate_moves(game: &Game, buffer: MoveBuffer, captures_only: bool) {
                     continue;
                 }
 
-            moves.add(Move::new_quiet(from, to, DOUBLE_PAWN_PUSH_FLAG, Pawn));
+            moves.add(Move::new_quiet(from, to, MoveFlag::DoublePawnPush, Pawn));
         }
     }
 
@@ -405,12 +405,13 @@ pub fn generate_moves(game: &Game, buffer: MoveBuffer, captures_only: bool) {
         for to in pawn_attack_pattern & opponent_pieces
         {
             if to.rank() == promotion_rank {
-                moves.add(Move::new_capture(from, to, KNIGHT_PROMO_CAPTURE_FLAG, Pawn, opp_ptype_at(to)));
-                moves.add(Move::new_capture(from, to, BISHOP_PROMO_CAPTURE_FLAG, Pawn, opp_ptype_at(to)));
-                moves.add(Move::new_capture(from, to, ROOK_PROMO_CAPTURE_FLAG, Pawn, opp_ptype_at(to)));
-                moves.add(Move::new_capture(from, to, QUEEN_PROMO_CAPTURE_FLAG, Pawn, opp_ptype_at(to)));
+                let captured = Some(opp_ptype_at(to));
+                moves.add(Move::new_promotion(from, to, Knight, captured));
+                moves.add(Move::new_promotion(from, to, Bishop, captured));
+                moves.add(Move::new_promotion(from, to, Rook, captured));
+                moves.add(Move::new_promotion(from, to, Queen, captured));
             } else {
-                moves.add(Move::new_capture(from, to, CAPTURE_FLAG, Pawn, opp_ptype_at(to)));
+                moves.add(Move::new_capture(from, to, MoveFlag::Capture, Pawn, opp_ptype_at(to)));
             }
         }
 
@@ -431,13 +432,12 @@ pub fn generate_moves(game: &Game, buffer: MoveBuffer, captures_only: bool) {
 
                         *board_copy.get_pieces_mut(opponent_color, Pawn) &= !captured_sq.bitrep();
                         *board_copy.get_pieces_mut(friendly_color, Pawn) ^= from.bitrep() | ep_capture_square.bitrep();
-                        *board_copy.occupied_by_mut(opponent_color) &= !captured_sq.bitrep();
-                        *board_copy.occupied_by_mut(friendly_color) ^= from.bitrep() | ep_capture_square.bitrep();
+                        board_copy.clear_occupied(opponent_color, captured_sq.bitrep());
+                        board_copy.toggle_occupied(friendly_color, from.bitrep() | ep_capture_square.bitrep());
 
                         let attackers = board_copy.attackers(king_square, opponent_color);
                         if attackers.empty() {
-                            moves.add(Move::new_capture(from, ep_capture_square, EP_CAPTURE_FLAG,
-                                                            Pawn, opp_ptype_at(captured_sq)));
+                            moves.add(Move::new_ep(from, ep_capture_square));
                         }
                     }
             }
@@ -449,86 +449,174 @@ pub fn generate_moves(game: &Game, buffer: MoveBuffer, captures_only: bool) {
     /* KING */
     /********/
 
+    if !king_selected {
+        return;
+    }
+
     let king_idx = king_square.idx();
     let king_moves = KING_TABLE[king_square.idx()];
 
     /* quiets */
     if !captures_only {
         for to in king_moves & empty_squares & !king_danger_squares {
-            moves.add( Move::new_quiet(king_square, to, QUIET_FLAG, King) );
+            moves.add( Move::new_quiet(king_square, to, MoveFlag::Quiet, King) );
         }
     }
 
     /* captures */
     for to in king_moves & opponent_pieces & !king_danger_squares {
-        moves.add( Move::new_capture(king_square, to, CAPTURE_FLAG, King, opp_ptype_at(to)) );
+        moves.add( Move::new_capture(king_square, to, MoveFlag::Capture, King, opp_ptype_at(to)) );
     }
 
     /* castling */
-    if !captures_only {
-        let has_kingside_castle_rights = match friendly_color {
-            White => game.castling_rights.intersects(CastlingRights::WHITE_KINGSIDE),
-            Black => game.castling_rights.intersects(CastlingRights::BLACK_KINGSIDE)
-        };
+    if !captures_only && !in_check {
+        for kingside in [true, false].iter() {
+            let right = CastlingRights::for_side(friendly_color, *kingside);
 
-        let has_queenside_castle_rights = match friendly_color {
-            White => game.castling_rights.intersects(CastlingRights::WHITE_QUEENSIDE),
-            Black => game.castling_rights.intersects(CastlingRights::BLACK_QUEENSIDE)
-        };
+            if game.can_castle(right, king_danger_squares) {
+                let (_, king_to, _, _) = game.castle_squares(right).unwrap();
+                moves.add(Move::new_castle(king_square, king_to, *kingside));
+            }
+        }
+    }
+}
 
-        if has_kingside_castle_rights && !in_check {
-            let kingside_bits = match friendly_color {
-                White => WHITE_KINGSIDE_CASTLE_BITS,
-                Black => BLACK_KINGSIDE_CASTLE_BITS
-            };
+fn castle_rook_squares(game: &Game, flag: MoveFlag) -> (Square, Square) {
+    let right = CastlingRights::for_side(game.to_move, flag == MoveFlag::KingCastle);
+    let (_, _, rook_from, rook_to) = game.castle_squares(right).unwrap();
+    return (rook_from, rook_to);
+}
 
-            let kingside_castle_path_open = (occupied_squares & kingside_bits).empty();
+// occupancy after a castle, where the king and rook may swap or stay put
+fn occupied_after_castle(game: &Game, m: Move) -> Bitboard {
+    let (rook_from, rook_to) = castle_rook_squares(game, m.flag());
+    (game.board.occupied() & !m.from().bitrep() & !rook_from.bitrep()) | m.to().bitrep() | rook_to.bitrep()
+}
 
-            if kingside_castle_path_open {
-                let mut castle_path_is_safe: bool = true;
+// Determines whether a (legal) move checks the opponent, without making it.
+pub fn gives_check(game: &Game, m: Move) -> bool {
+    gives_check_with(game, m, &discoverers(game))
+}
 
-                if (kingside_bits & king_danger_squares).nonempty() {
-                    castle_path_is_safe = false;
-                }
+// for callers asking about many moves from the same position, who can share the discoverers
+pub fn gives_check_with(game: &Game, m: Move, discoverers: &PinFinder) -> bool {
+    gives_direct_check(game, m) || gives_discovered_check_with(game, m, discoverers)
+}
 
-                if castle_path_is_safe {
-                    match friendly_color {
-                        White => moves.add(Move::new_quiet(king_square, Square::new(1), KING_CASTLE_FLAG, King)),
-                        Black => moves.add(Move::new_quiet(king_square, Square::new(57), KING_CASTLE_FLAG, King)),
-                    }
-                }
-            }
-        }
+// the pieces of the side to move that would uncover a check by moving off their line to the king
+pub fn discoverers(game: &Game) -> PinFinder {
+    let mut discoverers = PinFinder::new();
+    discoverers.update_discovered(game.to_move, &game.board);
+    return discoverers;
+}
 
-        if has_queenside_castle_rights && !in_check {
-            let queenside_path_bits = match friendly_color {
-                White => WHITE_QUEENSIDE_CASTLE_BITS,
-                Black => BLACK_QUEENSIDE_CASTLE_BITS
-            };
+// the moved piece (or the castled rook) attacks the enemy king from its destination
+pub fn gives_direct_check(game: &Game, m: Move) -> bool {
+    use PieceType::*;
 
-            let queenside_safety_bits = match friendly_color {
-                White => WHITE_QUEENSIDE_CASTLE_SAFETY_BITS,
-                Black => BLACK_QUEENSIDE_CASTLE_SAFETY_BITS
-            };
+    let moving_color = game.to_move;
+    let enemy_king = game.board.get_pieces(!moving_color, King);
+    let to_sq = m.to();
+    let occupied = (game.board.occupied() & !m.from().bitrep()) | to_sq.bitrep();
 
-            let queenside_castle_path_open = (occupied_squares & queenside_path_bits).empty();
+    if m.is_castle() {
+        let (_, rook_to) = castle_rook_squares(game, m.flag());
+        return (get_rook_rays(rook_to, occupied_after_castle(game, m)) & enemy_king).nonempty();
+    }
 
-            if queenside_castle_path_open {
-                let mut castle_path_is_safe: bool = true;
+    let ptype = match m.promotion_piece() {
+        Some(promoted) => promoted,
+        None => m.moved_piece()
+    };
 
-                if (queenside_safety_bits & king_danger_squares).nonempty() {
-                    castle_path_is_safe = false;
-                }
+    let attacks = match ptype {
+        Pawn   => PAWN_ATTACKS[moving_color as usize][to_sq.idx()],
+        Knight => KNIGHT_TABLE[to_sq.idx()],
+        Bishop => get_bishop_rays(to_sq, occupied),
+        Rook   => get_rook_rays(to_sq, occupied),
+        Queen  => get_queen_rays(to_sq, occupied),
+        King   => Bitboard::new(0)
+    };
 
-                if castle_path_is_safe {
-                    match friendly_color {
-                        White => moves.add(Move::new_quiet(king_square, Square::new(5), QUEEN_CASTLE_FLAG, King)),
-                        Black => moves.add(Move::new_quiet(king_square, Square::new(61), QUEEN_CASTLE_FLAG, King))
-                    }
-                }
-            }
+    return (attacks & enemy_king).nonempty();
+}
+
+// the moved piece uncovers an attack on the enemy king by one of our sliders
+pub fn gives_discovered_check(game: &Game, m: Move) -> bool {
+    gives_discovered_check_with(game, m, &discoverers(game))
+}
+
+pub fn gives_discovered_check_with(game: &Game, m: Move, discoverers: &PinFinder) -> bool {
+    use PieceType::*;
+
+    let moving_color = game.to_move;
+    let from_sq = m.from();
+    let to_sq = m.to();
+    if m.is_en_passant() || m.is_castle() {
+        // two squares are vacated here, so just recompute the slider attacks on the king
+        let king_square = game.board.get_king_square(!moving_color);
+        let mut occupied = (game.board.occupied() & !from_sq.bitrep()) | to_sq.bitrep();
+
+        let mut rooks_queens = game.board.get_pieces(moving_color, Rook) | game.board.get_pieces(moving_color, Queen);
+        let mut bishops_queens = game.board.get_pieces(moving_color, Bishop) | game.board.get_pieces(moving_color, Queen);
+        rooks_queens &= !from_sq.bitrep();
+        bishops_queens &= !from_sq.bitrep();
+
+        if m.is_castle() {
+            // the rook on its new square is a direct check, not a discovered one
+            let (rook_from, _) = castle_rook_squares(game, m.flag());
+            occupied = occupied_after_castle(game, m);
+            rooks_queens &= !rook_from.bitrep();
+        } else {
+            let captured_sq = match moving_color {
+                Color::White => Square::new(to_sq.unwrap() - 8),
+                Color::Black => Square::new(to_sq.unwrap() + 8)
+            };
+            occupied &= !captured_sq.bitrep();
         }
+
+        let attackers = (get_rook_rays(king_square, occupied) & rooks_queens)
+                      | (get_bishop_rays(king_square, occupied) & bishops_queens);
+
+        return attackers.nonempty();
+    }
+
+    let from_bit = from_sq.bitrep();
+    let to_bit = to_sq.bitrep();
+
+    if (from_bit & discoverers.pinned_diagonally()).nonempty()
+        && (to_bit & discoverers.diagonal_constraint(from_sq)).empty() {
+        return true;
+    }
+
+    if (from_bit & discoverers.pinned_nondiagonally()).nonempty()
+        && (to_bit & discoverers.nondiagonal_constraint(from_sq)).empty() {
+        return true;
+    }
+
+    return false;
+}
+
+// How UCI writes m, a legal move in game. With chess960 set, as UCI_Chess960 asks, castles
+// are written as the king capturing its own rook.
+pub fn move_to_uci_str(game: &Game, m: Move, chess960: bool) -> String {
+    if chess960 && m.is_castle() {
+        let (rook_from, _) = castle_rook_squares(game, m.flag());
+        return format!("{}{}", m.from().to_algebraic(), rook_from.to_algebraic());
     }
+
+    return m.to_uci_str();
+}
+
+// as move_to_uci_str, for moves played one after another from game
+pub fn uci_move_strs(game: &Game, moves: &[Move], chess960: bool) -> Vec<String> {
+    let mut game = *game;
+
+    moves.iter().map(|&m| {
+        let move_str = move_to_uci_str(&game, m, chess960);
+        game.make_move(m);
+        move_str
+    }).collect()
 }
 
 //NOTE: highly inefficient, but this will rarely be used.
@@ -559,30 +647,31 @@ pub fn move_from_algebraic(game: &Game, move_str: String) -> Option<Move> {
             if m.from() == from_sq && m.to() == to_sq {
                 return Some(*m);
             }
+
+            // Chess960 castles are written as the king capturing its own rook, which is never
+            // a legal move otherwise, so it's read that way for standard chess too
+            if m.is_castle() && m.from() == from_sq && castle_rook_squares(game, m.flag()).0 == to_sq {
+                return Some(*m);
+            }
         }
     } else {
 
-        let promo_flag = match move_str.chars().nth(4) {
-            Some('k') => KNIGHT_PROMO_FLAG,
-            Some('K') => KNIGHT_PROMO_FLAG,
-            Some('n') => KNIGHT_PROMO_FLAG,
-            Some('N') => KNIGHT_PROMO_FLAG,
-            Some('b') => BISHOP_PROMO_FLAG,
-            Some('B') => BISHOP_PROMO_FLAG,
-            Some('r') => ROOK_PROMO_FLAG,
-            Some('R') => ROOK_PROMO_FLAG,
-            Some('q') => QUEEN_PROMO_FLAG,
-            Some('Q') => QUEEN_PROMO_FLAG,
-            _ => 0
+        let promoted = match move_str.chars().nth(4) {
+            Some('k') => PieceType::Knight,
+            Some('K') => PieceType::Knight,
+            Some('n') => PieceType::Knight,
+            Some('N') => PieceType::Knight,
+            Some('b') => PieceType::Bishop,
+            Some('B') => PieceType::Bishop,
+            Some('r') => PieceType::Rook,
+            Some('R') => PieceType::Rook,
+            Some('q') => PieceType::Queen,
+            Some('Q') => PieceType::Queen,
+            _ => return None
         };
 
-        if promo_flag == 0 {
-            return None;
-        }
-
         for m in next_moves_standalone(game).iter() {
-            let move_flag = m.flag() & 0b1011; // don't need to compare capture status
-            if m.from() == from_sq && m.to() == to_sq && (move_flag == promo_flag) {
+            if m.from() == from_sq && m.to() == to_sq && m.promotion_piece() == Some(promoted) {
                 return Some(*m);
             }
         }
@@ -591,6 +680,66 @@ pub fn move_from_algebraic(game: &Game, move_str: String) -> Option<Move> {
     return None;
 }
 
+// Standard algebraic notation for a legal move, with only as much of the origin square as it
+// takes to tell it apart from other pieces of the same kind that could go to the same square.
+pub fn move_to_san(game: &Game, m: Move) -> String {
+    use PieceType::*;
+
+    let mut san = String::new();
+    let from_str = m.from().to_algebraic();
+
+    if m.is_castle() {
+        san.push_str(if m.flag() == MoveFlag::KingCastle { "O-O" } else { "O-O-O" });
+    } else {
+        let ptype = m.moved_piece();
+
+        if ptype == Pawn {
+            if m.is_capture() {
+                san.push_str(&from_str[.. 1]);
+            }
+        } else {
+            san.push(ptype.to_char_upper());
+
+            let rivals: Vec<Square> = next_moves_standalone(game).iter()
+                .filter(|o| o.moved_piece() == ptype && o.to() == m.to() && o.from() != m.from())
+                .map(|o| o.from())
+                .collect();
+
+            if !rivals.is_empty() {
+                if rivals.iter().all(|sq| sq.file() != m.from().file()) {
+                    san.push_str(&from_str[.. 1]);
+                } else if rivals.iter().all(|sq| sq.rank() != m.from().rank()) {
+                    san.push_str(&from_str[1 ..]);
+                } else {
+                    san.push_str(&from_str);
+                }
+            }
+        }
+
+        if m.is_capture() {
+            san.push('x');
+        }
+
+        san.push_str(&m.to().to_algebraic());
+
+        if let Some(promoted) = m.promotion_piece() {
+            san.push('=');
+            san.push(promoted.to_char_upper());
+        }
+    }
+
+    let mut after = *game;
+    after.make_move(m);
+
+    match after.outcome {
+        Some(GameResult::Win(_)) => san.push('#'),
+        _ if after.in_check() => san.push('+'),
+        _ => {}
+    }
+
+    return san;
+}
+
 pub fn can_move(game: &Game) -> bool {
     use Color::*;
     use PieceType::*;
@@ -920,8 +1069,8 @@ pub fn can_move(game: &Game) -> bool {
 
                             *board_copy.get_pieces_mut(opponent_color, Pawn) &= !captured_sq.bitrep();
                             *board_copy.get_pieces_mut(friendly_color, Pawn) ^= from.bitrep() | ep_capture_square.bitrep();
-                            *board_copy.occupied_by_mut(opponent_color) &= !captured_sq.bitrep();
-                            *board_copy.occupied_by_mut(friendly_color) ^= from.bitrep() | ep_capture_square.bitrep();
+                            board_copy.clear_occupied(opponent_color, captured_sq.bitrep());
+                            board_copy.toggle_occupied(friendly_color, from.bitrep() | ep_capture_square.bitrep());
 
                             let attackers = board_copy.attackers(king_square, opponent_color);
                             if attackers.empty() {
@@ -952,64 +1101,326 @@ pub fn can_move(game: &Game) -> bool {
     }
 
     /* castling */
-    {
-        let has_kingside_castle_rights = match friendly_color {
-            White => game.castling_rights.intersects(CastlingRights::WHITE_KINGSIDE),
-            Black => game.castling_rights.intersects(CastlingRights::BLACK_KINGSIDE)
-        };
+    if !in_check {
+        for kingside in [true, false].iter() {
+            if game.can_castle(CastlingRights::for_side(friendly_color, *kingside), king_danger_squares) {
+                return true;
+            }
+        }
+    }
 
-        let has_queenside_castle_rights = match friendly_color {
-            White => game.castling_rights.intersects(CastlingRights::WHITE_QUEENSIDE),
-            Black => game.castling_rights.intersects(CastlingRights::BLACK_QUEENSIDE)
-        };
+    return false;
+}
+
+// Every move that obeys the piece movement rules, whether or not it leaves the king in check.
+// Pins and checks are deliberately ignored so this can serve as a reference for generate_moves.
+fn pseudo_legal_moves(game: &Game) -> Vec<Move> {
+    use Color::*;
+    use PieceType::*;
 
-        if has_kingside_castle_rights && !in_check {
-            let kingside_bits = match friendly_color {
-                White => WHITE_KINGSIDE_CASTLE_BITS,
-                Black => BLACK_KINGSIDE_CASTLE_BITS
+    let mut moves = Vec::new();
+
+    let friendly_color   = game.to_move;
+    let opponent_color   = !friendly_color;
+    let empty_squares    = game.board.unoccupied();
+    let occupied_squares = game.board.occupied();
+    let opponent_pieces  = game.board.occupied_by(opponent_color);
+    let promotion_rank   = if friendly_color == White { 8 } else { 1 };
+    let double_push_rank = if friendly_color == White { 2 } else { 7 };
+
+    let captured_at = |sq: Square| game.board.piece_at(sq).unwrap().ptype;
+
+    for ptype in [Knight, Bishop, Rook, Queen, King].iter() {
+        for from in game.board.get_pieces(friendly_color, *ptype) {
+            let targets = match *ptype {
+                Knight => KNIGHT_TABLE[from.idx()],
+                Bishop => get_bishop_rays(from, occupied_squares),
+                Rook => get_rook_rays(from, occupied_squares),
+                Queen => get_queen_rays(from, occupied_squares),
+                _ => KING_TABLE[from.idx()]
             };
 
-            let kingside_castle_path_open = (occupied_squares & kingside_bits).empty();
+            for to in targets & empty_squares {
+                moves.push(Move::new_quiet(from, to, MoveFlag::Quiet, *ptype));
+            }
+
+            for to in targets & opponent_pieces {
+                moves.push(Move::new_capture(from, to, MoveFlag::Capture, *ptype, captured_at(to)));
+            }
+        }
+    }
+
+    let promotions = [Knight, Bishop, Rook, Queen];
+
+    let forward = |sq: Square| if friendly_color == White {
+        Square::new(sq.unwrap() + 8)
+    } else {
+        Square::new(sq.unwrap() - 8)
+    };
 
-            if kingside_castle_path_open {
-                let mut castle_path_is_safe: bool = true;
+    for from in game.board.get_pieces(friendly_color, Pawn) {
+        let single = forward(from);
 
-                if (kingside_bits & king_danger_squares).nonempty() {
-                    castle_path_is_safe = false;
+        if (single.bitrep() & empty_squares).nonempty() {
+            if single.rank() == promotion_rank {
+                for promoted in promotions.iter() {
+                    moves.push(Move::new_promotion(from, single, *promoted, None));
                 }
+            } else {
+                moves.push(Move::new_quiet(from, single, MoveFlag::Quiet, Pawn));
+            }
 
-                if castle_path_is_safe {
-                    return true;
+            if from.rank() == double_push_rank {
+                let double = forward(single);
+                if (double.bitrep() & empty_squares).nonempty() {
+                    moves.push(Move::new_quiet(from, double, MoveFlag::DoublePawnPush, Pawn));
                 }
             }
         }
 
-        if has_queenside_castle_rights && !in_check {
-            let queenside_path_bits = match friendly_color {
-                White => WHITE_QUEENSIDE_CASTLE_BITS,
-                Black => BLACK_QUEENSIDE_CASTLE_BITS
-            };
+        let attacks = PAWN_ATTACKS[friendly_color as usize][from.idx()];
 
-            let queenside_safety_bits = match friendly_color {
-                White => WHITE_QUEENSIDE_CASTLE_SAFETY_BITS,
-                Black => BLACK_QUEENSIDE_CASTLE_SAFETY_BITS
-            };
+        for to in attacks & opponent_pieces {
+            if to.rank() == promotion_rank {
+                for promoted in promotions.iter() {
+                    moves.push(Move::new_promotion(from, to, *promoted, Some(captured_at(to))));
+                }
+            } else {
+                moves.push(Move::new_capture(from, to, MoveFlag::Capture, Pawn, captured_at(to)));
+            }
+        }
+
+        if let Some(ep_square) = game.ep_square {
+            if (attacks & ep_square.bitrep()).nonempty() {
+                moves.push(Move::new_ep(from, ep_square));
+            }
+        }
+    }
+
+    // the king's path can't be checked by make/unmake alone, so castling keeps its own test
+    if !game.in_check() {
+        let king_square = game.board.get_king_square(friendly_color);
+        let king_danger_squares = game.board.attacked(opponent_color, true);
+
+        for kingside in [true, false].iter() {
+            let right = CastlingRights::for_side(friendly_color, *kingside);
+
+            if game.can_castle(right, king_danger_squares) {
+                let (_, king_to, _, _) = game.castle_squares(right).unwrap();
+                moves.push(Move::new_castle(king_square, king_to, *kingside));
+            }
+        }
+    }
+
+    return moves;
+}
+
+// The slow way to find legal moves: make each pseudo-legal move and keep it if the mover's king is safe.
+pub fn legal_moves_by_makeunmake(game: &Game) -> Vec<Move> {
+    let mut legal_moves = Vec::new();
+
+    for m in pseudo_legal_moves(game) {
+        let mut game_copy = *game;
+        game_copy.make_move(m);
+
+        let king_square = game_copy.board.get_king_square(game.to_move);
+        if game_copy.board.attackers(king_square, !game.to_move).empty() {
+            legal_moves.push(m);
+        }
+    }
 
-            let queenside_castle_path_open = (occupied_squares & queenside_path_bits).empty();
+    return legal_moves;
+}
+
+#[cfg(test)]
+mod test {
+    use movegen::*;
+    use builder::*;
+
+    #[test]
+    fn matches_make_unmake_filter() {
+        use rand::thread_rng;
+
+        let fen_strings = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1"
+        ];
+
+        let sorted = |moves: Vec<Move>| {
+            let mut bits: Vec<u32> = moves.iter().map(|m| m.unwrap()).collect();
+            bits.sort();
+            bits
+        };
+
+        let mut rng = thread_rng();
+
+        for fen in fen_strings.iter() {
+            for _ in 0 .. 250 {
+                let mut game = Game::from_fen_str(fen).unwrap();
+
+                for _ in 0 .. 40 {
+                    let fast = sorted(next_moves_standalone(&game).iter().cloned().collect());
+                    let slow = sorted(legal_moves_by_makeunmake(&game));
+                    assert_eq!(fast, slow, "{}", game.to_fen());
 
-            if queenside_castle_path_open {
-                let mut castle_path_is_safe: bool = true;
+                    if fast.is_empty() || game.outcome.is_some() {
+                        break;
+                    }
 
-                if (queenside_safety_bits & king_danger_squares).nonempty() {
-                    castle_path_is_safe = false;
+                    game.make_move(Move::wrap(fast[rng.gen_range(0, fast.len())]));
                 }
+            }
+        }
+    }
 
-                if castle_path_is_safe {
-                    return true;
+    fn compare_gives_check(game: Game, depth: usize) {
+        if depth == 0 {
+            return;
+        }
+
+        for m in next_moves_standalone(&game).iter() {
+            let mut game_copy = game;
+            game_copy.make_move(*m);
+            assert!(gives_check(&game, *m) == game_copy.in_check(),
+                    "gives_check disagrees with make_move: {} {}", game.to_fen(), m.to_uci_str());
+            compare_gives_check(game_copy, depth - 1);
+        }
+    }
+
+    #[test]
+    fn gives_check_matches_make_move() {
+        let fen_strings: Vec<&'static str> = vec![
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1"
+        ];
+
+        for fen in fen_strings.iter() {
+            compare_gives_check(Game::from_fen_str(fen).unwrap(), 4);
+        }
+    }
+
+    // every pawn move to the last rank must come in all four promotion flavors
+    fn check_promotions(game: Game, depth: usize) {
+        use PieceType::*;
+
+        if depth == 0 {
+            return;
+        }
+
+        let moves = next_moves_standalone(&game);
+
+        for m in moves.iter() {
+            let last_rank = match game.to_move { Color::White => 8, Color::Black => 1 };
+
+            if m.moved_piece() == Pawn && m.to().rank() == last_rank {
+                assert!(m.is_promotion(), "{} {}", game.to_fen(), m.to_uci_str());
+
+                for ptype in [Knight, Bishop, Rook, Queen].iter() {
+                    let found = moves.iter().any(|other| other.from() == m.from() && other.to() == m.to()
+                                                 && other.promotion_piece() == Some(*ptype));
+                    assert!(found, "missing {:?} promotion: {} {}", ptype, game.to_fen(), m.to_uci_str());
                 }
             }
+
+            let mut game_copy = game;
+            game_copy.make_move(*m);
+            check_promotions(game_copy, depth - 1);
         }
     }
 
-    return false;
+    #[test]
+    fn all_promotions_generated() {
+        let fen_strings: Vec<&'static str> = vec![
+            "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8"
+        ];
+
+        for fen in fen_strings.iter() {
+            check_promotions(Game::from_fen_str(fen).unwrap(), 3);
+        }
+    }
+
+    #[test]
+    fn knight_promotion() {
+        let game = GameBuilder::new()
+            .piece(Color::White, PieceType::King, "e1")
+            .piece(Color::White, PieceType::Pawn, "b7")
+            .piece(Color::Black, PieceType::King, "e7")
+            .build().unwrap();
+
+        let knight_promo = next_moves_standalone(&game).iter()
+            .find(|m| m.promotion_piece() == Some(PieceType::Knight))
+            .cloned()
+            .unwrap();
+
+        assert_eq!(knight_promo.to_uci_str(), "b7b8n");
+
+        let mut game_copy = game;
+        game_copy.make_move(knight_promo);
+        assert_eq!(game_copy.to_fen(), "1N6/4k3/8/8/8/8/8/4K3 b - - 0 1");
+    }
+
+    #[test]
+    fn san() {
+        let san_of = |fen: &str, uci: &str| -> String {
+            let game = Game::from_fen_str(fen).unwrap();
+            move_to_san(&game, move_from_algebraic(&game, uci.to_string()).unwrap())
+        };
+
+        let en_passant = "r3k2r/8/8/3pP3/8/8/8/R3K2R w KQkq d6 0 1";
+        assert_eq!(san_of(en_passant, "e5d6"), "exd6");
+        assert_eq!(san_of(en_passant, "e1g1"), "O-O");
+        assert_eq!(san_of(en_passant, "e1c1"), "O-O-O");
+        assert_eq!(san_of(en_passant, "a1a8"), "Rxa8+");
+        assert_eq!(san_of(en_passant, "a1d1"), "Rd1");
+
+        let knights = "r6k/1P6/8/8/8/8/8/1N2KN2 w - - 0 1";
+        assert_eq!(san_of(knights, "b1d2"), "Nbd2");
+        assert_eq!(san_of(knights, "b7a8n"), "bxa8=N");
+        assert_eq!(san_of(knights, "b7b8q"), "b8=Q+");
+
+        // three knights reach c4, two of them share the a-file and two the third rank
+        let knights_on_c4 = "4k3/8/8/N7/8/N3N3/8/4K3 w - - 0 1";
+        assert_eq!(san_of(knights_on_c4, "a5c4"), "N5c4");
+        assert_eq!(san_of(knights_on_c4, "a3c4"), "Na3c4");
+        assert_eq!(san_of(knights_on_c4, "e3c4"), "Nec4");
+
+        assert_eq!(san_of("6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1", "d1d8"), "Rd8#");
+    }
+
+    #[test]
+    fn pinned_pieces() {
+        let destinations = |fen: &str, from: &str| -> Vec<String> {
+            let game = Game::from_fen_str(fen).unwrap();
+            let mut moves: Vec<String> = next_moves_standalone(&game).iter()
+                .filter(|m| m.from().to_algebraic() == from)
+                .map(|m| m.to().to_algebraic())
+                .collect();
+            moves.sort();
+            moves
+        };
+
+        // the bishop, rook and knight are all pinned to the king on e1
+        let pins = "4k3/4r3/8/8/1q5b/8/3BRN2/4K3 w - - 0 1";
+
+        // the bishop can only step towards the queen or take it
+        assert_eq!(destinations(pins, "d2"), vec!["b4", "c3"]);
+        assert_eq!(destinations(pins, "e2"), vec!["e3", "e4", "e5", "e6", "e7"]);
+        assert!(destinations(pins, "f2").is_empty());
+
+        let pinned_queen = "4k3/8/8/8/7b/8/5Q2/4K3 w - - 0 1";
+        assert_eq!(destinations(pinned_queen, "f2"), vec!["g3", "h4"]);
+
+        let game = Game::from_fen_str(pins).unwrap();
+        let queen_capture = next_moves_standalone(&game).iter()
+            .find(|m| m.to_uci_str() == "d2b4")
+            .cloned()
+            .unwrap();
+        assert_eq!(queen_capture.captured_piece(), Some(PieceType::Queen));
+    }
 }
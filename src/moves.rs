@@ -7,33 +7,83 @@ use rand::Rng;
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub struct Move(u32);
 
-//TODO: create newtype for MoveFlag
-/* 4-bit sequences describing move type */
-pub const QUIET_FLAG                : u32 = 0b0000;
-pub const DOUBLE_PAWN_PUSH_FLAG     : u32 = 0b0001;
-pub const KING_CASTLE_FLAG          : u32 = 0b0010;
-pub const QUEEN_CASTLE_FLAG         : u32 = 0b0011;
-pub const CAPTURE_FLAG              : u32 = 0b0100;
-pub const EP_CAPTURE_FLAG           : u32 = 0b0101;
-pub const KNIGHT_PROMO_FLAG         : u32 = 0b1000;
-pub const BISHOP_PROMO_FLAG         : u32 = 0b1001;
-pub const ROOK_PROMO_FLAG           : u32 = 0b1010;
-pub const QUEEN_PROMO_FLAG          : u32 = 0b1011;
-pub const KNIGHT_PROMO_CAPTURE_FLAG : u32 = 0b1100;
-pub const BISHOP_PROMO_CAPTURE_FLAG : u32 = 0b1101;
-pub const ROOK_PROMO_CAPTURE_FLAG   : u32 = 0b1110;
-pub const QUEEN_PROMO_CAPTURE_FLAG  : u32 = 0b1111;
+// The four bits of a Move above its squares, saying what kind of move it is. Promotions have
+// the 0b1000 bit, captures the 0b0100 bit, and the two low bits of a promotion are the piece.
+// 0b0110 and 0b0111 are left unused.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum MoveFlag {
+    Quiet              = 0b0000,
+    DoublePawnPush     = 0b0001,
+    KingCastle         = 0b0010,
+    QueenCastle        = 0b0011,
+    Capture            = 0b0100,
+    EpCapture          = 0b0101,
+    KnightPromo        = 0b1000,
+    BishopPromo        = 0b1001,
+    RookPromo          = 0b1010,
+    QueenPromo         = 0b1011,
+    KnightPromoCapture = 0b1100,
+    BishopPromoCapture = 0b1101,
+    RookPromoCapture   = 0b1110,
+    QueenPromoCapture  = 0b1111
+}
+
+impl MoveFlag {
+    pub fn from_bits(bits: u32) -> Option<MoveFlag> {
+        use MoveFlag::*;
+
+        match bits {
+            0b0000 => Some(Quiet),
+            0b0001 => Some(DoublePawnPush),
+            0b0010 => Some(KingCastle),
+            0b0011 => Some(QueenCastle),
+            0b0100 => Some(Capture),
+            0b0101 => Some(EpCapture),
+            0b1000 => Some(KnightPromo),
+            0b1001 => Some(BishopPromo),
+            0b1010 => Some(RookPromo),
+            0b1011 => Some(QueenPromo),
+            0b1100 => Some(KnightPromoCapture),
+            0b1101 => Some(BishopPromoCapture),
+            0b1110 => Some(RookPromoCapture),
+            0b1111 => Some(QueenPromoCapture),
+            _ => None
+        }
+    }
+
+    pub fn bits(self) -> u32 {
+        self as u32
+    }
+
+    pub fn all() -> &'static [MoveFlag] {
+        use MoveFlag::*;
+
+        static FLAGS: [MoveFlag; 14] = [
+            Quiet, DoublePawnPush, KingCastle, QueenCastle, Capture, EpCapture,
+            KnightPromo, BishopPromo, RookPromo, QueenPromo,
+            KnightPromoCapture, BishopPromoCapture, RookPromoCapture, QueenPromoCapture
+        ];
+
+        return &FLAGS;
+    }
+
+    // promoted must be a knight, bishop, rook or queen
+    pub fn promotion(promoted: PieceType, capture: bool) -> MoveFlag {
+        let capture_bit = if capture { 0b0100 } else { 0 };
+        MoveFlag::from_bits(0b1000 | capture_bit | (promoted as u32 - PieceType::Knight as u32)).unwrap()
+    }
+}
 
 impl Move {
     pub fn new_quiet( from: Square
                     , to: Square
-                    , flag: u32
+                    , flag: MoveFlag
                     , moved_piece: PieceType
                     ) -> Move
     {
         return Move(
               ((moved_piece as u32) << 16)
-            | (flag << 12)
+            | (flag.bits() << 12)
             | (from.unwrap() << 6)
             | to.unwrap()
         );
@@ -41,7 +91,7 @@ impl Move {
 
     pub fn new_capture( from: Square
                       , to: Square
-                      , flag: u32
+                      , flag: MoveFlag
                       , moved_piece: PieceType
                       , captured_piece: PieceType
                       ) -> Move
@@ -49,24 +99,49 @@ impl Move {
         return Move(
               ((captured_piece as u32) << 19)
             | ((moved_piece as u32) << 16)
-            | (flag << 12)
+            | (flag.bits() << 12)
             | (from.unwrap() << 6)
             | to.unwrap()
         );
     }
 
+    // promoted must be a knight, bishop, rook or queen
+    pub fn new_promotion( from: Square
+                        , to: Square
+                        , promoted: PieceType
+                        , captured_piece: Option<PieceType>
+                        ) -> Move
+    {
+        debug_assert!(promoted != PieceType::Pawn && promoted != PieceType::King);
+
+        return match captured_piece {
+            None => Move::new_quiet(from, to, MoveFlag::promotion(promoted, false), PieceType::Pawn),
+            Some(captured) => Move::new_capture(from, to, MoveFlag::promotion(promoted, true), PieceType::Pawn, captured)
+        };
+    }
+
+    // from and to are the king's squares
+    pub fn new_castle(from: Square, to: Square, kingside: bool) -> Move {
+        let flag = if kingside { MoveFlag::KingCastle } else { MoveFlag::QueenCastle };
+        return Move::new_quiet(from, to, flag, PieceType::King);
+    }
+
+    // to is the en passant square, not the square of the captured pawn
+    pub fn new_ep(from: Square, to: Square) -> Move {
+        return Move::new_capture(from, to, MoveFlag::EpCapture, PieceType::Pawn, PieceType::Pawn);
+    }
 
     #[allow(dead_code)]
     pub fn new_capture_detailed( from: Square
                       , to: Square
-                      , flag: u32
+                      , flag: MoveFlag
                       , moved_piece: PieceType
                       , captured_piece: PieceType
                       ) -> Move
     {
         let a = ((captured_piece as u32) << 19);
         let b = ((moved_piece as u32) << 16);
-        let c = ((flag) << 12);
+        let c = ((flag.bits()) << 12);
         let d = ((from.unwrap()) << 6);
         let e = (to.unwrap());
 
@@ -88,16 +163,57 @@ impl Move {
         return Square::new((self.0 >> 6) & 0x3f);
     }
 
-    pub fn flag(&self) -> u32 {
+    fn flag_bits(&self) -> u32 {
         return (self.0 >> 12) & 0xf;
     }
 
+    // only moves made by the constructors, Game::is_legal checks the ones that might not be
+    pub fn flag(&self) -> MoveFlag {
+        match MoveFlag::from_bits(self.flag_bits()) {
+            Some(flag) => flag,
+            None => panic!("Invalid move flag: {:04b}", self.flag_bits())
+        }
+    }
+
     pub fn is_capture(&self) -> bool {
-        return self.flag() & 0b0100 != 0;
+        return self.flag_bits() & 0b0100 != 0;
     }
 
     pub fn is_promotion(&self) -> bool {
-        return self.flag() & 0b1000 != 0;
+        return self.flag_bits() & 0b1000 != 0;
+    }
+
+    pub fn is_castle(&self) -> bool {
+        match self.flag() {
+            MoveFlag::KingCastle | MoveFlag::QueenCastle => true,
+            _ => false
+        }
+    }
+
+    pub fn is_en_passant(&self) -> bool {
+        return self.flag() == MoveFlag::EpCapture;
+    }
+
+    pub fn is_double_push(&self) -> bool {
+        return self.flag() == MoveFlag::DoublePawnPush;
+    }
+
+    // double pawn pushes count as quiet
+    pub fn is_quiet(&self) -> bool {
+        return !self.is_capture() && !self.is_promotion() && !self.is_castle();
+    }
+
+    pub fn promotion_piece(&self) -> Option<PieceType> {
+        if !self.is_promotion() {
+            return None;
+        }
+
+        return match self.flag_bits() & 0b0011 {
+            0 => Some(PieceType::Knight),
+            1 => Some(PieceType::Bishop),
+            2 => Some(PieceType::Rook),
+            _ => Some(PieceType::Queen)
+        }
     }
 
     pub fn moved_piece(&self) -> PieceType {
@@ -127,9 +243,17 @@ impl Move {
         self.0 == 0x0
     }
 
+    // as standard chess has it, Chess960 castles need the position and move_to_uci_str
     pub fn to_uci_str(&self) -> String {
-        //TODO: add promotion type
-        format!("{}{}", self.from().to_algebraic(), self.to().to_algebraic())
+        let promo_str = match self.promotion_piece() {
+            Some(PieceType::Knight) => "n",
+            Some(PieceType::Bishop) => "b",
+            Some(PieceType::Rook)   => "r",
+            Some(PieceType::Queen)  => "q",
+            _ => ""
+        };
+
+        format!("{}{}{}", self.from().to_algebraic(), self.to().to_algebraic(), promo_str)
     }
 }
 
@@ -138,24 +262,8 @@ mod test {
     use moves::*;
     use rand::{thread_rng, Rng};
 
-    fn random_flag() -> u32 {
-        match thread_rng().gen_range(0,14) {
-            0 => QUIET_FLAG,
-            1 => DOUBLE_PAWN_PUSH_FLAG,
-            2 => KING_CASTLE_FLAG,
-            3 => QUEEN_CASTLE_FLAG,
-            4 => CAPTURE_FLAG,
-            5 => EP_CAPTURE_FLAG,
-            6 => KNIGHT_PROMO_FLAG,
-            7 => BISHOP_PROMO_FLAG,
-            8 => ROOK_PROMO_FLAG,
-            9 => QUEEN_PROMO_FLAG,
-            10 => KNIGHT_PROMO_CAPTURE_FLAG,
-            11 => BISHOP_PROMO_CAPTURE_FLAG,
-            12 => ROOK_PROMO_CAPTURE_FLAG,
-            13 => QUEEN_PROMO_CAPTURE_FLAG,
-            _ => panic!("Invalid random flag generation!")
-        }
+    fn random_flag() -> MoveFlag {
+        MoveFlag::all()[thread_rng().gen_range(0, MoveFlag::all().len())]
     }
 
     fn random_square() -> Square {
@@ -191,4 +299,113 @@ mod test {
             assert!(cm.captured_piece().unwrap() == captured_ptype);
         }
     }
+
+    #[test]
+    fn exhaustive_round_trip() {
+        use std::mem::size_of;
+        assert_eq!(size_of::<Move>(), 4);
+
+        for from in 0 .. 64 {
+            for to in 0 .. 64 {
+                for &flag in MoveFlag::all() {
+                    for moved in PieceType::all() {
+                        let m = Move::new_quiet(Square::new(from), Square::new(to), flag, *moved);
+                        assert_eq!((m.from().unwrap(), m.to().unwrap(), m.flag(), m.moved_piece(), m.captured_piece()),
+                                   (from, to, flag, *moved, None));
+                        assert_eq!(Move::wrap(m.unwrap()), m);
+
+                        for captured in PieceType::all() {
+                            let m = Move::new_capture(Square::new(from), Square::new(to), flag, *moved, *captured);
+                            assert_eq!((m.from().unwrap(), m.to().unwrap(), m.flag(), m.moved_piece(), m.captured_piece()),
+                                       (from, to, flag, *moved, Some(*captured)));
+                            assert_eq!(Move::wrap(m.unwrap()), m);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn flag_encodings() {
+        use PieceType::*;
+
+        let mut valid = 0;
+
+        for bits in 0 .. 16 {
+            match MoveFlag::from_bits(bits) {
+                None => assert!(bits == 0b0110 || bits == 0b0111, "{:04b}", bits),
+                Some(flag) => {
+                    valid += 1;
+                    assert_eq!(flag.bits(), bits);
+
+                    let m = Move::new_quiet(Square::new(12), Square::new(28), flag, Pawn);
+                    assert_eq!(m.flag(), flag);
+                    assert_eq!(m.is_capture(), bits & 0b0100 != 0);
+                    assert_eq!(m.is_promotion(), bits & 0b1000 != 0);
+                    assert_eq!(m.is_promotion(), m.promotion_piece().is_some());
+
+                    if let Some(promoted) = m.promotion_piece() {
+                        assert_eq!(MoveFlag::promotion(promoted, m.is_capture()), flag);
+                    }
+                }
+            }
+        }
+
+        assert_eq!(valid, MoveFlag::all().len());
+    }
+
+    #[test]
+    fn special_constructors() {
+        use PieceType::*;
+
+        let sq = |alg: &str| Square::from_algebraic(alg).unwrap();
+
+        for &promoted in [Knight, Bishop, Rook, Queen].iter() {
+            let m = Move::new_promotion(sq("b7"), sq("b8"), promoted, None);
+            assert_eq!(m.promotion_piece(), Some(promoted));
+            assert_eq!((m.moved_piece(), m.captured_piece(), m.is_capture()), (Pawn, None, false));
+
+            let m = Move::new_promotion(sq("b7"), sq("a8"), promoted, Some(Rook));
+            assert_eq!(m.promotion_piece(), Some(promoted));
+            assert_eq!((m.moved_piece(), m.captured_piece(), m.is_capture()), (Pawn, Some(Rook), true));
+        }
+
+        assert_eq!(Move::new_promotion(sq("b7"), sq("b8"), Queen, None).flag(), MoveFlag::QueenPromo);
+        assert_eq!(Move::new_promotion(sq("b7"), sq("a8"), Knight, Some(Rook)).flag(), MoveFlag::KnightPromoCapture);
+
+        let castle = Move::new_castle(sq("e1"), sq("g1"), true);
+        assert_eq!((castle.flag(), castle.moved_piece(), castle.to_uci_str()), (MoveFlag::KingCastle, King, "e1g1".to_string()));
+        assert_eq!(Move::new_castle(sq("e8"), sq("c8"), false).flag(), MoveFlag::QueenCastle);
+
+        let ep = Move::new_ep(sq("e5"), sq("d6"));
+        assert_eq!((ep.flag(), ep.moved_piece(), ep.captured_piece()), (MoveFlag::EpCapture, Pawn, Some(Pawn)));
+        assert!(ep.is_capture() && !ep.is_promotion());
+    }
+
+    #[test]
+    fn predicates() {
+        use game::*;
+        use movegen::*;
+
+        let positions = [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/Pp2P3/2N2Q1p/1PPBBPPP/R3K2R b KQkq a3 0 1",
+            "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1"
+        ];
+
+        for fen in positions.iter() {
+            let game = Game::from_fen_str(fen).unwrap();
+
+            for m in next_moves_standalone(&game).iter() {
+                let flag = m.flag();
+                let is_castle = flag == MoveFlag::KingCastle || flag == MoveFlag::QueenCastle;
+
+                assert_eq!(m.is_castle(), is_castle);
+                assert_eq!(m.is_en_passant(), flag == MoveFlag::EpCapture);
+                assert_eq!(m.is_double_push(), flag == MoveFlag::DoublePawnPush);
+                assert_eq!(m.is_quiet(), flag == MoveFlag::Quiet || flag == MoveFlag::DoublePawnPush);
+            }
+        }
+    }
 }
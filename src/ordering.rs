@@ -0,0 +1,246 @@
+use core::*;
+use game::*;
+use moves::*;
+use move_list::*;
+use movegen::*;
+use tree::*;
+
+// quiet move scores, indexed by [color][from][to]
+pub type HistoryTable = [[[u32; 64]; 64]; 2];
+
+// the reply that refuted each move, indexed by [from][to] of the move being answered
+pub type CountermoveTable = [[Move; 64]; 64];
+
+// Everything move ordering may know about the node being searched. Fields left empty
+// simply don't contribute, so perft and quiescence can use OrderingContext::none().
+#[derive(Clone, Copy)]
+pub struct OrderingContext<'a> {
+    pub hash_move: Option<Move>,
+    pub killers: [Option<Move>; 2],
+    pub history: Option<&'a HistoryTable>,
+    pub countermoves: Option<&'a CountermoveTable>,
+    pub previous_move: Option<Move>
+}
+
+impl<'a> OrderingContext<'a> {
+    pub fn none() -> OrderingContext<'a> {
+        OrderingContext {
+            hash_move: None,
+            killers: [None, None],
+            history: None,
+            countermoves: None,
+            previous_move: None
+        }
+    }
+
+    pub fn with_hash_move(hash_move: Option<Move>) -> OrderingContext<'a> {
+        OrderingContext { hash_move: hash_move, .. OrderingContext::none() }
+    }
+
+    fn countermove(&self) -> Option<Move> {
+        match (self.countermoves, self.previous_move) {
+            (Some(table), Some(previous)) if !previous.is_null() => {
+                let reply = table[previous.from().idx()][previous.to().idx()];
+                if reply.is_null() { None } else { Some(reply) }
+            },
+            _ => None
+        }
+    }
+}
+
+// The hash move, then captures by MVV/LVA, then killers, the countermove and
+// finally the remaining quiet moves by history score. The sort is stable, so moves
+// that nothing distinguishes keep their generated order.
+pub fn order_moves(moves: &mut MoveList, game: &Game, ordering: &OrderingContext) {
+    let countermove = ordering.countermove();
+    let color = game.to_move as usize;
+
+    moves.sort_by_key(|m| {
+        if Some(*m) == ordering.hash_move {
+            return (0, 0);
+        }
+
+        if m.is_capture() {
+            let gain = m.captured_piece().unwrap() as i64 - m.moved_piece() as i64;
+            return (1, -gain);
+        }
+
+        if Some(*m) == ordering.killers[0] {
+            return (2, 0);
+        }
+
+        if Some(*m) == ordering.killers[1] {
+            return (3, 0);
+        }
+
+        if Some(*m) == countermove {
+            return (4, 0);
+        }
+
+        let history_score = match ordering.history {
+            Some(table) => table[color][m.from().idx()][m.to().idx()] as i64,
+            None => 0
+        };
+
+        return (5, -history_score);
+    });
+}
+
+// Hands out the moves at the focus, starting with the hash move before any others are
+// generated. The hash move usually causes a cutoff, and then generating the rest is
+// never needed.
+pub struct MovePicker {
+    // legal here, since it may come from another position with the same hash
+    hash_move: Option<Move>,
+    hash_move_given: bool,
+    moves: Option<MoveList>,
+    index: usize
+}
+
+impl MovePicker {
+    pub fn new(game: &Game, hash_move: Option<Move>) -> MovePicker {
+        MovePicker {
+            hash_move: match hash_move {
+                Some(m) if game.is_legal(m) => Some(m),
+                _ => None
+            },
+            hash_move_given: false,
+            moves: None,
+            index: 0
+        }
+    }
+
+    pub fn has_hash_move(&self) -> bool {
+        self.hash_move.is_some()
+    }
+
+    pub fn generate(&mut self, tree: &SearchTree, ordering: &OrderingContext) {
+        if self.moves.is_none() {
+            self.moves = Some(tree.next_moves(ordering));
+        }
+    }
+
+    // the number of legal moves, once they have been generated
+    pub fn generated(&self) -> Option<usize> {
+        self.moves.as_ref().map(|moves| moves.len())
+    }
+
+    pub fn next(&mut self, tree: &SearchTree, ordering: &OrderingContext) -> Option<Move> {
+        if !self.hash_move_given && self.hash_move.is_some() {
+            self.hash_move_given = true;
+            return self.hash_move;
+        }
+
+        self.generate(tree, ordering);
+
+        let moves = self.moves.as_ref().unwrap();
+        while self.index < moves.len() {
+            let m = moves.at(self.index);
+            self.index += 1;
+
+            if Some(m) != self.hash_move {
+                return Some(m);
+            }
+        }
+
+        return None;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ordering::*;
+    use movegen::*;
+
+    fn ordered(game: &Game, ordering: &OrderingContext) -> Vec<String> {
+        let mut moves = next_moves_standalone(game);
+        order_moves(&mut moves, game, ordering);
+        moves.iter().map(|m| m.to_uci_str()).collect()
+    }
+
+    fn find(game: &Game, move_str: &str) -> Move {
+        *next_moves_standalone(game).iter().find(|m| m.to_uci_str() == move_str).unwrap()
+    }
+
+    #[test]
+    fn ordering_priorities() {
+        let kiwipete = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        let game = Game::from_fen_str(kiwipete).unwrap();
+
+        // nothing known: captures first, most valuable victim and least valuable attacker first
+        let plain = ordered(&game, &OrderingContext::none());
+        let captures = next_moves_standalone(&game).iter().filter(|m| m.is_capture()).count();
+        assert!(plain[.. captures].iter().all(|s| find(&game, s).is_capture()));
+
+        let gains: Vec<i32> = plain[.. captures].iter()
+            .map(|s| find(&game, s))
+            .map(|m| m.captured_piece().unwrap() as i32 - m.moved_piece() as i32)
+            .collect();
+        assert!(gains.windows(2).all(|pair| pair[0] >= pair[1]));
+
+        let hash_move = find(&game, "a1b1");
+        let killer = find(&game, "g2g3");
+        let second_killer = find(&game, "a2a3");
+        let previous = Move::new_quiet(Square::from_algebraic("h8").unwrap(), Square::from_algebraic("h7").unwrap(),
+                                       MoveFlag::Quiet, PieceType::Rook);
+
+        let mut countermoves = [[Move::null(); 64]; 64];
+        countermoves[previous.from().idx()][previous.to().idx()] = find(&game, "c3b1");
+
+        let mut history = [[[0; 64]; 64]; 2];
+        let favoured = find(&game, "e1d1");
+        history[Color::White as usize][favoured.from().idx()][favoured.to().idx()] = 100;
+
+        let ordering = OrderingContext {
+            hash_move: Some(hash_move),
+            killers: [Some(killer), Some(second_killer)],
+            history: Some(&history),
+            countermoves: Some(&countermoves),
+            previous_move: Some(previous)
+        };
+
+        let full = ordered(&game, &ordering);
+        assert_eq!(full[0], "a1b1");
+        assert_eq!(full[1 .. captures + 1], plain[.. captures]);
+        assert_eq!(full[captures + 1 .. captures + 5], ["g2g3", "a2a3", "c3b1", "e1d1"]);
+
+        // with only a hash move, every other move keeps its plain order
+        let hash_only = ordered(&game, &OrderingContext::with_hash_move(Some(hash_move)));
+        let plain_without_hash: Vec<String> = plain.iter().filter(|s| *s != "a1b1").cloned().collect();
+        assert_eq!(hash_only[0], "a1b1");
+        assert_eq!(hash_only[1 ..], plain_without_hash[..]);
+    }
+
+    #[test]
+    fn move_picker() {
+        let kiwipete = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        let game = Game::from_fen_str(kiwipete).unwrap();
+        let tree = SearchTree::new(game);
+        let hash_move = find(&game, "a1b1");
+
+        let picked = |picker: &mut MovePicker| -> Vec<String> {
+            let mut moves = Vec::new();
+            while let Some(m) = picker.next(&tree, &OrderingContext::none()) {
+                moves.push(m.to_uci_str());
+            }
+            moves
+        };
+
+        // the hash move comes before anything is generated, and isn't repeated afterwards
+        let mut picker = MovePicker::new(&game, Some(hash_move));
+        assert_eq!(picker.next(&tree, &OrderingContext::none()), Some(hash_move));
+        assert_eq!(picker.generated(), None);
+
+        let rest = picked(&mut picker);
+        assert_eq!(picker.generated(), Some(48));
+        assert_eq!(rest.len(), 47);
+        assert!(!rest.contains(&"a1b1".to_string()));
+
+        // a move from a colliding position isn't legal here and is left out
+        let black_to_move = Game::from_fen_str(&kiwipete.replace(" w ", " b ")).unwrap();
+        let collision = find(&black_to_move, "e8g8");
+        let mut picker = MovePicker::new(&game, Some(collision));
+        assert!(!picker.has_hash_move());
+        assert_eq!(picked(&mut picker), ordered(&game, &OrderingContext::none()));
+    }
+}
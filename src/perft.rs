@@ -6,6 +6,8 @@ use core::*;
 use game::*;
 use movegen::*;
 use moves::*;
+use ordering::*;
+use pins::*;
 use tables::*;
 use tree::*;
 
@@ -15,6 +17,7 @@ use std::thread;
 use std::ops::Add;
 use std::os;
 use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use prettytable::Table;
 use prettytable::cell::Cell;
@@ -22,7 +25,8 @@ use prettytable::row::Row;
 
 //TODO: read from ENV variable
 const QPERFT_PATH: &'static str = "/Users/zac/Code/qperft/qperft";
-const MAX_PERFT_DEPTH: usize = 20;
+// results are indexed by depth, so perft can go at most MAX_PERFT_DEPTH - 1 plies deep
+pub const MAX_PERFT_DEPTH: usize = 20;
 
 struct PerftContext {
     tree: SearchTree,
@@ -37,7 +41,10 @@ pub struct PerftResult {
     pub castles     : [usize; MAX_PERFT_DEPTH],
     pub promotions  : [usize; MAX_PERFT_DEPTH],
     pub checks      : [usize; MAX_PERFT_DEPTH],
-    pub check_mates : [usize; MAX_PERFT_DEPTH]
+    pub discovery_checks : [usize; MAX_PERFT_DEPTH],
+    pub double_checks    : [usize; MAX_PERFT_DEPTH],
+    pub check_mates : [usize; MAX_PERFT_DEPTH],
+    pub stalemates  : [usize; MAX_PERFT_DEPTH]
 }
 
 impl PerftResult {
@@ -49,13 +56,48 @@ impl PerftResult {
             castles     : [0; MAX_PERFT_DEPTH],
             promotions  : [0; MAX_PERFT_DEPTH],
             checks      : [0; MAX_PERFT_DEPTH],
-            check_mates : [0; MAX_PERFT_DEPTH]
+            discovery_checks : [0; MAX_PERFT_DEPTH],
+            double_checks    : [0; MAX_PERFT_DEPTH],
+            check_mates : [0; MAX_PERFT_DEPTH],
+            stalemates  : [0; MAX_PERFT_DEPTH]
         };
 
         new_result.node_count[0] = 1;
 
         return new_result;
     }
+
+    pub fn total_nodes(&self) -> usize {
+        self.node_count.iter().sum()
+    }
+
+    fn fields(&self) -> [(&'static str, &[usize; MAX_PERFT_DEPTH]); 10] {
+        [("nodes", &self.node_count),
+         ("captures", &self.captures),
+         ("ep captures", &self.ep_captures),
+         ("castles", &self.castles),
+         ("promotions", &self.promotions),
+         ("checks", &self.checks),
+         ("discovery checks", &self.discovery_checks),
+         ("double checks", &self.double_checks),
+         ("check-mates", &self.check_mates),
+         ("stalemates", &self.stalemates)]
+    }
+
+    // One line for every depth and field where the two results disagree, empty when they're equal.
+    pub fn diff(&self, other: &PerftResult) -> Vec<String> {
+        let mut differences = Vec::new();
+
+        for depth in 0 .. MAX_PERFT_DEPTH {
+            for (&(name, mine), &(_, theirs)) in self.fields().iter().zip(other.fields().iter()) {
+                if mine[depth] != theirs[depth] {
+                    differences.push(format!("depth {} {}: {} vs {}", depth, name, mine[depth], theirs[depth]));
+                }
+            }
+        }
+
+        return differences;
+    }
 }
 
 impl Add for PerftResult {
@@ -71,7 +113,10 @@ impl Add for PerftResult {
             result.castles[i]     = self.castles[i]     + other.castles[i];
             result.promotions[i]  = self.promotions[i]  + other.promotions[i];
             result.checks[i]      = self.checks[i]      + other.checks[i];
+            result.discovery_checks[i] = self.discovery_checks[i] + other.discovery_checks[i];
+            result.double_checks[i]    = self.double_checks[i]    + other.double_checks[i];
             result.check_mates[i] = self.check_mates[i] + other.check_mates[i];
+            result.stalemates[i]  = self.stalemates[i]  + other.stalemates[i];
         }
 
         return result;
@@ -92,53 +137,102 @@ impl PerftContext {
             return;
         }
 
-        let next_moves = self.tree.next_moves(None);
+        let next_moves = self.tree.next_moves(&OrderingContext::none());
+        let discoverers = discoverers(self.tree.focus());
+
+        for m in next_moves.iter() {
+            self.visit(*m, max_depth, &discoverers);
+        }
+    }
 
-        for m in next_moves.borrow().iter() {
-            let game_copy = *self.tree.focus();
+    // like go, but reports the leaf count below each root move as soon as it is known.
+    // returns false if cancel was set before all root moves were searched.
+    fn go_root<F: FnMut(&Move, u64)>(&mut self, max_depth: usize, cancel: &AtomicBool, f: &mut F) -> bool {
+        debug_assert!(self.tree.search_depth() == 0);
 
-            self.tree.make_move(*m);
+        if max_depth == 0 {
+            return true;
+        }
 
-            self.result.node_count[self.tree.search_depth()] += 1;
+        let next_moves = self.tree.next_moves(&OrderingContext::none());
+        let discoverers = discoverers(self.tree.focus());
 
-            if m.flag() == EP_CAPTURE_FLAG {
-                self.result.ep_captures[self.tree.search_depth()] += 1;
+        for m in next_moves.iter() {
+            if cancel.load(Ordering::Relaxed) {
+                return false;
             }
 
-            if m.is_capture() {
-                self.result.captures[self.tree.search_depth()] += 1;
-            }
+            let leaves_before = self.result.node_count[max_depth];
+            self.visit(*m, max_depth, &discoverers);
+            f(m, (self.result.node_count[max_depth] - leaves_before) as u64);
+        }
 
-            if m.flag() == KING_CASTLE_FLAG || m.flag() == QUEEN_CASTLE_FLAG {
-                self.result.castles[self.tree.search_depth()] += 1;
-            }
+        return true;
+    }
 
-            if m.is_promotion() {
-                self.result.promotions[self.tree.search_depth()] += 1;
-            }
+    fn visit(&mut self, m: Move, max_depth: usize, discoverers: &PinFinder) {
+        let discovered_check = gives_discovered_check_with(self.tree.focus(), m, discoverers);
 
-            if self.tree.focus().in_check() {
-                self.result.checks[self.tree.search_depth()] += 1;
-            }
+        self.tree.make_move(m);
+
+        debug_assert!(gives_check(self.tree.parent(), m) == self.tree.focus().in_check());
+
+        self.result.node_count[self.tree.search_depth()] += 1;
+
+        if m.is_en_passant() {
+            self.result.ep_captures[self.tree.search_depth()] += 1;
+        }
+
+        if m.is_capture() {
+            self.result.captures[self.tree.search_depth()] += 1;
+        }
 
-            match self.tree.focus().outcome {
-                Some(GameResult::Win(_)) => self.result.check_mates[self.tree.search_depth()] += 1,
-                _ => {}
+        if m.is_castle() {
+            self.result.castles[self.tree.search_depth()] += 1;
+        }
+
+        if m.is_promotion() {
+            self.result.promotions[self.tree.search_depth()] += 1;
+        }
+
+        if self.tree.focus().in_check() {
+            self.result.checks[self.tree.search_depth()] += 1;
+
+            if self.tree.focus().king_attackers.population() > 1 {
+                self.result.double_checks[self.tree.search_depth()] += 1;
+            } else if discovered_check {
+                self.result.discovery_checks[self.tree.search_depth()] += 1;
             }
 
-            self.go(max_depth);
-            self.tree.unmake_move(game_copy);
+            // perft counts the tree of legal moves rather than game results, so the outcome
+            // isn't used: the tree may have declared a position drawn by repetition or the
+            // fifty-move rule, which says nothing about whether there's a move left
+            if !can_move(self.tree.focus()) {
+                self.result.check_mates[self.tree.search_depth()] += 1;
+            }
+        } else if !can_move(self.tree.focus()) {
+            self.result.stalemates[self.tree.search_depth()] += 1;
         }
+
+        self.go(max_depth);
+        self.tree.unmake_move();
     }
 }
 
+// Counts only, nothing is printed.
+pub fn perft_count(game: Game, depth: usize) -> PerftResult {
+    let never_cancelled = AtomicBool::new(false);
+    return perft_with_callback(game, depth, &never_cancelled, |_, _| {}).unwrap();
+}
+
+// f is called with each root move and the number of leaf nodes below it, in the same order
+// moves are generated. cancel is checked between root moves, None is returned if it was set.
+pub fn perft_with_callback<F: FnMut(&Move, u64)>(game: Game, depth: usize, cancel: &AtomicBool, mut f: F) -> Option<PerftResult> {
+    debug_assert!(depth < MAX_PERFT_DEPTH, "perft depth {} is too deep, the maximum is {}", depth, MAX_PERFT_DEPTH - 1);
 
-pub fn perft(game: Game, depth: usize) -> PerftResult {
     // let num_cpus = num_cpus::get() - 2;
     // let mut threads = Vec::new();
 
-    let start_time = Counter::new();
-
     // for move_subset in next_moves_standalone_chunked(&game, num_cpus) {
 
     //     threads.push(thread::spawn(move || {
@@ -162,10 +256,15 @@ pub fn perft(game: Game, depth: usize) -> PerftResult {
     // }
 
     let mut pc = PerftContext::new(game.clone());
-    pc.go(depth);
 
-    let final_result = &pc.result;
+    if pc.go_root(depth, cancel, &mut f) {
+        return Some(pc.result);
+    } else {
+        return None;
+    }
+}
 
+pub fn perft_report(result: &PerftResult) {
     let mut table = Table::new();
     table.add_row(row![
                   "DEPTH",
@@ -175,33 +274,33 @@ pub fn perft(game: Game, depth: usize) -> PerftResult {
                   "CASTLES",
                   "PROMOTIONS",
                   "CHECKS",
-                  "CHECK-MATES"
+                  "DISCOVERY CHECKS",
+                  "DOUBLE CHECKS",
+                  "CHECK-MATES",
+                  "STALEMATES"
     ]);
 
-    for i in 0 .. 20 {
-        let c = final_result.node_count[i];
+    for i in 0 .. MAX_PERFT_DEPTH {
+        let c = result.node_count[i];
         if c != 0 {
 
             table.add_row(Row::new(vec![
                                    Cell::new(&i.to_string()),
-                                   Cell::new(&final_result.node_count[i].to_string()),
-                                   Cell::new(&final_result.captures[i].to_string()),
-                                   Cell::new(&final_result.ep_captures[i].to_string()),
-                                   Cell::new(&final_result.castles[i].to_string()),
-                                   Cell::new(&final_result.promotions[i].to_string()),
-                                   Cell::new(&final_result.checks[i].to_string()),
-                                   Cell::new(&final_result.check_mates[i].to_string()) ]
+                                   Cell::new(&result.node_count[i].to_string()),
+                                   Cell::new(&result.captures[i].to_string()),
+                                   Cell::new(&result.ep_captures[i].to_string()),
+                                   Cell::new(&result.castles[i].to_string()),
+                                   Cell::new(&result.promotions[i].to_string()),
+                                   Cell::new(&result.checks[i].to_string()),
+                                   Cell::new(&result.discovery_checks[i].to_string()),
+                                   Cell::new(&result.double_checks[i].to_string()),
+                                   Cell::new(&result.check_mates[i].to_string()),
+                                   Cell::new(&result.stalemates[i].to_string()) ]
                                   )
                          );
         }
     }
 
-    let mut total_nodes: usize = 0;
-
-    for i in 0 .. 20 {
-        total_nodes += final_result.node_count[i];
-    }
-
     println!(r#"
  ___ ___ ___ ___ _____
 | _ \ __| _ \ __|_   _|
@@ -209,14 +308,41 @@ pub fn perft(game: Game, depth: usize) -> PerftResult {
 |_| |___|_|_\_|   |_|
         "#);
 
-    game.board.print();
     table.print_tty(false);
 
+    println!("Total Nodes Processed: {}", result.total_nodes());
+}
+
+// perft_count followed by the full report, with the board and the speed
+pub fn perft(game: Game, depth: usize) -> PerftResult {
+    let start_time = Counter::new();
+    let result = perft_count(game, depth);
+    let elapsed_ns = start_time.elapsed_ns();
+
+    game.board.print();
+    perft_report(&result);
+
     // println!("Threads used: {}", num_cpus);
-    println!("Total Nodes Processed: {}", total_nodes);
-    println!("MNodes/Sec: {:.2}", 1e-6 * total_nodes as f64 / (start_time.elapsed_ms() / 1000.0));
+    if elapsed_ns > 0 {
+        println!("MNodes/Sec: {:.2}", 1e3 * result.total_nodes() as f64 / elapsed_ns as f64);
+    }
 
-    return final_result.clone();
+    return result;
+}
+
+// The leaf count below each root move, as other engines print it to track down a movegen
+// bug by comparing the two move by move.
+pub fn perft_divide(game: Game, depth: usize) -> PerftResult {
+    let never_cancelled = AtomicBool::new(false);
+
+    let result = perft_with_callback(game, depth, &never_cancelled, |m, nodes| {
+        println!("{}: {}", m.to_uci_str(), nodes);
+    }).unwrap();
+
+    println!("");
+    println!("Nodes searched: {}", result.node_count[depth]);
+
+    return result;
 }
 
 // pub fn perft_divide(game: Game, depth: usize) -> HashMap<String, u32> {
@@ -343,6 +469,13 @@ pub fn perft(game: Game, depth: usize) -> PerftResult {
 mod test {
     use perft::*;
 
+    fn assert_send<T: Send>() {}
+
+    #[test]
+    fn context_is_send() {
+        assert_send::<PerftContext>();
+    }
+
     #[test]
     fn standard_position() {
         let mut correct_result = PerftResult::new();
@@ -389,6 +522,20 @@ mod test {
         correct_result.checks[5] = 27351;
         correct_result.checks[6] = 809099;
 
+        correct_result.discovery_checks[1] = 0;
+        correct_result.discovery_checks[2] = 0;
+        correct_result.discovery_checks[3] = 0;
+        correct_result.discovery_checks[4] = 0;
+        correct_result.discovery_checks[5] = 6;
+        correct_result.discovery_checks[6] = 329;
+
+        correct_result.double_checks[1] = 0;
+        correct_result.double_checks[2] = 0;
+        correct_result.double_checks[3] = 0;
+        correct_result.double_checks[4] = 0;
+        correct_result.double_checks[5] = 0;
+        correct_result.double_checks[6] = 46;
+
         correct_result.check_mates[1] = 0;
         correct_result.check_mates[2] = 0;
         correct_result.check_mates[3] = 0;
@@ -396,10 +543,17 @@ mod test {
         correct_result.check_mates[5] = 347;
         correct_result.check_mates[6] = 10828;
 
+        correct_result.stalemates[1] = 0;
+        correct_result.stalemates[2] = 0;
+        correct_result.stalemates[3] = 0;
+        correct_result.stalemates[4] = 0;
+        correct_result.stalemates[5] = 0;
+        correct_result.stalemates[6] = 0;
+
         let g = Game::starting_position();
-        let result = perft(g, 6);
+        let result = perft_count(g, 6);
 
-        assert!(result == correct_result);
+        assert!(result == correct_result, "\n{}", result.diff(&correct_result).join("\n"));
     }
 
     #[test]
@@ -442,16 +596,63 @@ mod test {
         correct_result.checks[4] = 25523;
         correct_result.checks[5] = 3309887;
 
+        correct_result.discovery_checks[1] = 0;
+        correct_result.discovery_checks[2] = 0;
+        correct_result.discovery_checks[3] = 0;
+        correct_result.discovery_checks[4] = 42;
+        correct_result.discovery_checks[5] = 19883;
+
+        correct_result.double_checks[1] = 0;
+        correct_result.double_checks[2] = 0;
+        correct_result.double_checks[3] = 0;
+        correct_result.double_checks[4] = 6;
+        correct_result.double_checks[5] = 2645;
+
         correct_result.check_mates[1] = 0;
         correct_result.check_mates[2] = 0;
         correct_result.check_mates[3] = 1;
         correct_result.check_mates[4] = 43;
         correct_result.check_mates[5] = 30171;
 
+        correct_result.stalemates[1] = 0;
+        correct_result.stalemates[2] = 0;
+        correct_result.stalemates[3] = 0;
+        correct_result.stalemates[4] = 0;
+        correct_result.stalemates[5] = 0;
+
         let g = Game::from_fen_str("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
-        let result = perft(g, 5);
+        let result = perft_count(g, 5);
 
-        assert!(result == correct_result);
+        assert!(result == correct_result, "\n{}", result.diff(&correct_result).join("\n"));
+    }
+
+    #[test]
+    fn checkmates_ignore_draws() {
+        let kiwipete = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        let expected = perft_count(Game::from_fen_str(kiwipete).unwrap(), 4);
+        assert_eq!(expected.check_mates[1..5], [0, 0, 1, 43]);
+
+        // every position in the tree is a fifty-move draw, but the mates are still there
+        let fifty_moves = kiwipete.replace(" 0 1", " 99 80");
+        let result = perft_count(Game::from_fen_str(&fifty_moves).unwrap(), 4);
+        assert!(result == expected, "\n{}", result.diff(&expected).join("\n"));
+
+        // a lone mate in one on the fiftieth move
+        let result = perft_count(Game::from_fen_str("6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 99 80").unwrap(), 1);
+        assert_eq!(result.check_mates[1], 1);
+    }
+
+    #[test]
+    fn stalemates() {
+        // Qb6 leaves the black king no move without giving check, every other move is just a move
+        let result = perft_count(Game::from_fen_str("k7/8/8/1Q6/8/8/8/K7 w - - 0 1").unwrap(), 2);
+        assert_eq!(result.stalemates[1..3], [1, 0]);
+        assert_eq!(result.check_mates[1], 0);
+        assert_eq!(result.node_count[1], 26);
+
+        // a mate is never also counted as a stalemate
+        let result = perft_count(Game::from_fen_str("6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1").unwrap(), 1);
+        assert_eq!((result.check_mates[1], result.stalemates[1]), (1, 0));
     }
 
     #[test]
@@ -465,7 +666,7 @@ mod test {
         correct_result.node_count[5] = 89941194;
 
         let g = Game::from_fen_str("rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8").unwrap();
-        let result = perft(g, 5);
+        let result = perft_count(g, 5);
 
         assert!(result.node_count[1] == correct_result.node_count[1]);
         assert!(result.node_count[2] == correct_result.node_count[2]);
@@ -527,6 +728,22 @@ mod test {
         correct_result.checks[6] = 452473;
         correct_result.checks[7] = 12797406;
 
+        correct_result.discovery_checks[1] = 0;
+        correct_result.discovery_checks[2] = 0;
+        correct_result.discovery_checks[3] = 3;
+        correct_result.discovery_checks[4] = 106;
+        correct_result.discovery_checks[5] = 1292;
+        correct_result.discovery_checks[6] = 26067;
+        correct_result.discovery_checks[7] = 370630;
+
+        correct_result.double_checks[1] = 0;
+        correct_result.double_checks[2] = 0;
+        correct_result.double_checks[3] = 0;
+        correct_result.double_checks[4] = 0;
+        correct_result.double_checks[5] = 3;
+        correct_result.double_checks[6] = 0;
+        correct_result.double_checks[7] = 3612;
+
         correct_result.check_mates[1] = 0;
         correct_result.check_mates[2] = 0;
         correct_result.check_mates[3] = 0;
@@ -535,9 +752,111 @@ mod test {
         correct_result.check_mates[6] = 2733;
         correct_result.check_mates[7] = 87;
 
+        correct_result.stalemates[1] = 0;
+        correct_result.stalemates[2] = 0;
+        correct_result.stalemates[3] = 0;
+        correct_result.stalemates[4] = 0;
+        correct_result.stalemates[5] = 0;
+        correct_result.stalemates[6] = 0;
+        correct_result.stalemates[7] = 0;
+
         let g = Game::from_fen_str("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1").unwrap();
-        let result = perft(g, 7);
+        let result = perft_count(g, 7);
+
+        assert!(result == correct_result, "\n{}", result.diff(&correct_result).join("\n"));
+    }
+
+    #[test]
+    fn promotions() {
+        let g = Game::from_fen_str("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1").unwrap();
+        let result = perft_count(g, 4);
+
+        assert!(result.node_count[1..5] == [6, 264, 9467, 422333]);
+        assert!(result.captures[1..5] == [0, 87, 1021, 131393]);
+        assert!(result.ep_captures[1..5] == [0, 0, 4, 0]);
+        assert!(result.castles[1..5] == [0, 6, 0, 7795]);
+        assert!(result.promotions[1..5] == [0, 48, 120, 60032]);
+        assert!(result.checks[1..5] == [0, 10, 38, 15492]);
+        assert!(result.check_mates[1..5] == [0, 0, 22, 5]);
+        assert!(result.stalemates[1..5] == [0, 0, 0, 0]);
+
+        let g = Game::from_fen_str("n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1").unwrap();
+        let result = perft_count(g, 5);
+
+        assert!(result.node_count[1..6] == [24, 496, 9483, 182838, 3605103]);
+    }
+
+    #[test]
+    fn chess960() {
+        let positions: [(&'static str, [usize; 4]); 8] = [
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w HAha - 0 1", [20, 400, 8902, 197281]),
+            ("bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9", [21, 528, 12189, 326672]),
+            ("2nnrbkr/p1qppppp/8/1ppb4/6PP/3PP3/PPP2P2/BQNNRBKR w HEhe - 1 9", [21, 807, 18002, 667366]),
+            ("b1q1rrkb/pppppppp/3nn3/8/P7/1PPP4/4PPPP/BQNNRKRB w GE - 1 9", [20, 479, 10471, 273318]),
+            ("qbbnnrkr/2pp2pp/p7/1p2pp2/8/P3PP2/1PPP1KPP/QBBNNR1R w hf - 0 9", [22, 593, 13440, 382958]),
+            ("1nbbnrkr/p1p1ppp1/3p4/1p3P1p/3Pq2P/8/PPP1P1P1/QNBBNRKR w HFhf - 0 9", [28, 1120, 31058, 1171749]),
+            ("qnbnr1kr/ppp1b1pp/4p3/3p1p2/8/2NPP3/PPP1BPPP/QNB1R1KR w HEhe - 1 9", [29, 899, 26578, 824055]),
+            ("q1bnrkr1/ppppp2p/2n2p2/4b1p1/2NP4/8/PPP1PPPP/QNB1RRKB w ge - 1 9", [30, 860, 24566, 732757])
+        ];
+
+        for &(fen, expected) in positions.iter() {
+            let g = Game::from_fen_str(fen).unwrap();
+            assert!(g.chess960);
+
+            let result = perft_count(g, 4);
+            assert!(result.node_count[1..5] == expected, "{}", fen);
+        }
+    }
+
+    #[test]
+    fn diff() {
+        let mut correct_result = PerftResult::new();
+        correct_result.node_count[1] = 20;
+        correct_result.node_count[2] = 400;
+        correct_result.checks[2] = 0;
+
+        let mut result = correct_result.clone();
+        assert!(result.diff(&correct_result).is_empty());
+
+        result.node_count[2] = 401;
+        result.checks[2] = 1;
+        result.check_mates[5] = 3;
+        result.stalemates[5] = 2;
+
+        assert_eq!(result.diff(&correct_result), vec![
+            "depth 2 nodes: 401 vs 400".to_string(),
+            "depth 2 checks: 1 vs 0".to_string(),
+            "depth 5 check-mates: 3 vs 0".to_string(),
+            "depth 5 stalemates: 2 vs 0".to_string()
+        ]);
+
+        assert_eq!(correct_result.diff(&result)[0], "depth 2 nodes: 400 vs 401");
+    }
+
+    #[test]
+    fn root_move_callback() {
+        let g = Game::from_fen_str("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+        let never_cancelled = AtomicBool::new(false);
+
+        let mut divide = Vec::new();
+        let result = perft_with_callback(g, 3, &never_cancelled, |m, count| divide.push((*m, count))).unwrap();
+
+        assert_eq!(divide.len(), 48);
+        assert_eq!(divide.iter().map(|&(_, count)| count).sum::<u64>(), 97862);
+        let expected = perft_count(g, 3);
+        assert!(result == expected, "\n{}", result.diff(&expected).join("\n"));
+
+        for &(m, count) in divide.iter() {
+            let mut child = g;
+            child.make_move(m);
+            assert_eq!(count as usize, perft_count(child, 2).node_count[2], "{}", m.to_uci_str());
+        }
 
-        assert!(result == correct_result);
+        // stopping after the first root move
+        let cancel = AtomicBool::new(false);
+        let mut reported = 0;
+        let cancelled = perft_with_callback(g, 3, &cancel, |_, _| { reported += 1; cancel.store(true, Ordering::Relaxed); });
+        assert!(cancelled.is_none());
+        assert_eq!(reported, 1);
     }
 }
@@ -0,0 +1,375 @@
+use core::*;
+use game::*;
+use moves::*;
+use movegen::*;
+use play::*;
+
+// PGN export format keeps movetext lines to this length
+const PGN_LINE_LENGTH: usize = 80;
+
+const RESULTS: [&'static str; 4] = ["1-0", "0-1", "1/2-1/2", "*"];
+
+// A game read from PGN: its tags in the order they came, the position it starts from
+// and the moves played from there.
+#[derive(Debug, Clone)]
+pub struct PgnGame {
+    pub tags: Vec<(String, String)>,
+    pub start: Game,
+    pub moves: Vec<Move>,
+    // as PGN writes it, "*" for a game that isn't finished
+    pub result: String
+}
+
+impl PgnGame {
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|&&(ref n, _)| n == name).map(|&(_, ref value)| value.as_str())
+    }
+
+    pub fn set_tag(&mut self, name: &str, value: &str) {
+        match self.tags.iter().position(|&(ref n, _)| n == name) {
+            Some(i) => self.tags[i].1 = value.to_string(),
+            None => self.tags.push((name.to_string(), value.to_string()))
+        }
+    }
+}
+
+// What to add to a move when writing it out: a suffix like "?" and a comment after it.
+#[derive(Debug, PartialEq, Clone)]
+pub struct MoveNote {
+    pub suffix: &'static str,
+    pub comment: Option<String>
+}
+
+impl MoveNote {
+    pub fn none() -> MoveNote {
+        MoveNote { suffix: "", comment: None }
+    }
+}
+
+// a game as it's read, before its result is known
+struct PartialGame {
+    tags: Vec<(String, String)>,
+    start: Option<Game>,
+    position: Game,
+    moves: Vec<Move>
+}
+
+impl PartialGame {
+    fn new() -> PartialGame {
+        PartialGame {
+            tags: Vec::new(),
+            start: None,
+            position: Game::starting_position(),
+            moves: Vec::new()
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.tags.is_empty() && self.start.is_none()
+    }
+
+    // the position the moves start from, a FEN tag's if there is one
+    fn begin(&mut self) -> Result<(), String> {
+        if self.start.is_some() {
+            return Ok(());
+        }
+
+        let start = match self.tags.iter().find(|&&(ref name, _)| name == "FEN") {
+            Some(&(_, ref fen)) => match Game::from_fen_str(fen) {
+                Some(game) => game,
+                None => return Err(format!("invalid FEN tag \"{}\"", fen))
+            },
+            None => Game::starting_position()
+        };
+
+        self.start = Some(start);
+        self.position = start;
+
+        return Ok(());
+    }
+
+    fn finish(mut self, result: &str) -> Result<PgnGame, String> {
+        self.begin()?;
+
+        Ok(PgnGame {
+            tags: self.tags,
+            start: self.start.unwrap(),
+            moves: self.moves,
+            result: result.to_string()
+        })
+    }
+}
+
+// Every game in a PGN file. Only the tags and the moves played are kept, comments, variations
+// and NAGs are skipped. A game that stops without a result, whether at the end of the file or
+// at the tags of the next game, is read as unfinished.
+pub fn read_pgn(text: &str) -> Result<Vec<PgnGame>, String> {
+    let mut games = Vec::new();
+    let mut current = PartialGame::new();
+    let mut chars = text.chars().peekable();
+    let mut at_line_start = true;
+
+    while let Some(c) = chars.next() {
+        let line_start = at_line_start;
+        at_line_start = c == '\n';
+
+        match c {
+            _ if c.is_whitespace() => {},
+
+            // escaped lines, and comments to the end of the line
+            '%' | ';' if c == ';' || line_start => {
+                while chars.peek().map_or(false, |&c| c != '\n') {
+                    chars.next();
+                }
+            },
+
+            '{' => {
+                if !chars.by_ref().any(|c| c == '}') {
+                    return Err("unterminated comment".to_string());
+                }
+            },
+
+            '(' => {
+                let mut nesting = 1;
+                while nesting > 0 {
+                    match chars.next() {
+                        Some('(') => nesting += 1,
+                        Some(')') => nesting -= 1,
+                        Some('{') => { chars.by_ref().any(|c| c == '}'); },
+                        Some(_) => {},
+                        None => return Err("unterminated variation".to_string())
+                    }
+                }
+            },
+
+            '$' => {
+                while chars.peek().map_or(false, |c| c.is_ascii_digit()) {
+                    chars.next();
+                }
+            },
+
+            '[' => {
+                let tag: String = chars.by_ref().take_while(|&c| c != ']').collect();
+
+                if !current.moves.is_empty() {
+                    games.push(current.finish("*")?);
+                    current = PartialGame::new();
+                }
+
+                current.tags.push(read_tag(&tag)?);
+            },
+
+            _ => {
+                let mut token = c.to_string();
+                while chars.peek().map_or(false, |&c| !c.is_whitespace() && !"{}()[];$".contains(c)) {
+                    token.push(chars.next().unwrap());
+                }
+
+                if RESULTS.contains(&token.as_str()) {
+                    games.push(current.finish(&token)?);
+                    current = PartialGame::new();
+                    continue;
+                }
+
+                // a move number, possibly run together with the move as in "1.e4"
+                let san = match token.rfind('.') {
+                    Some(i) if token.starts_with(|c: char| c.is_ascii_digit()) => token[i + 1 ..].to_string(),
+                    _ => token
+                };
+
+                if san.is_empty() {
+                    continue;
+                }
+
+                current.begin()?;
+                let m = parse_user_move(&current.position, &san)
+                    .map_err(|e| format!("game {}, after {} moves: {}", games.len() + 1, current.moves.len(), e))?;
+
+                current.position.make_move(m);
+                current.moves.push(m);
+            }
+        }
+    }
+
+    if !current.is_empty() {
+        games.push(current.finish("*")?);
+    }
+
+    return Ok(games);
+}
+
+// the inside of a tag pair, like Event "Casual game"
+fn read_tag(tag: &str) -> Result<(String, String), String> {
+    let invalid = || format!("invalid tag [{}]", tag);
+
+    let quote = tag.find('"').ok_or_else(&invalid)?;
+    let name = tag[.. quote].trim();
+    let quoted = tag[quote + 1 ..].trim_right();
+
+    if name.is_empty() || !quoted.ends_with('"') {
+        return Err(invalid());
+    }
+
+    let mut value = String::new();
+    let mut escaped = false;
+
+    for c in quoted[.. quoted.len() - 1].chars() {
+        match c {
+            '\\' if !escaped => escaped = true,
+            _ => {
+                value.push(c);
+                escaped = false;
+            }
+        }
+    }
+
+    return Ok((name.to_string(), value));
+}
+
+// PGN export format, with notes for as many of the moves as there are notes
+pub fn write_pgn(game: &PgnGame, notes: &[MoveNote]) -> String {
+    let mut pgn = String::new();
+
+    for &(ref name, ref value) in game.tags.iter() {
+        let escaped = value.replace("\\", "\\\\").replace("\"", "\\\"");
+        pgn.push_str(&format!("[{} \"{}\"]\n", name, escaped));
+    }
+    pgn.push('\n');
+
+    let mut tokens = Vec::new();
+    let mut position = game.start;
+    let mut after_comment = false;
+
+    for (i, m) in game.moves.iter().enumerate() {
+        let note = notes.get(i).cloned().unwrap_or(MoveNote::none());
+
+        // black's moves are numbered too when something comes between them and white's
+        if position.to_move == Color::White {
+            tokens.push(format!("{}.", position.fullmoves));
+        } else if i == 0 || after_comment {
+            tokens.push(format!("{}...", position.fullmoves));
+        }
+
+        tokens.push(format!("{}{}", move_to_san(&position, *m), note.suffix));
+
+        after_comment = note.comment.is_some();
+        if let Some(comment) = note.comment {
+            tokens.push(format!("{{{}}}", comment));
+        }
+
+        position.make_move(*m);
+    }
+
+    tokens.push(game.result.clone());
+
+    let mut line = String::new();
+    for token in tokens {
+        if !line.is_empty() && line.len() + 1 + token.len() > PGN_LINE_LENGTH {
+            pgn.push_str(&line);
+            pgn.push('\n');
+            line.clear();
+        }
+
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(&token);
+    }
+
+    pgn.push_str(&line);
+    pgn.push('\n');
+
+    return pgn;
+}
+
+#[cfg(test)]
+mod test {
+    use pgn::*;
+    use zobrist::*;
+
+    fn uci_moves(game: &PgnGame) -> Vec<String> {
+        game.moves.iter().map(|m| m.to_uci_str()).collect()
+    }
+
+    #[test]
+    fn reading() {
+        init_zobrist_hashing();
+
+        let text = r#"
+[Event "Casual \"blitz\" game"]
+[White "Someone"]
+
+1. e4 {best by test} e5 2.Nf3 (2. f4 exf4 {the King's gambit} (2... d5)) 2... Nc6 $1
+3. Bb5 a6?! ; the Morphy defence
+4. Ba4 1-0
+
+[Event "Endgame"]
+[SetUp "1"]
+[FEN "4k3/8/8/8/8/8/4P3/4K3 w - - 0 40"]
+
+40. e4 Kd7 41. e5
+
+[Event "No tags after this one"]
+1. d4 d5 1/2-1/2
+"#;
+
+        let games = read_pgn(text).unwrap();
+        assert_eq!(games.len(), 3);
+
+        assert_eq!(games[0].tag("Event"), Some("Casual \"blitz\" game"));
+        assert_eq!(games[0].tag("White"), Some("Someone"));
+        assert_eq!(uci_moves(&games[0]), vec!["e2e4", "e7e5", "g1f3", "b8c6", "f1b5", "a7a6", "b5a4"]);
+        assert_eq!(games[0].result, "1-0");
+
+        // cut off by the next game's tags
+        assert_eq!(games[1].start.to_fen(), "4k3/8/8/8/8/8/4P3/4K3 w - - 0 40");
+        assert_eq!(uci_moves(&games[1]), vec!["e2e4", "e8d7", "e4e5"]);
+        assert_eq!(games[1].result, "*");
+
+        assert_eq!(games[2].result, "1/2-1/2");
+
+        // an illegal move says which game it's in
+        let error = read_pgn("1. e4 e5 2. Ke3").unwrap_err();
+        assert!(error.starts_with("game 1, after 2 moves"), "{}", error);
+
+        assert!(read_pgn("1. e4 {unterminated").is_err());
+        assert!(read_pgn("[Event]\n1. e4").is_err());
+    }
+
+    #[test]
+    fn writing() {
+        init_zobrist_hashing();
+
+        let mut game = read_pgn("[Event \"Test\"]\n1. e4 e5 2. Qh5 Ke7 3. Qxe5# 1-0").unwrap().remove(0);
+        game.set_tag("Annotator", "feldspar");
+
+        let notes = vec![
+            MoveNote::none(),
+            MoveNote { suffix: "", comment: Some("[%eval 0.30/6]".to_string()) },
+            MoveNote::none(),
+            MoveNote { suffix: "??", comment: None }
+        ];
+
+        assert_eq!(write_pgn(&game, &notes),
+                   "[Event \"Test\"]\n[Annotator \"feldspar\"]\n\n1. e4 e5 {[%eval 0.30/6]} 2. Qh5 Ke7?? 3. Qxe5# 1-0\n");
+
+        // what's written reads back as the same game
+        let reread = read_pgn(&write_pgn(&game, &notes)).unwrap().remove(0);
+        assert_eq!(uci_moves(&reread), uci_moves(&game));
+        assert_eq!(reread.tags, game.tags);
+
+        // a game starting with black to move
+        let black_first = read_pgn("[FEN \"4k3/8/8/8/8/8/8/R3K3 b - - 0 1\"]\n1... Kd7 2. Ra2").unwrap().remove(0);
+        assert_eq!(write_pgn(&black_first, &[]), "[FEN \"4k3/8/8/8/8/8/8/R3K3 b - - 0 1\"]\n\n1... Kd7 2. Ra2 *\n");
+
+        // and one long enough to need more than one line
+        let mut movetext = String::new();
+        for n in 0 .. 20 {
+            movetext.push_str(&format!("{}. Nf3 Nf6 {}. Ng1 Ng8 ", 2 * n + 1, 2 * n + 2));
+        }
+
+        let written = write_pgn(&read_pgn(&movetext).unwrap().remove(0), &[]);
+        assert!(written.lines().count() > 3);
+        assert!(written.lines().all(|line| line.len() <= PGN_LINE_LENGTH));
+    }
+}
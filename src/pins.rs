@@ -24,31 +24,40 @@ impl PinFinder {
     }
 
     pub fn update(&mut self, moving_color: Color, board: &Board) {
+        let king_square = board.get_king_square(moving_color);
+        self.find(board, king_square, !moving_color, board.occupied_by(moving_color));
+    }
+
+    // Same ray machinery, but aimed at the opponent's king: the "pinned" pieces are
+    // then our own pieces that block one of our sliders, ie. discovered check candidates.
+    pub fn update_discovered(&mut self, moving_color: Color, board: &Board) {
+        let king_square = board.get_king_square(!moving_color);
+        self.find(board, king_square, moving_color, board.occupied_by(moving_color));
+    }
+
+    fn find(&mut self, board: &Board, king_square: Square, slider_color: Color, blockers: Bitboard) {
         use PieceType::*;
 
         self.pinned_diagonally = Bitboard::new(0);
         self.pinned_nondiagonally = Bitboard::new(0);
 
-        let opponent_color = !moving_color;
         let occupied_squares = board.occupied();
-        let friendly_pieces = board.occupied_by(moving_color);
-        let king_square = board.get_king_square(moving_color);
 
-        let op_rq = board.get_pieces(opponent_color, Rook) | board.get_pieces(opponent_color, Queen);
-        let mut pinner = xray_rook_attacks(occupied_squares, friendly_pieces, king_square) & op_rq;
+        let op_rq = board.get_pieces(slider_color, Rook) | board.get_pieces(slider_color, Queen);
+        let mut pinner = xray_rook_attacks(occupied_squares, blockers, king_square) & op_rq;
         for pinner_square in pinner {
             let connecting_bits = ray_between_squares(king_square, pinner_square);
-            let pinned_bit = connecting_bits & friendly_pieces;
+            let pinned_bit = connecting_bits & blockers & !pinner_square.bitrep();
             self.nondiag_pin_map[pinned_bit.bitscan_forward().idx()] = connecting_bits;
             debug_assert!(pinned_bit.population() == 1);
             self.pinned_nondiagonally |= pinned_bit;
         }
 
-        let op_bq = board.get_pieces(opponent_color, Bishop) | board.get_pieces(opponent_color, Queen);
-        pinner = xray_bishop_attacks(occupied_squares, friendly_pieces, king_square) & op_bq;
+        let op_bq = board.get_pieces(slider_color, Bishop) | board.get_pieces(slider_color, Queen);
+        pinner = xray_bishop_attacks(occupied_squares, blockers, king_square) & op_bq;
         for pinner_square in pinner {
             let connecting_bits = ray_between_squares(king_square, pinner_square);
-            let pinned_bit = connecting_bits & friendly_pieces;
+            let pinned_bit = connecting_bits & blockers & !pinner_square.bitrep();
             self.diag_pin_map[pinned_bit.bitscan_forward().idx()] = connecting_bits;
             debug_assert!(pinned_bit.population() == 1);
             self.pinned_diagonally |= pinned_bit;
@@ -75,3 +84,78 @@ impl PinFinder {
         self.nondiag_pin_map[sq.idx()]
     }
 }
+
+// Both sides' absolutely pinned pieces and the squares each of them can still move to, worked
+// out once for a position so that the eval doesn't count moves the pins rule out.
+#[derive(Clone, Copy)]
+pub struct PinInfo {
+    pinned: [Bitboard; 2],
+    // the squares between a pinned piece's king and its pinner, the pinner included,
+    // and every square for a piece that isn't pinned
+    rays: [Bitboard; 64]
+}
+
+impl PinInfo {
+    pub fn compute(board: &Board) -> PinInfo {
+        let mut info = PinInfo {
+            pinned: [Bitboard::new(0); 2],
+            rays: [Bitboard::new(!0); 64]
+        };
+
+        let mut finder = PinFinder::new();
+
+        for &color in [Color::White, Color::Black].iter() {
+            finder.update(color, board);
+            info.pinned[color as usize] = finder.pinned();
+
+            for sq in finder.pinned_diagonally() {
+                info.rays[sq.idx()] = finder.diagonal_constraint(sq);
+            }
+
+            for sq in finder.pinned_nondiagonally() {
+                info.rays[sq.idx()] = finder.nondiagonal_constraint(sq);
+            }
+        }
+
+        return info;
+    }
+
+    pub fn pinned(&self, color: Color) -> Bitboard {
+        self.pinned[color as usize]
+    }
+
+    pub fn legal_ray(&self, sq: Square) -> Bitboard {
+        self.rays[sq.idx()]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pins::*;
+
+    #[test]
+    fn pin_info() {
+        // each side's bishop on the a-file pins a piece to the other king
+        let game = Game::from_fen_str("4k3/3b4/8/b7/B7/8/3N4/4K3 w - - 0 1").unwrap();
+        let info = PinInfo::compute(&game.board);
+
+        let ray_squares = |sq: &str| -> Vec<String> {
+            let sq = Square::from_algebraic(sq).unwrap();
+            info.legal_ray(sq).into_iter().map(|sq| sq.to_algebraic()).collect()
+        };
+
+        assert_eq!(info.pinned(Color::White), Square::from_algebraic("d2").unwrap().bitrep());
+        assert_eq!(info.pinned(Color::Black), Square::from_algebraic("d7").unwrap().bitrep());
+
+        let mut d7_ray = ray_squares("d7");
+        d7_ray.sort();
+        assert_eq!(d7_ray, vec!["a4", "b5", "c6", "d7"]);
+
+        let mut d2_ray = ray_squares("d2");
+        d2_ray.sort();
+        assert_eq!(d2_ray, vec!["a5", "b4", "c3", "d2"]);
+
+        // nothing holds back the pinners
+        assert_eq!(info.legal_ray(Square::from_algebraic("a4").unwrap()), Bitboard::new(!0));
+    }
+}
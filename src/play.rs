@@ -1,45 +1,274 @@
+use adjudication::*;
+use core::*;
 use game::*;
 use movegen::*;
+use moves::*;
 use search::*;
 use tree::*;
 use eval::*;
+use feldspar::*;
+use skill::*;
+use uci::*;
 
-// pub fn play_against_ai() {
-//     // let mut tree = SearchTree::new(Game::from_fen_str("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap());
-//     let mut tree = SearchTree::new(Game::starting_position());
-//
-//     loop {
-//         tree.focus().board.print();
-//         println!("FEN: {}", tree.focus().to_fen());
-//         println!("score: {}", Score::recompute(&tree.focus()).val);
-//         println!("");
-//         print!("Enter your move: ");
-//
-//         use std::io::{stdin,stdout,Write};
-//         let _=stdout().flush();
-//         let mut s=String::new();
-//         stdin().read_line(&mut s).expect("Did not enter a correct string");
-//
-//         if let Some('\n')=s.chars().next_back() {
-//             s.pop();
-//         }
-//         if let Some('\r')=s.chars().next_back() {
-//             s.pop();
-//         }
-//
-//         match move_from_algebraic(&tree.focus(), s) {
-//             Some(m) => {
-//                 tree.make_move(m);
-//                 let game_copy = *tree.focus();
-//                 tree.reset_root(game_copy, m);
-//                 let (_, ai_move) = alpha_beta(&mut tree,6);
-//                 tree.make_move(ai_move);
-//             },
-//             None => println!("Invalid move! Try again...")
-//         }
-//     }
-//
-// }
+const PLAY_TABLE_SIZE: usize = 10000000;
+const PLAY_MOVE_TIME_MS: u32 = 2000;
+
+fn read_line() -> String {
+    use std::io::{stdin,stdout,Write};
+    let _=stdout().flush();
+    let mut s=String::new();
+    stdin().read_line(&mut s).expect("Did not enter a correct string");
+    s.trim().to_string()
+}
+
+// From start, with the engine searching depth plies deep when there is one and for a fixed
+// time when there isn't.
+pub fn play_against_ai(start: Game, depth: Option<u8>) {
+    let mut engine = Feldspar::with_table_size(PLAY_TABLE_SIZE);
+
+    print!("Choose difficulty (0-{}): ", MAX_SKILL_LEVEL);
+    let level = read_line().parse::<u8>().unwrap_or(MAX_SKILL_LEVEL);
+    engine.skill.set_level(level);
+
+    let mut game = start;
+    let mut history = Vec::new();
+    let mut last_move = None;
+
+    let mut limits = SearchLimits::new();
+    match depth {
+        Some(_) => limits.depth = depth,
+        None => limits.movetime = Some(PLAY_MOVE_TIME_MS)
+    }
+    let mut io = UciIo::stdio();
+
+    // only the engine reports scores, so it's the only one that resigns or offers draws
+    let mut adjudicator = Adjudicator::new(AdjudicationRules::new());
+    let mut adjudicated = None;
+
+    while game.outcome_with_history(&history).is_none() {
+        game.print(last_move, start.to_move);
+        println!("FEN: {}", game.to_fen());
+        println!("");
+        print!("Enter your move: ");
+
+        match parse_user_move(&game, &read_line()) {
+            Ok(m) => {
+                history.push(game.hash);
+                game.make_move(m);
+                last_move = Some(m);
+
+                if game.outcome_with_history(&history).is_some() {
+                    break;
+                }
+
+                // the search tree's history includes the root position itself
+                let mut root_history = history.clone();
+                root_history.push(game.hash);
+                engine.replace_game(game, root_history);
+                let result = engine.search_position(&limits, &mut io);
+
+                match adjudicator.record(game.to_move, result.score, game.fullmoves) {
+                    Some(Adjudication::Resignation(loser)) if loser == game.to_move => {
+                        println!("I resign");
+                        adjudicated = Some(Adjudication::Resignation(loser));
+                        break;
+                    },
+                    Some(Adjudication::Draw) => {
+                        print!("I offer a draw, do you accept? (y/n): ");
+                        if read_line().to_lowercase().starts_with('y') {
+                            adjudicated = Some(Adjudication::Draw);
+                            break;
+                        }
+                        adjudicator.decline_draw();
+                    },
+                    _ => {}
+                }
+
+                println!("feldspar plays {}", move_to_uci_str(&game, result.best_move, game.chess960));
+
+                history.push(game.hash);
+                game.make_move(result.best_move);
+                last_move = Some(result.best_move);
+            },
+            Err(message) => println!("{}", message)
+        }
+    }
+
+    game.print(last_move, start.to_move);
+
+    match adjudicated {
+        Some(adjudication) => println!("Game over: {:?} ({})", adjudication.result(), adjudication.termination()),
+        None => println!("Game over: {:?}", game.outcome_with_history(&history).unwrap())
+    }
+}
+
+// Whatever a person is likely to type for a move: coordinates like e2e4, e2-e4 or e7e8=Q in
+// any case, castling as O-O, 0-0 or oo, or SAN like Nf3 and exd6. SAN piece letters have to be
+// upper case, since b is the b-pawn's file. The error says why nothing matched, with the
+// legal moves that come closest.
+pub fn parse_user_move(game: &Game, input: &str) -> Result<Move, String> {
+    let legal_moves: Vec<Move> = next_moves_standalone(game).iter().cloned().collect();
+    let unreadable = format!("can't read \"{}\" as a move, try something like e2e4, Nf3 or O-O", input.trim());
+
+    if !input.is_ascii() {
+        return Err(unreadable);
+    }
+
+    // check marks, annotations and an en passant note don't change which move it is
+    let mut text = input.trim().trim_right_matches(|c| "+#!?".contains(c)).to_string();
+    for note in ["e.p.", "ep"].iter() {
+        if text.len() > 2 + note.len() && text.to_lowercase().ends_with(note) {
+            let len = text.len() - note.len();
+            text.truncate(len);
+        }
+    }
+    let text = text.trim().trim_right_matches(|c| "+#!?".contains(c));
+
+    if text.is_empty() {
+        return Err(unreadable);
+    }
+
+    let castle = text.to_lowercase().replace("-", "").replace("0", "o");
+    if castle == "oo" || castle == "ooo" {
+        let flag = if castle == "oo" { MoveFlag::KingCastle } else { MoveFlag::QueenCastle };
+        return match legal_moves.iter().find(|m| m.is_castle() && m.flag() == flag) {
+            Some(m) => Ok(*m),
+            None => Err(format!("{} isn't legal here", if castle == "oo" { "O-O" } else { "O-O-O" }))
+        };
+    }
+
+    let coordinates: String = text.to_lowercase().chars().filter(|c| !"-x=".contains(*c)).collect();
+    if let Some((from, to, promotion)) = read_coordinates(&coordinates) {
+        // Chess960 castles are written as the king capturing its own rook
+        let castle = move_from_algebraic(game, coordinates[.. 4].to_string()).filter(|m| m.is_castle());
+
+        let matches: Vec<Move> = legal_moves.iter()
+            .filter(|m| m.from() == from && (m.to() == to || Some(**m) == castle))
+            .filter(|m| promotion.is_none() || m.promotion_piece() == promotion)
+            .cloned()
+            .collect();
+
+        return pick_match(game, text, &matches, &near_misses(game, &legal_moves, Some(from), to));
+    }
+
+    let (ptype, from_file, from_rank, to, promotion) = match read_san(text) {
+        Some(parts) => parts,
+        None => return Err(unreadable)
+    };
+
+    let matches: Vec<Move> = legal_moves.iter()
+        .filter(|m| !m.is_castle() && m.moved_piece() == ptype && m.to() == to)
+        .filter(|m| from_file.map_or(ptype != PieceType::Pawn || m.from().file() == to.file(), |f| m.from().file() == f))
+        .filter(|m| from_rank.map_or(true, |r| m.from().rank() == r))
+        .filter(|m| promotion.is_none() || m.promotion_piece() == promotion)
+        .cloned()
+        .collect();
+
+    return pick_match(game, text, &matches, &near_misses(game, &legal_moves, None, to));
+}
+
+// from, to and promotion of moves like e2e4 and e7e8q, already in lower case
+fn read_coordinates(text: &str) -> Option<(Square, Square, Option<PieceType>)> {
+    if text.len() != 4 && text.len() != 5 {
+        return None;
+    }
+
+    let from = Square::from_algebraic(&text[0 .. 2])?;
+    let to = Square::from_algebraic(&text[2 .. 4])?;
+
+    let promotion = match text.chars().nth(4) {
+        None => None,
+        Some(c) if "nbrq".contains(c) => PieceType::from_char(c),
+        Some(_) => return None
+    };
+
+    return Some((from, to, promotion));
+}
+
+// piece, origin file and rank if given, destination and promotion of a SAN move
+fn read_san(text: &str) -> Option<(PieceType, Option<u32>, Option<u32>, Square, Option<PieceType>)> {
+    let mut chars: Vec<char> = text.chars().filter(|c| !"x:=".contains(*c)).collect();
+
+    let ptype = match chars.first() {
+        Some(c) if "NBRQK".contains(*c) => PieceType::from_char(*c),
+        _ => None
+    };
+
+    if ptype.is_some() {
+        chars.remove(0);
+    }
+
+    let promotion = match (chars.last().cloned(), chars.len()) {
+        (Some(c), n) if n >= 3 && "NBRQnbrq".contains(c) && chars[n - 2].is_ascii_digit() => {
+            chars.pop();
+            PieceType::from_char(c)
+        },
+        _ => None
+    };
+
+    if chars.len() < 2 || chars.len() > 4 || (promotion.is_some() && ptype.is_some()) {
+        return None;
+    }
+
+    let split = chars.len() - 2;
+    let to_str: String = chars[split ..].iter().map(|c| c.to_ascii_lowercase()).collect();
+    let to = Square::from_algebraic(&to_str)?;
+
+    let mut from_file = None;
+    let mut from_rank = None;
+
+    for c in chars[.. split].iter() {
+        match *c {
+            'a' ..= 'h' if from_file.is_none() && from_rank.is_none() => from_file = Some(*c as u32 - 'a' as u32 + 1),
+            '1' ..= '8' if from_rank.is_none() => from_rank = Some(*c as u32 - '0' as u32),
+            _ => return None
+        }
+    }
+
+    return Some((ptype.unwrap_or(PieceType::Pawn), from_file, from_rank, to, promotion));
+}
+
+// legal moves that were probably meant: those from the same square, those to the same square,
+// and an en passant capture of a pawn on it
+fn near_misses(game: &Game, legal_moves: &[Move], from: Option<Square>, to: Square) -> Vec<Move> {
+    let captured_by_ep = |m: &Move| {
+        if !m.is_en_passant() {
+            return false;
+        }
+
+        let captured_idx = match game.to_move {
+            Color::White => m.to().unwrap() - 8,
+            Color::Black => m.to().unwrap() + 8
+        };
+        captured_idx == to.unwrap()
+    };
+
+    legal_moves.iter()
+        .filter(|m| Some(m.from()) == from || m.to() == to || captured_by_ep(m))
+        .cloned()
+        .collect()
+}
+
+fn pick_match(game: &Game, text: &str, matches: &[Move], near_misses: &[Move]) -> Result<Move, String> {
+    let describe = |moves: &[Move]| -> String {
+        let names: Vec<String> = moves.iter().map(|m| {
+            if m.is_en_passant() {
+                format!("{} e.p.", move_to_san(game, *m))
+            } else {
+                move_to_san(game, *m)
+            }
+        }).collect();
+
+        names.join(" or ")
+    };
+
+    match matches.len() {
+        1 => Ok(matches[0]),
+        0 if near_misses.is_empty() => Err(format!("{} isn't a legal move", text)),
+        0 => Err(format!("{} isn't a legal move, did you mean {}?", text, describe(near_misses))),
+        _ => Err(format!("{} is ambiguous, did you mean {}?", text, describe(matches)))
+    }
+}
 
 // use rand::{thread_rng, ThreadRng, Rng};
 
@@ -60,3 +289,88 @@ use eval::*;
 //
 // }
 
+#[cfg(test)]
+mod test {
+    use play::*;
+    use zobrist::*;
+
+    #[test]
+    fn user_moves() {
+        init_zobrist_hashing();
+
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        // d7d5 was just played, so exd6 is en passant, and white can castle either way
+        let en_passant = "r3k2r/8/8/3pP3/8/8/8/R3K2R w KQkq d6 0 1";
+        let promotion = "r6k/1P6/8/8/8/8/8/1N2KN2 w - - 0 1";
+
+        // the expected move, or part of the error message
+        let cases: [(&str, &str, Result<&str, &str>); 55] = [
+            (start, "e2e4", Ok("e2e4")),
+            (start, "E2E4", Ok("e2e4")),
+            (start, "e2-e4", Ok("e2e4")),
+            (start, " e2e4 ", Ok("e2e4")),
+            (start, "e4", Ok("e2e4")),
+            (start, "b4", Ok("b2b4")),
+            (start, "Nf3", Ok("g1f3")),
+            (start, "Ngf3", Ok("g1f3")),
+            (start, "Ng1f3", Ok("g1f3")),
+            (start, "Nc3!?", Ok("b1c3")),
+            (start, "g1f3", Ok("g1f3")),
+            (start, "nf3", Err("can't read")),
+            (start, "Bb5", Err("isn't a legal move")),
+            (start, "e5", Err("e5 isn't a legal move")),
+            (start, "e2e5", Err("did you mean e3 or e4?")),
+            (start, "O-O", Err("O-O isn't legal here")),
+            (start, "", Err("can't read")),
+            (start, "hello", Err("can't read")),
+            (start, "e2é4", Err("can't read")),
+            (start, "Kxe9", Err("can't read")),
+
+            (en_passant, "exd6", Ok("e5d6")),
+            (en_passant, "exd6 e.p.", Ok("e5d6")),
+            (en_passant, "exd6ep", Ok("e5d6")),
+            (en_passant, "ed6", Ok("e5d6")),
+            (en_passant, "e5d6", Ok("e5d6")),
+            (en_passant, "e5xd6", Ok("e5d6")),
+            (en_passant, "exd5", Err("did you mean exd6 e.p.?")),
+            (en_passant, "e6", Ok("e5e6")),
+            (en_passant, "O-O", Ok("e1g1")),
+            (en_passant, "0-0", Ok("e1g1")),
+            (en_passant, "oo", Ok("e1g1")),
+            (en_passant, "o-o", Ok("e1g1")),
+            (en_passant, "O-O+", Ok("e1g1")),
+            (en_passant, "e1g1", Ok("e1g1")),
+            (en_passant, "O-O-O", Ok("e1c1")),
+            (en_passant, "0-0-0", Ok("e1c1")),
+            (en_passant, "ooo", Ok("e1c1")),
+            (en_passant, "Kf1", Ok("e1f1")),
+            (en_passant, "Rd1", Ok("a1d1")),
+            (en_passant, "Rf1", Ok("h1f1")),
+            (en_passant, "Rxa8+", Ok("a1a8")),
+            (en_passant, "Ra8", Ok("a1a8")),
+            (en_passant, "Rh8", Ok("h1h8")),
+
+            (promotion, "b8=Q", Ok("b7b8q")),
+            (promotion, "b8Q", Ok("b7b8q")),
+            (promotion, "b8q", Ok("b7b8q")),
+            (promotion, "b7b8q", Ok("b7b8q")),
+            (promotion, "B7-B8=Q", Ok("b7b8q")),
+            (promotion, "bxa8=N", Ok("b7a8n")),
+            (promotion, "bxa8b", Ok("b7a8b")),
+            (promotion, "b8", Err("b8=R+ or b8=Q+")),
+            (promotion, "b7b8", Err("is ambiguous")),
+            (promotion, "Nbd2", Ok("b1d2")),
+            (promotion, "Nfd2", Ok("f1d2")),
+            (promotion, "Nd2", Err("is ambiguous, did you mean Nfd2 or Nbd2?"))
+        ];
+
+        for &(fen, input, expected) in cases.iter() {
+            let game = Game::from_fen_str(fen).unwrap();
+            match (parse_user_move(&game, input), expected) {
+                (Ok(m), Ok(uci)) => assert_eq!(m.to_uci_str(), uci, "{}", input),
+                (Err(message), Err(part)) => assert!(message.contains(part), "{}: {}", input, message),
+                (result, _) => panic!("{}: {:?}", input, result.map(|m| m.to_uci_str()))
+            }
+        }
+    }
+}
@@ -0,0 +1,74 @@
+// Categorized test positions, for benchmarks that should report each kind of position separately.
+
+pub struct PositionCategory {
+    pub name: &'static str,
+    pub fens: &'static [&'static str]
+}
+
+pub const MOVEGEN_CATEGORIES: [PositionCategory; 5] = [
+    PositionCategory {
+        name: "open middlegame",
+        fens: &[
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+            "r1bq1rk1/pp2bppp/2n1pn2/3p4/2PP4/2N1PN2/PP1B1PPP/R2QKB1R w KQ - 3 8"
+        ]
+    },
+    PositionCategory {
+        name: "closed pawn chains",
+        fens: &[
+            "rnbqkbnr/pp3ppp/4p3/2ppP3/3P4/8/PPP2PPP/RNBQKBNR w KQkq - 0 4",
+            "r1bqk2r/pp1nbppp/2n1p3/2ppP3/3P1P2/2N1BN2/PPPQ2PP/R3KB1R b KQkq - 4 8",
+            "2r2rk1/pp1qbppp/2n1p3/3pPn2/3P4/P1N2N2/1P2BPPP/R2Q1RK1 w - - 0 14"
+        ]
+    },
+    PositionCategory {
+        name: "in check",
+        fens: &[
+            "4k3/8/8/8/8/8/4r3/R3K2R w KQ - 0 1",
+            "8/8/8/2k5/3Pp3/8/8/4K3 b - d3 0 1",
+            "rnb1kbnr/pppp1ppp/8/4p3/7q/5P2/PPPPP1PP/RNBQKBNR w KQkq - 1 3",
+            "4k3/8/8/8/8/5n2/8/4K2r w - - 0 1"
+        ]
+    },
+    PositionCategory {
+        name: "promotion endgame",
+        fens: &[
+            "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1",
+            "8/PPPPPPPk/8/8/8/8/pppppppK/8 w - - 0 1",
+            "3r4/4P3/8/8/8/8/4p3/3RK2k w - - 0 1"
+        ]
+    },
+    PositionCategory {
+        name: "many sliders",
+        fens: &[
+            "1q2r1k1/1b3pbp/p5p1/1p1r4/3B4/1BQ5/PP3PPP/2R1R1K1 w - - 0 1",
+            "r1b1k2r/2q1bppp/p1nppn2/1p6/3NP3/1BN1BQ2/PPP2PPP/R4RK1 w kq - 0 1",
+            "2k5/8/8/3QQ3/3QQ3/8/8/7K w - - 0 1"
+        ]
+    }
+];
+
+#[cfg(test)]
+mod test {
+    use positions::*;
+    use game::*;
+
+    #[test]
+    fn valid_positions() {
+        for category in MOVEGEN_CATEGORIES.iter() {
+            for fen in category.fens.iter() {
+                let game = Game::from_fen_str(fen).unwrap();
+                assert_eq!(&game.to_fen(), fen);
+
+                // the side that just moved can't be left in check
+                let waiting_king = game.board.get_king_square(!game.to_move);
+                assert!(game.board.attackers(waiting_king, game.to_move).empty(), "{}", fen);
+
+                if category.name == "in check" {
+                    assert!(game.in_check(), "{}", fen);
+                }
+            }
+        }
+    }
+}
@@ -3,34 +3,15 @@
 extern crate colored;
 use print::colored::Colorize;
 
+extern crate atty;
+
 use core::*;
 use bitboard::*;
 use board::*;
 use moves::*;
 use game::*;
 
-impl Piece {
-    fn to_unicode(&self) -> &'static str {
-        match self.color {
-            Color::White => match self.ptype {
-                PieceType::Pawn   => "♙ ",
-                PieceType::Knight => "♘ ",
-                PieceType::Bishop => "♗ ",
-                PieceType::Rook   => "♖ ",
-                PieceType::Queen  => "♕ ",
-                PieceType::King   => "♔ ",
-            },
-            Color::Black => match self.ptype {
-                PieceType::Pawn   => "♟ ",
-                PieceType::Knight => "♞ ",
-                PieceType::Bishop => "♝ ",
-                PieceType::Rook   => "♜ ",
-                PieceType::Queen  => "♛ ",
-                PieceType::King   => "♚ ",
-            }
-        }
-    }
-}
+use std::fmt;
 
 impl Bitboard {
 
@@ -54,10 +35,10 @@ impl Bitboard {
 
 impl Board {
     pub fn print(&self) {
-        let mut chars = vec!["  "; 64];
+        let mut chars = vec!["  ".to_string(); 64];
         for i in 0 .. 64 {
             match self.piece_at(Square::new(i)) {
-                Some(piece) => chars[63 - i as usize] = piece.to_unicode(),
+                Some(piece) => chars[63 - i as usize] = format!("{} ", piece.to_unicode()),
                 None => ()
             }
         }
@@ -86,6 +67,142 @@ impl Board {
     }
 }
 
+#[derive(Debug, PartialEq, Clone)]
+pub struct PrintOptions {
+    // the side whose pieces are at the bottom
+    pub perspective: Color,
+    pub coordinates: bool,
+    pub unicode_pieces: bool,
+    pub highlight: Vec<Square>,
+    // ANSI backgrounds on highlighted squares, rather than an asterisk in front of them
+    pub colors: bool
+}
+
+impl PrintOptions {
+    pub fn new() -> PrintOptions {
+        PrintOptions {
+            perspective: Color::White,
+            coordinates: true,
+            unicode_pieces: false,
+            highlight: Vec::new(),
+            colors: false
+        }
+    }
+}
+
+impl Board {
+    // One line per rank and no trailing newline. Every square is a separator and a piece, or a
+    // '.' when it's empty, so a highlighted square without colors reads as "*N".
+    pub fn render(&self, out: &mut fmt::Write, options: &PrintOptions) -> fmt::Result {
+        // squares run from h1 at index 0 to a8 at index 63, so black's view is just index order
+        let white = options.perspective == Color::White;
+        let ranks: Vec<u32> = if white { (1 .. 9).rev().collect() } else { (1 .. 9).collect() };
+        let files = if white { "a b c d e f g h" } else { "h g f e d c b a" };
+
+        if options.coordinates {
+            writeln!(out, "  {}", files)?;
+        }
+
+        for (i, &rank) in ranks.iter().enumerate() {
+            if i > 0 {
+                writeln!(out)?;
+            }
+            if options.coordinates {
+                write!(out, "{}", rank)?;
+            }
+
+            let mut squares: Vec<u32> = (8 * (rank - 1) .. 8 * rank).collect();
+            if white {
+                squares.reverse();
+            }
+
+            for &idx in squares.iter() {
+                let sq = Square::new(idx);
+                let c = match self.piece_at(sq) {
+                    Some(piece) if options.unicode_pieces => piece.to_unicode(),
+                    Some(piece) => piece.to_char(),
+                    None => '.'
+                };
+
+                if !options.highlight.contains(&sq) {
+                    write!(out, " {}", c)?;
+                } else if options.colors {
+                    write!(out, "{}", format!(" {}", c).color("black").on_color("yellow"))?;
+                } else {
+                    write!(out, "*{}", c)?;
+                }
+            }
+
+            if options.coordinates {
+                write!(out, " {}", rank)?;
+            }
+        }
+
+        if options.coordinates {
+            write!(out, "\n  {}", files)?;
+        }
+
+        return Ok(());
+    }
+}
+
+// plain ASCII, so that it reads the same in a test failure as on a terminal
+impl fmt::Debug for Board {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.render(f, &PrintOptions::new())
+    }
+}
+
+impl Game {
+    // the squares last_move went from and to, and any pieces giving check
+    pub fn highlighted_squares(&self, last_move: Option<Move>) -> Vec<Square> {
+        let mut squares = Vec::new();
+
+        if let Some(m) = last_move {
+            squares.push(m.from());
+            squares.push(m.to());
+        }
+
+        for sq in self.king_attackers {
+            if !squares.contains(&sq) {
+                squares.push(sq);
+            }
+        }
+
+        return squares;
+    }
+
+    // Game doesn't keep the moves that led to it, so whoever made the last one passes it in.
+    // Highlights are colored when stdout is a terminal and marked with asterisks otherwise.
+    pub fn print(&self, last_move: Option<Move>, perspective: Color) {
+        let mut options = PrintOptions::new();
+        options.perspective = perspective;
+        options.unicode_pieces = true;
+        options.highlight = self.highlighted_squares(last_move);
+        options.colors = atty::is(atty::Stream::Stdout);
+
+        let mut rendered = String::new();
+        self.board.render(&mut rendered, &options).unwrap();
+        println!("{}", rendered);
+    }
+}
+
+impl fmt::Debug for Game {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let fen = self.to_fen();
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+
+        writeln!(f, "{:?}", self.board)?;
+        writeln!(f, "to move    : {:?}", self.to_move)?;
+        writeln!(f, "castling   : {}", fields[2])?;
+        writeln!(f, "en passant : {}", fields[3])?;
+        writeln!(f, "halfmoves  : {}", self.halfmove_clock)?;
+        writeln!(f, "fullmoves  : {}", self.fullmoves)?;
+        writeln!(f, "hash       : {:016x}", self.hash.unwrap())?;
+        write!(f, "fen        : {}", fen)
+    }
+}
+
 impl Move {
     //TODO: expand to print details (capture, ep, promotion, etc)
     //TODO: UCI print output vs. normal print different functions
@@ -93,3 +210,81 @@ impl Move {
         eprintln!("{}{}", self.from().to_algebraic(), self.to().to_algebraic());
     }
 }
+
+#[cfg(test)]
+mod test {
+    use print::*;
+    use zobrist::*;
+    use moves::*;
+
+    #[test]
+    fn debug_output() {
+        init_zobrist_hashing();
+
+        let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R b KQkq - 3 12";
+        let game = Game::from_fen_str(fen).unwrap();
+        let debug = format!("{:?}", game);
+
+        assert!(debug.contains(fen));
+        assert!(debug.contains("to move    : Black"));
+        assert!(debug.contains("castling   : KQkq"));
+
+        let lines: Vec<&str> = debug.lines().collect();
+        assert_eq!(lines[0], "  a b c d e f g h");
+        assert_eq!(lines[1], "8 r . . . k . . r 8");
+        assert_eq!(lines[8], "1 R . . . K . . R 1");
+        assert_eq!(lines[9], "  a b c d e f g h");
+    }
+
+    #[test]
+    fn render_perspectives() {
+        init_zobrist_hashing();
+
+        // 4. Qxf7#, with the queen both the last move and the checking piece
+        let game = Game::from_fen_str("r1bqkb1r/pppp1Qpp/2n2n2/4p3/2B1P3/8/PPPP1PPP/RNB1K1NR b KQkq - 0 4").unwrap();
+        let last_move = Move::new_quiet(Square::from_algebraic("h5").unwrap(), Square::from_algebraic("f7").unwrap(),
+                                        MoveFlag::Quiet, PieceType::Queen);
+
+        let mut options = PrintOptions::new();
+        options.highlight = game.highlighted_squares(Some(last_move));
+        assert_eq!(options.highlight, vec![Square::from_algebraic("h5").unwrap(), Square::from_algebraic("f7").unwrap()]);
+
+        let mut white = String::new();
+        game.board.render(&mut white, &options).unwrap();
+        assert_eq!(white, ["  a b c d e f g h",
+                           "8 r . b q k b . r 8",
+                           "7 p p p p .*Q p p 7",
+                           "6 . . n . . n . . 6",
+                           "5 . . . . p . .*. 5",
+                           "4 . . B . P . . . 4",
+                           "3 . . . . . . . . 3",
+                           "2 P P P P . P P P 2",
+                           "1 R N B . K . N R 1",
+                           "  a b c d e f g h"].join("\n"));
+
+        options.perspective = Color::Black;
+        let mut black = String::new();
+        game.board.render(&mut black, &options).unwrap();
+        assert_eq!(black, ["  h g f e d c b a",
+                           "1 R N . K . B N R 1",
+                           "2 P P P . P P P P 2",
+                           "3 . . . . . . . . 3",
+                           "4 . . . P . B . . 4",
+                           "5*. . . p . . . . 5",
+                           "6 . . n . . n . . 6",
+                           "7 p p*Q . p p p p 7",
+                           "8 r . b k q b . r 8",
+                           "  h g f e d c b a"].join("\n"));
+
+        // without coordinates there's just the squares, one line per rank
+        options.coordinates = false;
+        options.unicode_pieces = true;
+        options.highlight = Vec::new();
+        let mut bare = String::new();
+        game.board.render(&mut bare, &options).unwrap();
+        let lines: Vec<&str> = bare.lines().collect();
+        assert_eq!(lines.len(), 8);
+        assert_eq!(lines[0], " ♖ ♘ . ♔ . ♗ ♘ ♖");
+        assert_eq!(lines[7], " ♜ . ♝ ♚ ♛ ♝ . ♜");
+    }
+}
@@ -4,23 +4,217 @@ use movegen::*;
 use moves::*;
 use tree::*;
 use eval::*;
+use ordering::*;
 use zobrist::*;
+use stats::*;
+
+use std::cmp::min;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub const MAX_SEARCH_DEPTH: u8 = 128;
+
+const REVERSE_FUTILITY_DEPTH: u8 = 3;
+const REVERSE_FUTILITY_MARGIN: i16 = 120;
+// only a ply from the horizon: further out, razoring gives up on the nodes futility pruning
+// would thin out, and checking both costs more than it saves
+const FUTILITY_MARGINS: [i16; 2] = [0, 50];
+const RAZORING_MARGINS: [i16; 4] = [0, 150, 250, 350];
+
+// Each technique that can change the result of a search, so a misbehaving one can be found
+// by turning them off one at a time. With none of them the score is the exact alpha-beta score.
+bitflags! {
+    pub struct SearchOptions: u8 {
+        const REVERSE_FUTILITY = 0b0001;
+        const FUTILITY         = 0b0010;
+        // doesn't change the score, only how much work it takes to find it
+        const PVS              = 0b0100;
+        const RAZORING         = 0b1000;
+        const EXTENSIONS       = 0b1_0000;
+    }
+}
+
+// Reasons to search a node a ply deeper than its depth allows. They're all granted by
+// extend(), which charges them to a single budget for the whole line, so no combination
+// of them can make a line run away.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Extension {
+    // the side to move is in check
+    Check,
+    // the side to move has only one legal move
+    SingleReply
+}
+
+pub const EXTENSION_KINDS: usize = 2;
+
+// At the root, an evaluation this good for the side to move is no time to settle for a draw.
+// A move that draws then scores below any other within the penalty of it.
+const WINNING_SCORE: i16 = 300;
+const DRAW_AVOIDANCE_PENALTY: i16 = 50;
 
 pub struct SearchContext {
     pub tree: SearchTree,
     pub qtree: SearchTree,
-    pub table: TranspositionTable,
+    // shared with the other threads searching the same position
+    pub table: Arc<TranspositionTable>,
     pub timer: SearchTimer,
-    pub ran_out_of_time: bool
+    // set by another thread to end the search as if the timer had run out
+    pub stop: Arc<AtomicBool>,
+    pub ran_out_of_time: bool,
+    // deepest ply reached including quiescence, reset by the caller for each search
+    pub seldepth: usize,
+    // if non-empty, only these moves are considered at the root
+    pub root_moves: Vec<Move>,
+    pub options: SearchOptions,
+    // centipawns a draw is worth less than zero to the side to move at the root
+    pub contempt: i16,
+    pub stats: SearchStats,
+    // extensions granted so far along the current line, one entry for each ply
+    path_extensions: Vec<u8>,
+    // the most a line may be extended in total, set from the depth of the root search
+    extension_budget: u8,
+    // the score the last root search finished with, and the root it was for
+    root_score: Option<(Hash, Score)>,
+    // makes the search panic once the tree has seen this many nodes
+    #[cfg(test)]
+    pub panic_at_node: Option<u64>
+}
+
+impl SearchContext {
+    pub fn new(game: Game, table_entries: usize) -> SearchContext {
+        SearchContext::with_table(game, Arc::new(TranspositionTable::new(table_entries)))
+    }
+
+    pub fn with_table(game: Game, table: Arc<TranspositionTable>) -> SearchContext {
+        let mut qtree = SearchTree::new(game);
+        qtree.in_quiescence = true;
+
+        SearchContext {
+            tree: SearchTree::new(game),
+            qtree: qtree,
+            table: table,
+            timer: SearchTimer::new(u32::max_value()),
+            stop: Arc::new(AtomicBool::new(false)),
+            ran_out_of_time: false,
+            seldepth: 0,
+            root_moves: Vec::new(),
+            options: SearchOptions::all(),
+            contempt: 0,
+            stats: SearchStats::new(),
+            path_extensions: Vec::new(),
+            extension_budget: 0,
+            root_score: None,
+            #[cfg(test)]
+            panic_at_node: None
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchLimits {
+    pub wtime: u32,
+    pub btime: u32,
+    pub winc: u32,
+    pub binc: u32,
+    pub movetime: Option<u32>,
+    pub depth: Option<u8>,
+    // "go mate N", a mate in N moves
+    pub mate: Option<u8>,
+    pub searchmoves: Vec<Move>,
+    // "go infinite", searched until the GUI says stop whatever else is given
+    pub infinite: bool
+}
+
+impl SearchLimits {
+    pub fn new() -> SearchLimits {
+        SearchLimits {
+            wtime: 0,
+            btime: 0,
+            winc: 0,
+            binc: 0,
+            movetime: None,
+            depth: None,
+            mate: None,
+            searchmoves: Vec::new(),
+            infinite: false
+        }
+    }
+}
+
+pub fn negamax(context: &mut SearchContext, depth_left: u8, alpha: Score, beta: Score) -> (Score, Move) {
+    negamax_reporting(context, depth_left, alpha, beta, &mut |_, _, _| {})
+}
+
+// negamax that calls report(depth, move, move number) as the search reaches each root move
+pub fn negamax_reporting(context: &mut SearchContext, depth_left: u8, alpha: Score, beta: Score,
+                         report: &mut FnMut(u8, Move, usize)) -> (Score, Move) {
+    let (score, best_move, _) = search_node(context, depth_left, alpha, beta, report);
+    return (score, best_move);
 }
 
-pub fn negamax(context: &mut SearchContext, mut depth_left: u8, mut alpha: Score, mut beta: Score) -> (Score, Move) {
+fn search_node(context: &mut SearchContext, mut depth_left: u8, mut alpha: Score, mut beta: Score,
+               report: &mut FnMut(u8, Move, usize)) -> (Score, Move, ScoreKind) {
+
+    context.stats.record_node();
+
+    #[cfg(test)]
+    {
+        if context.panic_at_node.map_or(false, |n| context.tree.nodes >= n) {
+            panic!("injected panic at node {}", context.tree.nodes);
+        }
+    }
+
+    // always search the root, so that a best move is produced and searchmoves are respected
+    let at_root = context.tree.search_depth() == 0;
+
+    if context.tree.search_depth() > context.seldepth {
+        context.seldepth = context.tree.search_depth();
+    }
+
+    if !at_root && context.tree.focus().outcome == Some(GameResult::Draw) {
+        return (draw_score(context), Move::null(), ScoreKind::Draw);
+    }
+
+    // A move back to an earlier position makes a draw the side to move can have whatever the
+    // rest of the node comes to, and at the horizon the quiescence search would never see it.
+    let mut draw_floor = None;
+    if !at_root {
+        let draw = draw_score(context);
+
+        if alpha < draw && context.tree.upcoming_repetition() {
+            if draw >= beta {
+                return (draw, Move::null(), ScoreKind::Draw);
+            }
+
+            alpha = draw;
+            draw_floor = Some(draw);
+        }
+    }
+
+    // a line inherits the extensions of its parent, and the root's depth is all it may add
+    let ply = context.tree.search_depth();
+    context.path_extensions.truncate(ply);
+    if at_root {
+        context.extension_budget = min(depth_left, MAX_SEARCH_DEPTH - 1);
+        context.path_extensions.push(0);
+    } else {
+        let inherited = context.path_extensions.get(ply - 1).cloned().unwrap_or(0);
+        context.path_extensions.push(inherited);
+    }
+
+    // a node is extended at most once
+    let mut extended = false;
+
+    // checks at the horizon are extended, the quiescence search can't get out of them
+    if !at_root && context.tree.focus().in_check() {
+        extended = extend(context, Extension::Check, &mut depth_left);
+    }
 
-    if depth_left == 0 || context.tree.focus().outcome.is_some() {
-        //OPTIMIZE: this copy is not necessary
-        context.qtree.reset_root(*context.tree.focus(), vec![]);
-        let (qscore, _) = quiescence(&mut context.qtree, alpha, beta);
-        return (qscore, Move::null());
+    // a root already drawn by rule is still searched, the GUI may not adjudicate it
+    if depth_left == 0 || (!at_root && context.tree.focus().outcome.is_some()) {
+        let qscore = quiescence_at_focus(context, alpha, beta);
+        let kind = if draw_floor == Some(qscore) { ScoreKind::Draw } else { ScoreKind::Normal };
+        return (qscore, Move::null(), kind);
     }
 
     // null move reduction
@@ -28,7 +222,6 @@ pub fn negamax(context: &mut SearchContext, mut depth_left: u8, mut alpha: Score
     // if !context.tree.focus().in_check() && context.tree.focus().board.occupied().population() > 10 {
     //     let R = if depth_left > 6 { 3 } else { 2 };
 
-    //     let game_copy = *context.tree.focus();
     //     context.tree.make_null_move();
 
     //     let null_move_depth = if depth_left >= R + 1 {
@@ -39,7 +232,7 @@ pub fn negamax(context: &mut SearchContext, mut depth_left: u8, mut alpha: Score
 
     //     let (s1,mb) = negamax(context, null_move_depth, beta.flipped(), alpha.flipped());
     //     let s2 = s1.flipped();
-    //     context.tree.unmake_null_move(game_copy);
+    //     context.tree.unmake_null_move();
 
     //     if (s2 >= beta) {
     //         if depth_left > 2 {
@@ -55,56 +248,223 @@ pub fn negamax(context: &mut SearchContext, mut depth_left: u8, mut alpha: Score
 
     let alpha_orig = alpha;
 
+    // judged on the window we were given, before the table narrows it
+    let pv_node = beta.unwrap() as i32 - alpha.unwrap() as i32 > 1;
+
+    let restricted_root = at_root && !context.root_moves.is_empty();
+
     let mut best_move_candidate = None;
+    // a bound the table narrowed the window to, and the kind of score it came from
+    let mut table_bound = None;
+
+    context.stats.record_tt_probe();
 
     match context.table.probe(context.tree.focus().hash) {
         None => {},
         Some(tentry) => {
+            context.stats.record_tt_hit();
             best_move_candidate = Some(tentry.best_move());
-            if tentry.depth() >= depth_left {
+            if !at_root && tentry.depth() >= depth_left {
                 let lookup_score = tentry.score();
+                // All nodes failed low so their score is an upper bound, Cut nodes a lower bound
                 match tentry.node_type() {
-                    NodeType::PV => return (lookup_score, Move::null()),
-                    NodeType::All => if lookup_score > alpha { alpha = lookup_score }
-                    NodeType::Cut => if lookup_score < beta { beta = lookup_score }
+                    NodeType::PV => {
+                        context.stats.record_tt_cutoff();
+                        return (lookup_score, Move::null(), tentry.score_kind());
+                    },
+                    NodeType::All => if lookup_score < beta {
+                        beta = lookup_score;
+                        table_bound = Some((lookup_score, tentry.score_kind()));
+                    },
+                    NodeType::Cut => if lookup_score > alpha {
+                        alpha = lookup_score;
+                        table_bound = Some((lookup_score, tentry.score_kind()));
+                    }
                 }
 
                 if alpha >= beta {
-                    return (lookup_score, Move::null());
+                    context.stats.record_tt_cutoff();
+                    return (lookup_score, Move::null(), tentry.score_kind());
                 }
             }
         }
     }
 
+    let in_check = context.tree.focus().in_check();
+    let mate_window = alpha.is_mate() || beta.is_mate();
+
+    let pruning = context.options.intersects(SearchOptions::REVERSE_FUTILITY | SearchOptions::FUTILITY
+                                             | SearchOptions::RAZORING);
+
+    // static eval is only needed for the shallow pruning below, so compute it once here
+    let static_eval = if pruning && !at_root && !in_check && !mate_window && depth_left <= REVERSE_FUTILITY_DEPTH {
+        Some(context.tree.static_eval())
+    } else {
+        None
+    };
+
+    // reverse futility pruning
+    if let Some(eval) = static_eval {
+        let margin = REVERSE_FUTILITY_MARGIN * depth_left as i16;
+        let enabled = context.options.contains(SearchOptions::REVERSE_FUTILITY);
+        if enabled && !pv_node && !eval.is_mate() && eval.unwrap() - margin >= beta.unwrap() {
+            return (eval, Move::null(), ScoreKind::Normal);
+        }
+    }
+
+    // razoring: this far below alpha only a capture could help, so if the quiescence
+    // search can't find one that does, the whole node is given up on
+    if let Some(eval) = static_eval {
+        let enabled = context.options.contains(SearchOptions::RAZORING);
+        if enabled && !pv_node && !eval.is_mate()
+            && eval.unwrap() + RAZORING_MARGINS[depth_left as usize] <= alpha.unwrap() {
+            let qscore = quiescence_at_focus(context, alpha, Score::new(alpha.unwrap() + 1));
+            if qscore <= alpha {
+                return (qscore, Move::null(), ScoreKind::Normal);
+            }
+        }
+    }
+
+    // futility pruning: quiet moves can't raise the score enough to matter
+    let futile = match static_eval {
+        _ if !context.options.contains(SearchOptions::FUTILITY) => false,
+        Some(eval) if (depth_left as usize) < FUTILITY_MARGINS.len() && !eval.is_mate() =>
+            eval.unwrap() + FUTILITY_MARGINS[depth_left as usize] <= alpha.unwrap(),
+        _ => false
+    };
+
+    // the previous iteration says whether a draw is worth avoiding, the scores of this one's
+    // moves can't when a draw among them might be the best of them
+    let root_hash = context.tree.focus().hash;
+    let avoid_draws = at_root && match context.root_score {
+        Some((hash, score)) => hash == root_hash && is_winning(score),
+        None => false
+    };
+
     let mut best_move = Move::null();
     let mut best_value = Score::min();
-    let next_moves = context.tree.next_moves(best_move_candidate);
+    // best_value as it counts towards choosing a move, which is lower for a draw being avoided
+    let mut best_choice = Score::min();
+    let mut best_kind = ScoreKind::Normal;
+    let mut moves_searched = 0;
 
-    for m in next_moves.borrow().iter() {
-        let game_copy = *context.tree.focus();
+    if restricted_root && best_move_candidate.map_or(false, |m| !context.root_moves.contains(&m)) {
+        best_move_candidate = None;
+    }
 
-        context.tree.make_move(*m);
-        let (s1,mb) = negamax(context, depth_left - 1, beta.flipped(), alpha.flipped());
-        let s2 = s1.flipped();
-        //TODO: make sure an additional copy is not occuring here (just a move)
-        context.tree.unmake_move(game_copy);
+    let ordering = OrderingContext {
+        killers: context.tree.killers(),
+        .. OrderingContext::with_hash_move(best_move_candidate)
+    };
+    let mut picker = MovePicker::new(context.tree.focus(), best_move_candidate);
+
+    // A single reply can only be seen once the moves are generated, so it isn't extended
+    // when there's a hash move to search first. It would be searched a ply too shallow
+    // by then, and most of the time it causes a cutoff that leaves the moves ungenerated.
+    if !picker.has_hash_move() {
+        picker.generate(&context.tree, &ordering);
 
-        if (s2 > best_value || best_move == Move::null()) {
-            best_move = *m;
+        if !at_root && !extended && picker.generated() == Some(1) {
+            extend(context, Extension::SingleReply, &mut depth_left);
+        }
+    }
+
+    // only needed to see whether a futile quiet move checks, and then only once per node
+    let mut discoverers_cache = None;
+
+    while let Some(m) = picker.next(&context.tree, &ordering) {
+        if restricted_root && !context.root_moves.contains(&m) {
+            continue;
+        }
+
+        if futile && moves_searched > 0 && !m.is_capture() && !m.is_promotion() {
+            let game = context.tree.focus();
+            let discoverers = discoverers_cache.get_or_insert_with(|| discoverers(game));
+            if !gives_check_with(game, m, discoverers) {
+                continue;
+            }
+        }
+
+        moves_searched += 1;
+
+        if at_root {
+            report(depth_left, m, moves_searched);
+        }
+
+        // With the root already winning, a move that makes progress towards the fifty move rule
+        // takes the place of an equally good one that doesn't. Its window starts a point lower,
+        // so that a tie with alpha comes back as an exact score instead of a fail low.
+        let breaks_ties = at_root && moves_searched > 1 && is_winning(best_value)
+            && resets_fifty_move_clock(m) && !resets_fifty_move_clock(best_move);
+        let move_alpha = if breaks_ties { Score::new(alpha.unwrap() - 1) } else { alpha };
+
+        context.tree.make_move(m);
+
+        // principal variation search: once a first move has been searched, the others only
+        // have to prove they're no better, which a null window around alpha does cheaply.
+        // one that fails high inside the window needs the full search for its exact score.
+        let (s2, kind) = if moves_searched == 1 || !context.options.contains(SearchOptions::PVS) {
+            search_child(context, depth_left, move_alpha, beta)
+        } else {
+            let null_beta = Score::new(move_alpha.unwrap() + 1);
+            let (null_score, null_kind) = search_child(context, depth_left, move_alpha, null_beta);
+
+            let researched = null_score > move_alpha && null_score < beta;
+            context.stats.record_null_window(researched);
+
+            if researched {
+                search_child(context, depth_left, move_alpha, beta)
+            } else {
+                (null_score, null_kind)
+            }
+        };
+
+        context.tree.unmake_move();
+
+        // the penalty only decides which move is played, the score reported and stored is the real one
+        let choice = if avoid_draws && kind == ScoreKind::Draw {
+            Score::new(s2.unwrap() - DRAW_AVOIDANCE_PENALTY)
+        } else {
+            s2
+        };
+
+        if choice > best_choice || best_move == Move::null() || (breaks_ties && choice == best_choice) {
+            best_move = m;
             best_value = s2;
+            best_choice = choice;
+            best_kind = kind;
         }
 
-        if s2 > alpha {
-            alpha = s2;
+        if choice > alpha {
+            alpha = choice;
+            context.tree.update_pv(m);
         }
 
         if alpha >= beta {
+            context.stats.record_cutoff(moves_searched == 1);
+            if !m.is_capture() && !m.is_promotion() {
+                context.tree.record_killer(m);
+            }
             break;
         }
 
-        if context.timer.finished() {
+        if context.timer.finished() || context.stop.load(Ordering::Relaxed) {
             context.ran_out_of_time = true;
-            return (best_value, best_move);
+            return (best_value, best_move, best_kind);
+        }
+    }
+
+    // a fail hard child hands back the bound itself, which is only as much a draw as the entry was
+    if let Some((bound, kind)) = table_bound {
+        if best_value == bound {
+            best_kind = kind;
+        }
+    }
+
+    if let Some(draw) = draw_floor {
+        if best_value < draw {
+            best_value = draw;
+            best_kind = ScoreKind::Draw;
         }
     }
 
@@ -121,13 +481,82 @@ pub fn negamax(context: &mut SearchContext, mut depth_left: u8, mut alpha: Score
             best_value,
             depth_left,
             new_node_type,
+            best_kind,
             //TODO: test switching this to halfmove_clock
-            (context.tree.focus().fullmoves % 256) as u8
+            (context.tree.focus().fullmoves % 128) as u8
         );
 
-    context.table.update(context.tree.focus().hash, new_tentry);
+    if at_root {
+        context.root_score = Some((root_hash, best_value));
+    }
+
+    // a restricted root result isn't the true value of the position
+    if !restricted_root {
+        context.table.update(context.tree.focus().hash, new_tentry);
+        context.stats.record_tt_store();
+    }
+
+    return (best_value, best_move, best_kind);
+}
+
+// one more ply for the focus, as long as the line leading to it has budget left for it
+fn extend(context: &mut SearchContext, extension: Extension, depth_left: &mut u8) -> bool {
+    if !context.options.contains(SearchOptions::EXTENSIONS) {
+        return false;
+    }
+
+    let used = context.path_extensions.last_mut().unwrap();
+    if *used >= context.extension_budget {
+        return false;
+    }
+
+    *used += 1;
+    *depth_left += 1;
+    context.stats.record_extension(extension);
+
+    return true;
+}
+
+fn quiescence_at_focus(context: &mut SearchContext, alpha: Score, beta: Score) -> Score {
+    //OPTIMIZE: this copy is not necessary
+    context.qtree.reset_root(*context.tree.focus(), vec![]);
+    context.qtree.seldepth = 0;
+    let qnodes_before = context.qtree.nodes;
+    let (qscore, _) = quiescence(&mut context.qtree, alpha, beta);
+    context.stats.record_qnodes(context.qtree.nodes - qnodes_before);
+
+    let qdepth = context.tree.search_depth() + context.qtree.seldepth;
+    if qdepth > context.seldepth {
+        context.seldepth = qdepth;
+    }
+
+    return qscore;
+}
+
+// a draw from the point of view of the side to move at the focus
+fn draw_score(context: &SearchContext) -> Score {
+    if context.tree.search_depth() % 2 == 0 {
+        Score::new(-context.contempt)
+    } else {
+        Score::new(context.contempt)
+    }
+}
+
+// score of the focus from the parent's point of view, the window is the parent's too
+fn search_child(context: &mut SearchContext, parent_depth_left: u8, alpha: Score, beta: Score)
+                -> (Score, ScoreKind) {
+    let (score, _, kind) = search_node(context, parent_depth_left - 1, beta.flipped(), alpha.flipped(),
+                                       &mut |_, _, _| {});
+    return (score.flipped(), kind);
+}
+
+fn is_winning(score: Score) -> bool {
+    score.unwrap() >= WINNING_SCORE
+}
 
-    return (best_value, best_move);
+// captures and pawn moves, which can't be repeated and restart the fifty move count
+fn resets_fifty_move_clock(m: Move) -> bool {
+    !m.is_null() && (m.is_capture() || m.moved_piece() == PieceType::Pawn)
 }
 
 //TODO: don't bother returning a Move from this function
@@ -144,14 +573,12 @@ pub fn quiescence(tree: &mut SearchTree, mut alpha: Score, mut beta: Score) -> (
         alpha = stand_pat;
     }
 
-    let next_moves = tree.next_moves(None);
-
-    for m in next_moves.borrow().iter() {
-        let game_copy = *tree.focus();
+    let next_moves = tree.next_moves(&OrderingContext::none());
 
+    for m in next_moves.iter() {
         tree.make_move(*m);
         let (s1,_) = quiescence(tree, beta.flipped(), alpha.flipped());
-        tree.unmake_move(game_copy);
+        tree.unmake_move();
         let s2 = s1.flipped();
 
         if s2 >= beta {
@@ -165,3 +592,800 @@ pub fn quiescence(tree: &mut SearchTree, mut alpha: Score, mut beta: Score) -> (
 
     return (alpha, Move::null());
 }
+
+// The shortest forced mate of at most `moves` moves for the side to move at the context's
+// root, as the line leading to it, starting with one of root_moves when there are any. There's
+// no evaluation or pruning, so a mate this finds is certain and one it doesn't find isn't
+// there, unless the timer ran out or the search was stopped, which leaves ran_out_of_time set.
+pub fn find_mate(context: &mut SearchContext, moves: u8) -> Option<Vec<Move>> {
+    for n in 1 .. moves as usize + 1 {
+        if let Some(line) = mate_attack(context, 2 * n - 1) {
+            return Some(line);
+        }
+
+        if context.ran_out_of_time {
+            break;
+        }
+    }
+
+    return None;
+}
+
+fn mate_search_stopped(context: &mut SearchContext) -> bool {
+    if context.timer.finished() || context.stop.load(Ordering::Relaxed) {
+        context.ran_out_of_time = true;
+    }
+
+    return context.ran_out_of_time;
+}
+
+fn mate_attack(context: &mut SearchContext, plies_left: usize) -> Option<Vec<Move>> {
+    if mate_search_stopped(context) {
+        return None;
+    }
+
+    let restricted_root = context.tree.search_depth() == 0 && !context.root_moves.is_empty();
+    let next_moves = context.tree.next_moves(&OrderingContext::none());
+
+    // the last move has to give check to mate
+    let discoverers = discoverers(context.tree.focus());
+
+    for m in next_moves.iter() {
+        if restricted_root && !context.root_moves.contains(m) {
+            continue;
+        }
+
+        if plies_left == 1 && !gives_check_with(context.tree.focus(), *m, &discoverers) {
+            continue;
+        }
+
+        context.tree.make_move(*m);
+        let defence = mate_defence(context, plies_left - 1);
+        context.tree.unmake_move();
+
+        if let Some(mut line) = defence {
+            line.insert(0, *m);
+            return Some(line);
+        }
+
+        if context.ran_out_of_time {
+            return None;
+        }
+    }
+
+    return None;
+}
+
+// the line along which every defence is mated, holding out the longest
+fn mate_defence(context: &mut SearchContext, plies_left: usize) -> Option<Vec<Move>> {
+    match context.tree.focus().outcome {
+        Some(GameResult::Win(_)) => return Some(Vec::new()),
+        Some(GameResult::Draw) => return None,
+        None => {}
+    }
+
+    if plies_left == 0 || mate_search_stopped(context) {
+        return None;
+    }
+
+    let mut longest: Option<Vec<Move>> = None;
+    let next_moves = context.tree.next_moves(&OrderingContext::none());
+
+    for m in next_moves.iter() {
+        context.tree.make_move(*m);
+        let attack = mate_attack(context, plies_left - 1);
+        context.tree.unmake_move();
+
+        match attack {
+            None => return None,
+            Some(mut line) => {
+                if longest.as_ref().map_or(true, |l| line.len() >= l.len()) {
+                    line.insert(0, *m);
+                    longest = Some(line);
+                }
+            }
+        }
+    }
+
+    return longest;
+}
+
+#[cfg(test)]
+mod test {
+    use search::*;
+    use std::thread;
+
+    fn best_move_at_depth(fen: &str, depth: u8) -> Move {
+        let game = Game::from_fen_str(fen).unwrap();
+        let mut context = SearchContext::new(game, 100000);
+
+        let mut best_move = Move::null();
+        for d in 1 .. depth + 1 {
+            let (_, m) = negamax(&mut context, d, Score::min(), Score::max());
+            best_move = m;
+        }
+
+        return best_move;
+    }
+
+    #[test]
+    fn kpk_at_quiescence_horizon() {
+        init_zobrist_hashing();
+
+        // Kxb3 is the only capture, and leaves king and pawn against king. The quiescence search
+        // sees what the bitbase makes of that rather than a pawn up.
+        let quiescence_score = |fen: &str| -> i16 {
+            let mut context = SearchContext::new(Game::from_fen_str(fen).unwrap(), 1);
+            quiescence(&mut context.qtree, Score::min(), Score::max()).0.unwrap()
+        };
+
+        // a rook pawn with the black king in the corner is a draw
+        assert_eq!(quiescence_score("k7/8/8/P7/8/1n6/2K5/8 w - - 0 1"), 0);
+
+        // the black king can't catch the d-pawn
+        assert!(quiescence_score("7k/8/8/3P4/8/1n6/2K5/8 w - - 0 1") > 500);
+    }
+
+    #[test]
+    fn tactics() {
+        init_zobrist_hashing();
+
+        let positions = [
+            ("6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1", "d1d8"),
+            ("4k3/8/8/3q4/8/8/8/3RK3 w - - 0 1", "d1d5"),
+            ("r3k3/8/8/1N6/8/8/8/4K3 w - - 0 1", "b5c7"),
+            ("3k4/8/8/8/8/8/1r3PPP/6K1 b - - 0 1", "b2b1")
+        ];
+
+        for &(fen, expected) in positions.iter() {
+            assert_eq!(best_move_at_depth(fen, 4).to_uci_str(), expected, "{}", fen);
+        }
+    }
+
+    #[test]
+    fn futility_pruning() {
+        init_zobrist_hashing();
+
+        // from Win at Chess, each with a single winning move
+        let positions = [
+            ("2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - 0 1", "g3g6"),
+            ("5rk1/1ppb3p/p1pb4/6q1/3P1p1r/2P1R2P/PP1BQ1P1/5RKN w - - 0 1", "e3g3"),
+            ("r1bq2rk/pp3pbp/2p1p1pQ/7P/3P4/2PB1N2/PP3PPR/2KR4 w - - 0 1", "h6h7"),
+            ("5k2/6pp/p1qN4/1p1p4/3P4/2PKP2Q/PP3r2/3R4 b - - 0 1", "c6c4"),
+            ("7k/p7/1R5K/6r1/6p1/6P1/8/8 w - - 0 1", "b6b7"),
+            ("rnbqkb1r/pppp1ppp/8/4P3/6n1/7P/PPPNPPP1/R1BQKBNR b KQkq - 0 1", "g4e3"),
+            ("r4q1k/p2bR1rp/2p2Q1N/5p2/5p2/2P5/PP3PPP/R5K1 w - - 0 1", "e7f7"),
+            ("3q1rk1/p4pp1/2pb3p/3p4/6Pr/1PNQ4/P1PB1PP1/4RRK1 b - - 0 1", "d6h2"),
+            ("2br2k1/2q3rn/p2NppQ1/2p1P3/Pp5R/4P3/1P3PPP/3R2K1 w - - 0 1", "h4h7")
+        ];
+
+        let search = |fen: &str, options: SearchOptions| -> (Move, u64) {
+            let mut context = SearchContext::new(Game::from_fen_str(fen).unwrap(), 100000);
+            context.options = options;
+
+            let mut best_move = Move::null();
+            for d in 1 .. 7 {
+                best_move = negamax(&mut context, d, Score::min(), Score::max()).1;
+            }
+
+            (best_move, context.tree.nodes + context.qtree.nodes)
+        };
+
+        // razoring stays on throughout, so each kind of futility pruning has to save
+        // nodes on top of what it already does
+        let kinds = [SearchOptions::REVERSE_FUTILITY, SearchOptions::FUTILITY];
+        let mut pruned_nodes = 0;
+        let mut unpruned_nodes = [0; 2];
+
+        for &(fen, expected) in positions.iter() {
+            let (m, nodes) = search(fen, SearchOptions::all());
+            pruned_nodes += nodes;
+            assert_eq!(m.to_uci_str(), expected, "{}", fen);
+
+            for (i, kind) in kinds.iter().enumerate() {
+                let (m, nodes) = search(fen, SearchOptions::all() - *kind);
+                unpruned_nodes[i] += nodes;
+                assert_eq!(m.to_uci_str(), expected, "{:?} off: {}", kind, fen);
+            }
+        }
+
+        for (i, kind) in kinds.iter().enumerate() {
+            assert!(pruned_nodes < unpruned_nodes[i], "{:?}: {} {}", kind, pruned_nodes, unpruned_nodes[i]);
+        }
+    }
+
+    #[test]
+    fn seldepth() {
+        init_zobrist_hashing();
+
+        let seldepth_at = |fen: &str, depth: u8| -> usize {
+            let mut context = SearchContext::new(Game::from_fen_str(fen).unwrap(), 100000);
+            negamax(&mut context, depth, Score::min(), Score::max());
+            context.seldepth
+        };
+
+        // kiwipete has capture sequences that run well past the horizon
+        let kiwipete = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        assert!(seldepth_at(kiwipete, 3) > 3);
+
+        // the kings are too far from the pawns for any capture within five plies
+        let quiet = "k7/8/4p3/8/8/4P3/8/K7 w - - 0 1";
+        assert_eq!(seldepth_at(quiet, 4), 4);
+    }
+
+    #[test]
+    fn pvs_matches_alpha_beta() {
+        init_zobrist_hashing();
+
+        let positions = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+            "6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1"
+        ];
+
+        let search = |fen: &str, use_pvs: bool| -> (Score, Move, u64) {
+            let mut context = SearchContext::new(Game::from_fen_str(fen).unwrap(), 100000);
+            context.options.set(SearchOptions::PVS, use_pvs);
+
+            let mut result = (Score::new(0), Move::null());
+            for d in 1 .. 5 {
+                result = negamax(&mut context, d, Score::min(), Score::max());
+            }
+
+            (result.0, result.1, context.tree.nodes + context.qtree.nodes)
+        };
+
+        let mut pvs_nodes = 0;
+        let mut alpha_beta_nodes = 0;
+
+        for fen in positions.iter() {
+            let (pvs_score, pvs_move, nodes) = search(fen, true);
+            pvs_nodes += nodes;
+
+            let (score, m, nodes) = search(fen, false);
+            alpha_beta_nodes += nodes;
+
+            assert_eq!((pvs_score, pvs_move.to_uci_str()), (score, m.to_uci_str()), "{}", fen);
+        }
+
+        assert!(pvs_nodes < alpha_beta_nodes, "{} {}", pvs_nodes, alpha_beta_nodes);
+    }
+
+    #[test]
+    fn contempt() {
+        init_zobrist_hashing();
+
+        let mut game = Game::starting_position();
+        let mut history = vec![game.hash];
+
+        for move_str in ["g1f3", "g8f6", "f3g1", "f6g8", "g1f3", "g8f6", "f3g1"].iter() {
+            let m = move_from_algebraic(&game, move_str.to_string()).unwrap();
+            game.make_move(m);
+            history.push(game.hash);
+        }
+
+        // black can repeat the starting position a third time
+        let repetition = move_from_algebraic(&game, "f6g8".to_string()).unwrap();
+
+        let repetition_score = |contempt: i16| -> Score {
+            let mut context = SearchContext::new(game, 1000);
+            context.tree.reset_root(game, history.clone());
+            context.root_moves = vec![repetition];
+            context.contempt = contempt;
+
+            let (score, m) = negamax(&mut context, 3, Score::min(), Score::max());
+            assert_eq!(m, repetition);
+            score
+        };
+
+        assert_eq!(repetition_score(0), Score::new(0));
+        assert_eq!(repetition_score(20), Score::new(-20));
+    }
+
+    #[test]
+    fn repetition_since_root() {
+        init_zobrist_hashing();
+
+        // white could give perpetual check with Nb2+ and Na4+, but that only draws
+        let game = Game::from_fen_str("7K/5p2/8/7P/N6p/1B1k4/P1p5/8 w - - 0 1").unwrap();
+
+        let mut tree = SearchTree::new(game);
+        for move_str in ["a4b2", "d3c3", "b2a4", "c3d3"].iter() {
+            let m = move_from_algebraic(tree.focus(), move_str.to_string()).unwrap();
+            assert_eq!(tree.focus().outcome, None);
+            tree.make_move(m);
+        }
+
+        // the root has come around once, which is enough
+        assert_eq!(tree.focus().outcome, Some(GameResult::Draw));
+
+        let mut context = SearchContext::new(game, 100000);
+        let (score, best_move) = negamax(&mut context, 4, Score::min(), Score::max());
+        assert_eq!(best_move.to_uci_str(), "b3c2");
+        assert!(score > Score::new(0));
+    }
+
+    // alpha-beta with nothing but the rules of the game, ending in the same quiescence search
+    fn reference_search(tree: &mut SearchTree, qtree: &mut SearchTree, depth_left: u8,
+                        mut alpha: Score, beta: Score) -> (Score, Move) {
+        let at_root = tree.search_depth() == 0;
+
+        if !at_root && tree.focus().outcome == Some(GameResult::Draw) {
+            return (Score::new(0), Move::null());
+        }
+
+        if depth_left == 0 || (!at_root && tree.focus().outcome.is_some()) {
+            qtree.reset_root(*tree.focus(), vec![]);
+            return quiescence(qtree, alpha, beta);
+        }
+
+        let mut best = (Score::min(), Move::null());
+        let next_moves = tree.next_moves(&OrderingContext::none());
+
+        for m in next_moves.iter() {
+            tree.make_move(*m);
+            let (s, _) = reference_search(tree, qtree, depth_left - 1, beta.flipped(), alpha.flipped());
+            tree.unmake_move();
+
+            if s.flipped() > best.0 || best.1 == Move::null() {
+                best = (s.flipped(), *m);
+            }
+
+            if best.0 > alpha {
+                alpha = best.0;
+            }
+
+            if alpha >= beta {
+                break;
+            }
+        }
+
+        return best;
+    }
+
+    #[test]
+    fn unpruned_search_is_exact() {
+        init_zobrist_hashing();
+
+        let positions = [
+            "6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1",
+            "r3k3/8/8/1N6/8/8/8/4K3 w - - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10"
+        ];
+
+        for fen in positions.iter() {
+            let game = Game::from_fen_str(fen).unwrap();
+
+            let mut tree = SearchTree::new(game);
+            let mut qtree = SearchTree::new(game);
+            qtree.in_quiescence = true;
+            let (expected_score, expected_move) =
+                reference_search(&mut tree, &mut qtree, 3, Score::min(), Score::max());
+
+            let search = |options: SearchOptions| -> (Score, Move) {
+                let mut context = SearchContext::new(game, 100000);
+                context.options = options;
+                negamax(&mut context, 3, Score::min(), Score::max())
+            };
+
+            let (score, m) = search(SearchOptions::empty());
+            assert_eq!((score, m.to_uci_str()), (expected_score, expected_move.to_uci_str()), "{}", fen);
+
+            // pruning may cost the score some accuracy, but not the move in these positions
+            let (_, m) = search(SearchOptions::all());
+            assert_eq!(m, expected_move, "{}", fen);
+        }
+    }
+
+    #[test]
+    fn mate_search() {
+        init_zobrist_hashing();
+
+        let mate_line = |fen: &str, moves: u8| -> Option<Vec<String>> {
+            let mut context = SearchContext::new(Game::from_fen_str(fen).unwrap(), 1);
+            find_mate(&mut context, moves).map(|line| line.iter().map(|m| m.to_uci_str()).collect())
+        };
+
+        // the shortest mate is found, even when a longer one is allowed
+        assert_eq!(mate_line("6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1", 3), Some(vec!["d1d8".to_string()]));
+
+        let ladder = "7k/8/8/8/8/8/R7/1R4K1 w - - 0 1";
+        assert_eq!(mate_line(ladder, 1), None);
+
+        let line = mate_line(ladder, 2).unwrap();
+        assert_eq!(line.len(), 3);
+        assert!(line[0] == "a2a7" || line[0] == "b1b7", "{:?}", line);
+
+        let mut game = Game::from_fen_str(ladder).unwrap();
+        for move_str in line.iter() {
+            let m = move_from_algebraic(&game, move_str.clone()).unwrap();
+            game.make_move(m);
+        }
+        assert_eq!(game.outcome, Some(GameResult::Win(Color::White)));
+
+        // a rook alone can't mate in two
+        assert_eq!(mate_line("7k/8/8/8/8/8/8/1R4K1 w - - 0 1", 2), None);
+
+        // searchmoves leaves only the mates that start with one of them
+        let game = Game::from_fen_str(ladder).unwrap();
+        let mut context = SearchContext::new(game, 1);
+        context.root_moves = vec![move_from_algebraic(&game, "b1b7".to_string()).unwrap()];
+        assert_eq!(find_mate(&mut context, 2).unwrap()[0].to_uci_str(), "b1b7");
+
+        context.root_moves = vec![move_from_algebraic(&game, "g1g2".to_string()).unwrap()];
+        assert_eq!(find_mate(&mut context, 2), None);
+        assert!(!context.ran_out_of_time);
+    }
+
+    #[test]
+    fn mate_search_stops() {
+        init_zobrist_hashing();
+
+        // there's no mate, so without the timer a mate in 10 would be searched for ever
+        let kiwipete = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+
+        let mut context = SearchContext::new(Game::from_fen_str(kiwipete).unwrap(), 1);
+        context.timer = SearchTimer::new(50);
+        assert_eq!(find_mate(&mut context, 10), None);
+        assert!(context.ran_out_of_time);
+
+        let mut context = SearchContext::new(Game::from_fen_str(kiwipete).unwrap(), 1);
+        context.stop.store(true, Ordering::Relaxed);
+        assert_eq!(find_mate(&mut context, 10), None);
+        assert!(context.ran_out_of_time);
+    }
+
+    #[test]
+    fn razoring() {
+        init_zobrist_hashing();
+
+        let positions = [
+            ("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", "d5e6"),
+            ("r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10", "c3d5"),
+            ("r1bq1rk1/pp2bppp/2n1pn2/3p4/2PP4/2N1PN2/PP1B1PPP/R2QKB1R w KQ - 3 8", "c4d5"),
+            ("4k3/8/8/3q4/8/8/8/3RK3 w - - 0 1", "d1d5"),
+            ("r3k3/8/8/1N6/8/8/8/4K3 w - - 0 1", "b5c7"),
+            ("6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1", "d1d8")
+        ];
+
+        let search = |fen: &str, razoring: bool| -> (Move, u64) {
+            let mut context = SearchContext::new(Game::from_fen_str(fen).unwrap(), 100000);
+            context.options.set(SearchOptions::RAZORING, razoring);
+
+            let mut best_move = Move::null();
+            for d in 1 .. 6 {
+                best_move = negamax(&mut context, d, Score::min(), Score::max()).1;
+            }
+
+            (best_move, context.tree.nodes + context.qtree.nodes)
+        };
+
+        let mut razored_nodes = 0;
+        let mut full_nodes = 0;
+
+        for &(fen, expected) in positions.iter() {
+            let (razored_move, nodes) = search(fen, true);
+            razored_nodes += nodes;
+            assert_eq!(razored_move.to_uci_str(), expected, "{}", fen);
+
+            let (full_move, nodes) = search(fen, false);
+            full_nodes += nodes;
+            assert_eq!(full_move.to_uci_str(), expected, "{}", fen);
+        }
+
+        assert!(razored_nodes < full_nodes, "{} {}", razored_nodes, full_nodes);
+    }
+
+    // iterative deepening up to max_depth, returning the first depth with a mate score
+    fn mate_depth(fen: &str, max_depth: u8, extensions: bool) -> Option<u8> {
+        let mut context = SearchContext::new(Game::from_fen_str(fen).unwrap(), 100000);
+        context.options.set(SearchOptions::EXTENSIONS, extensions);
+
+        for d in 1 .. max_depth + 1 {
+            let (score, _) = negamax(&mut context, d, Score::min(), Score::max());
+            if score.is_mate() {
+                return Some(d);
+            }
+        }
+
+        return None;
+    }
+
+    #[test]
+    fn single_reply_extension() {
+        init_zobrist_hashing();
+
+        // Nf7+ Kg8 Nh6+ Kh8 Qg8+ Rxg8 Nf7#, with only one legal reply to each check
+        let smothered = "4r2k/6pp/8/4N3/2Q5/8/8/5RK1 w - - 0 1";
+
+        assert_eq!(mate_depth(smothered, 6, true), Some(6));
+        assert_eq!(mate_depth(smothered, 6, false), None);
+        assert_eq!(mate_depth(smothered, 8, false), Some(8));
+    }
+
+    #[test]
+    fn extensions_are_bounded() {
+        init_zobrist_hashing();
+
+        // white has little to do but check the black king, and every check is extended
+        let checks = "7k/6p1/7p/8/8/8/qr6/4Q2K w - - 0 1";
+
+        // with a table of one entry the node counts don't depend on the random zobrist keys
+        let nodes = |depth: u8, extensions: bool| -> u64 {
+            let mut context = SearchContext::new(Game::from_fen_str(checks).unwrap(), 1);
+            context.options.set(SearchOptions::EXTENSIONS, extensions);
+
+            for d in 1 .. depth + 1 {
+                negamax(&mut context, d, Score::min(), Score::max());
+            }
+
+            context.tree.nodes + context.qtree.nodes
+        };
+
+        // the budget keeps every line within twice the nominal depth, which in practice
+        // costs less than searching two plies deeper
+        assert!(nodes(7, true) < nodes(9, false));
+    }
+
+    #[test]
+    fn perpetual_check() {
+        init_zobrist_hashing();
+
+        // two queens down, white can only check back and forth between e8 and h5 forever
+        let game = Game::from_fen_str("6k1/qq4p1/5p2/7Q/8/8/6PP/7K w - - 0 1").unwrap();
+        let check = move_from_algebraic(&game, "h5e8".to_string()).unwrap();
+
+        let mut context = SearchContext::new(game, 1);
+        context.options.insert(SearchOptions::EXTENSIONS);
+
+        let mut result = (Score::min(), Move::null());
+        for d in 1 .. 7 {
+            result = negamax(&mut context, d, Score::min(), Score::max());
+        }
+
+        // every move of the line is a check or the only reply to one, and all of them are
+        // extended, so it's the repetition that has to end it
+        assert_eq!(result, (Score::new(0), check));
+        let nodes = context.tree.nodes + context.qtree.nodes;
+        assert!(nodes < 100000, "{}", nodes);
+    }
+
+    #[test]
+    fn search_frames() {
+        init_zobrist_hashing();
+
+        let game = Game::from_fen_str("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+        let mut context = SearchContext::new(game, 100000);
+        let (_, best_move) = negamax(&mut context, 4, Score::min(), Score::max());
+
+        // the line the root's frame keeps starts with the best move and can be played out
+        let pv = context.tree.principal_variation().to_vec();
+        assert!(!pv.is_empty() && pv.len() <= 4);
+        assert_eq!(pv[0], best_move);
+
+        let mut line = game;
+        for m in pv.iter() {
+            assert!(line.is_legal(*m));
+            line.make_move(*m);
+        }
+
+        // a move leaves the root's line alone, and the new frame evaluates its own game
+        context.tree.make_move(pv[0]);
+        assert!(context.tree.principal_variation() == &pv[..]);
+        assert_eq!(context.tree.static_eval(), Score::recompute_symmetric(context.tree.focus(), 1));
+        context.tree.unmake_move();
+
+        // killers are kept most recent first, without repeats
+        let quiet = |s: &str| move_from_algebraic(&game, s.to_string()).unwrap();
+        context.tree.record_killer(quiet("a2a3"));
+        context.tree.record_killer(quiet("g2g3"));
+        context.tree.record_killer(quiet("g2g3"));
+        assert_eq!(context.tree.killers(), [Some(quiet("g2g3")), Some(quiet("a2a3"))]);
+    }
+
+    #[test]
+    fn hash_move_first() {
+        init_zobrist_hashing();
+
+        let game = Game::from_fen_str("4k3/8/8/3q4/8/8/8/3RK3 w - - 0 1").unwrap();
+        let capture = move_from_algebraic(&game, "d1d5".to_string()).unwrap();
+
+        let mut context = SearchContext::new(game, 1000);
+
+        // too shallow for its score to be used, but the move is still the one to try
+        context.table.update(game.hash, EntryData::new(capture, Score::new(900), 1, NodeType::PV, ScoreKind::Normal, 1));
+
+        let mut searched = Vec::new();
+        let (score, m) = negamax_reporting(&mut context, 3, Score::min(), Score::new(100),
+                                           &mut |_, m, _| searched.push(m));
+
+        // the first move is enough to fail high
+        assert_eq!(searched, vec![capture]);
+        assert_eq!(m, capture);
+        assert!(score >= Score::new(100));
+    }
+
+    #[test]
+    fn avoids_stalemate() {
+        init_zobrist_hashing();
+
+        // queen to e4 or g6 stalemates, queen to e1 mates
+        let game = Game::from_fen_str("4Q3/8/8/8/8/1K6/8/k7 w - - 0 1").unwrap();
+        let stalemates: Vec<Move> = ["e8e4", "e8g6"].iter()
+            .map(|s| move_from_algebraic(&game, s.to_string()).unwrap())
+            .collect();
+
+        let mut context = SearchContext::new(game, 100000);
+
+        // the draw is told apart from an ordinary score on its way back up
+        for m in next_moves_standalone(&game).iter() {
+            context.tree.make_move(*m);
+            let (_, kind) = search_child(&mut context, 2, Score::min(), Score::max());
+            context.tree.unmake_move();
+
+            assert_eq!(kind == ScoreKind::Draw, stalemates.contains(m), "{}", m.to_uci_str());
+        }
+
+        for depth in 1 .. 5 {
+            let (score, m) = negamax(&mut context, depth, Score::min(), Score::max());
+            assert!(!stalemates.contains(&m), "{} at depth {}", m.to_uci_str(), depth);
+            assert!(is_winning(score));
+        }
+    }
+
+    #[test]
+    fn draw_avoidance() {
+        init_zobrist_hashing();
+
+        // queen to g6 stalemates, and no move mates
+        let game = Game::from_fen_str("7k/8/8/8/8/8/8/KQ6 w - - 0 1").unwrap();
+        let stalemate = move_from_algebraic(&game, "b1g6".to_string()).unwrap();
+        let others: Vec<Move> = next_moves_standalone(&game).iter().cloned().filter(|&m| m != stalemate).collect();
+
+        // iterative deepening, so that the last iteration knows the root is winning
+        let search = |root_moves: Vec<Move>, contempt: i16| -> (Score, Move) {
+            let mut context = SearchContext::new(game, 100000);
+            context.root_moves = root_moves;
+            context.contempt = contempt;
+            let mut result = (Score::min(), Move::null());
+            for depth in 1 .. 3 {
+                result = negamax(&mut context, depth, Score::min(), Score::max());
+            }
+            result
+        };
+
+        // contempt that makes the draw worth a little more than the best of the other moves,
+        // so that only the penalty keeps the stalemate from being played
+        let best_other = search(others, 0).0.unwrap();
+        let contempt = -(best_other + DRAW_AVOIDANCE_PENALTY / 2);
+
+        // the penalty never shows in the score
+        let (draw, _) = search(vec![stalemate], contempt);
+        assert_eq!(draw, Score::new(best_other + DRAW_AVOIDANCE_PENALTY / 2));
+
+        let (score, m) = search(Vec::new(), contempt);
+        assert!(m != stalemate);
+        assert!(score < draw, "{:?} {:?}", score, draw);
+    }
+
+    #[test]
+    fn draw_kind_in_table() {
+        init_zobrist_hashing();
+
+        // the queen checks from b2, and taking it leaves too little material to mate with
+        let game = Game::from_fen_str("7K/8/8/8/8/1Q6/8/k7 w - - 0 1").unwrap();
+        let check = move_from_algebraic(&game, "b3b2".to_string()).unwrap();
+
+        let mut context = SearchContext::new(game, 1000);
+        context.tree.make_move(check);
+
+        let first = search_child(&mut context, 3, Score::min(), Score::max());
+        assert_eq!(first, (Score::new(0), ScoreKind::Draw));
+
+        // the second time round the score comes straight from the table
+        let nodes = context.tree.nodes;
+        let second = search_child(&mut context, 3, Score::min(), Score::max());
+        assert_eq!(context.tree.nodes, nodes);
+        assert_eq!(second, first);
+    }
+
+    fn assert_send<T: Send>() {}
+    fn assert_sync<T: Sync>() {}
+
+    #[test]
+    fn contexts_are_send() {
+        assert_send::<SearchTree>();
+        assert_send::<SearchContext>();
+        assert_sync::<TranspositionTable>();
+    }
+
+    #[test]
+    fn concurrent_searches() {
+        init_zobrist_hashing();
+
+        let positions = [
+            ("6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1", "d1d8"),
+            ("r3k3/8/8/1N6/8/8/8/4K3 w - - 0 1", "b5c7")
+        ];
+
+        // each context is built here and moved to the thread that searches with it
+        let searches: Vec<_> = positions.iter().map(|&(fen, expected)| {
+            let mut context = SearchContext::new(Game::from_fen_str(fen).unwrap(), 100000);
+
+            thread::spawn(move || {
+                let mut best_move = Move::null();
+                for depth in 1 .. 5 {
+                    best_move = negamax(&mut context, depth, Score::min(), Score::max()).1;
+                }
+
+                (best_move.to_uci_str(), expected)
+            })
+        }).collect();
+
+        for search in searches {
+            let (best_move, expected) = search.join().unwrap();
+            assert_eq!(best_move, expected);
+        }
+    }
+
+    #[test]
+    fn progress_with_clock_running() {
+        init_zobrist_hashing();
+
+        // a pawn up, white has time to bring the king forward first while the clock is fresh
+        let search = |halfmoves: u16, depth: u8| -> (Score, Move) {
+            let game = Game::from_fen_str(&format!("8/5k2/8/8/3K4/8/1P6/8 w - - {} 80", halfmoves)).unwrap();
+            let mut context = SearchContext::new(game, 100000);
+            negamax(&mut context, depth, Score::min(), Score::max())
+        };
+
+        let (_, m) = search(0, 3);
+        assert_eq!(m.moved_piece(), PieceType::King);
+
+        // the eval fades with every quiet move, so later on the pawn goes first
+        for depth in 3 .. 6 {
+            let (score, m) = search(60, depth);
+            assert!(resets_fifty_move_clock(m), "{} at depth {}", m.to_uci_str(), depth);
+            assert!(is_winning(score));
+        }
+    }
+
+    #[test]
+    fn upcoming_repetition() {
+        init_zobrist_hashing();
+
+        // A queen against two rooks and pawns: black checks with Qh4+ and Qe1+, and the white
+        // king can only go between g1 and h2. After Qh4+ Kg1 black has Qe1+ again, to a position
+        // that's been there twice.
+        let start = Game::from_fen_str("k7/8/2R5/1R6/8/8/PPP3P1/4q1K1 w - - 0 1").unwrap();
+
+        let root_score = |moves: &[&str]| -> Score {
+            let mut game = start;
+            let mut history = vec![game.hash];
+
+            for move_str in moves.iter() {
+                let m = move_from_algebraic(&game, move_str.to_string()).unwrap();
+                game.make_move(m);
+                history.push(game.hash);
+            }
+
+            let mut context = SearchContext::new(game, 1000);
+            context.tree.reset_root(game, history);
+            context.root_moves = vec![move_from_algebraic(&game, "e1h4".to_string()).unwrap()];
+
+            // only a leaf is left for Qe1+, and the quiescence search doesn't look at checks.
+            // Extending the checks would get round to the repetition without any help.
+            context.options.remove(SearchOptions::EXTENSIONS);
+            let (score, _) = negamax(&mut context, 2, Score::min(), Score::max());
+            score
+        };
+
+        assert_eq!(root_score(&["g1h2", "e1h4", "h2g1", "h4e1", "g1h2"]), Score::new(0));
+
+        // with the checks given only once, the position would come around a second time, not a draw yet
+        assert!(root_score(&["g1h2"]) < Score::new(-300));
+    }
+}
@@ -0,0 +1,187 @@
+use eval::*;
+use moves::*;
+use ordering::*;
+use search::*;
+
+use rand::{thread_rng, Rng, SeedableRng};
+use rand::prng::XorShiftRng;
+
+use std::cmp::min;
+
+pub const MAX_SKILL_LEVEL: u8 = 20;
+
+// Weakens play by sampling among the best root moves. Lower levels search
+// shallower, consider more moves and tolerate bigger blunders.
+pub struct Skill {
+    level: u8,
+    rng: XorShiftRng
+}
+
+impl Skill {
+    pub fn new(level: u8) -> Skill {
+        Skill {
+            level: min(level, MAX_SKILL_LEVEL),
+            rng: XorShiftRng::from_rng(thread_rng()).unwrap()
+        }
+    }
+
+    pub fn seeded(level: u8, seed: u64) -> Skill {
+        let mut seed_bytes = [0u8; 16];
+        for i in 0 .. 8 {
+            seed_bytes[i] = (seed >> (8 * i)) as u8;
+            seed_bytes[i + 8] = ((seed ^ 0x9E3779B97F4A7C15) >> (8 * i)) as u8;
+        }
+
+        Skill {
+            level: min(level, MAX_SKILL_LEVEL),
+            rng: XorShiftRng::from_seed(seed_bytes)
+        }
+    }
+
+    pub fn level(&self) -> u8 { self.level }
+
+    pub fn set_level(&mut self, level: u8) {
+        self.level = min(level, MAX_SKILL_LEVEL);
+    }
+
+    pub fn is_full_strength(&self) -> bool {
+        self.level >= MAX_SKILL_LEVEL
+    }
+
+    pub fn search_depth(&self) -> u8 {
+        1 + self.level / 4
+    }
+
+    // the chosen move is never worse than the best by more than this (centipawns)
+    fn margin(&self) -> i32 {
+        let handicap = (MAX_SKILL_LEVEL - self.level) as i32;
+        handicap * handicap * handicap / 4
+    }
+
+    fn temperature(&self) -> f64 {
+        10.0 + 15.0 * (MAX_SKILL_LEVEL - self.level) as f64
+    }
+
+    fn candidate_count(&self) -> usize {
+        4 + (MAX_SKILL_LEVEL - self.level) as usize / 2
+    }
+
+    // scored_moves must be sorted best first
+    pub fn pick_move(&mut self, scored_moves: &[(Move, Score)]) -> Move {
+        debug_assert!(!scored_moves.is_empty());
+
+        let best_score = scored_moves[0].1.unwrap() as i32;
+        let margin = self.margin();
+        let temperature = self.temperature();
+
+        let candidates: Vec<(Move, f64)> = scored_moves.iter()
+            .take(self.candidate_count())
+            .filter(|&&(_, s)| best_score - s.unwrap() as i32 <= margin)
+            .map(|&(m, s)| (m, ((s.unwrap() as i32 - best_score) as f64 / temperature).exp()))
+            .collect();
+
+        let total_weight: f64 = candidates.iter().map(|&(_, w)| w).sum();
+        let mut target = self.rng.gen::<f64>() * total_weight;
+
+        for &(m, w) in candidates.iter() {
+            if target < w {
+                return m;
+            }
+            target -= w;
+        }
+
+        return candidates.last().unwrap().0;
+    }
+}
+
+// Full-window score for every root move, or only those in context.root_moves when there are
+// any, best first. Once the timer runs out the moves that are left go unscored, and so does
+// the one it ran out on, unless that's the only move there is to play.
+pub fn score_root_moves(context: &mut SearchContext, depth: u8) -> Vec<(Move, Score)> {
+    debug_assert!(depth > 0);
+
+    context.ran_out_of_time = false;
+
+    let mut scored_moves = Vec::new();
+    let next_moves = context.tree.next_moves(&OrderingContext::none());
+
+    for m in next_moves.iter() {
+        if !context.root_moves.is_empty() && !context.root_moves.contains(m) {
+            continue;
+        }
+
+        context.tree.make_move(*m);
+        let (score, _) = negamax(context, depth - 1, Score::min(), Score::max());
+        context.tree.unmake_move();
+
+        if context.ran_out_of_time {
+            if scored_moves.is_empty() {
+                scored_moves.push((*m, score.flipped()));
+            }
+            break;
+        }
+
+        scored_moves.push((*m, score.flipped()));
+    }
+
+    scored_moves.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+
+    return scored_moves;
+}
+
+#[cfg(test)]
+mod test {
+    use skill::*;
+
+    #[test]
+    fn margin_never_exceeded() {
+        use game::*;
+        use movegen::*;
+
+        let game = Game::starting_position();
+        let moves = next_moves_standalone(&game);
+
+        let scored_moves: Vec<(Move, Score)> = moves.iter().enumerate()
+            .map(|(i, m)| (*m, Score::new(-100 * i as i16)))
+            .collect();
+
+        let mut skill = Skill::seeded(10, 7);
+        for _ in 0 .. 1000 {
+            let m = skill.pick_move(&scored_moves);
+            let score = scored_moves.iter().find(|&&(sm, _)| sm == m).unwrap().1;
+            assert!(-score.unwrap() as i32 <= skill.margin());
+        }
+    }
+
+    #[test]
+    fn scored_root_moves() {
+        use core::*;
+        use game::*;
+        use movegen::*;
+        use zobrist::*;
+        use std::thread;
+        use std::time::Duration;
+
+        init_zobrist_hashing();
+
+        let game = Game::starting_position();
+        let searchmoves = vec![move_from_algebraic(&game, "e2e4".to_string()).unwrap(),
+                               move_from_algebraic(&game, "g1f3".to_string()).unwrap()];
+
+        let mut context = SearchContext::new(game, 1000);
+        assert_eq!(score_root_moves(&mut context, 2).len(), 20);
+        assert!(!context.ran_out_of_time);
+
+        context.root_moves = searchmoves.clone();
+        let scored_moves = score_root_moves(&mut context, 2);
+        assert_eq!(scored_moves.len(), 2);
+        assert!(scored_moves.iter().all(|&(m, _)| searchmoves.contains(&m)));
+
+        // out of time before it starts, so only the move it was searching is left
+        let mut context = SearchContext::new(game, 1000);
+        context.timer = SearchTimer::new(0);
+        thread::sleep(Duration::from_millis(5));
+        assert_eq!(score_root_moves(&mut context, 2).len(), 1);
+        assert!(context.ran_out_of_time);
+    }
+}
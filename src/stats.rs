@@ -0,0 +1,360 @@
+// Search statistics, only recorded when built with the `stats` feature.
+// Without it SearchStats is an empty struct and every method compiles to nothing.
+
+use search::*;
+
+#[cfg(feature = "stats")]
+use prettytable::Table;
+
+#[cfg(feature = "stats")]
+#[derive(Debug, Clone, Copy)]
+pub struct DepthStats {
+    pub nodes: u64,
+    pub qnodes: u64,
+    pub tt_probes: u64,
+    pub tt_hits: u64,
+    // hits deep enough to return from the node without searching it
+    pub tt_cutoffs: u64,
+    pub tt_stores: u64,
+    pub beta_cutoffs: u64,
+    pub first_move_cutoffs: u64,
+    // principal variation search, moves tried with a null window and how many needed another
+    pub null_windows: u64,
+    pub researches: u64,
+    // indexed by Extension
+    pub extensions: [u64; EXTENSION_KINDS]
+}
+
+#[cfg(feature = "stats")]
+impl DepthStats {
+    fn new() -> DepthStats {
+        DepthStats {
+            nodes: 0,
+            qnodes: 0,
+            tt_probes: 0,
+            tt_hits: 0,
+            tt_cutoffs: 0,
+            tt_stores: 0,
+            beta_cutoffs: 0,
+            first_move_cutoffs: 0,
+            null_windows: 0,
+            researches: 0,
+            extensions: [0; EXTENSION_KINDS]
+        }
+    }
+
+    fn add(&mut self, other: &DepthStats) {
+        self.nodes += other.nodes;
+        self.qnodes += other.qnodes;
+        self.tt_probes += other.tt_probes;
+        self.tt_hits += other.tt_hits;
+        self.tt_cutoffs += other.tt_cutoffs;
+        self.tt_stores += other.tt_stores;
+        self.beta_cutoffs += other.beta_cutoffs;
+        self.first_move_cutoffs += other.first_move_cutoffs;
+        self.null_windows += other.null_windows;
+        self.researches += other.researches;
+
+        for i in 0 .. EXTENSION_KINDS {
+            self.extensions[i] += other.extensions[i];
+        }
+    }
+
+    pub fn first_move_cutoff_rate(&self) -> Option<f64> {
+        if self.beta_cutoffs == 0 {
+            return None;
+        }
+
+        Some(self.first_move_cutoffs as f64 / self.beta_cutoffs as f64)
+    }
+}
+
+#[cfg(feature = "stats")]
+fn percent(count: u64, total: u64) -> String {
+    if total == 0 {
+        return "-".to_string();
+    }
+
+    format!("{:.1}%", 100.0 * count as f64 / total as f64)
+}
+
+#[cfg(feature = "stats")]
+pub struct SearchStats {
+    pub depths: Vec<DepthStats>
+}
+
+#[cfg(not(feature = "stats"))]
+pub struct SearchStats;
+
+#[cfg(feature = "stats")]
+impl SearchStats {
+    pub fn new() -> SearchStats {
+        SearchStats { depths: Vec::new() }
+    }
+
+    pub fn clear(&mut self) {
+        self.depths.clear();
+    }
+
+    pub fn begin_iteration(&mut self) {
+        self.depths.push(DepthStats::new());
+    }
+
+    fn current(&mut self) -> &mut DepthStats {
+        if self.depths.is_empty() {
+            self.depths.push(DepthStats::new());
+        }
+
+        self.depths.last_mut().unwrap()
+    }
+
+    #[inline]
+    pub fn record_node(&mut self) { self.current().nodes += 1; }
+
+    #[inline]
+    pub fn record_qnodes(&mut self, count: u64) { self.current().qnodes += count; }
+
+    #[inline]
+    pub fn record_tt_probe(&mut self) { self.current().tt_probes += 1; }
+
+    #[inline]
+    pub fn record_tt_hit(&mut self) { self.current().tt_hits += 1; }
+
+    #[inline]
+    pub fn record_tt_cutoff(&mut self) { self.current().tt_cutoffs += 1; }
+
+    #[inline]
+    pub fn record_tt_store(&mut self) { self.current().tt_stores += 1; }
+
+    #[inline]
+    pub fn record_cutoff(&mut self, first_move: bool) {
+        let current = self.current();
+        current.beta_cutoffs += 1;
+        if first_move {
+            current.first_move_cutoffs += 1;
+        }
+    }
+
+    #[inline]
+    pub fn record_null_window(&mut self, researched: bool) {
+        let current = self.current();
+        current.null_windows += 1;
+        if researched {
+            current.researches += 1;
+        }
+    }
+
+    #[inline]
+    pub fn record_extension(&mut self, extension: Extension) { self.current().extensions[extension as usize] += 1; }
+
+    // other's iterations added to the same iterations of these, to sum up several searches
+    pub fn merge(&mut self, other: &SearchStats) {
+        for (i, d) in other.depths.iter().enumerate() {
+            if i == self.depths.len() {
+                self.depths.push(DepthStats::new());
+            }
+            self.depths[i].add(d);
+        }
+    }
+
+    fn total(&self) -> DepthStats {
+        let mut total = DepthStats::new();
+
+        for d in self.depths.iter() {
+            total.add(d);
+        }
+
+        total
+    }
+
+    pub fn extension_counts(&self) -> [u64; EXTENSION_KINDS] {
+        self.total().extensions
+    }
+
+    // nodes(d) / nodes(d - 1) for each iteration after the first, quiescence nodes included
+    pub fn branching_factors(&self) -> Vec<f64> {
+        self.depths.windows(2)
+            .map(|w| (w[1].nodes + w[1].qnodes) as f64 / (w[0].nodes + w[0].qnodes) as f64)
+            .collect()
+    }
+
+    // the whole search in one line, for an info string
+    pub fn summary(&self) -> Option<String> {
+        let total = self.total();
+
+        let branching: Vec<String> = self.branching_factors().iter().map(|b| format!("{:.2}", b)).collect();
+
+        Some(format!("stats first move cutoffs {} tt hits {} tt cutoffs {} researches {} qnodes {} ebf {}",
+                     percent(total.first_move_cutoffs, total.beta_cutoffs),
+                     percent(total.tt_hits, total.tt_probes),
+                     percent(total.tt_cutoffs, total.tt_probes),
+                     percent(total.researches, total.null_windows),
+                     percent(total.qnodes, total.nodes + total.qnodes),
+                     if branching.is_empty() { "-".to_string() } else { branching.join(" ") }))
+    }
+
+    // how often each extension fired over the whole search, for an info string
+    pub fn extension_summary(&self) -> Option<String> {
+        let counts = self.extension_counts();
+
+        Some(format!("extensions check {} single reply {}",
+                     counts[Extension::Check as usize], counts[Extension::SingleReply as usize]))
+    }
+
+    pub fn first_move_cutoff_rate(&self) -> Option<f64> {
+        self.total().first_move_cutoff_rate()
+    }
+
+    pub fn print_summary(&self) {
+        let mut table = Table::new();
+
+        table.add_row(row!["DEPTH", "NODES", "QNODES", "TT PROBES", "TT HITS", "TT CUTOFFS", "TT STORES", "CUTOFFS",
+                           "FIRST MOVE CUTOFF %", "RESEARCH %", "CHECK EXT", "SINGLE REPLY EXT"]);
+
+        for (i, d) in self.depths.iter().enumerate() {
+            let rate = match d.first_move_cutoff_rate() {
+                Some(r) => format!("{:.1}", 100.0 * r),
+                None => "-".to_string()
+            };
+
+            table.add_row(row![i + 1, d.nodes, d.qnodes, d.tt_probes, d.tt_hits, d.tt_cutoffs, d.tt_stores,
+                               d.beta_cutoffs, rate, percent(d.researches, d.null_windows),
+                               d.extensions[Extension::Check as usize],
+                               d.extensions[Extension::SingleReply as usize]]);
+        }
+
+        table.print_tty(false);
+    }
+}
+
+#[cfg(not(feature = "stats"))]
+impl SearchStats {
+    #[inline(always)]
+    pub fn new() -> SearchStats { SearchStats }
+
+    #[inline(always)]
+    pub fn clear(&mut self) {}
+
+    #[inline(always)]
+    pub fn begin_iteration(&mut self) {}
+
+    #[inline(always)]
+    pub fn record_node(&mut self) {}
+
+    #[inline(always)]
+    pub fn record_qnodes(&mut self, _count: u64) {}
+
+    #[inline(always)]
+    pub fn record_tt_probe(&mut self) {}
+
+    #[inline(always)]
+    pub fn record_tt_hit(&mut self) {}
+
+    #[inline(always)]
+    pub fn record_tt_cutoff(&mut self) {}
+
+    #[inline(always)]
+    pub fn record_tt_store(&mut self) {}
+
+    #[inline(always)]
+    pub fn record_cutoff(&mut self, _first_move: bool) {}
+
+    #[inline(always)]
+    pub fn record_null_window(&mut self, _researched: bool) {}
+
+    #[inline(always)]
+    pub fn record_extension(&mut self, _extension: Extension) {}
+
+    #[inline(always)]
+    pub fn merge(&mut self, _other: &SearchStats) {}
+
+    #[inline(always)]
+    pub fn summary(&self) -> Option<String> { None }
+
+    #[inline(always)]
+    pub fn extension_summary(&self) -> Option<String> { None }
+
+    #[inline(always)]
+    pub fn print_summary(&self) {}
+}
+
+#[cfg(all(test, feature = "stats"))]
+mod test {
+    use stats::*;
+    use core::*;
+    use eval::*;
+    use game::*;
+    use search::*;
+    use zobrist::*;
+
+    #[test]
+    fn first_move_cutoff_rate() {
+        init_zobrist_hashing();
+
+        let kiwipete = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        let mut context = SearchContext::new(Game::from_fen_str(kiwipete).unwrap(), 100000);
+
+        for d in 1 .. 5 {
+            context.stats.begin_iteration();
+            negamax(&mut context, d, Score::min(), Score::max());
+        }
+
+        assert_eq!(context.stats.depths.len(), 4);
+
+        let rate = context.stats.first_move_cutoff_rate().unwrap();
+        assert!(rate >= 0.0 && rate <= 1.0);
+    }
+
+    // Move ordering is good when most cutoffs come from the first move tried. The bench
+    // positions get about 80% at depth 5, this catches ordering changes that lose ground.
+    #[test]
+    fn bench_move_ordering() {
+        use bench::*;
+
+        init_zobrist_hashing();
+
+        let mut total = SearchStats::new();
+
+        for fen in BENCH_POSITIONS.iter() {
+            let mut context = SearchContext::new(Game::from_fen_str(fen).unwrap(), 100000);
+
+            for d in 1 .. 6 {
+                context.stats.begin_iteration();
+                negamax(&mut context, d, Score::min(), Score::max());
+            }
+
+            total.merge(&context.stats);
+        }
+
+        assert_eq!(total.depths.len(), 5);
+        assert_eq!(total.branching_factors().len(), 4);
+
+        let rate = total.first_move_cutoff_rate().unwrap();
+        assert!(rate > 0.75, "first move cutoff rate {:.3}", rate);
+
+        let summary = total.summary().unwrap();
+        assert!(summary.starts_with("stats first move cutoffs "), "{}", summary);
+    }
+
+    #[test]
+    fn extension_counts() {
+        init_zobrist_hashing();
+
+        // checks all over the tree
+        let back_rank = "6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1";
+        let mut context = SearchContext::new(Game::from_fen_str(back_rank).unwrap(), 100000);
+        context.stats.begin_iteration();
+        negamax(&mut context, 3, Score::min(), Score::max());
+
+        let counts = context.stats.extension_counts();
+        assert!(counts[Extension::Check as usize] > 0);
+        assert!(context.stats.extension_summary().unwrap().starts_with("extensions check"));
+
+        // and none at all without them
+        context.options.remove(SearchOptions::EXTENSIONS);
+        context.stats.clear();
+        context.stats.begin_iteration();
+        negamax(&mut context, 3, Score::min(), Score::max());
+        assert_eq!(context.stats.extension_counts(), [0; EXTENSION_KINDS]);
+    }
+}
@@ -22,14 +22,8 @@ pub const RANK8: Bitboard = Bitboard::new(18374686479671623680);
 // pub const FILE7: Bitboard = Bitboard::new(4629771061636907072);
 // pub const FILE8: Bitboard = Bitboard::new(9259542123273814144);
 
-pub const WHITE_KINGSIDE_CASTLE_BITS: Bitboard = Bitboard::new(1 << 1 | 1 << 2);
-pub const BLACK_KINGSIDE_CASTLE_BITS: Bitboard = Bitboard::new(1 << 63 - 6 | 1 << 63 - 5);
-
-pub const BLACK_QUEENSIDE_CASTLE_BITS: Bitboard = Bitboard::new(1 << 63 - 1 | 1 << 63 - 2 | 1 << 63 - 3);
-pub const WHITE_QUEENSIDE_CASTLE_BITS: Bitboard = Bitboard::new(1 << 4 | 1 << 5 | 1 << 6);
-
-pub const BLACK_QUEENSIDE_CASTLE_SAFETY_BITS: Bitboard = Bitboard::new(1 << 63 - 2 | 1 << 63 - 3);
-pub const WHITE_QUEENSIDE_CASTLE_SAFETY_BITS: Bitboard = Bitboard::new(1 << 4 | 1 << 5);
+pub const LIGHT_SQUARES: Bitboard = Bitboard::new(0xaa55aa55aa55aa55);
+pub const DARK_SQUARES: Bitboard = Bitboard::new(0x55aa55aa55aa55aa);
 
 pub const KNIGHT_TABLE: [Bitboard; 64] =
   [ Bitboard::new(132096)
@@ -919,6 +913,35 @@ pub fn get_queen_rays(square: Square, occupied: Bitboard) -> Bitboard
          | get_rook_rays(square, occupied);
 }
 
+// The squares of each castle, indexed by CastlingRights::index. The king and rook land on
+// the same squares in Chess960, but start from wherever they stand, which for the rook is
+// kept in Game::castling_rooks. The squares they start from here are standard chess's.
+pub struct Castle {
+    pub king_from: u32,
+    pub king_to: u32,
+    pub rook_from: u32,
+    pub rook_to: u32
+}
+
+pub const CASTLES: [Castle; 4] = [
+    // white kingside, e1g1 with the rook from h1 to f1
+    Castle { king_from: 3, king_to: 1, rook_from: 0, rook_to: 2 },
+    // white queenside, e1c1 with the rook from a1 to d1
+    Castle { king_from: 3, king_to: 5, rook_from: 7, rook_to: 4 },
+    // black queenside
+    Castle { king_from: 59, king_to: 61, rook_from: 63, rook_to: 60 },
+    // black kingside
+    Castle { king_from: 59, king_to: 57, rook_from: 56, rook_to: 58 }
+];
+
+// every square from sq_a to sq_b inclusive, which must share a rank
+pub fn rank_span(sq_a: Square, sq_b: Square) -> Bitboard {
+    debug_assert!(sq_a.rank() == sq_b.rank());
+
+    let (lo, hi) = if sq_a.idx() < sq_b.idx() { (sq_a.idx(), sq_b.idx()) } else { (sq_b.idx(), sq_a.idx()) };
+    Bitboard::new((u64::max_value() >> (63 - hi)) & (u64::max_value() << lo))
+}
+
 pub fn ray_between_squares(sq_a: Square, sq_b: Square) -> Bitboard
 {
     //TODO: turn this into a lookup table
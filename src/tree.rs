@@ -8,24 +8,63 @@ use tables::*;
 use game::*;
 use pins::*;
 use eval::*;
+use ordering::*;
 use zobrist::*;
 
+use std::cmp::min;
+
 const MAX_GAME_TREE_DEPTH: usize = 256;
 const MAX_CHESS_GAME_LENGTH: usize = 550;
 
-pub struct SearchTree {
+// Everything the tree keeps for a single ply. Making a move copies the parent
+// game into the next frame, so unmaking is just popping back to the parent. Game has
+// no unmake of its own, which is what a smaller undo state would need.
+// The moves of a ply live with whoever is searching them, on their own stack,
+// which leaves nothing in the tree to share and the tree free to move between threads.
+struct SearchFrame {
     game: Game,
+    // the static evaluation of the game, once something has asked for it
+    static_eval: Option<Score>,
+    // the last two quiet moves to cause a cutoff at this ply, in any of its nodes
+    killers: [Option<Move>; 2],
+    // the best line found from the game so far, starting with its move
+    pv: Vec<Move>
+}
+
+impl SearchFrame {
+    fn new(game: Game) -> SearchFrame {
+        SearchFrame {
+            game: game,
+            static_eval: None,
+            killers: [None, None],
+            pv: Vec::new()
+        }
+    }
+}
+
+pub struct SearchTree {
+    frames: Vec<SearchFrame>,
     search_depth: usize,
     pub root_history: Vec<Hash>,
+    // index of the root's own hash in root_history
+    root_index: usize,
     pub current_line: Vec<Move>,
     best_lines: Vec<(Score,MoveList)>,
-    move_stack: Vec<MoveBuffer>,
-    pub in_quiescence: bool
+    pub in_quiescence: bool,
+    pub nodes: u64,
+    // deepest ply reached since this was last zeroed
+    pub seldepth: usize
 }
 
 impl SearchTree {
     pub fn focus(&self) -> &Game {
-        &self.game
+        &self.frames[self.search_depth].game
+    }
+
+    // the position one ply above the focus
+    pub fn parent(&self) -> &Game {
+        debug_assert!(self.search_depth > 0);
+        &self.frames[self.search_depth - 1].game
     }
 
     pub fn last_move(&self) -> Move {
@@ -37,98 +76,198 @@ impl SearchTree {
     }
 
     pub fn new(new_game: Game) -> SearchTree {
-        let mut new_move_stack = Vec::new();
-        new_move_stack.reserve(MAX_GAME_TREE_DEPTH);
+        let mut new_frames = Vec::new();
+        new_frames.reserve(MAX_GAME_TREE_DEPTH);
         for _ in 0 .. MAX_GAME_TREE_DEPTH {
-            new_move_stack.push(alloc_move_buffer());
+            new_frames.push(SearchFrame::new(new_game));
         }
 
         let mut new_current_line = Vec::new();
         new_current_line.reserve(MAX_GAME_TREE_DEPTH);
 
         SearchTree {
-            game: new_game,
+            frames: new_frames,
             search_depth: 0,
             current_line: new_current_line,
             best_lines: Vec::new(),
-            root_history: Vec::new(),
-            move_stack: new_move_stack,
-            in_quiescence: false
+            root_history: vec![new_game.hash],
+            root_index: 0,
+            in_quiescence: false,
+            nodes: 0,
+            seldepth: 0
         }
     }
 
-    pub fn next_moves(&self, best_move_candidate: Option<Move>) -> MoveBuffer {
-        {
-            let buf = self.move_stack[self.search_depth].clone();
-            if self.in_quiescence {
-                //TODO: handle checks in quiescence
-                generate_moves(&self.game, buf.clone(), true);
-            } else {
-                generate_moves(&self.game, buf.clone(), false);
-            }
-            buf.borrow_mut().sort(best_move_candidate);
+    pub fn next_moves(&self, ordering: &OrderingContext) -> MoveList {
+        let game = self.focus();
+        let mut moves = MoveList::new();
+
+        //TODO: handle checks in quiescence
+        generate_moves(game, &mut moves, self.in_quiescence);
+        order_moves(&mut moves, game, ordering);
+
+        return moves;
+    }
+
+    // The evaluation of the focus from the side to move's point of view, computed at most
+    // once however many times the node is searched.
+    pub fn static_eval(&mut self) -> Score {
+        let depth = self.search_depth;
+        let frame = &mut self.frames[depth];
+
+        if frame.static_eval.is_none() {
+            frame.static_eval = Some(Score::recompute_symmetric(&frame.game, depth));
         }
 
-        self.move_stack[self.search_depth].clone()
+        return frame.static_eval.unwrap();
     }
 
-    pub fn make_null_move(&mut self) {
-        self.game.make_null_move();
+    pub fn killers(&self) -> [Option<Move>; 2] {
+        self.frames[self.search_depth].killers
+    }
+
+    pub fn record_killer(&mut self, m: Move) {
+        let killers = &mut self.frames[self.search_depth].killers;
+        if killers[0] != Some(m) {
+            killers[1] = killers[0];
+            killers[0] = Some(m);
+        }
+    }
+
+    // m, followed by the best line found after it, becomes the best line from the focus
+    pub fn update_pv(&mut self, m: Move) {
+        debug_assert!(self.search_depth + 1 < MAX_GAME_TREE_DEPTH);
+        let (parents, children) = self.frames.split_at_mut(self.search_depth + 1);
+        let pv = &mut parents[self.search_depth].pv;
+        pv.clear();
+        pv.push(m);
+        pv.extend_from_slice(&children[0].pv);
+    }
+
+    // the best line found from the root by the last search
+    pub fn principal_variation(&self) -> &[Move] {
+        &self.frames[0].pv
+    }
+
+    // copies the focus into the next frame and moves the focus there
+    fn push_frame(&mut self) {
+        debug_assert!(self.search_depth + 1 < MAX_GAME_TREE_DEPTH);
+        let (parents, children) = self.frames.split_at_mut(self.search_depth + 1);
+        let child = &mut children[0];
+        child.game = parents[self.search_depth].game;
+        child.static_eval = None;
+        child.pv.clear();
         self.search_depth += 1;
-        self.move_stack[self.search_depth].borrow_mut().clear();
+    }
+
+    fn pop_frame(&mut self) {
+        debug_assert!(self.search_depth > 0);
+        self.search_depth -= 1;
+    }
+
+    pub fn make_null_move(&mut self) {
+        self.push_frame();
+        self.frames[self.search_depth].game.make_null_move();
     }
 
     pub fn make_move(&mut self, m: Move) {
-        self.game.make_move(m);
+        self.push_frame();
         self.current_line.push(m);
-        self.search_depth += 1;
-        self.move_stack[self.search_depth].borrow_mut().clear();
+        self.nodes += 1;
+
+        if self.search_depth > self.seldepth {
+            self.seldepth = self.search_depth;
+        }
+
+        let game = &mut self.frames[self.search_depth].game;
+        game.make_move(m);
 
         if !self.in_quiescence {
-            let mut repetition_count = 1;
-            for h in self.root_history.iter() {
-                if *h == self.game.hash {
-                    repetition_count += 1;
-                }
-            }
+            game.outcome = game.outcome_with_history(&self.root_history);
 
-            if repetition_count >= 3 {
-                self.game.outcome = Some(GameResult::Draw);
+            // Returning to any position from the root onward is a draw already. Whoever
+            // could avoid it inside the search can avoid it in the game, so there's no point
+            // in waiting for the third occurrence. That keeps a winning side from shuffling.
+            if game.outcome.is_none() && self.root_history[self.root_index ..].contains(&game.hash) {
+                game.outcome = Some(GameResult::Draw);
             }
 
-            self.root_history.push(self.game.hash);
+            self.root_history.push(game.hash);
         }
-
-        //TODO: check for three-fold repetition here.
     }
 
-    pub fn unmake_null_move(&mut self, previous_game: Game) {
-        debug_assert!(self.search_depth > 0);
-        self.move_stack[self.search_depth].borrow_mut().clear();
-        self.search_depth -= 1;
-        self.game = previous_game;
+    pub fn unmake_null_move(&mut self) {
+        self.pop_frame();
     }
 
-    // currently we unmake move by copy
-    // OPTIMIZE: is this copying twice??? nail down rust copy/move semantics
-    pub fn unmake_move(&mut self, previous_game: Game) {
-        debug_assert!(self.search_depth > 0);
-        self.move_stack[self.search_depth].borrow_mut().clear();
-        self.search_depth -= 1;
-        self.game = previous_game;
+    pub fn unmake_move(&mut self) {
+        self.pop_frame();
         self.current_line.pop();
         self.root_history.pop();
     }
 
+    // Whether the side to move at the focus has a move back to a position that would then be a
+    // draw by repetition, under the same rules as make_move: from the root on once is enough,
+    // before it the position needs to have been there twice. No moves are generated, the
+    // hashes of earlier positions with the other side to move are checked for a difference of
+    // a single move in the cuckoo tables, and then whether that move is there to be made.
+    pub fn upcoming_repetition(&self) -> bool {
+        let game = self.focus();
+
+        // the quiescence tree and null moves don't keep the history
+        if self.root_history.last() != Some(&game.hash) {
+            return false;
+        }
+
+        let current = self.root_history.len() - 1;
+        let occupied = game.board.occupied();
+
+        // nothing before the last capture or pawn move can come back
+        let end = min(game.halfmove_clock as usize, current);
+
+        let mut i = 3;
+        while i <= end {
+            let earlier = current - i;
+            let earlier_hash = self.root_history[earlier];
+
+            if let Some(m) = cuckoo_move(game.hash.unwrap() ^ earlier_hash.unwrap()) {
+                // the table has the move one way round, the piece is on whichever square it's on
+                let (from, to) = if (m.from().bitrep() & occupied).nonempty() {
+                    (m.from(), m.to())
+                } else {
+                    (m.to(), m.from())
+                };
+
+                let movable = game.board.piece_at(from) == Some(Piece::new(game.to_move, m.moved_piece()))
+                    && ((ray_between_squares(from, to) | to.bitrep()) & occupied).empty();
+
+                if movable && (earlier >= self.root_index || self.root_history[.. earlier].contains(&earlier_hash)) {
+                    return true;
+                }
+            }
+
+            i += 2;
+        }
+
+        return false;
+    }
+
+    // History holds the hashes of every position in the game so far, new_game's included. The
+    // killers are kept for the next search, which is often from a position close to this one.
     pub fn reset_root(&mut self, new_game: Game, history: Vec<Hash>) {
-        self.game = new_game;
+        let root = &mut self.frames[0];
+        root.game = new_game;
+        root.static_eval = None;
+        root.pv.clear();
+
         self.search_depth = 0;
         self.current_line.clear();
-        self.root_history = history.clone();
+        self.root_history = history;
 
-        for i in 0 .. self.search_depth {
-            self.move_stack[i].borrow_mut().clear();
+        if self.root_history.last() != Some(&new_game.hash) {
+            self.root_history.push(new_game.hash);
         }
+
+        self.root_index = self.root_history.len() - 1;
     }
 }
-
@@ -0,0 +1,350 @@
+use core::*;
+use eval::*;
+use eval_params::*;
+use game::*;
+use movegen::*;
+use move_list::*;
+
+use std::fs;
+use std::sync::Arc;
+use std::thread;
+
+// each candidate value is tried this far either side of the current one
+const TUNING_STEP: i16 = 1;
+
+// the material values cancel out, so there's nothing to learn about the king's,
+// and the win/draw/loss model doesn't take part in the evaluation at all
+const UNTUNED_PARAMS: [&'static str; 3] = ["king_value", "wdl_center", "wdl_spread"];
+
+#[derive(Clone, Copy)]
+pub struct TrainingPosition {
+    pub game: Game,
+    // from white's point of view: 1 for a win, 0.5 for a draw, 0 for a loss
+    pub result: f64
+}
+
+// One position per line, as EPD with the game result in a c9 opcode:
+//     rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - c9 "1/2-1/2";
+// Positions that are already decided are left out, they say nothing about the eval,
+// and so are impossible ones where the side that just moved is still in check.
+pub fn parse_training_set(text: &str) -> Option<Vec<TrainingPosition>> {
+    let mut positions = Vec::new();
+
+    for line in text.lines().map(|l| l.trim()) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.splitn(5, ' ').collect();
+        if fields.len() < 5 {
+            return None;
+        }
+
+        let game = Game::from_fen_str(&format!("{} 0 1", fields[0 .. 4].join(" ")))?;
+
+        let operations = fields[4];
+        let c9 = operations.find("c9 \"")?;
+        let result_str = operations[c9 + 4 ..].split('"').next()?;
+
+        let result = match result_str {
+            "1-0" => 1.0,
+            "0-1" => 0.0,
+            "1/2-1/2" => 0.5,
+            _ => return None
+        };
+
+        let opponent_king = game.board.get_king_square(!game.to_move);
+        let impossible = game.board.attackers(opponent_king, game.to_move).nonempty();
+
+        if game.outcome().is_none() && !impossible {
+            positions.push(TrainingPosition { game: game, result: result });
+        }
+    }
+
+    return Some(positions);
+}
+
+pub fn load_training_set(path: &str) -> Option<Vec<TrainingPosition>> {
+    match fs::read_to_string(path) {
+        Ok(text) => parse_training_set(&text),
+        Err(_) => None
+    }
+}
+
+// The position at the end of the best capture sequence, so that the eval isn't
+// trained on positions halfway through an exchange.
+pub fn quiet_position(game: &Game, params: &EvalParams) -> Game {
+    quiet_leaf(game, Score::min(), Score::max(), params).1
+}
+
+fn quiet_leaf(game: &Game, mut alpha: Score, beta: Score, params: &EvalParams) -> (Score, Game) {
+    let stand_pat = match game.to_move {
+        Color::White => Score::recompute_with_params(game, 0, params),
+        Color::Black => Score::recompute_with_params(game, 0, params).flipped()
+    };
+
+    if stand_pat >= beta {
+        return (beta, *game);
+    }
+
+    let mut best_leaf = *game;
+
+    if alpha < stand_pat {
+        alpha = stand_pat;
+    }
+
+    let mut captures = MoveList::new();
+    generate_moves(game, &mut captures, true);
+
+    for m in captures.iter() {
+        let mut child = *game;
+        child.make_move(*m);
+
+        let (s, leaf) = quiet_leaf(&child, beta.flipped(), alpha.flipped(), params);
+        let s = s.flipped();
+
+        if s >= beta {
+            return (beta, leaf);
+        }
+
+        if s > alpha {
+            alpha = s;
+            best_leaf = leaf;
+        }
+    }
+
+    return (alpha, best_leaf);
+}
+
+// expected score for white of a position evaluated at eval centipawns
+fn win_probability(eval: f64, k: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-k * eval / 400.0))
+}
+
+fn mean_squared_error(positions: &[TrainingPosition], evals: &[f64], k: f64) -> f64 {
+    let total: f64 = positions.iter().zip(evals.iter())
+        .map(|(p, eval)| (p.result - win_probability(*eval, k)).powi(2))
+        .sum();
+
+    total / positions.len() as f64
+}
+
+// Texel's method: adjust the parameters one at a time, keeping any change that
+// lowers the error between the eval's predicted results and the actual ones.
+pub struct Tuner {
+    positions: Arc<Vec<TrainingPosition>>,
+    threads: usize,
+    pub params: EvalParams,
+    // scales centipawns into the sigmoid, fitted once to the starting parameters
+    pub k: f64,
+    pub error: f64
+}
+
+impl Tuner {
+    pub fn new(positions: Vec<TrainingPosition>, params: EvalParams, threads: usize) -> Tuner {
+        debug_assert!(!positions.is_empty() && threads > 0);
+
+        let mut tuner = Tuner {
+            positions: Arc::new(positions),
+            threads: threads,
+            params: params,
+            k: 1.0,
+            error: 0.0
+        };
+
+        let evals = tuner.evaluate_all(&params);
+
+        // the error is smooth in k, a fine scan is plenty
+        let mut best = (::std::f64::MAX, tuner.k);
+        for i in 1 .. 301 {
+            let k = i as f64 / 100.0;
+            let error = mean_squared_error(&tuner.positions, &evals, k);
+            if error < best.0 {
+                best = (error, k);
+            }
+        }
+
+        tuner.error = best.0;
+        tuner.k = best.1;
+        return tuner;
+    }
+
+    // white's static eval of every position. Each thread evaluates its own copies of the
+    // positions, and recompute_with_params doesn't touch the pawn hash table.
+    fn evaluate_all(&self, params: &EvalParams) -> Vec<f64> {
+        let chunk_size = (self.positions.len() + self.threads - 1) / self.threads;
+        let mut handles = Vec::new();
+
+        for start in (0 .. self.positions.len()).step_by(chunk_size) {
+            let positions = self.positions.clone();
+            let params = *params;
+            let end = (start + chunk_size).min(positions.len());
+
+            handles.push(thread::spawn(move || {
+                positions[start .. end].iter()
+                    .map(|p| Score::recompute_with_params(&p.game, 0, &params).unwrap() as f64)
+                    .collect::<Vec<f64>>()
+            }));
+        }
+
+        let mut evals = Vec::with_capacity(self.positions.len());
+        for handle in handles {
+            evals.extend(handle.join().unwrap());
+        }
+
+        return evals;
+    }
+
+    pub fn error_with(&self, params: &EvalParams) -> f64 {
+        let evals = self.evaluate_all(params);
+        mean_squared_error(&self.positions, &evals, self.k)
+    }
+
+    // one pass over every middle-game and end-game value, returns whether any of them changed
+    pub fn iterate(&mut self) -> bool {
+        let mut improved = false;
+        let num_params = self.params.named_values().len();
+
+        for i in 0 .. num_params {
+            if UNTUNED_PARAMS.contains(&self.params.named_values()[i].0.as_str()) {
+                continue;
+            }
+
+            for phase in 0 .. 2 {
+                for &step in [TUNING_STEP, -TUNING_STEP].iter() {
+                    let mut candidate = self.params;
+                    {
+                        let mut named_values = candidate.named_values();
+                        let value = &mut named_values[i].1;
+                        if phase == 0 { value.0 += step; } else { value.1 += step; }
+                    }
+
+                    let error = self.error_with(&candidate);
+
+                    if error < self.error {
+                        self.params = candidate;
+                        self.error = error;
+                        improved = true;
+                        break;
+                    }
+                }
+            }
+        }
+
+        return improved;
+    }
+}
+
+pub fn tune(training_path: &str, output_path: &str, iterations: usize, quiesce: bool, threads: usize) {
+    let mut positions = match load_training_set(training_path) {
+        Some(positions) => positions,
+        None => {
+            eprintln!("Invalid training set: {}", training_path);
+            return;
+        }
+    };
+
+    if positions.is_empty() {
+        eprintln!("No undecided positions in {}", training_path);
+        return;
+    }
+
+    let start_params = eval_params();
+
+    if quiesce {
+        for p in positions.iter_mut() {
+            p.game = quiet_position(&p.game, &start_params);
+        }
+    }
+
+    let timer = Counter::new();
+    let mut tuner = Tuner::new(positions, start_params, threads);
+    let start_error = tuner.error;
+
+    println!("positions: {}, k: {:.2}, error: {:.6}", tuner.positions.len(), tuner.k, start_error);
+
+    for i in 0 .. iterations {
+        let improved = tuner.iterate();
+        println!("iteration {}: error {:.6}", i + 1, tuner.error);
+
+        if !improved {
+            break;
+        }
+    }
+
+    match tuner.params.save(output_path) {
+        Ok(_) => println!("wrote {}", output_path),
+        Err(e) => eprintln!("can't write {}: {}", output_path, e)
+    }
+
+    println!("===========================");
+    println!("Error before   : {:.6}", start_error);
+    println!("Error after    : {:.6}", tuner.error);
+    println!("Total time (s) : {:.1}", timer.elapsed_ms() / 1000.0);
+}
+
+#[cfg(test)]
+mod test {
+    use tune::*;
+    use zobrist::*;
+
+    const SAMPLE_TRAINING_SET: &'static str = include_str!("../tuning/sample.epd");
+
+    #[test]
+    fn parse() {
+        init_zobrist_hashing();
+
+        let positions = parse_training_set(SAMPLE_TRAINING_SET).unwrap();
+        assert!(positions.len() >= 35);
+
+        let first = &positions[0];
+        assert_eq!(first.game.to_fen(), "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1");
+        assert_eq!(first.result, 0.5);
+
+        // the scholar's mate is over already
+        assert!(positions.iter().all(|p| p.game.outcome().is_none()));
+
+        // white can't be in check with black to move
+        assert!(parse_training_set("6k1/5ppp/8/8/8/8/5PPP/3r2K1 b - - c9 \"0-1\";").unwrap().is_empty());
+
+        assert!(parse_training_set("8/8/4k3/8/3PK3/8/8/8 w - - c9 \"2-0\";").is_none());
+        assert!(parse_training_set("8/8/4k3/8/3PK3/8/8/8 w - -").is_none());
+        assert!(parse_training_set("# nothing but a comment").unwrap().is_empty());
+    }
+
+    #[test]
+    fn quiet_positions() {
+        init_zobrist_hashing();
+
+        // white's queen takes the undefended rook, then nothing is left to capture
+        let game = Game::from_fen_str("4k3/8/8/3r4/8/8/3Q4/4K3 w - - 0 1").unwrap();
+        let quiet = quiet_position(&game, &DEFAULT_EVAL_PARAMS);
+        assert_eq!(quiet.to_fen(), "4k3/8/8/3Q4/8/8/8/4K3 b - - 0 1");
+
+        let start = Game::starting_position();
+        assert_eq!(quiet_position(&start, &DEFAULT_EVAL_PARAMS), start);
+    }
+
+    #[test]
+    fn error_decreases() {
+        init_zobrist_hashing();
+
+        let positions = parse_training_set(SAMPLE_TRAINING_SET).unwrap();
+        let mut tuner = Tuner::new(positions, DEFAULT_EVAL_PARAMS, 4);
+
+        let start_error = tuner.error;
+        assert_eq!(tuner.error_with(&DEFAULT_EVAL_PARAMS), start_error);
+
+        let mut last_error = start_error;
+        for _ in 0 .. 3 {
+            assert!(tuner.iterate());
+            assert!(tuner.error < last_error, "{} {}", tuner.error, last_error);
+            last_error = tuner.error;
+        }
+
+        // the reported error belongs to the parameters that were kept
+        assert_eq!(tuner.error_with(&tuner.params), tuner.error);
+        assert!(tuner.params != DEFAULT_EVAL_PARAMS);
+        assert_eq!(tuner.params.material(PieceType::King), DEFAULT_EVAL_PARAMS.material(PieceType::King));
+    }
+}
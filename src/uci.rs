@@ -1,35 +1,208 @@
-use std::io::stdin;
-use std::io::BufRead;
+use std::io;
+use std::io::{stdin, stdout, BufRead, BufReader, Write};
 use std::str::SplitWhitespace;
 
-use std::fs::OpenOptions;
-use std::io::prelude::*;
+use std::fs::{File, OpenOptions};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+use std::time::Duration;
 
+#[cfg(test)] use std::cell::RefCell;
+#[cfg(test)] use std::rc::Rc;
+
+use chrono::prelude::*;
+
+use core::*;
+use eval::*;
 use game::*;
 use movegen::*;
 use moves::*;
+use search::*;
 use zobrist::*;
 
+// Every line exchanged with the GUI goes through here, so it can be logged in one place.
+pub struct UciIo {
+    // handed to the thread that reads it once the first line is asked for
+    reader: Option<Box<BufRead + Send>>,
+    lines: Option<Receiver<String>>,
+    writer: Box<Write>,
+    // set as soon as "stop" is read, even while a search keeps the commands before it waiting
+    stop: Arc<AtomicBool>,
+    // "debug on" also sends internal diagnostics to the GUI as info strings
+    pub debug: bool,
+    log: Option<(String, File)>
+}
+
+impl UciIo {
+    pub fn new(reader: Box<BufRead + Send>, writer: Box<Write>) -> UciIo {
+        UciIo {
+            reader: Some(reader),
+            lines: None,
+            writer: writer,
+            stop: Arc::new(AtomicBool::new(false)),
+            debug: false,
+            log: None
+        }
+    }
+
+    pub fn stdio() -> UciIo {
+        UciIo::new(Box::new(BufReader::new(stdin())), Box::new(stdout()))
+    }
+
+    // an empty path turns logging off
+    pub fn set_log_file(&mut self, path: &str) {
+        self.log = None;
+
+        if path.is_empty() || path == "<empty>" {
+            return;
+        }
+
+        match OpenOptions::new().create(true).append(true).open(path) {
+            Ok(file) => self.log = Some((path.to_string(), file)),
+            Err(e) => eprintln!("warning: can't open log file {}: {}", path, e)
+        }
+    }
+
+    pub fn logging(&self) -> bool {
+        self.log.is_some()
+    }
+
+    fn log_line(&mut self, direction: &str, line: &str) {
+        let failed = match self.log {
+            Some((_, ref mut file)) => {
+                let timestamp = Local::now().format("%Y-%m-%d %H:%M:%S%.3f");
+                writeln!(file, "{} {} {}", timestamp, direction, line).is_err()
+            },
+            None => false
+        };
+
+        // warn once and stop logging, a broken log must never take the engine down
+        if failed {
+            let (path, _) = self.log.take().unwrap();
+            eprintln!("warning: can't write to log file {}, logging disabled", path);
+        }
+    }
+
+    // the next line from the GUI, or None once the input is closed
+    pub fn receive(&mut self) -> Option<String> {
+        if self.lines.is_none() {
+            let reader = self.reader.take().unwrap();
+            self.lines = Some(read_lines(reader, self.stop.clone()));
+        }
+
+        let line = match self.lines.as_ref().unwrap().recv() {
+            Ok(line) => line,
+            Err(_) => return None
+        };
+
+        // the search it was meant for is over, so the reader can go on to the next command
+        if is_stop_command(&line) {
+            self.stop.store(false, Ordering::SeqCst);
+        }
+
+        self.log_line("<", &line);
+        return Some(line);
+    }
+
+    // what a search should watch to know the GUI wants it to stop
+    pub fn stop_signal(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+
+    pub fn send(&mut self, line: &str) {
+        if writeln!(self.writer, "{}", line).and_then(|_| self.writer.flush()).is_err() {
+            eprintln!("warning: can't write to the GUI: {}", line);
+        }
+
+        self.log_line(">", line);
+    }
+
+    // internal diagnostics, always logged but only sent to the GUI in debug mode
+    pub fn info_string(&mut self, message: &str) {
+        let line = format!("info string {}", message);
+
+        if self.debug {
+            self.send(&line);
+        } else {
+            self.log_line("#", &line);
+        }
+    }
+}
+
+// Reads lines on a thread of its own, so that a stop is seen while the main loop is still busy
+// with the search it's for. It then waits for the main loop to get to the stop too, otherwise
+// a second one read ahead would stop the next search before it started.
+fn read_lines(mut reader: Box<BufRead + Send>, stop: Arc<AtomicBool>) -> Receiver<String> {
+    let (sender, receiver) = channel();
+
+    thread::spawn(move || {
+        loop {
+            let mut line = String::new();
+
+            match reader.read_line(&mut line) {
+                Ok(0) | Err(_) => return,
+                Ok(_) => {}
+            }
+
+            let line = line.trim_right().to_string();
+            let stops = is_stop_command(&line);
+            let quits = line.split_whitespace().next() == Some("quit");
+
+            if stops {
+                stop.store(true, Ordering::SeqCst);
+            }
+
+            if sender.send(line).is_err() || quits {
+                return;
+            }
+
+            while stops && stop.load(Ordering::SeqCst) {
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+    });
+
+    return receiver;
+}
+
+// ponderhit ends a ponder search the same way, the move is answered with straight away
+fn is_stop_command(line: &str) -> bool {
+    match line.split_whitespace().next() {
+        Some("stop") | Some("ponderhit") => true,
+        _ => false
+    }
+}
+
 pub trait UCIEngine {
     fn name(&self) -> &'static str;
     fn author(&self) -> &'static str;
     fn init(&mut self) -> () {}
     fn reset(&mut self) -> () {}
+    fn print_options(&self, _io: &mut UciIo) -> () {}
+    fn set_option(&mut self, _name: &str, _value: &str) -> () {}
     fn replace_game(&mut self, new_game: Game, history: Vec<Hash>);
-    fn find_best_move(&mut self, wtime: u32, btime: u32, winc: u32, binc: u32) -> ();
+    fn current_game(&self) -> Game;
+    fn find_best_move(&mut self, limits: SearchLimits, io: &mut UciIo) -> ();
     // fn infinite_search(&mut self) -> ();
 
     //TODO: move to UCIEngine trait default implementation
-    fn update_position<'a>(&mut self, args: &mut SplitWhitespace<'a>) {
+    fn update_position<'a>(&mut self, args: &mut SplitWhitespace<'a>, io: &mut UciIo) {
+        let mut args = args.peekable();
         let mut g = Game::empty_position();
 
         match args.next() {
             Some("startpos") => g = Game::starting_position(),
-            Some("fen") => {
-                g = Game::from_fen(args).unwrap();
-            }
+            Some("fen") => match Game::from_fen(&mut args) {
+                Some(game) => g = game,
+                None => {
+                    io.info_string("error! invalid fen, keeping the previous position");
+                    return;
+                }
+            },
             _ => {
-                eprintln!("error! invalid position string passed!");
+                io.info_string("error! invalid position string passed!");
                 return;
             }
         }
@@ -37,66 +210,117 @@ pub trait UCIEngine {
         match args.next() {
             Some("moves") => {},
             _ => {
-                self.replace_game(g, Vec::new());
+                self.replace_game(g, vec![g.hash]);
                 return
             }
         }
 
-        let mut history = Vec::new();
-        loop {
-            if let Some(move_str) = args.next() {
-                let m = move_from_algebraic(&g, move_str.to_string()).unwrap();
-                g.make_move(m);
-                history.push(g.hash);
-            } else {
-                break;
+        let moves: Vec<&str> = args.collect();
+
+        // every position reached in the game, including the one the search starts from,
+        // so that repetitions of positions from before the root are recognized
+        let positions = match g.trace_moves(&moves) {
+            Ok(positions) => positions,
+            Err(error) => {
+                io.info_string(&format!("error! {}, ignoring it and the rest", error));
+                g.trace_moves(&moves[.. error.index]).unwrap()
             }
-        }
+        };
+
+        g = *positions.last().unwrap();
+        let history = positions.iter().map(|position| position.hash).collect();
 
-        eprintln!("FEN re-created by feldspar: {}", g.to_fen());
+        io.info_string(&format!("FEN re-created by feldspar: {}", g.to_fen()));
 
         self.replace_game(g, history);
     }
 
-    fn parse_go_cmd<'a>(&mut self, args: &mut SplitWhitespace<'a>) {
+    fn parse_go_cmd<'a>(&mut self, args: &mut SplitWhitespace<'a>, io: &mut UciIo) {
+        let limits = parse_go_limits(&self.current_game(), args);
 
-        let mut wtime = 0;
-        let mut btime = 0;
-        let mut winc = 0;
-        let mut binc = 0;
+        // nothing to search, so answer right away instead of handing the engine an empty move list
+        if let Some(lines) = terminal_position_response(&self.current_game()) {
+            for line in lines.iter() {
+                io.send(line);
+            }
+            return;
+        }
 
-        loop {
-            match args.next() {
-                Some("wtime") => wtime = args.next().unwrap().parse().unwrap(),
-                Some("btime") => btime = args.next().unwrap().parse().unwrap(),
-                Some("winc") => winc = args.next().unwrap().parse().unwrap(),
-                Some("binc") => binc = args.next().unwrap().parse().unwrap(),
-                Some(_) => break,
-                None => break
+        self.find_best_move(limits, io);
+    }
+
+    // setoption name <id> [value <x>], where both id and x may contain spaces
+    fn parse_setoption_cmd<'a>(&mut self, args: &mut SplitWhitespace<'a>, io: &mut UciIo) {
+        let mut name = Vec::new();
+        let mut value = Vec::new();
+        let mut reading_value = false;
+
+        match args.next() {
+            Some("name") => {},
+            _ => {
+                io.info_string("error! invalid setoption string passed!");
+                return;
+            }
+        }
+
+        for token in args {
+            if token == "value" && !reading_value {
+                reading_value = true;
+            } else if reading_value {
+                value.push(token);
+            } else {
+                name.push(token);
             }
         }
 
-        self.find_best_move(wtime, btime, winc, binc);
+        // the log belongs to the protocol layer rather than the engine
+        if name.join(" ") == "LogFile" {
+            io.set_log_file(&value.join(" "));
+            return;
+        }
+
+        self.set_option(&name.join(" "), &value.join(" "));
     }
 
-    fn run(&mut self) -> () {
-        let stdin = stdin();
-        for line in stdin.lock().lines() {
-            eprintln!("line before received from gui/server: {:?}", line);
-            let line = line.unwrap_or("".into());
-            eprintln!("line received from gui/server: {}", line);
-
-            let mut file = OpenOptions::new()
-                .write(true)
-                .append(true)
-                .open("C:/Code/feldspar/log.txt")
-                .unwrap();
-
-            if let Err(e) = writeln!(file, "{}", line) {
-                eprintln!("Couldn't write to file: {}", e);
+    // Not part of UCI: the static evaluation of the current position in centipawns, from
+    // white's point of view. "eval trace" sends each term of it first.
+    fn print_eval(&self, params: &mut SplitWhitespace, io: &mut UciIo) {
+        let trace = eval_trace(&self.current_game());
+
+        if params.next() == Some("trace") {
+            for line in trace.lines() {
+                io.send(&line);
             }
+        }
+
+        io.send(&format!("eval cp {}", trace.score.unwrap()));
+    }
 
+    // Not part of UCI either: the current position with its FEN and hash, the static
+    // evaluation, and whether the fields make_move keeps up to date still agree with it.
+    fn print_position(&self, io: &mut UciIo) {
+        let game = self.current_game();
 
+        for line in format!("{:?}", game).lines() {
+            io.send(line);
+        }
+
+        io.send(&format!("eval cp {}", eval_trace(&game).score.unwrap()));
+
+        match game.verify_derived_fields() {
+            Ok(()) => io.send("derived fields ok"),
+            Err(diff) => for line in diff.lines() {
+                io.send(&format!("derived fields stale, {}", line));
+            }
+        }
+    }
+
+    fn run(&mut self) -> () {
+        self.run_with(&mut UciIo::stdio());
+    }
+
+    fn run_with(&mut self, io: &mut UciIo) -> () {
+        while let Some(line) = io.receive() {
             let mut params = line.split_whitespace();
 
             if let Some(first_word) = params.next() {
@@ -104,20 +328,435 @@ pub trait UCIEngine {
                 match first_word {
 
                     "uci" => {
-                        println!("id name {}", self.name());
-                        println!("id author {}", self.author());
-                        println!("uciok");
+                        io.send(&format!("id name {}", self.name()));
+                        io.send(&format!("id author {}", self.author()));
+                        self.print_options(io);
+                        io.send("option name LogFile type string default <empty>");
+                        io.send("uciok");
+                    },
+
+                    "debug" => match params.next() {
+                        Some("on") => io.debug = true,
+                        Some("off") => io.debug = false,
+                        _ => io.info_string("debug expects on or off")
                     },
 
-                    "setoption" => {},
-                    "isready"    => println!("readyok"),
+                    "setoption" => self.parse_setoption_cmd(&mut params, io),
+                    "isready"    => io.send("readyok"),
                     "ucinewgame" => self.reset(),
-                    "position"   => self.update_position(&mut params),
+                    "position"   => self.update_position(&mut params, io),
                     "quit"       => return,
-                    "go"         => self.parse_go_cmd(&mut params),
-                    _ => println!("Un-used command from GUI/server: {}", first_word)
+                    "go"         => self.parse_go_cmd(&mut params, io),
+                    // the search it stopped has already answered by the time these are read
+                    "stop"       => {},
+                    "ponderhit"  => {},
+                    "eval"       => self.print_eval(&mut params, io),
+                    "d"          => self.print_position(io),
+                    _ => io.info_string(&format!("Un-used command from GUI/server: {}", first_word))
                 }
             }
         }
     }
 }
+
+// The reply to "go" when the side to move has no legal moves, or None if there is something to search.
+pub fn terminal_position_response(game: &Game) -> Option<Vec<String>> {
+    if can_move(game) {
+        return None;
+    }
+
+    let (score_str, reason) = if game.in_check() {
+        ("mate 0", "checkmate")
+    } else {
+        ("cp 0", "stalemate")
+    };
+
+    return Some(vec![
+        format!("info depth 0 score {}", score_str),
+        format!("info string no legal moves, position is {}", reason),
+        "bestmove (none)".to_string()
+    ]);
+}
+
+// The arguments of "go", with searchmoves resolved against game.
+pub fn parse_go_limits<'a>(game: &Game, args: &mut SplitWhitespace<'a>) -> SearchLimits {
+    let mut limits = SearchLimits::new();
+    let mut tokens = args.peekable();
+
+    loop {
+        match tokens.next() {
+            Some("wtime") => limits.wtime = tokens.next().unwrap().parse().unwrap(),
+            Some("btime") => limits.btime = tokens.next().unwrap().parse().unwrap(),
+            Some("winc") => limits.winc = tokens.next().unwrap().parse().unwrap(),
+            Some("binc") => limits.binc = tokens.next().unwrap().parse().unwrap(),
+            Some("movetime") => limits.movetime = Some(tokens.next().unwrap().parse().unwrap()),
+            Some("depth") => limits.depth = Some(tokens.next().unwrap().parse().unwrap()),
+            Some("mate") => limits.mate = Some(tokens.next().unwrap().parse().unwrap()),
+            // there's no pondering on the expected move yet, so a ponder search is an infinite one
+            Some("infinite") | Some("ponder") => limits.infinite = true,
+            Some("searchmoves") => {
+                let mut requested = 0;
+
+                while let Some(move_str) = tokens.peek().cloned() {
+                    if is_go_keyword(move_str) {
+                        break;
+                    }
+
+                    tokens.next();
+                    requested += 1;
+
+                    match move_from_algebraic(game, move_str.to_string()) {
+                        Some(m) => limits.searchmoves.push(m),
+                        None => eprintln!("warning: ignoring illegal searchmoves entry {}", move_str)
+                    }
+                }
+
+                if limits.searchmoves.is_empty() {
+                    eprintln!("warning: no legal searchmoves given ({} requested), searching all moves", requested);
+                }
+            },
+            //TODO: support these, for now skip their values so later arguments are still read
+            Some("movestogo") | Some("nodes") => { tokens.next(); },
+            Some(_) => {},
+            None => break
+        }
+    }
+
+    return limits;
+}
+
+fn is_go_keyword(token: &str) -> bool {
+    match token {
+        "searchmoves" | "ponder" | "wtime" | "btime" | "winc" | "binc" | "movestogo"
+            | "depth" | "nodes" | "mate" | "movetime" | "infinite" => true,
+        _ => false
+    }
+}
+
+// A writer that can still be read after being boxed into a UciIo, for tests.
+#[cfg(test)]
+#[derive(Clone)]
+pub struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+#[cfg(test)]
+impl SharedBuffer {
+    pub fn new() -> SharedBuffer {
+        SharedBuffer(Rc::new(RefCell::new(Vec::new())))
+    }
+
+    pub fn contents(&self) -> String {
+        String::from_utf8(self.0.borrow().clone()).unwrap()
+    }
+}
+
+#[cfg(test)]
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> { Ok(()) }
+}
+
+#[cfg(test)]
+mod test {
+    use uci::*;
+    use feldspar::*;
+
+    use std::env;
+    use std::fs;
+    use std::io;
+    use std::process;
+
+    fn run_session(input: &str) -> (String, UciIo) {
+        let output = SharedBuffer::new();
+        let mut io = UciIo::new(Box::new(io::Cursor::new(input.to_string().into_bytes())),
+                                Box::new(output.clone()));
+
+        Feldspar::with_table_size(1000).run_with(&mut io);
+
+        return (output.contents(), io);
+    }
+
+    // the score of the last completed iteration and the bestmove line, for a "position" command
+    fn search_score(position: &str, go: &str) -> (i32, String) {
+        let (output, _) = run_session(&format!("{}\n{}\nquit\n", position, go));
+
+        let last_info = output.lines().filter(|l| l.contains(" score cp ")).last().unwrap();
+        let tokens: Vec<&str> = last_info.split_whitespace().collect();
+        let cp_index = tokens.iter().position(|t| *t == "cp").unwrap();
+
+        let bestmove = output.lines().find(|l| l.starts_with("bestmove")).unwrap();
+        return (tokens[cp_index + 1].parse().unwrap(), bestmove.to_string());
+    }
+
+    #[test]
+    fn transcript() {
+        let log_path = env::temp_dir().join(format!("feldspar_uci_{}.log", process::id()));
+        let _ = fs::remove_file(&log_path);
+
+        let input = format!("setoption name LogFile value {}\n\
+                             uci\n\
+                             isready\n\
+                             position startpos moves e2e4\n\
+                             go depth 1\n\
+                             debug on\n\
+                             position startpos moves d2d4\n\
+                             quit\n", log_path.display());
+
+        let (output, io) = run_session(&input);
+        assert!(io.logging());
+        assert!(io.debug);
+
+        let output_lines: Vec<&str> = output.lines().collect();
+        assert!(output_lines.contains(&"uciok"));
+        assert!(output_lines.contains(&"readyok"));
+        assert!(output_lines.iter().any(|l| l.starts_with("bestmove ")));
+
+        // diagnostics only reach the GUI once debug is on
+        let diagnostics: Vec<&&str> = output_lines.iter().filter(|l| l.starts_with("info string")).collect();
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].ends_with("rnbqkbnr/pppppppp/8/8/3P4/8/PPP1PPPP/RNBQKBNR b KQkq - 0 1"));
+
+        let log = fs::read_to_string(&log_path).unwrap();
+        let log_lines: Vec<&str> = log.lines().collect();
+        let _ = fs::remove_file(&log_path);
+
+        // the setoption line itself was read before the log was opened
+        assert_eq!(log_lines.len(), output_lines.len() + 7 + 1);
+        assert!(log_lines[0].ends_with(" < uci"));
+        assert!(log_lines.iter().any(|l| l.ends_with(" > uciok")));
+        assert!(log_lines.iter().any(|l| l.ends_with(" < isready")));
+        assert!(log_lines.iter().any(|l| l.contains(" # info string FEN re-created by feldspar")));
+        assert!(log_lines.last().unwrap().ends_with(" < quit"));
+    }
+
+    #[test]
+    fn scripted_session() {
+        init_zobrist_hashing();
+
+        let (output, _) = run_session("debug on\nuci\nisready\nposition startpos\ngo depth 2\nstop\nisready\nquit\ngo depth 1\n");
+        let lines: Vec<&str> = output.lines().collect();
+
+        // in the order they were asked for, nothing after quit, and stop is no unknown command
+        let uciok = lines.iter().position(|&l| l == "uciok").unwrap();
+        let bestmoves: Vec<usize> = (0 .. lines.len()).filter(|&i| lines[i].starts_with("bestmove ")).collect();
+        let readyoks: Vec<usize> = (0 .. lines.len()).filter(|&i| lines[i] == "readyok").collect();
+
+        assert_eq!(bestmoves.len(), 1);
+        assert_eq!(readyoks.len(), 2);
+        assert!(uciok < readyoks[0] && readyoks[0] < bestmoves[0] && bestmoves[0] < readyoks[1]);
+        assert!(!output.contains("Un-used command"));
+    }
+
+    #[test]
+    fn illegal_position_move() {
+        init_zobrist_hashing();
+
+        let (output, _) = run_session("debug on\nposition startpos moves e2e4 e7e5 e4e5 d7d5\ngo depth 1\nquit\n");
+        assert!(output.contains("info string error! illegal move e4e5"));
+
+        // the game stops before the illegal move, with white to move
+        assert!(output.contains("FEN re-created by feldspar: rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2"));
+        assert!(output.lines().any(|l| l.starts_with("bestmove ")));
+    }
+
+    #[test]
+    fn invalid_fen_position() {
+        init_zobrist_hashing();
+
+        // no black king, so the position the engine had is kept
+        let (output, _) = run_session("debug on\nposition fen 8/8/8/8/8/8/8/4K3 w - - 0 1\nd\ngo depth 1\nquit\n");
+        assert!(output.contains("info string error! invalid fen"));
+        assert!(output.contains("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"));
+        assert!(output.lines().any(|l| l.starts_with("bestmove ")));
+    }
+
+    #[test]
+    fn short_fen_position() {
+        init_zobrist_hashing();
+
+        // no clocks, and the moves start right where they would have been
+        let (output, _) = run_session("debug on\nposition fen 4k3/8/8/8/8/8/8/4K2R w K - moves e1g1\nquit\n");
+        assert!(output.contains("FEN re-created by feldspar: 4k3/8/8/8/8/8/8/5RK1 b - - 1 1"));
+    }
+
+    #[test]
+    fn eval_command() {
+        init_zobrist_hashing();
+
+        let eval_cp = |output: &str| -> i32 {
+            let line = output.lines().find(|l| l.starts_with("eval cp ")).unwrap();
+            line["eval cp ".len() ..].parse().unwrap()
+        };
+
+        // black has lost its queen, and it's white's point of view with black to move too
+        let (output, _) = run_session("position fen rnb1kbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR b KQkq - 0 1\neval\nquit\n");
+        let cp = eval_cp(&output);
+        assert!(cp > 700 && cp < 1200, "{}", cp);
+        assert_eq!(output.lines().count(), 1);
+
+        let (output, _) = run_session("position startpos moves e2e4 d7d5 e4d5 c7c6 d5c6\neval trace\nquit\n");
+        assert!(output.lines().next().unwrap().starts_with("material mg "));
+        assert!(eval_cp(&output) > 100);
+    }
+
+    #[test]
+    fn d_command() {
+        init_zobrist_hashing();
+
+        let (output, _) = run_session("position startpos moves e2e4\nd\nquit\n");
+        let lines: Vec<&str> = output.lines().collect();
+
+        assert_eq!(lines[0], "  a b c d e f g h");
+        assert_eq!(lines[4], "5 . . . . . . . . 5");
+        assert_eq!(lines[5], "4 . . . . P . . . 4");
+        assert!(lines.contains(&"fen        : rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1"));
+
+        let game = Game::from_fen_str("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1").unwrap();
+        assert!(lines.contains(&format!("hash       : {:016x}", game.hash.unwrap()).as_str()));
+        assert!(lines.iter().any(|l| l.starts_with("eval cp ")));
+        assert_eq!(lines.last(), Some(&"derived fields ok"));
+    }
+
+    #[test]
+    fn unwritable_log_file() {
+        let (output, io) = run_session("setoption name LogFile value /nonexistent/feldspar/log.txt\nisready\n");
+        assert!(!io.logging());
+        assert_eq!(output, "readyok\n");
+    }
+
+    #[test]
+    fn go_searchmoves() {
+        let game = Game::starting_position();
+        let uci_strs = |limits: &SearchLimits| -> Vec<String> {
+            limits.searchmoves.iter().map(|m| m.to_uci_str()).collect()
+        };
+
+        let limits = parse_go_limits(&game, &mut "wtime 1000 movestogo 40 searchmoves e2e4 d2d4 depth 5".split_whitespace());
+        assert_eq!(uci_strs(&limits), vec!["e2e4", "d2d4"]);
+        assert_eq!(limits.wtime, 1000);
+        assert_eq!(limits.depth, Some(5));
+
+        let limits = parse_go_limits(&game, &mut "searchmoves e2e5 g1f3".split_whitespace());
+        assert_eq!(uci_strs(&limits), vec!["g1f3"]);
+
+        let limits = parse_go_limits(&game, &mut "infinite".split_whitespace());
+        assert!(limits.searchmoves.is_empty());
+        assert!(limits.infinite);
+
+        let limits = parse_go_limits(&game, &mut "ponder wtime 1000".split_whitespace());
+        assert!(limits.infinite);
+        assert_eq!(limits.wtime, 1000);
+        assert!(!parse_go_limits(&game, &mut "depth 3".split_whitespace()).infinite);
+    }
+
+    #[test]
+    fn go_infinite() {
+        init_zobrist_hashing();
+
+        // the infinite search answers once stopped, and the stop is used up by it
+        let (output, _) = run_session("position startpos\ngo infinite\nstop\ngo depth 2\nquit\n");
+        let bestmoves = output.lines().filter(|l| l.starts_with("bestmove ")).count();
+        assert_eq!(bestmoves, 2, "{}", output);
+        assert!(output.lines().skip_while(|l| !l.starts_with("bestmove ")).any(|l| l.starts_with("info depth 2 ")), "{}", output);
+
+        // a stop with nothing to stop is no reason to cut the next search short
+        let (output, _) = run_session("position startpos\nstop\ngo depth 3\nquit\n");
+        assert!(output.lines().any(|l| l.starts_with("info depth 3 ")), "{}", output);
+    }
+
+    #[test]
+    fn go_mate() {
+        init_zobrist_hashing();
+
+        let game = Game::starting_position();
+        let limits = parse_go_limits(&game, &mut "mate 2 searchmoves e2e4".split_whitespace());
+        assert_eq!(limits.mate, Some(2));
+        assert_eq!(limits.searchmoves.len(), 1);
+
+        // Ra7 and Rb8, or Rb7 and Ra8
+        let position = "position fen 7k/8/8/8/8/8/R7/1R4K1 w - - 0 1";
+
+        let (output, _) = run_session(&format!("{}\ngo mate 2\nquit\n", position));
+        let mate_info = output.lines().find(|l| l.contains("score mate")).unwrap();
+        let tokens: Vec<&str> = mate_info.split_whitespace().collect();
+        assert_eq!(tokens[.. 6], ["info", "depth", "3", "score", "mate", "2"]);
+        assert_eq!(tokens[6], "pv");
+        assert_eq!(tokens.len(), 10);
+        assert!(output.contains(&format!("bestmove {}", tokens[7])));
+
+        // no mate in one, the best move from a normal search is played instead
+        let (output, _) = run_session(&format!("{}\ngo mate 1\nquit\n", position));
+        assert!(!output.contains("score mate"));
+        assert!(output.lines().any(|l| l.starts_with("bestmove")));
+    }
+
+    #[test]
+    fn position_history() {
+        init_zobrist_hashing();
+
+        // black can return to the starting position for the third time with f6g8
+        let shuffle = "moves g1f3 g8f6 f3g1 f6g8 g1f3 g8f6 f3g1";
+
+        let search = |fen: &str| search_score(&format!("position fen {} {}", fen, shuffle), "go depth 4");
+
+        // a queen down, black takes the draw
+        let (losing_score, losing_move) = search("rnb1kbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+        assert_eq!(losing_score, 0);
+        assert_eq!(losing_move, "bestmove f6g8");
+
+        // a queen up, black plays on
+        let (winning_score, winning_move) = search("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNB1KBNR w KQkq - 0 1");
+        assert!(winning_score > 500, "{}", winning_score);
+        assert!(winning_move != "bestmove f6g8");
+    }
+
+    #[test]
+    fn repetition_boundary() {
+        init_zobrist_hashing();
+
+        // A queen down, black goes back to the starting position. It has been there once before
+        // the root, so that's only the second time, and white doesn't have to allow a third.
+        let position = "position fen rnb1kbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1 moves g1f3 g8f6 f3g1";
+        let (score, _) = search_score(position, "go depth 4 searchmoves f6g8");
+        assert!(score < -500, "{}", score);
+
+        // Two rooks down, black checks with Qh4+ and Qe1+ while the white king can only go
+        // between g1 and h2. The root comes around again after four plies, a draw from then on.
+        let position = "position fen k7/8/2R5/1R6/8/8/6PK/4q3 b - - 0 1";
+        let (score, bestmove) = search_score(position, "go depth 4 searchmoves e1h4");
+        assert_eq!(score, 0);
+        assert_eq!(bestmove, "bestmove e1h4");
+
+        // the same checks when the game has already been through the root, two checks earlier
+        let position = "position fen k7/8/2R5/1R6/8/8/6PK/4q3 b - - 0 1 moves e1h4 h2g1 h4e1 g1h2";
+        let (score, _) = search_score(position, "go depth 4 searchmoves e1h4");
+        assert_eq!(score, 0);
+    }
+
+    #[test]
+    fn chess960_castle_notation() {
+        init_zobrist_hashing();
+
+        let bestmove = |session: &str| -> String {
+            let (output, _) = run_session(session);
+            output.lines().find(|l| l.starts_with("bestmove")).unwrap().to_string()
+        };
+
+        // either way of writing a castle is read, and UCI_Chess960 decides how it's written
+        let castling = "position startpos moves e2e4 e7e5 g1f3 b8c6 f1c4 f8c5";
+        assert_eq!(bestmove(&format!("{}\ngo depth 1 searchmoves e1h1\nquit\n", castling)), "bestmove e1g1");
+        assert_eq!(bestmove(&format!("setoption name UCI_Chess960 value true\n{}\ngo depth 1 searchmoves e1g1\nquit\n", castling)),
+                   "bestmove e1h1");
+
+        // an X-FEN position is Chess960 without the option, here with a king that castles in place
+        let chess960 = "position fen 1r4kr/8/8/8/8/8/8/1R4KR w HBhb - 0 1";
+        assert_eq!(bestmove(&format!("{}\ngo depth 1 searchmoves g1h1\nquit\n", chess960)), "bestmove g1h1");
+
+        // and the pv in the info lines is written the same way
+        let (output, _) = run_session(&format!("{}\ngo depth 2 searchmoves g1b1\nquit\n", chess960));
+        assert!(output.lines().any(|l| l.starts_with("info depth 2") && l.contains(" pv g1b1 ")), "{}", output);
+    }
+}
@@ -9,6 +9,11 @@ use eval::*;
 
 use rand::Rng;
 
+use std::cmp::{max, min};
+use std::mem;
+use std::sync::{Once, ONCE_INIT};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub struct Hash(u64);
 
@@ -16,6 +21,16 @@ static mut piece_keys: [[u64;64]; 12] = [ [0;64]; 12 ];
 static mut black_to_move_key: u64 = 0;
 static mut castle_keys: [u64; 16] = [0; 16];
 static mut ep_keys: [u64; 8] = [0; 8];
+static ZOBRIST_INIT: Once = ONCE_INIT;
+
+// Every move of a piece other than a pawn between two squares it could go back and forth
+// between on an empty board, by the difference it makes to the hash (which is the same
+// both ways). Two positions whose hashes differ by one of these keys are a move apart.
+// Cuckoo hashing, as Stockfish does it: an entry is in one of the two slots its key hashes
+// to, so a lookup is two probes and doesn't need anything else to tell the keys apart.
+const CUCKOO_SIZE: usize = 8192;
+static mut cuckoo_keys: [u64; CUCKOO_SIZE] = [0; CUCKOO_SIZE];
+static mut cuckoo_moves: [u32; CUCKOO_SIZE] = [0; CUCKOO_SIZE];
 
 impl Hash {
     pub fn change_piece(&mut self, color: Color, piece_type: PieceType, square: Square) {
@@ -49,26 +64,18 @@ impl Hash {
     }
 
     pub fn new(game: &Game) -> Hash {
-        let mut hash = Hash::empty();
-
-        unsafe {
-            for color in [Color::White, Color::Black].iter() {
-                for piece_type in PieceType::all() {
-                    for square in game.board.get_pieces(*color, *piece_type) {
-                        hash.change_piece(*color, *piece_type, square);
-                    }
-                }
-            }
-
-            hash.0 ^= castle_keys[game.castling_rights.bits() as usize];
+        Hash(game.board.zobrist_key(game.to_move, game.castling_rights, game.ep_square))
+    }
+}
 
-            if game.to_move == Color::Black {
-                hash.0 ^= black_to_move_key;
-            }
+impl Hash {
+    // key over the pawns alone, kept up to date in Game::make_move
+    pub fn pawns(board: &Board) -> Hash {
+        let mut hash = Hash::empty();
 
-            match game.ep_square {
-                Some(square) => hash.0 ^= ep_keys[square.file() as usize - 1],
-                None => {}
+        for color in [Color::White, Color::Black].iter() {
+            for square in board.get_pieces(*color, PieceType::Pawn) {
+                hash.change_piece(*color, PieceType::Pawn, square);
             }
         }
 
@@ -76,24 +83,140 @@ impl Hash {
     }
 }
 
+// number of times hash appears in a history of earlier positions
+pub fn count_occurrences(history: &[Hash], hash: Hash) -> usize {
+    history.iter().filter(|h| **h == hash).count()
+}
+
+// the keys are the same in every run, so hashes, table contents and node counts are reproducible
+const ZOBRIST_SEED: u64 = 0x6665_6c64_7370_6172;
+
+// only the first call generates keys, so hashes stay consistent within a process
 pub fn init_zobrist_hashing() {
+    init_zobrist_hashing_seeded(ZOBRIST_SEED);
+}
+
+// The same with keys from another seed, if it's the first call. Once there are keys, every
+// hash already computed depends on them, so a later call keeps them whatever its seed.
+pub fn init_zobrist_hashing_seeded(seed: u64) {
+    ZOBRIST_INIT.call_once(|| generate_zobrist_keys(seed));
+}
+
+// SplitMix64, written out rather than taken from rand so that the keys can't change with its version
+struct KeyGenerator(u64);
+
+impl KeyGenerator {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        return z ^ (z >> 31);
+    }
+}
+
+fn generate_zobrist_keys(seed: u64) {
+    let mut keys = KeyGenerator(seed);
+
     unsafe {
         for i in 0 .. 12 {
             for j in 0 .. 64 {
-                piece_keys[i][j] = rand::random::<u64>();
+                piece_keys[i][j] = keys.next();
             }
         }
 
-        black_to_move_key = rand::random::<u64>();
+        black_to_move_key = keys.next();
 
         for i in 0 .. 16 {
-            castle_keys[i] = rand::random::<u64>();
+            castle_keys[i] = keys.next();
         }
 
         for i in 0 .. 8 {
-            ep_keys[i] = rand::random::<u64>();
+            ep_keys[i] = keys.next();
         }
     }
+
+    fill_cuckoo_tables();
+}
+
+fn cuckoo_first(key: u64) -> usize {
+    (key & (CUCKOO_SIZE as u64 - 1)) as usize
+}
+
+fn cuckoo_second(key: u64) -> usize {
+    ((key >> 16) & (CUCKOO_SIZE as u64 - 1)) as usize
+}
+
+fn fill_cuckoo_tables() {
+    use PieceType::*;
+
+    for color in [Color::White, Color::Black].iter() {
+        for ptype in [Knight, Bishop, Rook, Queen, King].iter() {
+            for i in 0 .. 64 {
+                let from = Square::new(i);
+
+                let reach = match *ptype {
+                    Knight => KNIGHT_TABLE[from.idx()],
+                    Bishop => get_bishop_rays(from, Bitboard::new(0)),
+                    Rook   => get_rook_rays(from, Bitboard::new(0)),
+                    Queen  => get_queen_rays(from, Bitboard::new(0)),
+                    _      => KING_TABLE[from.idx()]
+                };
+
+                // each pair once, from the lower square
+                for to in reach {
+                    if to.idx() <= from.idx() {
+                        continue;
+                    }
+
+                    let mut hash = Hash::empty();
+                    hash.change_piece(*color, *ptype, from);
+                    hash.change_piece(*color, *ptype, to);
+                    hash.update_black_to_move();
+
+                    insert_cuckoo(hash.unwrap(), Move::new_quiet(from, to, MoveFlag::Quiet, *ptype).unwrap());
+                }
+            }
+        }
+    }
+}
+
+// kicks out whatever is in the first slot, which goes to its other slot, and so on
+fn insert_cuckoo(mut key: u64, mut m: u32) {
+    let mut i = cuckoo_first(key);
+
+    unsafe {
+        loop {
+            mem::swap(&mut cuckoo_keys[i], &mut key);
+            mem::swap(&mut cuckoo_moves[i], &mut m);
+
+            if m == 0 {
+                return;
+            }
+
+            i = if i == cuckoo_first(key) { cuckoo_second(key) } else { cuckoo_first(key) };
+        }
+    }
+}
+
+// The move one of whose pieces makes up the difference between two hashes, from the lower
+// square to the higher one whichever way it goes. Hashes that differ by a side to move and
+// one piece on one square or another are the only ones found.
+pub fn cuckoo_move(hash_difference: u64) -> Option<Move> {
+    if hash_difference == 0 {
+        return None;
+    }
+
+    unsafe {
+        for &i in [cuckoo_first(hash_difference), cuckoo_second(hash_difference)].iter() {
+            if cuckoo_keys[i] == hash_difference {
+                return Some(Move::wrap(cuckoo_moves[i]));
+            }
+        }
+    }
+
+    return None;
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -106,15 +229,27 @@ pub enum NodeType {
     Cut = 2
 }
 
+// Where the score of a node came from. A drawn position anywhere in the line the score was
+// taken from (stalemate, repetition, the fifty move rule or too little material) makes it a
+// Draw, while pruning on the static evaluation knows nothing of the line and is Normal.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ScoreKind {
+    Normal = 0,
+    Draw = 1
+}
+
 impl EntryData {
+    // the age only has seven bits, the eighth is the score kind
     pub fn new( best_move: Move
               , score: Score
               , depth: u8
               , node_type: NodeType
+              , kind: ScoreKind
               , age: u8) -> EntryData
     {
         EntryData(
-              (age as u64) << 56
+              ((age & 0x7f) as u64) << 57
+            | (kind as u64) << 56
             | (depth as u64) << 50
             | (node_type as u64) << 48
             | (score.store_u16() as u64) << 32
@@ -131,7 +266,11 @@ impl EntryData {
     }
 
     pub fn age(self) -> u8 {
-        ((self.0 >> 56) & 0xff) as u8
+        ((self.0 >> 57) & 0x7f) as u8
+    }
+
+    pub fn score_kind(self) -> ScoreKind {
+        if (self.0 >> 56) & 1 == 1 { ScoreKind::Draw } else { ScoreKind::Normal }
     }
 
     pub fn best_move(self) -> Move {
@@ -171,65 +310,90 @@ impl Key {
     fn unwrap(self) -> u64 { self.0 }
 }
 
-#[derive(Debug, PartialEq, Clone)]
+// Two words written and read without a lock, so a thread can read one word of a store and
+// the other of a store made at the same time by another thread. The key is the hash XORed
+// with the data, and an entry torn like that fails the key check just as an entry for some
+// other position does (the lockless scheme of Hyatt and Mann).
+#[derive(Debug)]
 pub struct TableEntry {
-    key: Key,
-    entry: EntryData
+    key: AtomicU64,
+    entry: AtomicU64
 }
 
 impl TableEntry {
-    pub fn new(new_hash: Hash, new_entry: EntryData) -> TableEntry {
+    pub fn empty() -> TableEntry {
         TableEntry {
-            key: Key::new(new_hash, new_entry),
-            entry: new_entry
+            key: AtomicU64::new(Key::empty().unwrap()),
+            entry: AtomicU64::new(EntryData::empty().unwrap())
         }
     }
 
-    pub fn empty() -> TableEntry {
-        TableEntry {
-            key: Key::empty(),
-            entry: EntryData::empty()
-        }
+    fn load(&self) -> (Key, EntryData) {
+        (Key(self.key.load(Ordering::Relaxed)), EntryData(self.entry.load(Ordering::Relaxed)))
+    }
+
+    fn store(&self, key: Key, entry: EntryData) {
+        self.key.store(key.unwrap(), Ordering::Relaxed);
+        self.entry.store(entry.unwrap(), Ordering::Relaxed);
     }
 }
 
-#[derive(Debug, Clone)]
+// Shared by every search thread.
+#[derive(Debug)]
 pub struct TranspositionTable {
-    entries: Vec<TableEntry>
+    entries: Vec<TableEntry>,
+    // age of the most recent store, entries with any other age are left over from earlier searches
+    generation: AtomicUsize
 }
 
 impl TranspositionTable {
     pub fn new(count: usize) -> TranspositionTable {
         TranspositionTable {
-            entries: vec![TableEntry::empty(); count]
+            entries: (0 .. max(1, count)).map(|_| TableEntry::empty()).collect(),
+            generation: AtomicUsize::new(0)
         }
     }
 
-    pub fn probe(&self, hash: Hash) -> Option<EntryData> {
+    fn entry(&self, hash: Hash) -> &TableEntry {
         let idx = (hash.unwrap() % self.entries.len() as u64) as usize;
+        return unsafe { self.entries.get_unchecked(idx) };
+    }
 
-        let probed_entry = unsafe { self.entries.get_unchecked(idx) };
+    pub fn probe(&self, hash: Hash) -> Option<EntryData> {
+        let (key, entry) = self.entry(hash).load();
 
-        if (probed_entry.key.unwrap() ^ probed_entry.entry.unwrap() == hash.unwrap()) {
-            return Some(probed_entry.entry);
+        if key.unwrap() ^ entry.unwrap() == hash.unwrap() {
+            return Some(entry);
         } else {
             return None;
         }
     }
 
-    pub fn update(&mut self, hash: Hash, new_entry: EntryData) {
-        let idx = (hash.unwrap() % self.entries.len() as u64) as usize;
-        let new_table_entry = TableEntry::new(hash, new_entry);
-        unsafe {
-            *self.entries.get_unchecked_mut(idx) = new_table_entry;
-        }
+    pub fn update(&self, hash: Hash, new_entry: EntryData) {
+        self.entry(hash).store(Key::new(hash, new_entry), new_entry);
+        self.generation.store(new_entry.age() as usize, Ordering::Relaxed);
     }
 
-    pub fn reset(&mut self) {
-        for x in self.entries.iter_mut() {
-            x.key = Key::empty();
-            x.entry = EntryData::empty();
+    pub fn reset(&self) {
+        for x in self.entries.iter() {
+            x.store(Key::empty(), EntryData::empty());
         }
+        self.generation.store(0, Ordering::Relaxed);
+    }
+
+    // per-mille of sampled entries that were written by the current generation, for UCI hashfull
+    pub fn hashfull(&self) -> u32 {
+        const SAMPLE_SIZE: usize = 1000;
+
+        let sample_size = min(SAMPLE_SIZE, self.entries.len());
+        let generation = self.generation.load(Ordering::Relaxed) as u8;
+
+        let occupied = self.entries[.. sample_size].iter()
+            .map(|x| x.load().1)
+            .filter(|&entry| entry != EntryData::empty() && entry.age() == generation)
+            .count();
+
+        return (occupied * 1000 / sample_size) as u32;
     }
 
     pub fn get_pv(&self, mut game: Game, mut max_length: usize) -> Vec<EntryData> {
@@ -239,11 +403,14 @@ impl TranspositionTable {
             match self.probe(game.hash) {
                 None => break,
                 Some(tentry) => {
-                    match tentry.node_type() {
-                        NodeType::PV => variation.push(tentry),
-                        _ => break
-                    }
                     let best_move = tentry.best_move();
+
+                    // entries can collide, so don't trust the stored move blindly
+                    if tentry.node_type() != NodeType::PV || !game.is_legal(best_move) {
+                        break;
+                    }
+
+                    variation.push(tentry);
                     game.make_move(best_move);
                     max_length -= 1;
                 }
@@ -257,8 +424,12 @@ impl TranspositionTable {
 #[cfg(test)]
 mod test {
     use zobrist::*;
+    use movegen::*;
     use rand::{thread_rng, Rng};
 
+    use std::sync::Arc;
+    use std::thread;
+
     fn random_node_type() -> NodeType {
         match thread_rng().gen_range(0,3) {
             0 => NodeType::PV,
@@ -274,18 +445,141 @@ mod test {
         let mut entry_data = EntryData::empty();
         for _ in 0 .. 1000 {
             let random_move = Move::wrap(rand::random::<u32>());
-            let random_score = Score::new(rand::random::<i16>());
+            let random_score = Score::new(thread_rng().gen_range(Score::min().unwrap(), Score::max().unwrap() + 1));
             let random_depth = thread_rng().gen_range(0,0x3f);
             let random_node_type = random_node_type();
-            let random_age = thread_rng().gen_range(0,0xff) as u8;
+            let random_kind = if rand::random::<bool>() { ScoreKind::Draw } else { ScoreKind::Normal };
+            let random_age = thread_rng().gen_range(0,0x7f) as u8;
 
-            entry_data = EntryData::new(random_move, random_score, random_depth, random_node_type, random_age);
+            entry_data = EntryData::new(random_move, random_score, random_depth, random_node_type, random_kind, random_age);
 
             assert!(entry_data.best_move() == random_move);
             assert!(entry_data.score() == random_score);
             assert!(entry_data.age() == random_age);
             assert!(entry_data.depth() == random_depth as u8);
             assert!(entry_data.node_type() == random_node_type);
+            assert!(entry_data.score_kind() == random_kind);
+        }
+    }
+
+    #[test]
+    fn seeded_keys() {
+        init_zobrist_hashing();
+
+        // the same in every run, not just within this one
+        let start = Game::starting_position();
+        assert_eq!(start.hash.unwrap(), 0xedc122a2f3979f0a);
+
+        // two generators from the same seed agree, and another seed gives other keys
+        let keys = |seed: u64| -> Vec<u64> {
+            let mut generator = KeyGenerator(seed);
+            (0 .. 12 * 64 + 1 + 16 + 8).map(|_| generator.next()).collect()
+        };
+        assert_eq!(keys(ZOBRIST_SEED), keys(ZOBRIST_SEED));
+        assert!(keys(ZOBRIST_SEED).iter().zip(keys(ZOBRIST_SEED + 1).iter()).all(|(a, b)| a != b));
+
+        // keys already in use stay as they are
+        init_zobrist_hashing_seeded(ZOBRIST_SEED + 1);
+        assert_eq!(Game::starting_position().hash, start.hash);
+    }
+
+    #[test]
+    fn cuckoo_tables() {
+        init_zobrist_hashing();
+
+        // the number of reversible moves on an empty board, everyone gets the same
+        let entries = unsafe { cuckoo_moves.iter().filter(|&&m| m != 0).count() };
+        assert_eq!(entries, 3668);
+
+        // a quiet move that changes nothing but where the piece is can be found from the hashes
+        for _ in 0 .. 200 {
+            let game = Game::random_game();
+
+            for m in next_moves_standalone(&game).iter() {
+                if m.is_capture() || m.is_castle() || m.moved_piece() == PieceType::Pawn {
+                    continue;
+                }
+
+                let mut after = game;
+                after.make_move(*m);
+
+                let found = cuckoo_move(game.hash.unwrap() ^ after.hash.unwrap());
+                if after.castling_rights != game.castling_rights || game.ep_square.is_some() {
+                    assert_eq!(found, None);
+                    continue;
+                }
+
+                let found = found.unwrap();
+                assert_eq!(found.moved_piece(), m.moved_piece());
+                assert!((found.from(), found.to()) == (m.from(), m.to()) || (found.from(), found.to()) == (m.to(), m.from()));
+            }
+        }
+
+        assert_eq!(cuckoo_move(0), None);
+    }
+
+    #[test]
+    fn hashfull() {
+        let table = TranspositionTable::new(4096);
+        assert_eq!(table.hashfull(), 0);
+
+        let entry = EntryData::new(Move::null(), Score::new(0), 1, NodeType::PV, ScoreKind::Normal, 7);
+        for i in 0 .. 3000u64 {
+            table.update(Hash(i.wrapping_mul(0x9E3779B97F4A7C15)), entry);
+        }
+        let filled = table.hashfull();
+        assert!(filled > 300 && filled <= 1000, "{}", filled);
+
+        // entries from an older search don't count
+        let newer = EntryData::new(Move::null(), Score::new(0), 1, NodeType::PV, ScoreKind::Normal, 8);
+        table.update(Hash(1), newer);
+        assert!(table.hashfull() < 10);
+
+        table.reset();
+        assert_eq!(table.hashfull(), 0);
+    }
+
+    #[test]
+    fn concurrent_updates() {
+        const THREADS: usize = 4;
+        const KEYS: u64 = 256;
+
+        // everything but the depth follows from the hash, the depth says which thread stored it
+        let entry_for = |hash: u64, thread: usize| {
+            EntryData::new(Move::wrap(hash as u32 & 0xffff), Score::new((hash % 1000) as i16), thread as u8, NodeType::Cut,
+                           ScoreKind::Normal, 1)
+        };
+
+        // far fewer entries than keys, so that the threads fight over every one of them
+        let table = Arc::new(TranspositionTable::new(61));
+
+        let threads: Vec<_> = (0 .. THREADS).map(|thread| {
+            let table = table.clone();
+
+            thread::spawn(move || {
+                let mut found = 0;
+
+                for i in 0 .. 200000 {
+                    let hash = (i % KEYS).wrapping_mul(0x9E3779B97F4A7C15);
+                    table.update(Hash(hash), entry_for(hash, thread));
+
+                    let probed = (i * 7 + thread as u64) % KEYS;
+                    let probed = probed.wrapping_mul(0x9E3779B97F4A7C15);
+
+                    if let Some(entry) = table.probe(Hash(probed)) {
+                        let depth = entry.depth() as usize;
+                        assert!(depth < THREADS, "{:?}", entry);
+                        assert_eq!(entry, entry_for(probed, depth));
+                        found += 1;
+                    }
+                }
+
+                found
+            })
+        }).collect();
+
+        for thread in threads {
+            assert!(thread.join().unwrap() > 0);
         }
     }
 }